@@ -10,5 +10,8 @@ pub(crate) mod kube_client;
 /// Contains macros.
 pub(crate) mod macros;
 
+/// Contains tools to retry operations which fail with transient errors.
+pub(crate) mod retry;
+
 /// Contains tools to create storage API clients.
 pub(crate) mod rest_client;
@@ -1,3 +1,6 @@
+use semver::Version;
+use std::time::Duration;
+
 /// This is the name of the project that is being upgraded.
 pub(crate) const PRODUCT: &str = "Mayastor";
 
@@ -8,15 +11,82 @@ pub(crate) const UMBRELLA_CHART_NAME: &str = "openebs";
 /// This is the name of the Helm chart of this project.
 pub(crate) const CORE_CHART_NAME: &str = "mayastor";
 
+/// This is the helm chart type of charts which can be installed. Helm defaults to this type when
+/// the 'type' field is absent in a Chart.yaml file.
+pub(crate) const APPLICATION_CHART_TYPE: &str = "application";
+
 /// This is the shared Pod label of the <helm-release>-io-engine DaemonSet.
 pub(crate) const IO_ENGINE_LABEL: &str = "app=io-engine";
 
+/// This is the name of the io-engine container in the <helm-release>-io-engine DaemonSet Pods.
+pub(crate) const IO_ENGINE_CONTAINER_NAME: &str = "io-engine";
+
+/// This is the environment variable of the io-engine container which carries its log level.
+pub(crate) const IO_ENGINE_LOG_LEVEL_ENV: &str = "RUST_LOG";
+
 /// This is the shared Pod label of the <helm-release>-agent-core Deployment.
 pub(crate) const AGENT_CORE_LABEL: &str = "app=agent-core";
 
 /// This is the shared label across the helm chart components which carries the chart version.
 pub(crate) const CHART_VERSION_LABEL_KEY: &str = "openebs.io/version";
 
+/// This is the shared label on the Kubernetes Secrets which helm creates for helm release
+/// revisions.
+pub(crate) const HELM_RELEASE_OWNER_LABEL: &str = "owner=helm";
+
+/// This is the label on a helm release Secret which carries the revision number.
+pub(crate) const HELM_RELEASE_VERSION_LABEL_KEY: &str = "version";
+
+/// This is the suffix of the name of the ConfigMap which carries the data-plane upgrade progress,
+/// the prefix is the helm release name.
+pub(crate) const UPGRADE_STATE_CONFIGMAP_NAME_SUFFIX: &str = "-upgrade-state";
+
+/// This is the key of the data-plane upgrade progress in the upgrade state ConfigMap.
+pub(crate) const UPGRADE_STATE_CONFIGMAP_DATA_KEY: &str = "state";
+
+/// This is the key in the upgrade state ConfigMap which pauses the data-plane upgrade, when it is
+/// set to "true".
+pub(crate) const UPGRADE_PAUSED_CONFIGMAP_DATA_KEY: &str = "paused";
+
+/// This is the interval at which a paused data-plane upgrade checks if it has been resumed.
+pub(crate) const UPGRADE_PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// This is the prefix of the keys of the audit records in the audit ConfigMap, the suffix is the
+/// time of the audit export.
+pub(crate) const AUDIT_RECORD_CONFIGMAP_KEY_PREFIX: &str = "audit-";
+
+/// This is the default number of audit records which the audit ConfigMap retains. A ConfigMap is
+/// limited to 1MiB, so the oldest records are removed as new ones are added.
+pub(crate) const AUDIT_RECORD_CONFIGMAP_MAX_RECORDS: usize = 20;
+
+/// This is the suffix of the name of the api-rest Service, the prefix is the helm release name.
+pub(crate) const API_REST_SERVICE_NAME_SUFFIX: &str = "-api-rest";
+
+/// This is the http port of the api-rest Service.
+pub(crate) const API_REST_HTTP_PORT: u16 = 8081;
+
+/// This replaces the sensitive helm values in the upgrade plan and in the audit records.
+pub(crate) const REDACTED_VALUE: &str = "***";
+
+/// These are the key paths of the helm values which are redacted by default, unless --redact is
+/// set. A '*' matches any number of keys.
+pub(crate) const DEFAULT_REDACTED_VALUES_PATHS: [&str; 5] = [
+    "*.password",
+    "*.rootPassword",
+    "*.token",
+    "*.pullSecrets",
+    "*.imagePullSecrets.secrets",
+];
+
+/// This describes the helm values of the installed helm release, in error messages.
+pub(crate) const INSTALLED_VALUES_SOURCE: &str = "the installed helm release";
+
+/// This describes the helm values of the helm chart to upgrade to, in error messages.
+pub(crate) const TARGET_VALUES_SOURCE: &str = "the target helm chart";
+
+/// This describes the helm values which are passed to helm upgrade, in error messages.
+pub(crate) const UPGRADE_VALUES_SOURCE: &str = "the merged upgrade values";
+
 /// This is the label set on a storage API Node resource when a 'Node Drain' is issued.
 pub(crate) const DRAIN_FOR_UPGRADE: &str = "mayastor-upgrade";
 
@@ -38,3 +108,72 @@ pub(crate) const TWO_DOT_THREE: &str = "2.3.0";
 
 /// Version value for the earliest possible 2.4 release (there were no pre-releases).
 pub(crate) const TWO_DOT_FOUR: &str = "2.4.0";
+
+/// This is the name of the JSON schema file for the helm values, which a helm chart may ship.
+pub(crate) const HELM_VALUES_SCHEMA_FILENAME: &str = "values.schema.json";
+
+/// This is the maximum number of attempts for a Kubernetes API request which fails with transient
+/// errors.
+pub(crate) const KUBE_API_MAX_ATTEMPTS: u32 = 5;
+
+/// This is the delay before the first retry of an operation which failed with a transient error.
+pub(crate) const RETRY_INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// This is the upper bound of the delay between retries of an operation.
+pub(crate) const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// This is the earliest version of the Core helm chart which is expected to have the
+/// thin-provisioning options, i.e. 'agents.core.capacity.thin'. This is not verified against
+/// every released helm chart, so it only decides between a warning and silence when the options
+/// are absent.
+pub(crate) const THIN_PROVISIONING_MIN_VERSION: Version = Version::new(2, 2, 0);
+
+/// This is the oldest appVersion, i.e. the io-engine version, which may be upgraded from, including
+/// its pre-releases. Older installations have to be upgraded to this version first.
+pub(crate) const MIN_UPGRADABLE_FROM: Version = Version::new(2, 0, 0);
+
+/// This is the helm repository which publishes the Core helm chart.
+pub(crate) const HELM_REPO_URL: &str = "https://openebs.github.io/mayastor-extensions";
+
+/// This is the media type of OCI image manifests, which helm charts in OCI registries are
+/// published with.
+pub(crate) const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// This is the media type of the OCI image layer which has the helm chart .tgz.
+pub(crate) const HELM_CHART_LAYER_MEDIA_TYPE: &str =
+    "application/vnd.cncf.helm.chart.content.v1.tar+gzip";
+
+/// This is the maximum number of redirects which are followed to download an OCI blob.
+pub(crate) const OCI_MAX_REDIRECTS: usize = 5;
+
+/// This is the Kubernetes Node resource name of 2MiB hugepages.
+pub(crate) const HUGEPAGES_2MI_RESOURCE: &str = "hugepages-2Mi";
+
+/// This is the Kubernetes Node resource name of the local ephemeral storage.
+pub(crate) const EPHEMERAL_STORAGE_RESOURCE: &str = "ephemeral-storage";
+
+/// This is the Kubernetes Node condition for when the node is low on disk space.
+pub(crate) const DISK_PRESSURE_CONDITION: &str = "DiskPressure";
+
+/// This is the number of times the pre-upgrade webhook is called, if the request fails or times
+/// out.
+pub(crate) const PRE_UPGRADE_WEBHOOK_MAX_ATTEMPTS: u32 = 2;
+
+/// This is the number of minor versions which the io-engine may be behind the control-plane, after
+/// a control-plane only upgrade.
+pub(crate) const MAX_DATA_PLANE_MINOR_VERSION_SKEW: u64 = 1;
+
+/// This is the path of the io-engine DaemonSet's template in the Core helm chart, relative to the
+/// helm chart directory.
+pub(crate) const IO_ENGINE_DAEMONSET_TEMPLATE: &str =
+    "templates/mayastor/io/io-engine-daemonset.yaml";
+
+/// This is the tracing target which the output of the helm upgrade command is logged with.
+pub(crate) const HELM_OUTPUT_LOG_TARGET: &str = "helm";
+
+/// This is the number of lines from the end of the helm upgrade command's output which are
+/// included in the error, if the helm upgrade fails.
+pub(crate) const HELM_OUTPUT_TAIL_LINES: usize = 20;
+
+/// This is the number of unchanged lines shown around the changes in a unified diff.
+pub(crate) const UNIFIED_DIFF_CONTEXT_LINES: usize = 3;
@@ -1,12 +1,14 @@
 use crate::{
     common::constants::{
-        CHART_VERSION_LABEL_KEY, CORE_CHART_NAME, PRODUCT, TO_UMBRELLA_SEMVER, UMBRELLA_CHART_NAME,
-        UMBRELLA_CHART_UPGRADE_DOCS_URL,
+        APPLICATION_CHART_TYPE, CHART_VERSION_LABEL_KEY, CORE_CHART_NAME, PRODUCT,
+        TO_UMBRELLA_SEMVER, UMBRELLA_CHART_NAME, UMBRELLA_CHART_UPGRADE_DOCS_URL,
     },
     events::event_recorder::EventNote,
+    helm::chart::Percentage,
 };
+use semver::Version;
 use snafu::Snafu;
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, time::Duration};
 use url::Url;
 
 /// For use with multiple fallible operations which may fail for different reasons, but are
@@ -129,6 +131,77 @@ pub(crate) enum Error {
         input_yaml: String,
     },
 
+    /// Error for when a helm chart file could not be read.
+    #[snafu(display("Failed to read helm chart file {}: {}", path.display(), source))]
+    ChartFileRead {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    /// Error for when a helm chart file could not be parsed as yaml.
+    #[snafu(display("Failed to parse helm chart file {}: {}", path.display(), source))]
+    ChartYamlParse {
+        source: serde_yaml::Error,
+        path: PathBuf,
+    },
+
+    /// Error for when yaml could not be deserialized. This names the path of the yaml key which
+    /// failed deserialization.
+    #[snafu(display("Failed to deserialize yaml at '{}': {}", path, source))]
+    ValuesDeserialize {
+        source: serde_yaml::Error,
+        path: String,
+    },
+
+    /// Error for when the upgrade state ConfigMap could not be fetched.
+    #[snafu(display("Failed to GET upgrade state ConfigMap '{}': {}", name, source))]
+    GetUpgradeStateConfigMap { source: kube::Error, name: String },
+
+    /// Error for when the upgrade state ConfigMap could not be created or updated.
+    #[snafu(display("Failed to apply upgrade state ConfigMap '{}': {}", name, source))]
+    PatchUpgradeStateConfigMap { source: kube::Error, name: String },
+
+    /// Error for when the upgrade state ConfigMap could not be deleted.
+    #[snafu(display("Failed to delete upgrade state ConfigMap '{}': {}", name, source))]
+    DeleteUpgradeStateConfigMap { source: kube::Error, name: String },
+
+    /// Error for when the upgrade state in the ConfigMap is not valid JSON.
+    #[snafu(display(
+        "Failed to parse upgrade state from ConfigMap '{}' as JSON: {}",
+        name,
+        source
+    ))]
+    JsonParseUpgradeState {
+        source: serde_json::Error,
+        name: String,
+    },
+
+    /// Error for when the upgrade state could not be serialized to JSON.
+    #[snafu(display("Failed to serialize the upgrade state to JSON: {}", source))]
+    SerializeUpgradeState { source: serde_json::Error },
+
+    /// Error for when the stored upgrade state is of an upgrade to a different version.
+    #[snafu(display(
+        "The interrupted upgrade was to version {}, but the upgrade is now to version {}, delete \
+        the ConfigMap '{}' to discard the progress of the interrupted upgrade",
+        stored,
+        target,
+        name
+    ))]
+    UpgradeTargetChanged {
+        stored: String,
+        target: String,
+        name: String,
+    },
+
+    /// Error for when the upgrade plan could not be serialized to JSON.
+    #[snafu(display("Failed to serialize the upgrade plan to JSON: {}", source))]
+    SerializeUpgradePlan { source: serde_json::Error },
+
+    /// Error for when helm values could not be serialized to yaml.
+    #[snafu(display("Failed to serialize helm values to yaml: {}", source))]
+    SerializeValuesYaml { source: serde_yaml::Error },
+
     /// Error for when yaml could not be parsed from a file (Reader).
     #[snafu(display("Failed to parse YAML at {}: {}", filepath.display(), source))]
     YamlParseFromFile {
@@ -140,8 +213,17 @@ pub(crate) enum Error {
     #[snafu(display("Failed to parse unsupported versions yaml: {}", source))]
     YamlParseBufferForUnsupportedVersion { source: serde_yaml::Error },
 
+    /// Error for when yaml could not be parsed from bytes.
+    #[snafu(display("Failed to parse known versions yaml: {}", source))]
+    YamlParseBufferForKnownVersions { source: serde_yaml::Error },
+
+    /// Error for when yaml could not be parsed from bytes.
+    #[snafu(display("Failed to parse upgrade compatibility matrix yaml: {}", source))]
+    YamlParseBufferForCompatibilityMatrix { source: serde_yaml::Error },
+
     /// Error for when the Helm chart installed in the cluster is not of the umbrella or core
     /// variant.
+    #[allow(dead_code)]
     #[snafu(display(
         "Helm chart release {} in Namespace {} has an unsupported chart variant: {}",
         release_name,
@@ -187,6 +269,46 @@ pub(crate) enum Error {
     #[snafu(display("Failed to find valid Helm chart in path {}", path.display()))]
     FindingHelmChart { path: PathBuf },
 
+    /// Error for when the helm chart found in a path is not an installable chart.
+    #[snafu(display(
+        "Helm chart in path {} is of type '{}', only '{}' charts are supported",
+        path.display(),
+        chart_type,
+        APPLICATION_CHART_TYPE
+    ))]
+    NotAnApplicationHelmChart { path: PathBuf, chart_type: String },
+
+    /// Error for when the helm chart to upgrade to is deprecated, and upgrades to deprecated
+    /// charts are not allowed.
+    #[snafu(display(
+        "The {} helm chart version {} is deprecated, upgrade to deprecated charts is disabled",
+        name,
+        version
+    ))]
+    DeprecatedHelmChart { name: String, version: String },
+
+    /// Error for when a sub-chart dependency of a helm chart is absent in the chart's 'charts'
+    /// directory.
+    #[snafu(display(
+        "Failed to find helm chart dependency {} with version {} in directory {}",
+        name,
+        version_req,
+        path.display()
+    ))]
+    HelmChartDependencyAbsent {
+        name: String,
+        version_req: String,
+        path: PathBuf,
+    },
+
+    /// Error for when the helm chart to upgrade to is not the same chart as the installed one.
+    #[snafu(display(
+        "Helm chart '{}' cannot be upgraded to a different helm chart '{}'",
+        installed,
+        target
+    ))]
+    ChartNameMismatch { installed: String, target: String },
+
     /// Error for when a Kubernetes API request for GET-ing a Pod fails.
     #[snafu(display(
         "Failed to GET Kubernetes Pod {} in namespace {}: {}",
@@ -243,6 +365,7 @@ pub(crate) enum Error {
     EmptyPodNodeName { name: String, namespace: String },
 
     /// Error for when the metadata.uid of a Pod is empty.
+    #[allow(dead_code)]
     #[snafu(display(
         "Failed to get .metadata.uid from Pod {} in Namespace {}",
         name,
@@ -282,6 +405,28 @@ pub(crate) enum Error {
     #[snafu(display("Failed to get {} Node {}", PRODUCT, node_id))]
     EmptyStorageNodeSpec { node_id: String },
 
+    /// Error for when a GET request for a list of storage pools fails.
+    #[snafu(display("Failed to list {} Pools: {}", PRODUCT, source))]
+    ListStoragePools {
+        source: openapi::tower::client::Error<openapi::models::RestJsonError>,
+    },
+
+    /// Error for when there are volumes which are not Online, before upgrade.
+    #[snafu(display(
+        "Cannot upgrade while these {} Volumes are not Online: {:?}",
+        PRODUCT,
+        volumes
+    ))]
+    UnhealthyVolumesPresent { volumes: Vec<String> },
+
+    /// Error for when there are pools which are not Online, before upgrade.
+    #[snafu(display(
+        "Cannot upgrade while these {} Pools are not Online: {:?}",
+        PRODUCT,
+        pools
+    ))]
+    UnhealthyPoolsPresent { pools: Vec<String> },
+
     /// Error for when a GET request for a list of storage volumes fails.
     #[snafu(display("Failed to list {} Volumes: {}", PRODUCT, source))]
     ListStorageVolumes {
@@ -296,6 +441,7 @@ pub(crate) enum Error {
     },
 
     /// Error for when the requested YAML key is invalid.
+    #[allow(dead_code)]
     #[snafu(display("Failed to parse YAML path {}", yaml_path))]
     YamlStructure { yaml_path: String },
 
@@ -333,14 +479,38 @@ pub(crate) enum Error {
         std_err: String,
     },
 
-    /// Error for when a Helm upgrade command execution succeeds, but with an error.
+    /// Error for when a Helm upgrade command exits with a non-zero exit status.
+    #[snafu(display(
+        "`helm upgrade` command failed with {}, last lines of output:\n{}",
+        status,
+        last_lines.join("\n"),
+    ))]
+    HelmUpgradeFailed {
+        status: std::process::ExitStatus,
+        last_lines: Vec<String>,
+    },
+
+    /// Error for when a Helm rollback command execution succeeds, but with an error.
     #[snafu(display(
-        "`helm upgrade` command return an error,\ncommand: {},\nargs: {:?},\nstd_err: {}",
+        "`helm rollback` command return an error,\ncommand: {},\nargs: {:?},\nstd_err: {}",
         command,
         args,
         std_err,
     ))]
-    HelmUpgradeCommand {
+    HelmRollbackCommand {
+        command: String,
+        args: Vec<String>,
+        std_err: String,
+    },
+
+    /// Error for when a Helm template command execution succeeds, but with an error.
+    #[snafu(display(
+        "`helm template` command return an error,\ncommand: {},\nargs: {:?},\nstd_err: {}",
+        command,
+        args,
+        std_err,
+    ))]
+    HelmTemplateCommand {
         command: String,
         args: Vec<String>,
         std_err: String,
@@ -379,6 +549,7 @@ pub(crate) enum Error {
     EventRecorderOptionsAbsent,
 
     /// Error for when pod uid is not present.
+    #[allow(dead_code)]
     #[snafu(display("Pod Uid is None"))]
     PodUidIsNone,
 
@@ -408,13 +579,127 @@ pub(crate) enum Error {
         note: EventNote,
     },
 
+    /// Error for when the --max-unavailable value is neither an integer nor a percentage.
+    #[snafu(display(
+        "Failed to parse '{}' as an integer or a percentage no greater than 100%",
+        value
+    ))]
+    MaxUnavailableParse { value: String },
+
+    /// Error for when the --drain-grace-period value is not a positive duration.
+    #[snafu(display(
+        "Failed to parse '{}' as a positive duration, e.g. '90s' or '5m'",
+        value
+    ))]
+    DrainGracePeriodParse { value: String },
+
+    /// Error for when a restarted io-engine Pod does not become Ready in time.
+    #[snafu(display(
+        "Timed out waiting for the io-engine Pod on Node '{}' to become Ready, after {} \
+        (last observed Pod phase: {})",
+        node,
+        humantime::format_duration(*elapsed),
+        phase
+    ))]
+    NodeReadyTimeout {
+        node: String,
+        elapsed: Duration,
+        phase: String,
+    },
+
+    /// Error for when the volumes on a node are not Online again in time, after the restart of
+    /// the node's io-engine Pod.
+    #[snafu(display(
+        "Timed out waiting for volumes {:?} on Node '{}' to be Online, after {}",
+        volumes,
+        node,
+        humantime::format_duration(*elapsed)
+    ))]
+    NodeVolumesNotOnline {
+        node: String,
+        volumes: Vec<String>,
+        elapsed: Duration,
+    },
+
+    /// Error for when an io-engine Pod does not run the container image tag of the upgraded
+    /// helm release.
+    #[snafu(display(
+        "io-engine Pod '{}' runs container image tag '{}', expected '{}'",
+        pod,
+        actual,
+        expected
+    ))]
+    PodImageTagMismatch {
+        pod: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// Error for when an io-engine Pod does not have an io-engine container with an image.
+    #[snafu(display("Failed to find the io-engine container image of Pod '{}'", pod))]
+    IoEngineContainerAbsent { pod: String },
+
     /// Error for when there are too many io-engine Pods in one single node;
     #[snafu(display("Too many io-engine Pods in Node '{}'", node_name))]
     TooManyIoEnginePods { node_name: String },
 
     /// Error for when the thin-provisioning options are absent, but still tried to fetch it.
-    #[snafu(display("The agents.core.capacity yaml object is absent amongst the helm values"))]
-    ThinProvisioningOptionsAbsent,
+    #[snafu(display(
+        "The agents.core.capacity yaml object is absent amongst the helm values from {}, of \
+        helm chart version {}",
+        values_source,
+        chart_version
+    ))]
+    ThinProvisioningOptionsAbsent {
+        chart_version: Version,
+        values_source: String,
+    },
+
+    /// Error for when one of the thin-provisioning options is absent, while the
+    /// agents.core.capacity.thin yaml object is present.
+    #[snafu(display(
+        "The agents.core.capacity.thin.{} option is absent amongst the helm values",
+        field
+    ))]
+    ThinSubfieldAbsent { field: &'static str },
+
+    /// Error for when a string is not a valid percentage.
+    #[snafu(display("Failed to parse '{}' as a percentage", value))]
+    PercentageParse { value: String },
+
+    /// Error for when a thin-provisioning commitment value is not a valid percentage.
+    #[snafu(display(
+        "Failed to parse agents.core.capacity.thin.{} value '{}' as a percentage",
+        field,
+        value
+    ))]
+    ThinCommitmentParse { field: &'static str, value: String },
+
+    /// Error for when a per-pool or per-class thin-provisioning commitment override is not a valid
+    /// percentage.
+    #[snafu(display(
+        "Failed to parse agents.core.capacity.overrides.{}.{} value '{}' as a percentage",
+        name,
+        field,
+        value
+    ))]
+    ThinCommitmentOverrideParse {
+        name: String,
+        field: &'static str,
+        value: String,
+    },
+
+    /// Error for when the thin-provisioning initial volume commitment is larger than the volume
+    /// commitment.
+    #[snafu(display(
+        "Thin-provisioning volumeCommitmentInitial ({}) must not be larger than volumeCommitment ({})",
+        volume_commitment_initial,
+        volume_commitment
+    ))]
+    ThinVolumeCommitmentInverted {
+        volume_commitment_initial: Percentage,
+        volume_commitment: Percentage,
+    },
 
     /// Error when trying to send Events through the tokio::sync::channel::Sender<Event>
     /// synchronisation tool.
@@ -430,6 +715,92 @@ pub(crate) enum Error {
     ))]
     HelmChartVersionLabelHasNoValue { pod_name: String, namespace: String },
 
+    /// Error for when a Kubernetes API request for GET-ing a list of Secrets filtered by label(s)
+    /// fails.
+    #[snafu(display(
+        "Failed to list Secrets with label {} in namespace {}: {}",
+        label,
+        namespace,
+        source
+    ))]
+    ListSecretsWithLabel {
+        source: kube::Error,
+        label: String,
+        namespace: String,
+    },
+
+    /// Error for when no Kubernetes Secret is found for the current revision of a helm release.
+    #[snafu(display(
+        "Failed to find the Secret for a deployed or failed revision of helm release {} in \
+         namespace {}",
+        release_name,
+        namespace
+    ))]
+    HelmReleaseSecretAbsent {
+        release_name: String,
+        namespace: String,
+    },
+
+    /// Error for when there is no revision of a helm release to roll back to.
+    #[snafu(display(
+        "Failed to find a previously deployed revision of helm release {} in namespace {}",
+        release_name,
+        namespace
+    ))]
+    NoPreviousHelmRelease {
+        release_name: String,
+        namespace: String,
+    },
+
+    /// Error for when a rollback would have to reverse a helm values migration which cannot be
+    /// reversed.
+    #[snafu(display("Cannot roll back across irreversible migration: {}", migration))]
+    IrreversibleMigration { migration: String },
+
+    /// Error for when a helm release Secret does not contain the helm release payload.
+    #[snafu(display(
+        "Secret {} in namespace {} does not contain a helm release",
+        secret_name,
+        namespace
+    ))]
+    HelmReleaseSecretDataAbsent {
+        secret_name: String,
+        namespace: String,
+    },
+
+    /// Error for when the base64 encoding of a helm release cannot be decoded.
+    #[snafu(display(
+        "Failed to base64 decode the helm release in Secret {}: {}",
+        secret_name,
+        source
+    ))]
+    Base64DecodeHelmRelease {
+        source: base64::DecodeError,
+        secret_name: String,
+    },
+
+    /// Error for when the gzip compression of a helm release cannot be decompressed.
+    #[snafu(display(
+        "Failed to decompress the helm release in Secret {}: {}",
+        secret_name,
+        source
+    ))]
+    GzipDecodeHelmRelease {
+        source: std::io::Error,
+        secret_name: String,
+    },
+
+    /// Error for when a helm release cannot be parsed as JSON.
+    #[snafu(display(
+        "Failed to parse the helm release in Secret {}: {}",
+        secret_name,
+        source
+    ))]
+    JsonParseHelmRelease {
+        source: serde_json::Error,
+        secret_name: String,
+    },
+
     /// Error for when a pod does not have Namespace set on it.
     #[snafu(display(
         "Found None when trying to get Namespace for Pod {}, context: {}",
@@ -503,11 +874,36 @@ pub(crate) enum Error {
 
     /// Error for when the helm upgrade's target version is lower the source version.
     #[snafu(display(
-        "Failed to upgrade from {} to {}: upgrade to an earlier-released version is forbidden",
+        "Failed to upgrade from {} to {}: upgrade to an earlier-released version is not supported",
         from_version,
         to_version
     ))]
-    RollbackForbidden {
+    DowngradeNotSupported {
+        from_version: String,
+        to_version: String,
+    },
+
+    /// Error for when the helm upgrade's target version skips over a major version.
+    #[snafu(display(
+        "Failed to upgrade from {} to {}: upgrades across more than one major version are not supported",
+        from_version,
+        to_version
+    ))]
+    UnsupportedUpgradePath {
+        from_version: String,
+        to_version: String,
+    },
+
+    /// Error for when the helm upgrade's target version is a pre-release, and the source version
+    /// is a release.
+    #[snafu(display(
+        "Failed to upgrade from {} to {}: upgrades from a release to a pre-release are blocked, \
+        as pre-releases are not supported for production use, use --allow-prerelease to upgrade \
+        to a pre-release",
+        from_version,
+        to_version
+    ))]
+    PrereleaseRegression {
         from_version: String,
         to_version: String,
     },
@@ -599,6 +995,21 @@ pub(crate) enum Error {
     #[snafu(display("Failed to create CustomResourceDefinition '{}': {}", name, source))]
     CreateCrd { source: kube::Error, name: String },
 
+    /// Error for when a yaml document fails to (de)serialize, and there is no more specific error
+    /// for it. This lets a serde_yaml::Error be propagated using '?'.
+    #[snafu(context(false), display("Failed to (de)serialize yaml: {}", source))]
+    Yaml { source: serde_yaml::Error },
+
+    /// Error for when an I/O operation fails, and there is no more specific error for it. This
+    /// lets a std::io::Error be propagated using '?'.
+    #[snafu(context(false), display("I/O operation failed: {}", source))]
+    Io { source: std::io::Error },
+
+    /// Error for when a Kubernetes API request fails, and there is no more specific error for it.
+    /// This lets a kube::Error be propagated using '?'.
+    #[snafu(context(false), display("Kubernetes API request failed: {}", source))]
+    Kube { source: kube::Error },
+
     /// Error for when unwraping of Result<DirEntry, std::io::Error> fails.
     #[snafu(display(
         "Failed to collect DirEntry list from read_dir() into a Vec<_> for directory {}: {}",
@@ -609,7 +1020,1189 @@ pub(crate) enum Error {
         source: std::io::Error,
         path: PathBuf,
     },
+
+    /// Error for when the values.schema.json of a helm chart is not valid JSON.
+    #[snafu(display("Failed to parse helm values schema file {}: {}", filepath.display(), source))]
+    JsonParseValuesSchema {
+        source: serde_json::Error,
+        filepath: PathBuf,
+    },
+
+    /// Error for when the values.schema.json of a helm chart is not a valid JSON schema.
+    #[snafu(display("Failed to compile helm values JSON schema: {}", error))]
+    ValuesSchemaCompile { error: String },
+
+    /// Error for when helm values cannot be represented as JSON, e.g. when they have non-string
+    /// mapping keys.
+    #[snafu(display("Failed to convert helm values to JSON: {}", source))]
+    ValuesToJson { source: serde_json::Error },
+
+    /// Error for when helm values do not satisfy the JSON schema of the helm chart.
+    #[snafu(display(
+        "Helm values violate the helm chart's values schema: {}",
+        errors.join("; ")
+    ))]
+    ValuesSchemaViolations { errors: Vec<String> },
+
+    /// Error for when listing Kubernetes DaemonSets with a label fails.
+    #[snafu(display(
+        "Failed to list DaemonSets with label '{}' in namespace '{}': {}",
+        label,
+        namespace,
+        source
+    ))]
+    ListDaemonSetsWithLabel {
+        source: kube::Error,
+        label: String,
+        namespace: String,
+    },
+
+    /// Error for when the io-engine DaemonSet cannot be found.
+    #[snafu(display("Failed to find the io-engine DaemonSet in namespace '{}'", namespace))]
+    IoEngineDaemonSetAbsent { namespace: String },
+
+    /// Error for when the io-engine DaemonSet's Pod template does not have an io-engine container
+    /// with an image.
+    #[snafu(display("Failed to find the io-engine container image of DaemonSet '{}'", name))]
+    IoEngineDaemonSetContainerAbsent { name: String },
+
+    /// Error for when the helm repository URL is not a valid URI.
+    #[snafu(display("Failed to parse helm repository URL '{}': {}", url, source))]
+    HelmRepoUriParse {
+        source: http::uri::InvalidUri,
+        url: String,
+    },
+
+    /// Error for when the HTTPS client for the helm repository cannot be set up.
+    #[snafu(display("Failed to set up an HTTPS client for the helm repository: {}", source))]
+    HelmRepoHttpsConnector { source: openssl::error::ErrorStack },
+
+    /// Error for when the request for the helm repository index.yaml fails.
+    #[snafu(display("Failed to fetch helm repository index '{}': {}", url, source))]
+    HelmRepoFetch { source: hyper::Error, url: String },
+
+    /// Error for when the helm repository responds to the index.yaml request with an error.
+    #[snafu(display(
+        "Failed to fetch helm repository index '{}': HTTP status {}",
+        url,
+        status
+    ))]
+    HelmRepoHttpStatus { url: String, status: u16 },
+
+    /// Error for when the helm repository index.yaml cannot be deserialized.
+    #[snafu(display(
+        "Failed to parse the index.yaml of helm repository '{}': {}",
+        url,
+        source
+    ))]
+    HelmRepoIndexParse {
+        source: serde_yaml::Error,
+        url: String,
+    },
+
+    /// Error for when the helm repository does not publish the helm chart.
+    #[snafu(display(
+        "Helm chart '{}' is not published in helm repository '{}'",
+        chart_name,
+        url
+    ))]
+    HelmRepoChartAbsent { chart_name: String, url: String },
+
+    /// Error for when the list of published helm chart versions cannot be serialized to JSON.
+    #[snafu(display("Failed to serialize the helm chart versions to JSON: {}", source))]
+    SerializeChartVersions { source: serde_json::Error },
+
+    /// Error for when the Kubernetes cluster's version cannot be fetched.
+    #[snafu(display("Failed to get the Kubernetes cluster's version: {}", source))]
+    GetKubernetesVersion { source: kube::Error },
+
+    /// Error for when the kubeVersion of a helm chart's Chart.yaml is not a valid version range.
+    #[snafu(display("Failed to parse helm chart kubeVersion '{}': {}", constraint, source))]
+    KubeVersionConstraintParse {
+        source: semver::Error,
+        constraint: String,
+    },
+
+    /// Error for when the Kubernetes cluster's version is not supported by the helm chart.
+    #[snafu(display(
+        "Kubernetes version {} is not supported by the helm chart, it requires '{}'",
+        actual,
+        required
+    ))]
+    KubeVersionUnsupported { required: String, actual: Version },
+
+    /// Error for when a data-plane only upgrade is attempted before the control-plane is upgraded.
+    #[snafu(display(
+        "Cannot upgrade only the data-plane to version {}, the control-plane is at version {}, \
+        upgrade the control-plane first",
+        target,
+        installed
+    ))]
+    ControlPlaneNotUpgraded { installed: String, target: String },
+
+    /// Error for when a control-plane only upgrade would leave the data-plane at a version which
+    /// the upgraded control-plane does not support.
+    #[snafu(display(
+        "Cannot upgrade only the control-plane to version {}, it does not support the data-plane \
+        at version {}",
+        control_plane,
+        data_plane
+    ))]
+    DataPlaneVersionSkewUnsupported {
+        control_plane: Version,
+        data_plane: Version,
+    },
+
+    /// Error for when a --set value is not a 'key=value' pair with a non-empty key path.
+    #[snafu(display(
+        "Failed to parse '{}' as a 'key=value' pair, e.g. 'etcd.persistence.size=4Gi'",
+        input
+    ))]
+    SetValueParse { input: String },
+
+    /// Error for when a helm chart reference is not of the form oci://<registry>/<chart>:<tag>.
+    #[snafu(display(
+        "Failed to parse '{}' as an OCI helm chart reference, e.g. \
+        'oci://registry.example.com/charts/mayastor:2.5.0'",
+        reference
+    ))]
+    OciReferenceParse { reference: String },
+
+    /// Error for when a request to an OCI registry cannot be built.
+    #[snafu(display("Failed to build OCI registry request for '{}': {}", url, source))]
+    OciRequestBuild { source: http::Error, url: String },
+
+    /// Error for when a request to an OCI registry fails.
+    #[snafu(display("Failed to send OCI registry request to '{}': {}", url, source))]
+    OciRegistryRequest { source: hyper::Error, url: String },
+
+    /// Error for when an OCI registry responds to a request with an error.
+    #[snafu(display("OCI registry request to '{}' failed: HTTP status {}", url, status))]
+    OciRegistryHttpStatus { url: String, status: u16 },
+
+    /// Error for when the authorization challenge of an OCI registry cannot be answered.
+    #[snafu(display(
+        "Failed to parse the authorization challenge '{}' of OCI registry request '{}'",
+        challenge,
+        url
+    ))]
+    OciRegistryAuthChallenge { url: String, challenge: String },
+
+    /// Error for when an OCI registry rejects the request for lack of valid credentials.
+    #[snafu(display(
+        "OCI registry request to '{}' is unauthorized, check the credentials in the image pull \
+        secrets",
+        url
+    ))]
+    OciRegistryUnauthorized { url: String },
+
+    /// Error for when the token from an OCI registry's token service cannot be deserialized.
+    #[snafu(display("Failed to parse the OCI registry token from '{}': {}", url, source))]
+    OciRegistryTokenParse {
+        source: serde_json::Error,
+        url: String,
+    },
+
+    /// Error for when the tag of an OCI helm chart reference does not exist.
+    #[snafu(display("Helm chart '{}' does not exist in the OCI registry", reference))]
+    OciTagAbsent { reference: String },
+
+    /// Error for when the OCI image manifest of a helm chart cannot be deserialized.
+    #[snafu(display(
+        "Failed to parse the OCI image manifest of '{}': {}",
+        reference,
+        source
+    ))]
+    OciManifestParse {
+        source: serde_json::Error,
+        reference: String,
+    },
+
+    /// Error for when the OCI image manifest has no helm chart layer.
+    #[snafu(display("OCI image '{}' has no helm chart layer", reference))]
+    OciChartLayerAbsent { reference: String },
+
+    /// Error for when the downloaded helm chart layer does not have the digest in the manifest.
+    #[snafu(display("Helm chart OCI layer has digest '{}', expected '{}'", actual, digest))]
+    OciLayerDigestMismatch { digest: String, actual: String },
+
+    /// Error for when the helm chart .tgz pulled from an OCI registry cannot be extracted.
+    #[snafu(display("Failed to extract helm chart '{}': {}", reference, source))]
+    OciChartExtract {
+        source: std::io::Error,
+        reference: String,
+    },
+
+    /// Error for when a container image pull secret cannot be fetched.
+    #[snafu(display("Failed to get image pull secret '{}': {}", name, source))]
+    GetPullSecret { source: kube::Error, name: String },
+
+    /// Error for when the '.dockerconfigjson' of an image pull secret cannot be deserialized.
+    #[snafu(display(
+        "Failed to parse the .dockerconfigjson of image pull secret '{}': {}",
+        name,
+        source
+    ))]
+    DockerConfigJsonParse {
+        source: serde_json::Error,
+        name: String,
+    },
+
+    /// Error for when the pre-upgrade webhook URL is not a valid URI.
+    #[snafu(display("Failed to parse pre-upgrade webhook URL '{}': {}", url, source))]
+    PreUpgradeWebhookUriParse {
+        source: http::uri::InvalidUri,
+        url: String,
+    },
+
+    /// Error for when the HTTPS client for the pre-upgrade webhook cannot be set up.
+    #[snafu(display(
+        "Failed to set up an HTTPS client for the pre-upgrade webhook: {}",
+        source
+    ))]
+    PreUpgradeWebhookHttpsConnector { source: openssl::error::ErrorStack },
+
+    /// Error for when the request to the pre-upgrade webhook cannot be built.
+    #[snafu(display(
+        "Failed to build the request for pre-upgrade webhook '{}': {}",
+        url,
+        source
+    ))]
+    PreUpgradeWebhookRequestBuild { source: http::Error, url: String },
+
+    /// Error for when the request to the pre-upgrade webhook fails.
+    #[snafu(display("Failed to call pre-upgrade webhook '{}': {}", url, source))]
+    PreUpgradeWebhookRequest { source: hyper::Error, url: String },
+
+    /// Error for when the pre-upgrade webhook does not respond in time.
+    #[snafu(display(
+        "Pre-upgrade webhook '{}' did not respond within {}",
+        url,
+        humantime::format_duration(*timeout)
+    ))]
+    PreUpgradeWebhookTimeout { url: String, timeout: Duration },
+
+    /// Error for when the pre-upgrade webhook does not approve the upgrade.
+    #[snafu(display(
+        "Pre-upgrade webhook rejected the upgrade with HTTP status {}: {}",
+        status,
+        body
+    ))]
+    PreUpgradeWebhookRejected { status: u16, body: String },
+
+    /// Error for when a Kubernetes resource quantity cannot be parsed.
+    #[snafu(display("Failed to parse '{}' as a Kubernetes resource quantity", quantity))]
+    QuantityParse { quantity: String },
+
+    /// Error for when a Kubernetes Node cannot be fetched.
+    #[snafu(display("Failed to get Kubernetes Node {}: {}", node_name, source))]
+    GetNode {
+        source: kube::Error,
+        node_name: String,
+    },
+
+    /// Error for when a node has fewer hugepages than the upgraded io-engine requests.
+    #[snafu(display(
+        "Node {} has {} of allocatable 2MiB hugepages, the upgraded io-engine requires {}",
+        node,
+        available,
+        required
+    ))]
+    InsufficientHugepages {
+        node: String,
+        required: String,
+        available: String,
+    },
+
+    /// Error for when a SelfSubjectAccessReview cannot be created.
+    #[snafu(display(
+        "Failed to review the upgrade-job's RBAC permission to {}: {}",
+        permission,
+        source
+    ))]
+    SelfSubjectAccessReviewCreate {
+        source: kube::Error,
+        permission: String,
+    },
+
+    /// Error for when the upgrade-job lacks RBAC permissions which the upgrade needs.
+    #[snafu(display(
+        "The upgrade-job lacks the RBAC permissions to: {}",
+        denied.join(", ")
+    ))]
+    InsufficientRbac { denied: Vec<String> },
+
+    /// Error for when the canary node or its pools are not Online, after its io-engine Pod is
+    /// restarted.
+    #[snafu(display(
+        "Canary node '{}' failed verification, after {}, not Online: {}",
+        node,
+        humantime::format_duration(*elapsed),
+        unhealthy
+    ))]
+    CanaryVerificationTimeout {
+        node: String,
+        elapsed: Duration,
+        unhealthy: String,
+    },
+
+    /// Error for when a storage pool is already committed beyond the upgraded thin-provisioning
+    /// pool commitment.
+    #[snafu(display(
+        "Pool '{}' is already committed to {} of its capacity, the upgraded poolCommitment {} is lower",
+        pool,
+        current,
+        target
+    ))]
+    ThinCommitmentBelowCurrentUsage {
+        pool: String,
+        current: Percentage,
+        target: Percentage,
+    },
+
+    /// Error for when the upgrade takes longer than the --overall-timeout.
+    #[snafu(display(
+        "The upgrade exceeded its overall timeout, after {}",
+        humantime::format_duration(*elapsed)
+    ))]
+    OverallUpgradeTimeout { elapsed: Duration },
+
+    /// Error for when a CustomResourceDefinition cannot be read from the cluster.
+    #[snafu(display("Failed to GET CustomResourceDefinition '{}': {}", name, source))]
+    GetCrd { source: kube::Error, name: String },
+
+    /// Error for when the target helm chart bundles an older version of a CustomResourceDefinition
+    /// than the one installed in the cluster.
+    #[snafu(display(
+        "CustomResourceDefinition '{}' would regress from version {} to version {}",
+        crd,
+        installed,
+        target
+    ))]
+    CrdVersionRegression {
+        crd: String,
+        installed: String,
+        target: String,
+    },
+
+    /// Error for when the audit record could not be serialized to JSON.
+    #[snafu(display("Failed to serialize the upgrade audit record to JSON: {}", source))]
+    SerializeAuditRecord { source: serde_json::Error },
+
+    /// Error for when the audit ConfigMap cannot be read.
+    #[snafu(display("Failed to GET audit ConfigMap '{}': {}", name, source))]
+    GetAuditConfigMap { source: kube::Error, name: String },
+
+    /// Error for when the audit record cannot be stored in the audit ConfigMap.
+    #[snafu(display(
+        "Failed to store the upgrade audit record in ConfigMap '{}': {}",
+        name,
+        source
+    ))]
+    StoreAuditRecord { source: kube::Error, name: String },
+
+    /// Error for when the installed version is older than the oldest version which may be upgraded
+    /// from.
+    #[snafu(display(
+        "Installed version {} cannot be upgraded from, upgrade to version {} or later first",
+        installed,
+        minimum
+    ))]
+    InstalledVersionTooOldToUpgrade {
+        installed: Version,
+        minimum: Version,
+    },
+
+    /// Error for when more than one helm chart file fails to load.
+    #[snafu(display(
+        "Failed to load {} helm chart files: {}",
+        failures.len(),
+        failures
+            .iter()
+            .map(|(path, error)| format!("{}: {error}", path.display()))
+            .collect::<Vec<String>>()
+            .join("; ")
+    ))]
+    MultiLoadErrors { failures: Vec<(PathBuf, Error)> },
+
+    /// Error for when the upgrade moves the container images to a registry or repository which is
+    /// not allowlisted.
+    #[snafu(display(
+        "The upgrade moves the container images to '{}', which does not match any of the \
+        allowlisted image prefixes {:?}, the images may not be available to the cluster",
+        image,
+        allowlist
+    ))]
+    ImageNotInAllowlist {
+        image: String,
+        allowlist: Vec<String>,
+    },
+
+    /// Error for when a values file fails validation against a helm chart.
+    #[snafu(display(
+        "The values file {} has {} problems: {}",
+        filepath.display(),
+        problems.len(),
+        problems.join("; ")
+    ))]
+    InvalidValuesFile {
+        filepath: PathBuf,
+        problems: Vec<String>,
+    },
+
+    /// Error for when a --skip-node-label value is not a 'key=value' pair.
+    #[snafu(display("Failed to parse '{}' as a 'key=value' Node label", input))]
+    NodeLabelParse { input: String },
+
+    /// Error for when listing Kubernetes Nodes with a label fails.
+    #[snafu(display("Failed to list Kubernetes Nodes with label {}: {}", label, source))]
+    ListNodesWithLabel { source: kube::Error, label: String },
+
+    /// Error for when keys of the installed helm values are absent in the merged helm values.
+    #[snafu(display(
+        "The merged helm values for the upgrade are missing keys of the installed helm values: {}",
+        keys.join(", ")
+    ))]
+    ValuesKeysDropped { keys: Vec<String> },
+
+    /// Error for when the storage REST API is not reachable, ahead of the upgrade.
+    #[snafu(display(
+        "The {} control-plane REST API at '{}' is unreachable: {}",
+        PRODUCT,
+        endpoint,
+        source
+    ))]
+    ControlPlaneUnreachable {
+        source: openapi::tower::client::Error<openapi::models::RestJsonError>,
+        endpoint: String,
+    },
+
+    /// Error for when there is no revision of a helm release, in any state, in the namespace.
+    #[snafu(display(
+        "Helm release '{}' not found in namespace '{}', {}",
+        name,
+        namespace,
+        suggestion
+    ))]
+    ReleaseNotFound {
+        name: String,
+        namespace: String,
+        suggestion: String,
+    },
+
+    /// Error for when an upgrade is listed as unsupported in the compatibility matrix, or is
+    /// rejected by semver rules.
+    #[snafu(display(
+        "Upgrading from {} to {} is not a supported upgrade, use \
+        --skip-upgrade-path-validation to upgrade anyway",
+        from,
+        to
+    ))]
+    UnsupportedUpgradePair { from: String, to: String },
+
+    /// Error for when the CA certificate file for the storage REST API has no valid certificate.
+    #[snafu(display(
+        "Failed to load the control-plane CA certificate from {}: {}",
+        filepath.display(),
+        reason
+    ))]
+    ControlPlaneCaInvalid { filepath: PathBuf, reason: String },
+
+    /// Error for when the https client for the storage REST API cannot be set up.
+    #[snafu(display(
+        "Failed to set up an HTTPS client for the control-plane REST API: {}",
+        source
+    ))]
+    ControlPlaneTlsConnector { source: openssl::error::ErrorStack },
+
+    /// Error for when the storage REST API endpoint is not a valid URI for the https client.
+    #[snafu(display("Failed to parse {} as a URI: {}", rest_endpoint, source))]
+    RestUriParse {
+        source: hyper::http::uri::InvalidUri,
+        rest_endpoint: String,
+    },
+
+    /// Error for when the TLS handshake with the storage REST API fails, e.g. because its server
+    /// certificate is not signed by the configured CA.
+    #[snafu(display(
+        "TLS handshake with the {} control-plane REST API at '{}' failed, check \
+        --control-plane-ca: {}",
+        PRODUCT,
+        endpoint,
+        source
+    ))]
+    ControlPlaneTls {
+        source: openapi::tower::client::Error<openapi::models::RestJsonError>,
+        endpoint: String,
+    },
+
+    /// Error for when the yaml documents of a helm values file set values of different types at
+    /// the same key paths.
+    #[snafu(display(
+        "The yaml documents in {} set values of different types at: {}",
+        filepath.display(),
+        paths.join(", ")
+    ))]
+    ValuesDocumentsConflict {
+        filepath: PathBuf,
+        paths: Vec<String>,
+    },
+
+    /// Error for when the container image tag of the upgrade is not the target helm chart's
+    /// appVersion.
+    #[snafu(display(
+        "The container image tag '{}' does not match the target helm chart's appVersion {}",
+        tag,
+        app_version
+    ))]
+    ImageTagAppVersionMismatch { tag: String, app_version: Version },
+
+    /// Error for when a pre-upgrade or post-upgrade hook Job of the helm chart fails the helm
+    /// upgrade.
+    #[snafu(display(
+        "The {} hook Job '{}' of the helm chart failed the helm upgrade: {}",
+        hook,
+        job,
+        reason
+    ))]
+    UpgradeHookFailed {
+        hook: String,
+        job: String,
+        reason: String,
+    },
+
+    /// Error for when a node has less allocatable ephemeral-storage than the free disk space which
+    /// is required for the upgrade.
+    #[snafu(display(
+        "Node {} has {} bytes of allocatable ephemeral-storage, the upgrade requires {} bytes of \
+        free disk space",
+        node,
+        allocatable,
+        required
+    ))]
+    InsufficientNodeDiskSpace {
+        node: String,
+        required: u64,
+        allocatable: u64,
+    },
+
+    /// Error for when a node which runs an io-engine Pod reports DiskPressure.
+    #[snafu(display(
+        "Node {} has DiskPressure, it may not be able to pull the upgraded io-engine image",
+        node
+    ))]
+    NodeDiskPressure { node: String },
+
+    /// Error for when a Kubernetes API request for GET-ing a StatefulSet fails.
+    #[snafu(display(
+        "Failed to GET Kubernetes StatefulSet {} in namespace {}: {}",
+        name,
+        namespace,
+        source
+    ))]
+    GetStatefulSet {
+        source: kube::Error,
+        name: String,
+        namespace: String,
+    },
+
+    /// Error for when the drift report could not be serialized to JSON.
+    #[snafu(display(
+        "Failed to serialize the configuration drift report to JSON: {}",
+        source
+    ))]
+    SerializeConfigDrift { source: serde_json::Error },
+
+    /// Error for when the cluster does not match the installed helm release's values.
+    #[snafu(display(
+        "The cluster has drifted from the values of helm release '{}' in {} ways: {}",
+        release_name,
+        drifts.len(),
+        drifts.join("; ")
+    ))]
+    ConfigDriftDetected {
+        release_name: String,
+        drifts: Vec<String>,
+    },
+
+    /// Error for when the only replica of some volumes is on a node whose io-engine Pod is to be
+    /// restarted.
+    #[snafu(display(
+        "The only replica of volumes {:?} is on node {}, they will be unavailable while its \
+        io-engine Pod restarts",
+        volumes,
+        node
+    ))]
+    SingleReplicaVolumesOnNode { node: String, volumes: Vec<String> },
+
+    /// Error for when the helm values schema could not be serialized to JSON.
+    #[snafu(display("Failed to serialize the helm values schema to JSON: {}", source))]
+    SerializeValuesSchema { source: serde_json::Error },
+
+    /// Error for when the upgrade is not confirmed with '--yes', and there is no terminal to
+    /// prompt for the confirmation on.
+    #[snafu(display(
+        "The upgrade has to be confirmed with '--yes' when it does not run in an interactive \
+        terminal"
+    ))]
+    UpgradeConfirmationRequired,
+
+    /// Error for when the upgrade is declined at the confirmation prompt.
+    #[snafu(display("The upgrade was not confirmed, aborting"))]
+    UpgradeNotConfirmed,
+
+    /// Error for when the confirmation prompt cannot be written or answered.
+    #[snafu(display("Failed to prompt for the upgrade confirmation: {}", source))]
+    UpgradeConfirmationPrompt { source: std::io::Error },
+
+    /// Error for when the update strategy of the io-engine DaemonSet cannot be set.
+    #[snafu(display(
+        "Failed to set the update strategy of the DaemonSet '{}' in namespace '{}' to {}: {}",
+        name,
+        namespace,
+        strategy,
+        source
+    ))]
+    PatchIoEngineDaemonSet {
+        source: kube::Error,
+        name: String,
+        namespace: String,
+        strategy: String,
+    },
+
+    /// Error for when a subcommand which uses the cluster is missing the cluster's arguments.
+    #[snafu(display(
+        "The arguments {} are required for this operation on the cluster",
+        arguments
+    ))]
+    ClusterArgumentsMissing { arguments: String },
+
+    /// Error for when --dry-run is set for the rollback, which has no dry-run.
+    #[snafu(display(
+        "--dry-run (UPGRADE_DRY_RUN) cannot be used with the rollback subcommand, the rollback \
+        has no dry-run"
+    ))]
+    DryRunRollback,
+
+    /// Error for when the version of a helm chart's dependency is not a valid version range.
+    #[snafu(display(
+        "Failed to parse the version '{}' of the helm chart dependency '{}': {}",
+        constraint,
+        name,
+        source
+    ))]
+    DependencyVersionConstraintParse {
+        source: semver::Error,
+        name: String,
+        constraint: String,
+    },
+
+    /// Error for when the io-engine Pods run different container image tags, and the upgrade is
+    /// set to fail on that.
+    #[snafu(display("{}, and --fail-on-mixed-tags is set", warning))]
+    MixedIoEngineImageTags { warning: String },
+
+    /// Error for when the upgrade metrics cannot be created or registered.
+    #[snafu(display("Failed to register the upgrade metrics: {}", source))]
+    MetricsRegistration { source: prometheus::Error },
+
+    /// Error for when the upgrade metrics server cannot listen on its port.
+    #[snafu(display("Failed to serve the upgrade metrics on port {}: {}", port, source))]
+    MetricsServerBind { source: hyper::Error, port: u16 },
+}
+
+/// This implements Error::error_code() from the list of the variants and their codes. The list
+/// is kept for the tests too, to check that no two variants have the same code.
+macro_rules! error_codes {
+    ($($variant:ident => $code:literal,)*) => {
+        impl Error {
+            /// This is a stable code for the error, for automation to match on. The codes of
+            /// existing errors must not change, new errors get new codes.
+            pub(crate) fn error_code(&self) -> &'static str {
+                match self {
+                    $(Self::$variant { .. } => $code,)*
+                }
+            }
+        }
+
+        /// These are the variants of Error, with their codes.
+        #[cfg(test)]
+        const ERROR_CODES: &[(&str, &str)] = &[$((stringify!($variant), $code),)*];
+    };
+}
+
+error_codes! {
+    RestUrlParse => "E-VAL-001",
+    K8sClientGeneration => "E-K8S-001",
+    GetNamespace => "E-K8S-002",
+    RestClientConfiguration => "E-STOR-001",
+    HelmCommand => "E-HELM-001",
+    RegexCompile => "E-VAL-002",
+    HelmVersion => "E-HELM-002",
+    HelmRelease => "E-HELM-003",
+    NoInputHelmChartDir => "E-VAL-003",
+    JobPodOwnerNotFound => "E-K8S-003",
+    JobPodHasTooManyOwners => "E-K8S-004",
+    JobPodOwnerIsNotJob => "E-K8S-005",
+    YamlParseFromSlice => "E-VAL-004",
+    ChartFileRead => "E-IO-001",
+    ChartYamlParse => "E-VAL-005",
+    ValuesDeserialize => "E-VAL-006",
+    GetUpgradeStateConfigMap => "E-K8S-006",
+    PatchUpgradeStateConfigMap => "E-K8S-007",
+    DeleteUpgradeStateConfigMap => "E-K8S-008",
+    JsonParseUpgradeState => "E-VAL-007",
+    SerializeUpgradeState => "E-VAL-008",
+    UpgradeTargetChanged => "E-VAL-009",
+    SerializeUpgradePlan => "E-VAL-010",
+    SerializeValuesYaml => "E-VAL-011",
+    YamlParseFromFile => "E-VAL-012",
+    YamlParseBufferForUnsupportedVersion => "E-VAL-013",
+    YamlParseBufferForKnownVersions => "E-VAL-014",
+    ValidateDirPath => "E-IO-002",
+    ValidateFilePath => "E-IO-003",
+    NotADirectory => "E-VAL-016",
+    NotAFile => "E-VAL-017",
+    ReadingFile => "E-IO-004",
+    FindingHelmChart => "E-VAL-018",
+    NotAnApplicationHelmChart => "E-VAL-019",
+    DeprecatedHelmChart => "E-VAL-020",
+    HelmChartDependencyAbsent => "E-VAL-021",
+    ChartNameMismatch => "E-VAL-022",
+    GetPod => "E-K8S-009",
+    ListPodsWithLabel => "E-K8S-010",
+    ListPodsWithLabelAndField => "E-K8S-011",
+    EmptyPodSpec => "E-K8S-012",
+    EmptyPodNodeName => "E-K8S-013",
+    StorageNodeUncordon => "E-STOR-002",
+    PodDelete => "E-K8S-015",
+    ListStorageNodes => "E-STOR-003",
+    GetStorageNode => "E-STOR-004",
+    EmptyStorageNodeSpec => "E-STOR-005",
+    ListStoragePools => "E-STOR-006",
+    UnhealthyVolumesPresent => "E-VAL-023",
+    UnhealthyPoolsPresent => "E-VAL-024",
+    ListStorageVolumes => "E-STOR-007",
+    DrainStorageNode => "E-STOR-008",
+    U8VectorToString => "E-IO-005",
+    EventPublish => "E-K8S-016",
+    HelmListCommand => "E-HELM-004",
+    HelmVersionCommand => "E-HELM-005",
+    HelmUpgradeFailed => "E-HELM-006",
+    HelmRollbackCommand => "E-HELM-007",
+    HelmGetValuesCommand => "E-HELM-008",
+    NotAKnownHelmChart => "E-VAL-026",
+    KubeClientSetBuilderNs => "E-VAL-027",
+    EventRecorderOptionsAbsent => "E-VAL-028",
+    HelmClientNs => "E-VAL-029",
+    HelmUpgradeOptionsAbsent => "E-VAL-030",
+    SemverParse => "E-VAL-031",
+    InvalidUpgradePath => "E-VAL-032",
+    SerializeEventNote => "E-VAL-033",
+    MaxUnavailableParse => "E-VAL-034",
+    NodeReadyTimeout => "E-K8S-018",
+    PodImageTagMismatch => "E-VAL-035",
+    IoEngineContainerAbsent => "E-K8S-019",
+    TooManyIoEnginePods => "E-K8S-020",
+    ThinProvisioningOptionsAbsent => "E-VAL-036",
+    ThinSubfieldAbsent => "E-VAL-037",
+    PercentageParse => "E-VAL-038",
+    ThinCommitmentParse => "E-VAL-039",
+    ThinVolumeCommitmentInverted => "E-VAL-040",
+    EventChannelSend => "E-K8S-021",
+    HelmChartVersionLabelHasNoValue => "E-K8S-022",
+    ListSecretsWithLabel => "E-K8S-023",
+    HelmReleaseSecretAbsent => "E-HELM-009",
+    NoPreviousHelmRelease => "E-HELM-010",
+    IrreversibleMigration => "E-VAL-041",
+    HelmReleaseSecretDataAbsent => "E-HELM-011",
+    Base64DecodeHelmRelease => "E-HELM-012",
+    GzipDecodeHelmRelease => "E-HELM-013",
+    JsonParseHelmRelease => "E-HELM-014",
+    NoNamespaceInPod => "E-K8S-024",
+    UmbrellaChartNotUpgraded => "E-VAL-042",
+    CoreChartUpgradeNoneChartDir => "E-VAL-043",
+    NoRestDeployment => "E-K8S-025",
+    NoVersionLabelInDeployment => "E-K8S-026",
+    ListDeploymentsWithLabel => "E-K8S-027",
+    InvalidHelmUpgrade => "E-VAL-044",
+    DowngradeNotSupported => "E-VAL-045",
+    UnsupportedUpgradePath => "E-VAL-046",
+    PrereleaseRegression => "E-VAL-047",
+    YqCommandExec => "E-IO-006",
+    YqVersionCommand => "E-IO-007",
+    YqMergeCommand => "E-IO-008",
+    NotYqV4 => "E-IO-009",
+    TempFileCreation => "E-IO-010",
+    WriteToTempFile => "E-IO-011",
+    NotAValidYamlKeyForStringValue => "E-VAL-049",
+    YqSetCommand => "E-IO-012",
+    ReadingDirectoryContents => "E-IO-013",
+    InvalidHelmChartCrdDir => "E-VAL-050",
+    CreateCrd => "E-K8S-028",
+    CollectDirEntries => "E-IO-014",
+    Yaml => "E-VAL-051",
+    Io => "E-IO-015",
+    Kube => "E-K8S-029",
+    JsonParseValuesSchema => "E-VAL-052",
+    ValuesSchemaCompile => "E-VAL-053",
+    ValuesToJson => "E-VAL-054",
+    ValuesSchemaViolations => "E-VAL-055",
+    ListDaemonSetsWithLabel => "E-K8S-030",
+    IoEngineDaemonSetAbsent => "E-K8S-031",
+    IoEngineDaemonSetContainerAbsent => "E-K8S-032",
+    HelmRepoUriParse => "E-VAL-056",
+    HelmRepoHttpsConnector => "E-HELM-015",
+    HelmRepoFetch => "E-HELM-016",
+    HelmRepoHttpStatus => "E-HELM-017",
+    HelmRepoIndexParse => "E-HELM-018",
+    HelmRepoChartAbsent => "E-HELM-019",
+    SerializeChartVersions => "E-VAL-057",
+    GetKubernetesVersion => "E-K8S-033",
+    KubeVersionConstraintParse => "E-VAL-058",
+    KubeVersionUnsupported => "E-VAL-059",
+    ControlPlaneNotUpgraded => "E-VAL-060",
+    DataPlaneVersionSkewUnsupported => "E-VAL-061",
+    SetValueParse => "E-VAL-062",
+    MetricsRegistration => "E-IO-016",
+    MetricsServerBind => "E-IO-017",
+    OciReferenceParse => "E-VAL-063",
+    OciRequestBuild => "E-HELM-020",
+    OciRegistryRequest => "E-HELM-021",
+    OciRegistryHttpStatus => "E-HELM-022",
+    OciRegistryAuthChallenge => "E-HELM-023",
+    OciRegistryUnauthorized => "E-HELM-024",
+    OciRegistryTokenParse => "E-HELM-025",
+    OciTagAbsent => "E-HELM-026",
+    OciManifestParse => "E-HELM-027",
+    OciChartLayerAbsent => "E-HELM-028",
+    OciLayerDigestMismatch => "E-HELM-029",
+    OciChartExtract => "E-IO-018",
+    GetPullSecret => "E-K8S-034",
+    DockerConfigJsonParse => "E-VAL-064",
+    PreUpgradeWebhookUriParse => "E-VAL-065",
+    PreUpgradeWebhookHttpsConnector => "E-IO-019",
+    PreUpgradeWebhookRequestBuild => "E-IO-020",
+    PreUpgradeWebhookRequest => "E-IO-021",
+    PreUpgradeWebhookTimeout => "E-IO-022",
+    PreUpgradeWebhookRejected => "E-VAL-066",
+    QuantityParse => "E-VAL-067",
+    GetNode => "E-K8S-035",
+    InsufficientHugepages => "E-VAL-068",
+    SelfSubjectAccessReviewCreate => "E-K8S-036",
+    InsufficientRbac => "E-K8S-037",
+    CanaryVerificationTimeout => "E-STOR-009",
+    ThinCommitmentBelowCurrentUsage => "E-VAL-069",
+    OverallUpgradeTimeout => "E-VAL-070",
+    GetCrd => "E-K8S-038",
+    CrdVersionRegression => "E-VAL-071",
+    SerializeAuditRecord => "E-VAL-072",
+    GetAuditConfigMap => "E-K8S-039",
+    StoreAuditRecord => "E-K8S-040",
+    InstalledVersionTooOldToUpgrade => "E-VAL-073",
+    MultiLoadErrors => "E-VAL-074",
+    ImageNotInAllowlist => "E-VAL-075",
+    InvalidValuesFile => "E-VAL-076",
+    NodeLabelParse => "E-VAL-077",
+    ListNodesWithLabel => "E-K8S-041",
+    ValuesKeysDropped => "E-VAL-078",
+    ControlPlaneUnreachable => "E-STOR-010",
+    ReleaseNotFound => "E-HELM-030",
+    YamlParseBufferForCompatibilityMatrix => "E-VAL-079",
+    UnsupportedUpgradePair => "E-VAL-080",
+    ControlPlaneCaInvalid => "E-VAL-081",
+    ControlPlaneTlsConnector => "E-IO-023",
+    RestUriParse => "E-VAL-082",
+    ControlPlaneTls => "E-STOR-011",
+    ValuesDocumentsConflict => "E-VAL-083",
+    ImageTagAppVersionMismatch => "E-VAL-084",
+    UpgradeHookFailed => "E-HELM-031",
+    InsufficientNodeDiskSpace => "E-VAL-085",
+    GetStatefulSet => "E-K8S-042",
+    SerializeConfigDrift => "E-VAL-086",
+    ConfigDriftDetected => "E-VAL-087",
+    DrainGracePeriodParse => "E-VAL-088",
+    SingleReplicaVolumesOnNode => "E-VAL-089",
+    NodeVolumesNotOnline => "E-STOR-012",
+    SerializeValuesSchema => "E-VAL-090",
+    UpgradeConfirmationRequired => "E-VAL-091",
+    UpgradeNotConfirmed => "E-VAL-092",
+    UpgradeConfirmationPrompt => "E-IO-024",
+    ThinCommitmentOverrideParse => "E-VAL-093",
+    HelmTemplateCommand => "E-HELM-032",
+    PatchIoEngineDaemonSet => "E-K8S-043",
+    ClusterArgumentsMissing => "E-VAL-094",
+    DependencyVersionConstraintParse => "E-VAL-095",
+    NodeDiskPressure => "E-VAL-096",
+    MixedIoEngineImageTags => "E-VAL-097",
+    DryRunRollback => "E-VAL-098",
+    DetermineChartVariant => "E-VAL-015",
+    EmptyPodUid => "E-K8S-014",
+    YamlStructure => "E-VAL-025",
+    PodUidIsNone => "E-K8S-017",
+}
+
+impl Error {
+    /// This is the class of failure which the error belongs to.
+    pub(crate) fn category(&self) -> ErrorCategory {
+        match self {
+            Self::RestUrlParse { .. }
+            | Self::RegexCompile { .. }
+            | Self::NoInputHelmChartDir { .. }
+            | Self::YamlParseFromSlice { .. }
+            | Self::ChartYamlParse { .. }
+            | Self::ValuesDeserialize { .. }
+            | Self::JsonParseUpgradeState { .. }
+            | Self::SerializeUpgradeState { .. }
+            | Self::UpgradeTargetChanged { .. }
+            | Self::SerializeUpgradePlan { .. }
+            | Self::SerializeValuesYaml { .. }
+            | Self::YamlParseFromFile { .. }
+            | Self::YamlParseBufferForUnsupportedVersion { .. }
+            | Self::YamlParseBufferForKnownVersions { .. }
+            | Self::NotADirectory { .. }
+            | Self::NotAFile { .. }
+            | Self::FindingHelmChart { .. }
+            | Self::NotAnApplicationHelmChart { .. }
+            | Self::DeprecatedHelmChart { .. }
+            | Self::HelmChartDependencyAbsent { .. }
+            | Self::ChartNameMismatch { .. }
+            | Self::UnhealthyVolumesPresent { .. }
+            | Self::UnhealthyPoolsPresent { .. }
+            | Self::NotAKnownHelmChart { .. }
+            | Self::KubeClientSetBuilderNs { .. }
+            | Self::EventRecorderOptionsAbsent { .. }
+            | Self::HelmClientNs { .. }
+            | Self::HelmUpgradeOptionsAbsent { .. }
+            | Self::SemverParse { .. }
+            | Self::InvalidUpgradePath { .. }
+            | Self::SerializeEventNote { .. }
+            | Self::MaxUnavailableParse { .. }
+            | Self::PodImageTagMismatch { .. }
+            | Self::ThinProvisioningOptionsAbsent { .. }
+            | Self::ThinSubfieldAbsent { .. }
+            | Self::PercentageParse { .. }
+            | Self::ThinCommitmentParse { .. }
+            | Self::ThinVolumeCommitmentInverted { .. }
+            | Self::IrreversibleMigration { .. }
+            | Self::UmbrellaChartNotUpgraded { .. }
+            | Self::CoreChartUpgradeNoneChartDir { .. }
+            | Self::InvalidHelmUpgrade { .. }
+            | Self::DowngradeNotSupported { .. }
+            | Self::UnsupportedUpgradePath { .. }
+            | Self::PrereleaseRegression { .. }
+            | Self::NotAValidYamlKeyForStringValue { .. }
+            | Self::InvalidHelmChartCrdDir { .. }
+            | Self::Yaml { .. }
+            | Self::JsonParseValuesSchema { .. }
+            | Self::ValuesSchemaCompile { .. }
+            | Self::ValuesToJson { .. }
+            | Self::ValuesSchemaViolations { .. }
+            | Self::HelmRepoUriParse { .. }
+            | Self::SerializeChartVersions { .. }
+            | Self::KubeVersionConstraintParse { .. }
+            | Self::KubeVersionUnsupported { .. }
+            | Self::ControlPlaneNotUpgraded { .. }
+            | Self::DataPlaneVersionSkewUnsupported { .. }
+            | Self::SetValueParse { .. }
+            | Self::OciReferenceParse { .. }
+            | Self::DockerConfigJsonParse { .. }
+            | Self::PreUpgradeWebhookUriParse { .. }
+            | Self::PreUpgradeWebhookRejected { .. }
+            | Self::QuantityParse { .. }
+            | Self::InsufficientHugepages { .. }
+            | Self::ThinCommitmentBelowCurrentUsage { .. }
+            | Self::OverallUpgradeTimeout { .. }
+            | Self::CrdVersionRegression { .. }
+            | Self::SerializeAuditRecord { .. }
+            | Self::InstalledVersionTooOldToUpgrade { .. }
+            | Self::MultiLoadErrors { .. }
+            | Self::ImageNotInAllowlist { .. }
+            | Self::InvalidValuesFile { .. }
+            | Self::NodeLabelParse { .. }
+            | Self::ValuesKeysDropped { .. }
+            | Self::YamlParseBufferForCompatibilityMatrix { .. }
+            | Self::UnsupportedUpgradePair { .. }
+            | Self::ControlPlaneCaInvalid { .. }
+            | Self::RestUriParse { .. }
+            | Self::ValuesDocumentsConflict { .. }
+            | Self::ImageTagAppVersionMismatch { .. }
+            | Self::InsufficientNodeDiskSpace { .. }
+            | Self::SerializeConfigDrift { .. }
+            | Self::ConfigDriftDetected { .. }
+            | Self::DrainGracePeriodParse { .. }
+            | Self::SingleReplicaVolumesOnNode { .. }
+            | Self::SerializeValuesSchema { .. }
+            | Self::UpgradeConfirmationRequired
+            | Self::UpgradeNotConfirmed
+            | Self::ThinCommitmentOverrideParse { .. }
+            | Self::ClusterArgumentsMissing { .. }
+            | Self::DependencyVersionConstraintParse { .. }
+            | Self::NodeDiskPressure { .. }
+            | Self::MixedIoEngineImageTags { .. }
+            | Self::DryRunRollback
+            | Self::DetermineChartVariant { .. }
+            | Self::YamlStructure { .. } => ErrorCategory::Validation,
+            Self::K8sClientGeneration { .. }
+            | Self::GetNamespace { .. }
+            | Self::JobPodOwnerNotFound { .. }
+            | Self::JobPodHasTooManyOwners { .. }
+            | Self::JobPodOwnerIsNotJob { .. }
+            | Self::GetUpgradeStateConfigMap { .. }
+            | Self::PatchUpgradeStateConfigMap { .. }
+            | Self::DeleteUpgradeStateConfigMap { .. }
+            | Self::GetPod { .. }
+            | Self::ListPodsWithLabel { .. }
+            | Self::ListPodsWithLabelAndField { .. }
+            | Self::EmptyPodSpec { .. }
+            | Self::EmptyPodNodeName { .. }
+            | Self::EmptyPodUid { .. }
+            | Self::PodUidIsNone
+            | Self::PodDelete { .. }
+            | Self::EventPublish { .. }
+            | Self::NodeReadyTimeout { .. }
+            | Self::IoEngineContainerAbsent { .. }
+            | Self::TooManyIoEnginePods { .. }
+            | Self::EventChannelSend { .. }
+            | Self::HelmChartVersionLabelHasNoValue { .. }
+            | Self::ListSecretsWithLabel { .. }
+            | Self::NoNamespaceInPod { .. }
+            | Self::NoRestDeployment { .. }
+            | Self::NoVersionLabelInDeployment { .. }
+            | Self::ListDeploymentsWithLabel { .. }
+            | Self::CreateCrd { .. }
+            | Self::Kube { .. }
+            | Self::ListDaemonSetsWithLabel { .. }
+            | Self::IoEngineDaemonSetAbsent { .. }
+            | Self::IoEngineDaemonSetContainerAbsent { .. }
+            | Self::GetKubernetesVersion { .. }
+            | Self::GetPullSecret { .. }
+            | Self::GetNode { .. }
+            | Self::SelfSubjectAccessReviewCreate { .. }
+            | Self::InsufficientRbac { .. }
+            | Self::GetCrd { .. }
+            | Self::GetAuditConfigMap { .. }
+            | Self::StoreAuditRecord { .. }
+            | Self::ListNodesWithLabel { .. }
+            | Self::GetStatefulSet { .. }
+            | Self::PatchIoEngineDaemonSet { .. }
+            | Self::RestClientConfiguration { .. }
+            | Self::StorageNodeUncordon { .. }
+            | Self::ListStorageNodes { .. }
+            | Self::GetStorageNode { .. }
+            | Self::EmptyStorageNodeSpec { .. }
+            | Self::ListStoragePools { .. }
+            | Self::ListStorageVolumes { .. }
+            | Self::DrainStorageNode { .. }
+            | Self::CanaryVerificationTimeout { .. }
+            | Self::ControlPlaneUnreachable { .. }
+            | Self::ControlPlaneTls { .. }
+            | Self::NodeVolumesNotOnline { .. } => ErrorCategory::Kubernetes,
+            Self::HelmCommand { .. }
+            | Self::HelmVersion { .. }
+            | Self::HelmRelease { .. }
+            | Self::HelmListCommand { .. }
+            | Self::HelmVersionCommand { .. }
+            | Self::HelmUpgradeFailed { .. }
+            | Self::HelmRollbackCommand { .. }
+            | Self::HelmGetValuesCommand { .. }
+            | Self::HelmReleaseSecretAbsent { .. }
+            | Self::NoPreviousHelmRelease { .. }
+            | Self::HelmReleaseSecretDataAbsent { .. }
+            | Self::Base64DecodeHelmRelease { .. }
+            | Self::GzipDecodeHelmRelease { .. }
+            | Self::JsonParseHelmRelease { .. }
+            | Self::HelmRepoHttpsConnector { .. }
+            | Self::HelmRepoFetch { .. }
+            | Self::HelmRepoHttpStatus { .. }
+            | Self::HelmRepoIndexParse { .. }
+            | Self::HelmRepoChartAbsent { .. }
+            | Self::OciRequestBuild { .. }
+            | Self::OciRegistryRequest { .. }
+            | Self::OciRegistryHttpStatus { .. }
+            | Self::OciRegistryAuthChallenge { .. }
+            | Self::OciRegistryUnauthorized { .. }
+            | Self::OciRegistryTokenParse { .. }
+            | Self::OciTagAbsent { .. }
+            | Self::OciManifestParse { .. }
+            | Self::OciChartLayerAbsent { .. }
+            | Self::OciLayerDigestMismatch { .. }
+            | Self::ReleaseNotFound { .. }
+            | Self::UpgradeHookFailed { .. }
+            | Self::HelmTemplateCommand { .. } => ErrorCategory::Helm,
+            Self::ChartFileRead { .. }
+            | Self::ValidateDirPath { .. }
+            | Self::ValidateFilePath { .. }
+            | Self::ReadingFile { .. }
+            | Self::U8VectorToString { .. }
+            | Self::YqCommandExec { .. }
+            | Self::YqVersionCommand { .. }
+            | Self::YqMergeCommand { .. }
+            | Self::NotYqV4 { .. }
+            | Self::TempFileCreation { .. }
+            | Self::WriteToTempFile { .. }
+            | Self::YqSetCommand { .. }
+            | Self::ReadingDirectoryContents { .. }
+            | Self::CollectDirEntries { .. }
+            | Self::Io { .. }
+            | Self::MetricsRegistration { .. }
+            | Self::MetricsServerBind { .. }
+            | Self::OciChartExtract { .. }
+            | Self::PreUpgradeWebhookHttpsConnector { .. }
+            | Self::PreUpgradeWebhookRequestBuild { .. }
+            | Self::PreUpgradeWebhookRequest { .. }
+            | Self::PreUpgradeWebhookTimeout { .. }
+            | Self::ControlPlaneTlsConnector { .. }
+            | Self::UpgradeConfirmationPrompt { .. } => ErrorCategory::Io,
+        }
+    }
+}
+
+/// These are the classes of failures, for alerting on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ErrorCategory {
+    /// The inputs, the helm charts or the state of the cluster fail a check for upgrade.
+    Validation,
+    /// A Kubernetes or storage REST API request fails, or a Kubernetes object or storage resource
+    /// is not as expected.
+    Kubernetes,
+    /// A helm command fails, or a helm release is not as expected.
+    Helm,
+    /// A filesystem operation, or a command other than helm, fails.
+    Io,
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category = match self {
+            Self::Validation => "Validation",
+            Self::Kubernetes => "Kubernetes",
+            Self::Helm => "Helm",
+            Self::Io => "Io",
+        };
+        write!(f, "{category}")
+    }
 }
 
 /// A wrapper type to remove repeated Result<T, Error> returns.
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn error_codes_are_unique() {
+        let mut variants_by_code = HashMap::new();
+        for (variant, code) in ERROR_CODES {
+            assert!(
+                code.starts_with("E-") && code.rsplit('-').next().unwrap().len() == 3,
+                "{variant} has a malformed code {code}"
+            );
+            if let Some(other) = variants_by_code.insert(code, variant) {
+                panic!("{variant} and {other} have the same code {code}");
+            }
+        }
+    }
+}
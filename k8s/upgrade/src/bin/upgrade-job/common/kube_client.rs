@@ -1,12 +1,16 @@
-use crate::common::error::{K8sClientGeneration, KubeClientSetBuilderNs, Result};
+use crate::common::error::{
+    GetKubernetesVersion, K8sClientGeneration, KubeClientSetBuilderNs, Result, SemverParse,
+};
 use k8s_openapi::{
     api::{
-        apps::v1::Deployment,
-        core::v1::{Namespace, Pod},
+        apps::v1::{DaemonSet, Deployment, StatefulSet},
+        authorization::v1::SelfSubjectAccessReview,
+        core::v1::{ConfigMap, Namespace, Node, Pod, Secret},
     },
     apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
 };
 use kube::{api::Api, Client};
+use semver::Version;
 use snafu::ResultExt;
 
 /// Builder for Kubernetes clients.
@@ -34,13 +38,7 @@ impl KubeClientSetBuilder {
         let namespace = self.namespace.ok_or(KubeClientSetBuilderNs.build())?;
 
         let client = Client::try_default().await.context(K8sClientGeneration)?;
-        return Ok(KubeClientSet {
-            client: client.clone(),
-            pods_api: Api::namespaced(client.clone(), namespace.as_str()),
-            namespaces_api: Api::all(client.clone()),
-            deployments_api: Api::namespaced(client.clone(), namespace.as_str()),
-            crd_api: Api::all(client),
-        });
+        Ok(KubeClientSet::with_client(client, namespace.as_str()))
     }
 }
 
@@ -49,8 +47,14 @@ pub(crate) struct KubeClientSet {
     client: Client,
     pods_api: Api<Pod>,
     namespaces_api: Api<Namespace>,
+    nodes_api: Api<Node>,
     deployments_api: Api<Deployment>,
+    daemonsets_api: Api<DaemonSet>,
+    statefulsets_api: Api<StatefulSet>,
+    secrets_api: Api<Secret>,
+    configmaps_api: Api<ConfigMap>,
     crd_api: Api<CustomResourceDefinition>,
+    self_subject_access_reviews_api: Api<SelfSubjectAccessReview>,
 }
 
 impl KubeClientSet {
@@ -58,6 +62,23 @@ impl KubeClientSet {
         KubeClientSetBuilder::default()
     }
 
+    /// This creates the Api clients for a specific namespace, from a kube::Client.
+    pub(crate) fn with_client(client: Client, namespace: &str) -> Self {
+        Self {
+            client: client.clone(),
+            pods_api: Api::namespaced(client.clone(), namespace),
+            namespaces_api: Api::all(client.clone()),
+            nodes_api: Api::all(client.clone()),
+            deployments_api: Api::namespaced(client.clone(), namespace),
+            daemonsets_api: Api::namespaced(client.clone(), namespace),
+            statefulsets_api: Api::namespaced(client.clone(), namespace),
+            secrets_api: Api::namespaced(client.clone(), namespace),
+            configmaps_api: Api::namespaced(client.clone(), namespace),
+            crd_api: Api::all(client.clone()),
+            self_subject_access_reviews_api: Api::all(client),
+        }
+    }
+
     /// Generate the Pod api client.
     pub(crate) fn pods_api(&self) -> &Api<Pod> {
         &self.pods_api
@@ -68,18 +89,63 @@ impl KubeClientSet {
         &self.namespaces_api
     }
 
+    /// Generate the Node api client.
+    pub(crate) fn nodes_api(&self) -> &Api<Node> {
+        &self.nodes_api
+    }
+
     /// Generate the Deployment api client.
     pub(crate) fn deployments_api(&self) -> &Api<Deployment> {
         &self.deployments_api
     }
 
+    /// Generate the DaemonSet api client.
+    pub(crate) fn daemonsets_api(&self) -> &Api<DaemonSet> {
+        &self.daemonsets_api
+    }
+
+    /// Generate the StatefulSet api client.
+    pub(crate) fn statefulsets_api(&self) -> &Api<StatefulSet> {
+        &self.statefulsets_api
+    }
+
+    /// Generate the Secret api client.
+    pub(crate) fn secrets_api(&self) -> &Api<Secret> {
+        &self.secrets_api
+    }
+
+    /// Generate the ConfigMap api client.
+    pub(crate) fn configmaps_api(&self) -> &Api<ConfigMap> {
+        &self.configmaps_api
+    }
+
     /// Generate the CustomResourceDefinition api client.
     pub(crate) fn crd_api(&self) -> &Api<CustomResourceDefinition> {
         &self.crd_api
     }
 
+    /// Generate the SelfSubjectAccessReview api client.
+    pub(crate) fn self_subject_access_reviews_api(&self) -> &Api<SelfSubjectAccessReview> {
+        &self.self_subject_access_reviews_api
+    }
+
     /// Get a clone of the kube::Client.
     pub(crate) fn client(&self) -> Client {
         self.client.clone()
     }
+
+    /// This fetches the Kubernetes cluster's version from the API server, e.g. 1.27.3 for
+    /// 'v1.27.3'. Vendor suffixes like '+k3s1' are kept as the pre-release or build metadata.
+    pub(crate) async fn kubernetes_version(&self) -> Result<Version> {
+        let info = self
+            .client
+            .apiserver_version()
+            .await
+            .context(GetKubernetesVersion)?;
+        let git_version = info.git_version.trim_start_matches('v');
+
+        Version::parse(git_version).context(SemverParse {
+            version_string: info.git_version.clone(),
+        })
+    }
 }
@@ -1,9 +1,41 @@
-use crate::common::error::{RestClientConfiguration, RestUrlParse, Result};
+use crate::common::error::{
+    ControlPlaneCaInvalid, ControlPlaneTlsConnector, ReadingFile, RestClientConfiguration,
+    RestUriParse, RestUrlParse, Result,
+};
+use hyper::{client::HttpConnector, Body, Client, Uri};
+use hyper_openssl::HttpsConnector;
 use openapi::tower::client::{ApiClient, Configuration as RestConfig};
-use snafu::ResultExt;
-use std::time::Duration;
+use openssl::{
+    ssl::{self, SslConnector, SslMethod, SslVerifyMode},
+    x509::X509,
+};
+use snafu::{ensure, ResultExt};
+use std::{
+    error::Error as StdError,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use url::Url;
 
+/// This is the time to wait for the TLS handshake with the storage REST API, when a failed
+/// request is probed for the cause of its failure.
+const TLS_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// This is how the server certificate of the storage REST API is verified, if the REST API is
+/// served over https.
+#[derive(Clone, Default)]
+pub(crate) enum RestTls {
+    /// The server certificate is verified with the system's trusted CAs.
+    #[default]
+    SystemRoots,
+    /// The server certificate is verified with the system's trusted CAs, and with the CA
+    /// certificate(s) in this PEM file.
+    Ca(PathBuf),
+    /// The server certificate is not verified. This is meant for testing.
+    Insecure,
+}
+
 /// This is a wrapper for the openapi::tower::client::ApiClient.
 pub(crate) struct RestClientSet {
     client: ApiClient,
@@ -11,21 +43,29 @@ pub(crate) struct RestClientSet {
 
 impl RestClientSet {
     /// Build the RestConfig, and the eventually the ApiClient. Fails if configuration is invalid.
-    pub(crate) fn new_with_url(rest_endpoint: String) -> Result<Self> {
+    pub(crate) fn new_with_url(rest_endpoint: String, tls: &RestTls) -> Result<Self> {
         let rest_url =
             Url::try_from(rest_endpoint.as_str()).context(RestUrlParse { rest_endpoint })?;
 
-        let config = RestConfig::builder()
+        let builder = RestConfig::builder()
             .with_timeout(Duration::from_secs(30))
-            .with_tracing(true)
-            .build_url(rest_url.clone())
-            .map_err(|e| {
-                RestClientConfiguration {
-                    source: e,
-                    rest_endpoint: rest_url,
-                }
-                .build()
+            .with_tracing(true);
+        // The TLS options do not apply to plain http endpoints.
+        let config = if rest_url.scheme() == "https" {
+            let uri = rest_url.as_str().parse::<Uri>().context(RestUriParse {
+                rest_endpoint: rest_url.to_string(),
             })?;
+            builder.build_with_svc(uri, https_client(tls)?)
+        } else {
+            builder.build_url(rest_url.clone())
+        }
+        .map_err(|e| {
+            RestClientConfiguration {
+                source: e,
+                rest_endpoint: rest_url,
+            }
+            .build()
+        })?;
         let client = ApiClient::new(config);
 
         Ok(RestClientSet { client })
@@ -38,4 +78,235 @@ impl RestClientSet {
     pub(crate) fn volumes_api(&self) -> &dyn openapi::apis::volumes_api::tower::client::Volumes {
         self.client.volumes_api()
     }
+
+    pub(crate) fn pools_api(&self) -> &dyn openapi::apis::pools_api::tower::client::Pools {
+        self.client.pools_api()
+    }
+}
+
+/// This reads a PEM file of CA certificates, and makes sure that it has at least one valid
+/// certificate, so that a broken file fails here and not as a TLS handshake failure later.
+fn read_ca_certificates(ca_path: &Path) -> Result<Vec<X509>> {
+    let ca_pem = fs::read(ca_path).context(ReadingFile {
+        filepath: ca_path.to_path_buf(),
+    })?;
+    let certificates = X509::stack_from_pem(ca_pem.as_slice()).map_err(|error| {
+        ControlPlaneCaInvalid {
+            filepath: ca_path.to_path_buf(),
+            reason: error.to_string(),
+        }
+        .build()
+    })?;
+    ensure!(
+        !certificates.is_empty(),
+        ControlPlaneCaInvalid {
+            filepath: ca_path.to_path_buf(),
+            reason: "no PEM encoded certificate found",
+        }
+    );
+
+    Ok(certificates)
+}
+
+/// This is an https client which verifies the server certificate as set by the TLS options.
+fn https_client(tls: &RestTls) -> Result<Client<HttpsConnector<HttpConnector>>> {
+    // The connector trusts the system's CAs to begin with.
+    let mut ssl = SslConnector::builder(SslMethod::tls()).context(ControlPlaneTlsConnector)?;
+    match tls {
+        RestTls::SystemRoots => {}
+        RestTls::Ca(ca_path) => {
+            for certificate in read_ca_certificates(ca_path)? {
+                ssl.cert_store_mut()
+                    .add_cert(certificate)
+                    .context(ControlPlaneTlsConnector)?;
+            }
+        }
+        RestTls::Insecure => ssl.set_verify(SslVerifyMode::NONE),
+    }
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    let connector = HttpsConnector::with_connector(http, ssl).context(ControlPlaneTlsConnector)?;
+
+    Ok(Client::builder().build::<_, Body>(connector))
+}
+
+/// This probes an https storage REST API endpoint with the TLS options, and is true if the TLS
+/// handshake fails, e.g. because the server certificate is not signed by a trusted CA. This tells
+/// a misconfigured CA apart from an endpoint which cannot be reached.
+pub(crate) async fn tls_handshake_fails(rest_endpoint: &str, tls: &RestTls) -> bool {
+    let Ok(uri) = rest_endpoint.parse::<Uri>() else {
+        return false;
+    };
+    if uri.scheme_str() != Some("https") {
+        return false;
+    }
+    let Ok(client) = https_client(tls) else {
+        return false;
+    };
+
+    match tokio::time::timeout(TLS_PROBE_TIMEOUT, client.get(uri)).await {
+        Ok(Err(error)) => is_ssl_failure(&error),
+        _ => false,
+    }
+}
+
+/// This is true if an openssl error, which is not an I/O error, is the cause of an error.
+fn is_ssl_failure(error: &(dyn StdError + 'static)) -> bool {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if error
+            .downcast_ref::<ssl::Error>()
+            .is_some_and(|ssl_error| ssl_error.ssl_error().is_some())
+        {
+            return true;
+        }
+        cause = error.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        ssl::SslAcceptor,
+        x509::{
+            extension::{BasicConstraints, SubjectAlternativeName},
+            X509NameBuilder,
+        },
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+    use tempfile::NamedTempFile;
+
+    /// This is a self-signed certificate for 'localhost', with its private key.
+    fn self_signed_certificate() -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder
+            .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (builder.build(), key)
+    }
+
+    /// This serves https with the certificate on a local port, and returns the endpoint URL. Each
+    /// request which gets past the TLS handshake gets an empty 200 response.
+    fn serve_https(certificate: &X509, key: &PKey<Private>) -> String {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+        acceptor.set_certificate(certificate).unwrap();
+        acceptor.set_private_key(key).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(mut stream) = acceptor.accept(stream) else {
+                    continue;
+                };
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        });
+
+        format!("https://localhost:{port}")
+    }
+
+    /// This writes the certificate to a PEM file.
+    fn ca_file(certificate: &X509) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(certificate.to_pem().unwrap().as_slice())
+            .unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn ca_file_is_wired_into_the_https_client() {
+        let (certificate, key) = self_signed_certificate();
+        let endpoint = serve_https(&certificate, &key);
+        let ca_file = ca_file(&certificate);
+        let tls = RestTls::Ca(ca_file.path().to_path_buf());
+
+        let client = https_client(&tls).unwrap();
+        let response = client.get(endpoint.parse().unwrap()).await.unwrap();
+        assert!(response.status().is_success());
+        assert!(!tls_handshake_fails(endpoint.as_str(), &tls).await);
+    }
+
+    #[tokio::test]
+    async fn untrusted_server_certificate_is_a_tls_failure() {
+        let (certificate, key) = self_signed_certificate();
+        let endpoint = serve_https(&certificate, &key);
+
+        assert!(tls_handshake_fails(endpoint.as_str(), &RestTls::SystemRoots).await);
+
+        // A CA which did not sign the server certificate fails the same way.
+        let (other_certificate, _) = self_signed_certificate();
+        let other_ca_file = ca_file(&other_certificate);
+        let tls = RestTls::Ca(other_ca_file.path().to_path_buf());
+        assert!(tls_handshake_fails(endpoint.as_str(), &tls).await);
+    }
+
+    #[tokio::test]
+    async fn insecure_does_not_verify_the_server_certificate() {
+        let (certificate, key) = self_signed_certificate();
+        let endpoint = serve_https(&certificate, &key);
+
+        assert!(!tls_handshake_fails(endpoint.as_str(), &RestTls::Insecure).await);
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoint_is_not_a_tls_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let endpoint = format!("https://localhost:{port}");
+        assert!(!tls_handshake_fails(endpoint.as_str(), &RestTls::SystemRoots).await);
+    }
+
+    #[test]
+    fn ca_file_without_certificates_is_invalid() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"not a certificate").unwrap();
+
+        let result = read_ca_certificates(file.path());
+        assert!(matches!(result, Err(Error::ControlPlaneCaInvalid { .. })));
+    }
 }
@@ -0,0 +1,163 @@
+use crate::common::constants::{KUBE_API_MAX_ATTEMPTS, RETRY_INITIAL_DELAY, RETRY_MAX_DELAY};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// This runs a fallible operation until it succeeds, up to max_attempts times. Only the errors
+/// which is_transient accepts are retried, the rest are returned right away. The delay between
+/// attempts doubles after every attempt, up to a maximum, and is randomized so that concurrent
+/// callers do not retry in lockstep.
+pub(crate) async fn with_backoff<T, E, F, Fut>(
+    max_attempts: u32,
+    is_transient: fn(&E) -> bool,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let mut delay = RETRY_INITIAL_DELAY;
+    let mut attempt = 1_u32;
+    loop {
+        match operation().await {
+            Err(error) if attempt < max_attempts && is_transient(&error) => {
+                let jittered_delay = jitter(delay);
+                warn!(
+                    %error,
+                    attempt,
+                    max_attempts,
+                    "Transient error, retrying in {}ms",
+                    jittered_delay.as_millis()
+                );
+                tokio::time::sleep(jittered_delay).await;
+                delay = (delay * 2).min(RETRY_MAX_DELAY);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// This retries a Kubernetes API request, if it fails with a transient error.
+pub(crate) async fn kube_with_backoff<T, F, Fut>(operation: F) -> Result<T, kube::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    with_backoff(KUBE_API_MAX_ATTEMPTS, is_transient_kube_error, operation).await
+}
+
+/// This retries a Kubernetes API delete request, if it fails with a transient error. A resource
+/// which is not found is deleted already, e.g. by an earlier attempt whose response was lost, so
+/// 404 Not Found counts as success.
+pub(crate) async fn kube_delete_with_backoff<T, F, Fut>(operation: F) -> Result<(), kube::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    match kube_with_backoff(operation).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// This decides if a failed Kubernetes API request may succeed if re-tried. Throttling (429),
+/// server-side errors (5xx) and connection failures are transient. Errors like 403 Forbidden or
+/// 404 Not Found will not go away by retrying.
+pub(crate) fn is_transient_kube_error(error: &kube::Error) -> bool {
+    match error {
+        kube::Error::Api(response) => response.code == 429 || response.code >= 500,
+        kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+        _ => false,
+    }
+}
+
+/// This returns a random duration between half of the delay and the full delay.
+fn jitter(delay: Duration) -> Duration {
+    let half_delay_millis = (delay.as_millis() / 2) as u64;
+    // The sub-second part of the system time is random enough to spread out retries.
+    let random = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos() as u64)
+        .unwrap_or_default();
+
+    Duration::from_millis(half_delay_millis + random % (half_delay_millis + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::core::ErrorResponse;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// This is a Kubernetes API error response with a status code.
+    fn api_error(code: u16) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: format!("status {code}"),
+            reason: String::new(),
+            code,
+        })
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = with_backoff(3, is_transient_kube_error, || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match attempt {
+                    0 | 1 => Err(api_error(503)),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+
+        assert!(matches!(result, Ok(2)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retried_delete_which_is_not_found_succeeds() {
+        let attempts = AtomicU32::new(0);
+        let result = kube_delete_with_backoff(|| {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match attempt {
+                    0 => Err::<(), _>(api_error(503)),
+                    _ => Err(api_error(404)),
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn delete_which_is_forbidden_fails() {
+        let attempts = AtomicU32::new(0);
+        let result = kube_delete_with_backoff(|| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>(api_error(403)) }
+        })
+        .await;
+
+        assert!(matches!(result, Err(kube::Error::Api(response)) if response.code == 403));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        assert!(is_transient_kube_error(&api_error(429)));
+        assert!(is_transient_kube_error(&api_error(503)));
+        assert!(!is_transient_kube_error(&api_error(404)));
+        assert!(!is_transient_kube_error(&api_error(403)));
+    }
+}
@@ -10,7 +10,10 @@ use k8s_openapi::{api::core::v1::ObjectReference, serde_json};
 use kube::runtime::events::{Event, EventType, Recorder};
 use serde::Serialize;
 use snafu::{ensure, ResultExt};
-use std::{fmt::Display, time::Duration};
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
 use tokio::{select, sync::mpsc, time::sleep};
 use tracing::error;
 
@@ -20,6 +23,12 @@ pub(crate) struct EventNote {
     from_version: String,
     to_version: String,
     message: String,
+    /// The number of nodes whose io-engine Pods were upgraded, for the summary event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nodes_upgraded: Option<usize>,
+    /// The reason the upgrade failed, for the summary event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_reason: Option<String>,
 }
 
 impl From<&EventRecorder> for EventNote {
@@ -28,6 +37,8 @@ impl From<&EventRecorder> for EventNote {
             from_version: er.from_version.clone(),
             to_version: er.to_version.clone(),
             message: Default::default(),
+            nodes_upgraded: None,
+            failure_reason: None,
         }
     }
 }
@@ -37,6 +48,12 @@ impl EventNote {
         self.message = msg;
         self
     }
+
+    fn with_summary(mut self, nodes_upgraded: usize, failure_reason: Option<String>) -> EventNote {
+        self.nodes_upgraded = Some(nodes_upgraded);
+        self.failure_reason = failure_reason;
+        self
+    }
 }
 
 /// A builder for the Kubernetes event publisher.
@@ -175,6 +192,8 @@ impl EventRecorderBuilder {
             event_loop_handle,
             from_version,
             to_version,
+            nodes_upgraded: 0,
+            validation_failed: false,
         })
     }
 }
@@ -185,6 +204,8 @@ pub(crate) struct EventRecorder {
     event_loop_handle: tokio::task::JoinHandle<()>,
     from_version: String,
     to_version: String,
+    nodes_upgraded: usize,
+    validation_failed: bool,
 }
 
 impl EventRecorder {
@@ -256,6 +277,75 @@ impl EventRecorder {
             .map_err(|error| error!(%error, "Failed to upgrade {PRODUCT}"));
     }
 
+    /// This publishes the outcome of the upgrade, with the versions, the number of nodes whose
+    /// io-engine Pods were upgraded, and the failure reason, if any. This is the one Successful,
+    /// Failed or Validation Failed Event of the upgrade. The event is related to the helm release
+    /// object, if there is one, so that it is listed along with the helm release.
+    pub(crate) async fn publish_summary<Error>(
+        &self,
+        result: std::result::Result<(), &Error>,
+        helm_release: Option<ObjectReference>,
+    ) where
+        Error: Display,
+    {
+        let result = match self.summary_event(result, helm_release) {
+            Ok(event) => self.publish(event).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            error!(%error, "Failed to publish the {PRODUCT} upgrade summary event");
+        }
+    }
+
+    /// This builds the upgrade summary Event, for publish_summary().
+    fn summary_event<Error>(
+        &self,
+        result: std::result::Result<(), &Error>,
+        helm_release: Option<ObjectReference>,
+    ) -> Result<Event>
+    where
+        Error: Display,
+    {
+        let (type_, action, message, failure_reason) = match result {
+            Ok(()) => (
+                EventType::Normal,
+                EventAction::Successful,
+                format!(
+                    "Successfully upgraded {PRODUCT} from {} to {}, io-engine Pods were upgraded \
+                    on {} nodes",
+                    self.from_version, self.to_version, self.nodes_upgraded
+                ),
+                None,
+            ),
+            Err(error) => (
+                EventType::Warning,
+                if self.validation_failed {
+                    EventAction::ValidationFailed
+                } else {
+                    EventAction::Failed
+                },
+                format!(
+                    "Failed to upgrade {PRODUCT} from {} to {}, io-engine Pods were upgraded on \
+                    {} nodes: {error}",
+                    self.from_version, self.to_version, self.nodes_upgraded
+                ),
+                Some(error.to_string()),
+            ),
+        };
+
+        let note = EventNote::from(self)
+            .with_message(message)
+            .with_summary(self.nodes_upgraded, failure_reason);
+        let note = serde_json::to_string(&note).context(SerializeEventNote { note })?;
+        Ok(Event {
+            type_,
+            reason: format!("{PRODUCT}Upgrade"),
+            note: Some(note),
+            action: action.to_string(),
+            secondary: helm_release,
+        })
+    }
+
     /// Shuts down the event channel which makes the event loop worker exit its loop and return.
     pub(crate) async fn shutdown_worker(mut self) {
         // Dropping the sender, to signify no more channel messages.
@@ -274,6 +364,17 @@ impl EventRecorder {
     pub(crate) fn set_to_version(&mut self, version: String) {
         self.to_version = version
     }
+
+    /// Updates the number of nodes whose io-engine Pods were upgraded, for the summary event.
+    pub(crate) fn set_nodes_upgraded(&mut self, nodes_upgraded: usize) {
+        self.nodes_upgraded = nodes_upgraded
+    }
+
+    /// Marks the upgrade as having failed its validation, so that the summary event of a failed
+    /// upgrade has the 'Validation Failed' action.
+    pub(crate) fn set_validation_failed(&mut self) {
+        self.validation_failed = true
+    }
 }
 
 /// current volume status
@@ -293,18 +394,98 @@ pub enum EventAction {
     UpgradedDP,
     #[serde(rename = "Successful")]
     Successful,
+    #[serde(rename = "Rolling back")]
+    RollingBack,
+    #[serde(rename = "Rolled back")]
+    RolledBack,
 }
 
-impl ToString for EventAction {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Failed => String::from("Failed"),
-            Self::ValidationFailed => String::from("Validation Failed"),
-            Self::UpgradingCP => String::from("Upgrading control-plane"),
-            Self::UpgradedCP => String::from("Upgraded control-plane"),
-            Self::UpgradingDP => String::from("Upgrading data-plane"),
-            Self::UpgradedDP => String::from("Upgraded data-plane"),
-            Self::Successful => String::from("Successful"),
+impl fmt::Display for EventAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Self::Failed => "Failed",
+            Self::ValidationFailed => "Validation Failed",
+            Self::UpgradingCP => "Upgrading control-plane",
+            Self::UpgradedCP => "Upgraded control-plane",
+            Self::UpgradingDP => "Upgrading data-plane",
+            Self::UpgradedDP => "Upgraded data-plane",
+            Self::Successful => "Successful",
+            Self::RollingBack => "Rolling back",
+            Self::RolledBack => "Rolled back",
+        };
+        f.write_str(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This is an EventRecorder without an event loop worker, for the upgrade from 2.4.0 to 2.5.0.
+    fn recorder() -> EventRecorder {
+        EventRecorder {
+            event_sender: None,
+            event_loop_handle: tokio::spawn(async {}),
+            from_version: "2.4.0".to_string(),
+            to_version: "2.5.0".to_string(),
+            nodes_upgraded: 3,
+            validation_failed: false,
         }
     }
+
+    /// This parses the note of the Event.
+    fn note(event: &Event) -> serde_json::Value {
+        serde_json::from_str(event.note.as_deref().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn successful_upgrade_summary() {
+        let event = recorder().summary_event::<String>(Ok(()), None).unwrap();
+
+        assert!(matches!(event.type_, EventType::Normal));
+        assert_eq!(event.reason, format!("{PRODUCT}Upgrade"));
+        assert_eq!(event.action, "Successful");
+        let note = note(&event);
+        assert_eq!(
+            note["message"],
+            format!(
+                "Successfully upgraded {PRODUCT} from 2.4.0 to 2.5.0, io-engine Pods were \
+                upgraded on 3 nodes"
+            )
+        );
+        assert_eq!(note["nodesUpgraded"], 3);
+        assert!(note.get("failureReason").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_upgrade_summary() {
+        let error = "io-engine Pod did not become ready".to_string();
+        let event = recorder()
+            .summary_event(Err(&error), Some(ObjectReference::default()))
+            .unwrap();
+
+        assert!(matches!(event.type_, EventType::Warning));
+        assert_eq!(event.reason, format!("{PRODUCT}Upgrade"));
+        assert_eq!(event.action, "Failed");
+        assert!(event.secondary.is_some());
+        let note = note(&event);
+        assert_eq!(
+            note["message"],
+            format!(
+                "Failed to upgrade {PRODUCT} from 2.4.0 to 2.5.0, io-engine Pods were upgraded \
+                on 3 nodes: {error}"
+            )
+        );
+        assert_eq!(note["failureReason"], error);
+    }
+
+    #[tokio::test]
+    async fn failed_validation_summary() {
+        let mut recorder = recorder();
+        recorder.set_validation_failed();
+        let error = "invalid upgrade path".to_string();
+        let event = recorder.summary_event(Err(&error), None).unwrap();
+
+        assert_eq!(event.action, "Validation Failed");
+    }
 }
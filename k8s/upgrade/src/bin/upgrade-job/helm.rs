@@ -7,8 +7,44 @@ pub(crate) mod upgrade;
 /// Contains validation and logic to generate helm values options for the `helm upgrade` command.
 pub(crate) mod values;
 
+/// Contains validation for the helm values options of the `helm upgrade` command.
+pub(crate) mod values_validation;
+
+/// Contains validation of helm values against the JSON schema of a helm chart.
+pub(crate) mod schema;
+
+/// Contains tools to compare the helm values of the installed release and the target helm chart.
+pub(crate) mod diff;
+
+/// Contains tools to redact sensitive helm values, e.g. before logging them.
+pub(crate) mod redact;
+
+/// Contains tools to merge helm values.
+pub(crate) mod merge;
+
+/// Contains tools to read and to validate the CRDs bundled with a helm chart.
+pub(crate) mod crd;
+
+/// Contains the helm values which are set at upgrade time, on top of the installed values.
+pub(crate) mod overrides;
+
+/// Contains transformations of older helm values into the shape which newer helm charts accept.
+pub(crate) mod migration;
+
 /// Contains the structs required to deserialize yaml files from the helm charts.
 pub(crate) mod chart;
 
+/// Contains the concurrent loading of helm chart files.
+pub(crate) mod load;
+
+/// Contains tools to read the published helm chart versions from a helm repository.
+pub(crate) mod repo;
+
+/// Contains tools to pull helm charts from OCI registries.
+pub(crate) mod oci;
+
+/// Contains tools to read installed helm releases from their Kubernetes Secrets.
+pub(crate) mod release;
+
 /// This contains tools for use with yaml files.
 pub(crate) mod yaml;
@@ -1,13 +1,96 @@
-use semver::Version;
-use serde::Deserialize;
+use crate::common::{
+    constants::{APPLICATION_CHART_TYPE, CORE_CHART_NAME, THIN_PROVISIONING_MIN_VERSION},
+    error::{
+        ChartFileRead, ChartNameMismatch, DependencyVersionConstraintParse, Error,
+        KubeVersionConstraintParse, KubeVersionUnsupported, PercentageParse, Result,
+        ThinCommitmentOverrideParse, ThinCommitmentParse, ThinProvisioningOptionsAbsent,
+        ThinSubfieldAbsent, ValuesDeserialize, YamlParseFromSlice,
+    },
+};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use semver::{Version, VersionReq};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use snafu::{ensure, IntoError, ResultExt};
+use std::{collections::BTreeMap, fmt, fs, mem, path::Path, str::FromStr};
+
+/// This reads a yaml file from the filesystem, and deserializes it. This is implemented for the
+/// helm chart files, i.e. the Chart.yaml and the values.yaml files.
+pub(crate) trait FromPath: DeserializeOwned {
+    /// This reads and deserializes the yaml file at the path.
+    fn from_path(path: &Path) -> Result<Self> {
+        let yaml = fs::read(path).context(ChartFileRead {
+            path: path.to_path_buf(),
+        })?;
+
+        Self::from_slice(path, yaml.as_slice())
+    }
+
+    /// This deserializes the yaml which was read from the file at the path.
+    fn from_slice(path: &Path, yaml: &[u8]) -> Result<Self> {
+        deserialize_with_key_path(serde_yaml::Deserializer::from_slice(yaml)).map_err(|error| {
+            match error {
+                // The yaml couldn't be parsed at all, the file path is more useful here. The key
+                // path of a syntax error is where the parser stopped, which needn't be the root.
+                Error::ValuesDeserialize { source, .. }
+                    if serde_yaml::from_slice::<serde_yaml::Value>(yaml).is_err() =>
+                {
+                    Error::ChartYamlParse {
+                        path: path.to_path_buf(),
+                        source,
+                    }
+                }
+                error => error,
+            }
+        })
+    }
+}
+
+/// This deserializes yaml, and reports the path of the yaml key which failed deserialization,
+/// e.g. 'mayastor.agents.core.capacity.thin.poolCommitment'.
+pub(crate) fn deserialize_with_key_path<'de, T, D>(deserializer: D) -> Result<T>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de, Error = serde_yaml::Error>,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|error| {
+        let path = error.path().to_string();
+        ValuesDeserialize { path }.into_error(error.into_inner())
+    })
+}
+
+impl FromPath for Chart {}
+impl FromPath for CoreValues {}
+impl FromPath for serde_yaml::Value {}
 
 /// This struct is used to deserialize helm charts' Chart.yaml file.
 #[derive(Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct Chart {
+    /// This is the chart API version, 'v2' for charts which require helm v3, 'v1' for older ones.
+    api_version: String,
     /// This is the name of the helm chart.
     name: String,
     /// This is the version of the helm chart.
     version: Version,
+    /// This is the type of the helm chart, either 'application' or 'library'.
+    #[serde(rename(deserialize = "type"))]
+    chart_type: Option<String>,
+    /// This is the version of the application (the PRODUCT release) which the helm chart ships.
+    /// This is None if the field is absent, or if it is not a valid semver, e.g. 'develop'.
+    #[serde(default, deserialize_with = "deserialize_lenient_version")]
+    app_version: Option<Version>,
+    /// This is the list of sub-charts which the helm chart depends on.
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+    /// This is set to true if the helm chart is deprecated.
+    #[serde(default)]
+    deprecated: bool,
+    /// This is the range of Kubernetes versions which the helm chart supports, e.g. '>=1.20.0-0'.
+    kube_version: Option<String>,
 }
 
 impl Chart {
@@ -20,11 +103,645 @@ impl Chart {
     pub(crate) fn version(&self) -> &Version {
         &self.version
     }
+
+    /// This is a getter for the helm chart API version.
+    pub(crate) fn api_version(&self) -> &str {
+        self.api_version.as_str()
+    }
+
+    /// This is a getter for the helm chart type. Helm treats charts without a type as
+    /// 'application' charts, and so does this.
+    pub(crate) fn chart_type(&self) -> &str {
+        self.chart_type.as_deref().unwrap_or(APPLICATION_CHART_TYPE)
+    }
+
+    /// This is a getter for the helm chart appVersion.
+    pub(crate) fn app_version(&self) -> Option<&Version> {
+        self.app_version.as_ref()
+    }
+
+    /// This describes the upgrade from this helm chart to the target helm chart in one line, e.g.
+    /// 'upgrading mayastor 2.4.1 -> 2.6.0 (appVersion 2.4.0 -> 2.6.0)'. The target's name is
+    /// included if it differs. An appVersion which is absent is shown as 'unknown', and the
+    /// appVersion is left out if both helm charts are without it.
+    pub(crate) fn summarize_upgrade(&self, target: &Chart) -> String {
+        let to = if self.name.eq(&target.name) {
+            target.version.to_string()
+        } else {
+            format!("{} {}", target.name, target.version)
+        };
+        let mut summary = format!("upgrading {} {} -> {to}", self.name, self.version);
+
+        if self.app_version.is_some() || target.app_version.is_some() {
+            let describe = |app_version: Option<&Version>| {
+                app_version.map_or_else(|| "unknown".to_string(), Version::to_string)
+            };
+            summary.push_str(
+                format!(
+                    " (appVersion {} -> {})",
+                    describe(self.app_version()),
+                    describe(target.app_version())
+                )
+                .as_str(),
+            );
+        }
+
+        summary
+    }
+
+    /// This returns the appVersion if it is a valid semver, and the chart version otherwise.
+    pub(crate) fn app_version_or_chart_version(&self) -> &Version {
+        self.app_version().unwrap_or(self.version())
+    }
+
+    /// This is a getter for the list of sub-chart dependencies of the helm chart.
+    pub(crate) fn dependencies(&self) -> &[Dependency] {
+        self.dependencies.as_slice()
+    }
+
+    /// This is a predicate for the deprecation status of the helm chart.
+    pub(crate) fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    /// This is a getter for the range of Kubernetes versions which the helm chart supports.
+    pub(crate) fn kube_version(&self) -> Option<&str> {
+        self.kube_version.as_deref()
+    }
+
+    /// This returns the sub-chart dependency with the input name, if it exists.
+    pub(crate) fn dependency(&self, name: &str) -> Option<&Dependency> {
+        self.dependencies.iter().find(|dep| dep.name().eq(name))
+    }
+
+    /// This returns the yaml key which the Core chart's values are nested under, in the values of
+    /// this helm chart, i.e. the alias or the name of the Core chart dependency. This is the Core
+    /// chart's name if the helm chart does not depend on the Core chart.
+    pub(crate) fn core_values_key(&self) -> &str {
+        self.dependency(CORE_CHART_NAME)
+            .map(Dependency::values_key)
+            .unwrap_or(CORE_CHART_NAME)
+    }
 }
 
-/// This is used to deserialize the values.yaml of the Core chart.
+/// This is used to deserialize the members of the 'dependencies' list in a Chart.yaml file.
 #[derive(Deserialize)]
+pub(crate) struct Dependency {
+    /// This is the name of the sub-chart.
+    name: String,
+    /// This is the name which the sub-chart is installed as, if it is not the sub-chart's name.
+    alias: Option<String>,
+    /// This is the version or version range of the sub-chart, see masterminds_constraint.
+    version: String,
+    /// This is the helm repository URL of the sub-chart.
+    repository: Option<String>,
+    /// This is the yaml path to a boolean helm value which enables the sub-chart.
+    condition: Option<String>,
+}
+
+impl Dependency {
+    /// This is a getter for the sub-chart name.
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// This is the yaml key which the sub-chart's values are nested under, i.e. the alias of the
+    /// sub-chart if it has one, and its name otherwise.
+    pub(crate) fn values_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(self.name())
+    }
+
+    /// This is a getter for the sub-chart version requirement.
+    pub(crate) fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
+    /// This checks if a version of the sub-chart satisfies the version requirement.
+    pub(crate) fn version_matches(&self, version: &Version) -> Result<bool> {
+        let ranges =
+            masterminds_constraint(self.version()).context(DependencyVersionConstraintParse {
+                name: self.name(),
+                constraint: self.version(),
+            })?;
+
+        Ok(ranges.iter().any(|range| range.matches(version)))
+    }
+
+    /// This is a getter for the sub-chart helm repository URL.
+    pub(crate) fn repository(&self) -> Option<&str> {
+        self.repository.as_deref()
+    }
+
+    /// This is a getter for the yaml path of the sub-chart's enable/disable toggle.
+    pub(crate) fn condition(&self) -> Option<&str> {
+        self.condition.as_deref()
+    }
+}
+
+/// This validates that the helm chart to upgrade to is the same helm chart as the one which is
+/// installed, albeit with a different version.
+pub(crate) fn validate_chart_name_match(installed: &Chart, target: &Chart) -> Result<()> {
+    ensure!(
+        installed.name().eq(target.name()),
+        ChartNameMismatch {
+            installed: installed.name().to_string(),
+            target: target.name().to_string()
+        }
+    );
+
+    Ok(())
+}
+
+/// This validates that the Kubernetes cluster's version is in the range of Kubernetes versions
+/// which the helm chart supports, see masterminds_constraint. Pre-releases of the cluster's
+/// version, e.g. '1.27.3-gke.100', count as the release.
+pub(crate) fn validate_kube_version(chart: &Chart, cluster: &Version) -> Result<()> {
+    let Some(constraint) = chart.kube_version() else {
+        return Ok(());
+    };
+    let cluster_release = Version::new(cluster.major, cluster.minor, cluster.patch);
+
+    let ranges = masterminds_constraint(constraint).context(KubeVersionConstraintParse {
+        constraint: constraint.to_string(),
+    })?;
+    ensure!(
+        ranges.iter().any(|range| range.matches(&cluster_release)),
+        KubeVersionUnsupported {
+            required: constraint.to_string(),
+            actual: cluster.clone(),
+        }
+    );
+
+    Ok(())
+}
+
+/// This parses a helm version constraint, which has the syntax of the Masterminds semver library,
+/// into its alternative ranges. Alternative ranges are separated by '||', and the comparisons of
+/// a range by commas or spaces, e.g. '>= 1.20.0-0, < 1.30.0 || 1.31.x'. Hyphen ranges, e.g.
+/// '1.20 - 1.29', are inclusive, and a version without an operator is an exact match.
+pub(crate) fn masterminds_constraint(constraint: &str) -> Result<Vec<VersionReq>, semver::Error> {
+    constraint
+        .split("||")
+        .map(|range| {
+            let mut comparators: Vec<String> = Vec::new();
+            let mut tokens = range
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|token| !token.is_empty())
+                .peekable();
+            while let Some(token) = tokens.next() {
+                // An operator may be separated from its version by a space, e.g. '>= 1.20'.
+                let mut comparator = token.to_string();
+                if comparator.chars().all(|c| "=<>~^!".contains(c)) {
+                    comparator.push_str(tokens.next().unwrap_or_default());
+                }
+                if tokens.peek().is_some_and(|next| next.eq(&"-")) {
+                    tokens.next();
+                    let upper = tokens.next().unwrap_or_default();
+                    comparators.push(format!(">={comparator}"));
+                    comparators.push(format!("<={upper}"));
+                    continue;
+                }
+                let version_start = comparator
+                    .find(|c: char| !"=<>~^!".contains(c))
+                    .unwrap_or(comparator.len());
+                let (operator, version) = comparator.split_at(version_start);
+                let version = version.trim_start_matches(['v', 'V']);
+                let operator = if operator.is_empty() { "=" } else { operator };
+                comparators.push(format!("{operator}{version}"));
+            }
+            VersionReq::parse(comparators.join(", ").as_str())
+        })
+        .collect()
+}
+
+/// This checks if the CPU pinning of the io-engine differs between the installed values and the
+/// upgrade values. A non-empty coreList overrides the cpuCount, so the cpuCount is only compared
+/// when neither of the values sets a coreList.
+pub(crate) fn io_engine_cpu_pinning_changed(installed: &CoreValues, target: &CoreValues) -> bool {
+    let installed_core_list = installed.io_engine_core_list().unwrap_or_default();
+    let target_core_list = target.io_engine_core_list().unwrap_or_default();
+
+    if installed_core_list.is_empty() && target_core_list.is_empty() {
+        return installed
+            .io_engine_cpu_count()
+            .ne(&target.io_engine_cpu_count());
+    }
+
+    installed_core_list.ne(target_core_list)
+}
+
+/// This checks if the resource requests or limits of the io-engine differ between the installed
+/// values and the upgrade values.
+pub(crate) fn resources_changed(installed: &CoreValues, target: &CoreValues) -> bool {
+    installed
+        .io_engine_resources()
+        .ne(&target.io_engine_resources())
+}
+
+/// This is a change to a runtime tunable of the io-engine, between the installed values and the
+/// upgrade values.
+#[derive(Debug, PartialEq)]
+pub(crate) enum IoEngineEnvChange {
+    /// The tunable is set in the installed values, and not in the target values.
+    Removed { key: String, old: String },
+    /// The tunable is set to different values in the installed values and the target values.
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for IoEngineEnvChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Removed { key, old } => write!(f, "{key}: '{old}' was removed"),
+            Self::Changed { key, old, new } => write!(f, "{key}: '{old}' -> '{new}'"),
+        }
+    }
+}
+
+/// This lists the io-engine runtime tunables which the upgrade values remove or change, compared
+/// to the installed values. Tunables which are only set in the upgrade values are not listed, as
+/// they do not change the behaviour which the installed release was configured for.
+pub(crate) fn io_engine_env_changes(
+    installed: &CoreValues,
+    target: &CoreValues,
+) -> Vec<IoEngineEnvChange> {
+    let target_env = target.io_engine_env();
+    installed
+        .io_engine_env()
+        .into_iter()
+        .filter_map(|(key, old)| match target_env.get(&key) {
+            None => Some(IoEngineEnvChange::Removed { key, old }),
+            Some(new) if new.ne(&old) => Some(IoEngineEnvChange::Changed {
+                key,
+                old,
+                new: new.clone(),
+            }),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// This checks if the nvme initiator timeouts differ between the installed values and the upgrade
+/// values.
+pub(crate) fn nvme_timeouts_changed(installed: &CoreValues, upgrade: &CoreValues) -> bool {
+    installed.csi_node_nvme().ne(upgrade.csi_node_nvme())
+}
+
+/// These are the io-engine log levels, in increasing order of verbosity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// This parses a log level, case-insensitively. This is None for unknown log levels.
+    fn parse(level: &str) -> Option<Self> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        };
+        write!(f, "{level}")
+    }
+}
+
+/// This is the most verbose log level of an io-engine log filter, which is a comma-separated list
+/// of a default level and module-level overrides, e.g. 'debug' for 'info,io_engine=debug'. This is
+/// None if any of the levels is unknown, because the filter cannot be ordered then.
+pub(crate) fn log_filter_verbosity(filter: &str) -> Option<LogLevel> {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.rsplit_once('=') {
+            Some((_, level)) => LogLevel::parse(level),
+            None => LogLevel::parse(directive),
+        })
+        .collect::<Option<Vec<LogLevel>>>()?
+        .into_iter()
+        .max()
+}
+
+/// This checks if the io-engine logs more verbosely with the target values than with the installed
+/// values. Log filters with unknown levels are not compared.
+pub(crate) fn io_engine_log_level_more_verbose(
+    installed: &CoreValues,
+    target: &CoreValues,
+) -> bool {
+    match (
+        log_filter_verbosity(installed.io_engine_log_level()),
+        log_filter_verbosity(target.io_engine_log_level()),
+    ) {
+        (Some(installed_level), Some(target_level)) => target_level > installed_level,
+        _ => false,
+    }
+}
+
+/// This deserializes a semver::Version, and falls back to None instead of failing, if the yaml
+/// value is not a valid semver. The appVersion in a Chart.yaml is not required to be a semver.
+fn deserialize_lenient_version<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Version>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+    let version_string = match value {
+        serde_yaml::Value::String(version) => version,
+        // Unquoted yaml values like 2.4 are parsed as numbers.
+        serde_yaml::Value::Number(version) => version.to_string(),
+        _ => return Ok(None),
+    };
+
+    Ok(Version::parse(version_string.trim()).ok())
+}
+
+/// This deserializes an optional yaml scalar as a String. Helm's '--set' flag may set an unquoted
+/// value like 2 or 30 as a number instead of a string.
+fn deserialize_lenient_string<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<serde_yaml::Value>::deserialize(deserializer)?;
+    Ok(value.as_ref().and_then(yaml_scalar_to_string))
+}
+
+/// This deserializes an optional yaml number as a u32, and accepts a quoted number too. A
+/// values file may quote a number, e.g. replicaCount: "3". An absent or null value is 0.
+fn deserialize_lenient_u32<'de, D>(deserializer: D) -> std::result::Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<serde_yaml::Value>::deserialize(deserializer)?;
+    let Some(number) = value.as_ref().and_then(yaml_scalar_to_string) else {
+        return Ok(0);
+    };
+
+    number
+        .trim()
+        .parse()
+        .map_err(|_| serde::de::Error::custom(format!("invalid value '{number}', expected a u32")))
+}
+
+/// This deserializes an optional yaml sequence of scalars as a list of Strings.
+fn deserialize_lenient_string_list<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<Vec<serde_yaml::Value>>::deserialize(deserializer)?;
+    Ok(value.map(|list| list.iter().filter_map(yaml_scalar_to_string).collect()))
+}
+
+/// This deserializes an optional yaml map of scalars as a map of Strings. Entries whose values
+/// aren't scalars are skipped.
+fn deserialize_lenient_string_map<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<BTreeMap<String, String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<BTreeMap<String, serde_yaml::Value>>::deserialize(deserializer)?;
+    Ok(value.map(|map| {
+        map.into_iter()
+            .filter_map(|(key, value)| match value {
+                serde_yaml::Value::Bool(boolean) => Some((key, boolean.to_string())),
+                value => yaml_scalar_to_string(&value).map(|value| (key, value)),
+            })
+            .collect()
+    }))
+}
+
+/// This is the JSON schema of the values which deserialize_lenient_string accepts.
+fn lenient_string_schema(_: &mut SchemaGenerator) -> Schema {
+    scalar_schema(vec![InstanceType::String, InstanceType::Number])
+}
+
+/// This is the JSON schema of the values which deserialize_lenient_u32 accepts.
+fn lenient_u32_schema(_: &mut SchemaGenerator) -> Schema {
+    scalar_schema(vec![InstanceType::Integer, InstanceType::String])
+}
+
+/// This is the JSON schema of the values which deserialize_lenient_string_list accepts.
+fn lenient_string_list_schema(generator: &mut SchemaGenerator) -> Schema {
+    let mut schema = scalar_schema(vec![InstanceType::Array]).into_object();
+    schema.array().items = Some(lenient_string_schema(generator).into());
+    Schema::Object(schema)
+}
+
+/// This is the JSON schema of the values which deserialize_lenient_string_map accepts.
+fn lenient_string_map_schema(_: &mut SchemaGenerator) -> Schema {
+    let mut schema = scalar_schema(vec![InstanceType::Object]).into_object();
+    schema.object().additional_properties = Some(Box::new(scalar_schema(vec![
+        InstanceType::String,
+        InstanceType::Number,
+        InstanceType::Boolean,
+    ])));
+    Schema::Object(schema)
+}
+
+/// This is a JSON schema for a value of any of the types, or null.
+fn scalar_schema(mut instance_types: Vec<InstanceType>) -> Schema {
+    instance_types.push(InstanceType::Null);
+    Schema::Object(SchemaObject {
+        instance_type: Some(instance_types.into()),
+        ..Default::default()
+    })
+}
+
+/// This converts a yaml string or number to a String.
+fn yaml_scalar_to_string(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(string) => Some(string.clone()),
+        serde_yaml::Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// This is used to deserialize the values.yaml of the Umbrella chart. The Core chart's values are
+/// nested under the alias of the Core chart dependency in the Umbrella chart's values, or under the
+/// Core chart's name if it has no alias.
+pub(crate) struct UmbrellaValues {
+    /// This contains the values of the Core chart, which is a dependency of the Umbrella chart.
+    core: CoreValues,
+}
+
+impl UmbrellaValues {
+    /// This deserializes the Core chart's values, which are nested under the yaml key in the
+    /// Umbrella chart's values. The yaml key path in deserialization errors includes the key.
+    pub(crate) fn from_value(mut value: serde_yaml::Value, core_values_key: &str) -> Result<Self> {
+        let core_values = value
+            .get_mut(core_values_key)
+            .map(mem::take)
+            .unwrap_or_default();
+        let core = deserialize_with_key_path(core_values).map_err(|error| match error {
+            Error::ValuesDeserialize { path, source } => Error::ValuesDeserialize {
+                path: format!("{core_values_key}.{path}"),
+                source,
+            },
+            error => error,
+        })?;
+
+        Ok(Self { core })
+    }
+
+    /// This is a getter for the container image tag of the Core chart, installed as a dependency
+    /// of the Umbrella chart.
+    pub(crate) fn image_tag(&self) -> &str {
+        self.core.image_tag()
+    }
+
+    /// This is a getter for the io-engine container image tag of the Core chart, installed as a
+    /// dependency of the Umbrella chart.
+    pub(crate) fn io_engine_image_tag(&self) -> &str {
+        self.core.io_engine_image_tag()
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pods' logLevel of the Core chart, installed
+    /// as a dependency of the Umbrella chart.
+    pub(crate) fn io_engine_log_level(&self) -> &str {
+        self.core.io_engine_log_level()
+    }
+
+    /// This is a getter for the number of etcd replicas of the Core chart, installed as a
+    /// dependency of the Umbrella chart.
+    pub(crate) fn etcd_replica_count(&self) -> u32 {
+        self.core.etcd_replica_count()
+    }
+
+    /// This is a getter for the full container image reference of the Core chart, installed as
+    /// a dependency of the Umbrella chart.
+    pub(crate) fn image_full_reference(&self) -> String {
+        self.core.image_full_reference()
+    }
+
+    /// This is a getter for the container image pull policy of the Core chart, installed as a
+    /// dependency of the Umbrella chart.
+    pub(crate) fn image_pull_policy(&self) -> Option<&str> {
+        self.core.image_pull_policy()
+    }
+
+    /// This is a getter for the container image pull secrets of the Core chart, installed as a
+    /// dependency of the Umbrella chart.
+    pub(crate) fn image_pull_secrets(&self) -> Vec<&str> {
+        self.core.image_pull_secrets()
+    }
+}
+
+/// This is the values.yaml of either of the Umbrella chart and the Core chart.
+pub(crate) enum LoadedValues {
+    Umbrella(UmbrellaValues),
+    Core(CoreValues),
+}
+
+impl LoadedValues {
+    /// This is a getter for the container image tag of the Core chart.
+    pub(crate) fn image_tag(&self) -> &str {
+        match self {
+            Self::Umbrella(values) => values.image_tag(),
+            Self::Core(values) => values.image_tag(),
+        }
+    }
+
+    /// This is a getter for the io-engine container image tag of the Core chart.
+    pub(crate) fn io_engine_image_tag(&self) -> &str {
+        match self {
+            Self::Umbrella(values) => values.io_engine_image_tag(),
+            Self::Core(values) => values.io_engine_image_tag(),
+        }
+    }
+
+    /// This is a getter for the full container image reference of the Core chart.
+    pub(crate) fn image_full_reference(&self) -> String {
+        match self {
+            Self::Umbrella(values) => values.image_full_reference(),
+            Self::Core(values) => values.image_full_reference(),
+        }
+    }
+
+    /// This is a getter for the container image pull policy of the Core chart.
+    pub(crate) fn image_pull_policy(&self) -> Option<&str> {
+        match self {
+            Self::Umbrella(values) => values.image_pull_policy(),
+            Self::Core(values) => values.image_pull_policy(),
+        }
+    }
+
+    /// This is a getter for the container image pull secrets of the Core chart.
+    pub(crate) fn image_pull_secrets(&self) -> Vec<&str> {
+        match self {
+            Self::Umbrella(values) => values.image_pull_secrets(),
+            Self::Core(values) => values.image_pull_secrets(),
+        }
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pods' logLevel of the Core chart.
+    pub(crate) fn io_engine_log_level(&self) -> &str {
+        match self {
+            Self::Umbrella(values) => values.io_engine_log_level(),
+            Self::Core(values) => values.io_engine_log_level(),
+        }
+    }
+
+    /// This is a getter for the number of etcd replicas of the Core chart.
+    pub(crate) fn etcd_replica_count(&self) -> u32 {
+        match self {
+            Self::Umbrella(values) => values.etcd_replica_count(),
+            Self::Core(values) => values.etcd_replica_count(),
+        }
+    }
+}
+
+/// This deserializes helm values yaml as the values of the Umbrella chart if the Core chart's
+/// values are nested under the yaml key, and as the values of the Core chart otherwise. The yaml
+/// key is that of the Core chart dependency, see Chart::core_values_key().
+pub(crate) fn detect_and_load(yaml: &str, core_values_key: &str) -> Result<LoadedValues> {
+    let value: serde_yaml::Value = serde_yaml::from_str(yaml).context(YamlParseFromSlice {
+        input_yaml: yaml.to_string(),
+    })?;
+    let is_umbrella = value
+        .as_mapping()
+        .is_some_and(|mapping| mapping.contains_key(core_values_key));
+
+    if is_umbrella {
+        UmbrellaValues::from_value(value, core_values_key).map(LoadedValues::Umbrella)
+    } else {
+        deserialize_with_key_path(value).map(LoadedValues::Core)
+    }
+}
+
+/// This is used to deserialize the values.yaml of the Core chart.
+#[derive(Deserialize, JsonSchema)]
 pub(crate) struct CoreValues {
+    /// This is the yaml object which contains the configuration shared by the helm chart's
+    /// components, e.g. the container image pull secrets.
+    #[serde(default)]
+    base: Base,
     /// This is the yaml object which contains values for the container image registry, repository,
     /// tag, etc.
     image: Image,
@@ -35,14 +752,70 @@ pub(crate) struct CoreValues {
     eventing: Eventing,
     /// This contains Kubernetes CSI sidecar container image details.
     csi: Csi,
+    /// This contains the configuration for the etcd StatefulSet.
+    #[serde(default)]
+    etcd: Etcd,
+    /// This contains the configuration for the control-plane agents.
+    #[serde(default)]
+    agents: Agents,
+    /// This contains the configuration for the control-plane APIs.
+    #[serde(default)]
+    apis: Apis,
+    /// This contains the configuration for the bundled loki logging stack.
+    #[serde(default, rename(deserialize = "loki-stack"))]
+    loki_stack: LokiStack,
 }
 
 impl CoreValues {
+    /// This decides if a version of the Core helm chart is expected to have the thin-provisioning
+    /// options. Their absence in older helm charts is expected, whereas it is likely to be a
+    /// misconfiguration in newer ones. Pre-releases count as the release they precede.
+    pub(crate) fn supports_thin_provisioning(chart_version: &Version) -> bool {
+        let release = Version::new(
+            chart_version.major,
+            chart_version.minor,
+            chart_version.patch,
+        );
+        release.ge(&THIN_PROVISIONING_MIN_VERSION)
+    }
+
     /// This is a getter for the container image tag of the Core chart.
     pub(crate) fn image_tag(&self) -> &str {
         self.image.tag()
     }
 
+    /// This is a getter for the full container image reference of the Core chart, i.e.
+    /// registry/repo:tag.
+    pub(crate) fn image_full_reference(&self) -> String {
+        self.image.full_reference()
+    }
+
+    /// This is a getter for the container image registry of the Core chart.
+    pub(crate) fn image_registry(&self) -> Option<&str> {
+        self.image.registry()
+    }
+
+    /// This is a getter for the container image repository of the Core chart.
+    pub(crate) fn image_repo(&self) -> Option<&str> {
+        self.image.repo()
+    }
+
+    /// This is a getter for the container image name of the Core chart, i.e. registry/repo.
+    pub(crate) fn image_name(&self) -> String {
+        self.image.name()
+    }
+
+    /// This is a getter for the container image pull policy of the Core chart.
+    pub(crate) fn image_pull_policy(&self) -> Option<&str> {
+        self.image.pull_policy()
+    }
+
+    /// This is a getter for the names of the container image pull secrets of the Core chart. There
+    /// are none unless 'base.imagePullSecrets.enabled' is set.
+    pub(crate) fn image_pull_secrets(&self) -> Vec<&str> {
+        self.base.image_pull_secrets()
+    }
+
     /// This is a getter for the control-plane repoTag image tag set on a helm chart.
     pub(crate) fn control_plane_repotag(&self) -> &str {
         self.image.control_plane_repotag()
@@ -53,6 +826,15 @@ impl CoreValues {
         self.image.data_plane_repotag()
     }
 
+    /// This is a getter for the container image tag of the io-engine DaemonSet Pods' io-engine
+    /// container. The data-plane repoTag takes precedence over the image tag, if set.
+    pub(crate) fn io_engine_image_tag(&self) -> &str {
+        match self.data_plane_repotag() {
+            "" => self.image_tag(),
+            repotag => repotag,
+        }
+    }
+
     /// This is a getter for the extensions repoTag image tag set on a helm chart.
     pub(crate) fn extensions_repotag(&self) -> &str {
         self.image.extensions_repotag()
@@ -63,6 +845,128 @@ impl CoreValues {
         self.io_engine.log_level()
     }
 
+    /// This is a getter for the io-engine DaemonSet Pods' coreList.
+    pub(crate) fn io_engine_core_list(&self) -> Option<&[String]> {
+        self.io_engine.core_list()
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pods' cpuCount.
+    pub(crate) fn io_engine_cpu_count(&self) -> Option<&str> {
+        self.io_engine.cpu_count()
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pods' resource requests and limits.
+    pub(crate) fn io_engine_resources(&self) -> Option<&Resources> {
+        self.io_engine.resources()
+    }
+
+    /// This is a getter for the io-engine's runtime tunables. These are the environment variables
+    /// in io_engine.env, and the options in io_engine.envcontext, keyed by their flag, e.g.
+    /// '--iova-mode'.
+    pub(crate) fn io_engine_env(&self) -> BTreeMap<String, String> {
+        self.io_engine.env()
+    }
+
+    /// This is a getter for the core agent's tracing logLevel. This is None if the logLevel is
+    /// absent, as the helm chart's default may change between versions.
+    pub(crate) fn core_agent_log_level(&self) -> Option<&str> {
+        self.agents.core_log_level()
+    }
+
+    /// This is a getter for the number of REST API replicas, i.e. 'apis.rest.replicaCount'. This
+    /// is the only control-plane component whose replicas are configurable, the core agent always
+    /// has one replica. This is None if the replicaCount is absent.
+    pub(crate) fn api_rest_replica_count(&self) -> Option<u32> {
+        self.apis.rest_replica_count()
+    }
+
+    /// This is a getter for the installation setting of the bundled loki logging stack. This is
+    /// None if the helm values have no logging stack section.
+    pub(crate) fn loki_enabled(&self) -> Option<bool> {
+        self.loki_stack.enabled()
+    }
+
+    /// This is a getter for the configuration of the control-plane agents.
+    pub(crate) fn agents(&self) -> &Agents {
+        &self.agents
+    }
+
+    /// This is a getter for the thin-provisioning pool commitment of the core agent.
+    pub(crate) fn thin_pool_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.agents
+            .core_thin_pool_commitment(chart_version, values_source)
+    }
+
+    /// This is a getter for the thin-provisioning volume commitment of the core agent.
+    pub(crate) fn thin_volume_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.agents
+            .core_thin_volume_commitment(chart_version, values_source)
+    }
+
+    /// This is a getter for the thin-provisioning initial volume commitment of the core agent.
+    pub(crate) fn thin_volume_commitment_initial(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.agents
+            .core_thin_volume_commitment_initial(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning pool commitment of the core agent.
+    pub(crate) fn thin_pool_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.agents
+            .core_thin_pool_commitment_parsed(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning volume commitment of the core agent.
+    pub(crate) fn thin_volume_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.agents
+            .core_thin_volume_commitment_parsed(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning initial volume commitment of the core
+    /// agent.
+    pub(crate) fn thin_volume_commitment_initial_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.agents
+            .core_thin_volume_commitment_initial_parsed(chart_version, values_source)
+    }
+
+    /// This is a getter for the number of etcd replicas.
+    pub(crate) fn etcd_replica_count(&self) -> u32 {
+        self.etcd.replica_count()
+    }
+
+    /// This is a getter for the StorageClass of the etcd StatefulSet's PersistentVolumeClaims.
+    pub(crate) fn etcd_storage_class(&self) -> &str {
+        self.etcd.storage_class()
+    }
+
+    /// This is a getter for the size of the etcd StatefulSet's PersistentVolumeClaims.
+    pub(crate) fn etcd_persistence_size(&self) -> &str {
+        self.etcd.persistence_size()
+    }
+
     /// This is a getter for the eventing installation enable/disable state.
     pub(crate) fn eventing_enabled(&self) -> bool {
         self.eventing.enabled()
@@ -92,19 +996,31 @@ impl CoreValues {
     pub(crate) fn csi_node_driver_registrar_image_tag(&self) -> &str {
         self.csi.node_driver_registrar_image_tag()
     }
+
+    /// This is a getter for the nvme initiator timeouts of the csi-node plugin, which connects to
+    /// the io-engine's nvme targets.
+    pub(crate) fn csi_node_nvme(&self) -> &Nvme {
+        self.csi.node_nvme()
+    }
 }
 
 /// This is used to deserialize the yaml object "image", which contains details required for pulling
 /// container images.
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct Image {
+    /// The container image registry.
+    registry: Option<String>,
+    /// The container image repository, i.e. the registry's namespace.
+    repo: Option<String>,
     /// The container image tag.
     tag: String,
     /// This contains image tags set based on which PRODUCT repository the microservice originates
     /// from.
     #[serde(default)]
     repo_tags: RepoTags,
+    /// The container image pull policy, e.g. IfNotPresent, Always.
+    pull_policy: Option<String>,
 }
 
 impl Image {
@@ -113,6 +1029,41 @@ impl Image {
         self.tag.as_str()
     }
 
+    /// This is a getter for the container image registry.
+    pub(crate) fn registry(&self) -> Option<&str> {
+        self.registry.as_deref()
+    }
+
+    /// This is a getter for the container image repository.
+    pub(crate) fn repo(&self) -> Option<&str> {
+        self.repo.as_deref()
+    }
+
+    /// This composes the container image name registry/repo, i.e. the image reference without
+    /// the tag. The registry segment is left out if the registry is not set.
+    pub(crate) fn name(&self) -> String {
+        let repo = self.repo.as_deref().unwrap_or_default();
+        match self.registry.as_deref() {
+            Some(registry) => format!("{registry}/{repo}"),
+            None => repo.to_string(),
+        }
+    }
+
+    /// This composes the container image reference registry/repo:tag. The registry segment is
+    /// left out if the registry is not set.
+    pub(crate) fn full_reference(&self) -> String {
+        let repo = self.repo.as_deref().unwrap_or_default();
+        match self.registry.as_deref() {
+            Some(registry) => format!("{registry}/{repo}:{}", self.tag),
+            None => format!("{repo}:{}", self.tag),
+        }
+    }
+
+    /// This is a getter for the container image pull policy.
+    pub(crate) fn pull_policy(&self) -> Option<&str> {
+        self.pull_policy.as_deref()
+    }
+
     /// This is a getter for the control-plane repoTag set on a helm chart.
     pub(crate) fn control_plane_repotag(&self) -> &str {
         self.repo_tags.control_plane()
@@ -129,9 +1080,58 @@ impl Image {
     }
 }
 
+/// This is used to deserialize the yaml object 'base', which contains the configuration shared by
+/// the helm chart's components.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct Base {
+    /// The container image pull secrets.
+    #[serde(default)]
+    image_pull_secrets: ImagePullSecrets,
+}
+
+impl Base {
+    /// This is a getter for the names of the container image pull secrets, if they are enabled.
+    pub(crate) fn image_pull_secrets(&self) -> Vec<&str> {
+        self.image_pull_secrets.names()
+    }
+}
+
+/// This is used to deserialize the yaml object 'base.imagePullSecrets'.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct ImagePullSecrets {
+    /// This enables the use of the pull secrets for the helm chart's container images.
+    #[serde(default)]
+    enabled: bool,
+    /// The Secrets in the helm release's namespace, which are used to pull container images.
+    #[serde(default)]
+    secrets: Vec<SecretName>,
+}
+
+impl ImagePullSecrets {
+    /// This is a getter for the names of the pull secrets. There are none if they are disabled.
+    pub(crate) fn names(&self) -> Vec<&str> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        self.secrets
+            .iter()
+            .map(|secret| secret.name.as_str())
+            .collect()
+    }
+}
+
+/// This is used to deserialize a reference to a Secret by its name, e.g. an element of
+/// 'base.imagePullSecrets.secrets'.
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct SecretName {
+    /// The name of the Secret.
+    name: String,
+}
+
 /// This contains image tags for PRODUCT components based on the repository for the specific
 /// component.
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, JsonSchema, Default)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct RepoTags {
     /// This member of repoTags is used to set image tags for components from the control-plane
@@ -162,11 +1162,29 @@ impl RepoTags {
 
 /// This is used to deserialize the yaml object "io_engine", which contains configuration for the
 /// io-engine DaemonSet.
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct IoEngine {
     /// Tracing Loglevel details for the io-engine DaemonSet Pods.
     log_level: String,
+    /// The list of cores the io-engine is pinned to. This overrides cpuCount, if not empty.
+    #[serde(default, deserialize_with = "deserialize_lenient_string_list")]
+    #[schemars(schema_with = "lenient_string_list_schema")]
+    core_list: Option<Vec<String>>,
+    /// The number of cores the io-engine uses.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    cpu_count: Option<String>,
+    /// The resource requests and limits for the io-engine container.
+    resources: Option<Resources>,
+    /// The environment variables for the io-engine container.
+    #[serde(default, deserialize_with = "deserialize_lenient_string_map")]
+    #[schemars(schema_with = "lenient_string_map_schema")]
+    env: Option<BTreeMap<String, String>>,
+    /// The DPDK environment context options for the io-engine, e.g. 'iova-mode=pa'.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    envcontext: Option<String>,
 }
 
 impl IoEngine {
@@ -174,14 +1192,590 @@ impl IoEngine {
     pub(crate) fn log_level(&self) -> &str {
         self.log_level.as_str()
     }
-}
 
-/// This is used to deserialize the yaml object 'eventing', v2.3.0 has it disabled by default,
-/// the default thereafter has it enabled.
-#[derive(Deserialize, Default)]
-pub(crate) struct Eventing {
-    // This value is defaulted to 'false' when 'Eventing' is absent in the yaml.
-    // This works fine because we don't use the serde deserialized values during
+    /// This is a getter for the io-engine DaemonSet Pod's coreList.
+    pub(crate) fn core_list(&self) -> Option<&[String]> {
+        self.core_list.as_deref()
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pod's cpuCount.
+    pub(crate) fn cpu_count(&self) -> Option<&str> {
+        self.cpu_count.as_deref()
+    }
+
+    /// This is a getter for the io-engine DaemonSet Pod's resource requests and limits.
+    pub(crate) fn resources(&self) -> Option<&Resources> {
+        self.resources.as_ref()
+    }
+
+    /// This is a getter for the io-engine's environment variables and envcontext options. The
+    /// envcontext options are whitespace-separated 'key=value' or 'key' options, with or without
+    /// the leading '--'.
+    pub(crate) fn env(&self) -> BTreeMap<String, String> {
+        let mut env = self.env.clone().unwrap_or_default();
+        for option in self
+            .envcontext
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+        {
+            let option = option.trim_start_matches('-');
+            if option.is_empty() {
+                continue;
+            }
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            env.insert(format!("--{key}"), value.to_string());
+        }
+        env
+    }
+}
+
+/// This is used to deserialize the yaml object "resources", which contains the resource requests
+/// and limits of a container. Either of requests and limits may be absent.
+#[derive(Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub(crate) struct Resources {
+    /// The resources that the container is guaranteed.
+    #[serde(default)]
+    requests: ResourceList,
+    /// The resources that the container is not allowed to exceed.
+    #[serde(default)]
+    limits: ResourceList,
+}
+
+impl Resources {
+    /// This is a getter for the resource requests.
+    pub(crate) fn requests(&self) -> &ResourceList {
+        &self.requests
+    }
+
+    /// This is a getter for the resource limits.
+    pub(crate) fn limits(&self) -> &ResourceList {
+        &self.limits
+    }
+}
+
+/// This is used to deserialize the resource quantities in a resource requests or limits yaml
+/// object. Any of the quantities may be absent.
+#[derive(Deserialize, JsonSchema, Debug, Default, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct ResourceList {
+    /// The CPU quantity.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    cpu: Option<String>,
+    /// The memory quantity.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    memory: Option<String>,
+    /// The 2MiB hugepages quantity.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    hugepages2_mi: Option<String>,
+}
+
+impl ResourceList {
+    /// This is a getter for the CPU quantity.
+    pub(crate) fn cpu(&self) -> Option<&str> {
+        self.cpu.as_deref()
+    }
+
+    /// This is a getter for the memory quantity.
+    pub(crate) fn memory(&self) -> Option<&str> {
+        self.memory.as_deref()
+    }
+
+    /// This is a getter for the 2MiB hugepages quantity.
+    pub(crate) fn hugepages_2mi(&self) -> Option<&str> {
+        self.hugepages2_mi.as_deref()
+    }
+}
+
+/// This is used to deserialize the yaml object 'agents', which contains the configuration for the
+/// control-plane agents.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct Agents {
+    /// This contains the configuration for the core agent.
+    #[serde(default)]
+    core: Core,
+}
+
+impl Agents {
+    /// This is true if the core agent has the thin-provisioning options, i.e. the
+    /// agents.core.capacity yaml object.
+    pub(crate) fn has_thin_provisioning_options(&self) -> bool {
+        self.core.capacity.is_some()
+    }
+
+    /// This is a getter for the core agent's tracing logLevel.
+    pub(crate) fn core_log_level(&self) -> Option<&str> {
+        self.core.log_level()
+    }
+
+    /// This is a getter for the parsed per-pool and per-class thin-provisioning pool commitments
+    /// of the core agent, keyed by the pool or class name.
+    pub(crate) fn core_thin_pool_commitment_overrides_parsed(
+        &self,
+    ) -> Result<BTreeMap<String, Percentage>> {
+        self.core.thin_pool_commitment_overrides_parsed()
+    }
+
+    /// This is a getter for the thin-provisioning pool commitment of the core agent.
+    pub(crate) fn core_thin_pool_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.core.thin_pool_commitment(chart_version, values_source)
+    }
+
+    /// This is a getter for the thin-provisioning volume commitment of the core agent.
+    pub(crate) fn core_thin_volume_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.core
+            .thin_volume_commitment(chart_version, values_source)
+    }
+
+    /// This is a getter for the thin-provisioning initial volume commitment of the core agent.
+    pub(crate) fn core_thin_volume_commitment_initial(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.core
+            .thin_volume_commitment_initial(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning pool commitment of the core agent.
+    pub(crate) fn core_thin_pool_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.core
+            .thin_pool_commitment_parsed(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning volume commitment of the core agent.
+    pub(crate) fn core_thin_volume_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.core
+            .thin_volume_commitment_parsed(chart_version, values_source)
+    }
+
+    /// This is a getter for the parsed thin-provisioning initial volume commitment of the core
+    /// agent.
+    pub(crate) fn core_thin_volume_commitment_initial_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.core
+            .thin_volume_commitment_initial_parsed(chart_version, values_source)
+    }
+}
+
+/// This is used to deserialize the yaml object 'agents.core'.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct Core {
+    /// Tracing Loglevel details for the core agent.
+    log_level: Option<String>,
+    /// This contains the capacity options of the core agent. This is absent in older helm charts.
+    capacity: Option<Capacity>,
+}
+
+impl Core {
+    /// This is a getter for the core agent's tracing logLevel.
+    pub(crate) fn log_level(&self) -> Option<&str> {
+        self.log_level.as_deref()
+    }
+
+    /// This is a getter for the thin-provisioning options. Returns an error if the the
+    /// agents.core.capacity yaml object is absent. The chart version and the source of the helm
+    /// values are for the error message.
+    fn thin(&self, chart_version: &Version, values_source: &str) -> Result<&Thin> {
+        self.capacity.as_ref().map(|capacity| &capacity.thin).ok_or(
+            ThinProvisioningOptionsAbsent {
+                chart_version: chart_version.clone(),
+                values_source,
+            }
+            .build(),
+        )
+    }
+
+    /// This is a getter for the parsed per-pool and per-class thin-provisioning pool commitments,
+    /// keyed by the pool or class name. Helm values without the agents.core.capacity yaml object
+    /// have no overrides.
+    fn thin_pool_commitment_overrides_parsed(&self) -> Result<BTreeMap<String, Percentage>> {
+        self.capacity.as_ref().map_or(
+            Ok(BTreeMap::new()),
+            Capacity::pool_commitment_overrides_parsed,
+        )
+    }
+
+    /// This is a getter for the thin-provisioning pool commitment.
+    pub(crate) fn thin_pool_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.thin(chart_version, values_source)?.pool_commitment()
+    }
+
+    /// This is a getter for the thin-provisioning volume commitment.
+    pub(crate) fn thin_volume_commitment(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.thin(chart_version, values_source)?.volume_commitment()
+    }
+
+    /// This is a getter for the thin-provisioning initial volume commitment.
+    pub(crate) fn thin_volume_commitment_initial(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<&str> {
+        self.thin(chart_version, values_source)?
+            .volume_commitment_initial()
+    }
+
+    /// This is a getter for the parsed thin-provisioning pool commitment.
+    pub(crate) fn thin_pool_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.thin(chart_version, values_source)?
+            .pool_commitment_parsed()
+    }
+
+    /// This is a getter for the parsed thin-provisioning volume commitment.
+    pub(crate) fn thin_volume_commitment_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.thin(chart_version, values_source)?
+            .volume_commitment_parsed()
+    }
+
+    /// This is a getter for the parsed thin-provisioning initial volume commitment.
+    pub(crate) fn thin_volume_commitment_initial_parsed(
+        &self,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Percentage> {
+        self.thin(chart_version, values_source)?
+            .volume_commitment_initial_parsed()
+    }
+}
+
+/// This is used to deserialize the yaml object 'agents.core.capacity'.
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct Capacity {
+    /// This contains the thin-provisioning options.
+    thin: Thin,
+    /// This contains the thin-provisioning commitment overrides of individual pools or pool
+    /// classes, keyed by the pool or class name. This is absent in older helm charts.
+    #[serde(default)]
+    overrides: BTreeMap<String, ThinOverride>,
+}
+
+impl Capacity {
+    /// This is the thin-provisioning options which apply to the pool or pool class with the given
+    /// name. The override's commitments take the place of the global ones, and the global
+    /// options remain the default for everything which the override does not set.
+    pub(crate) fn override_for(&self, name: &str) -> Thin {
+        let mut thin = self.thin.clone();
+        if let Some(thin_override) = self.overrides.get(name) {
+            if let Some(pool_commitment) = thin_override.pool_commitment.as_ref() {
+                thin.pool_commitment = Some(pool_commitment.clone());
+            }
+            if let Some(volume_commitment) = thin_override.volume_commitment.as_ref() {
+                thin.volume_commitment = Some(volume_commitment.clone());
+            }
+        }
+        thin
+    }
+
+    /// This is the parsed pool commitment of every override, keyed by the pool or class name.
+    /// Overrides which only set the volumeCommitment have the global poolCommitment.
+    fn pool_commitment_overrides_parsed(&self) -> Result<BTreeMap<String, Percentage>> {
+        self.validate_overrides()?;
+        self.overrides
+            .keys()
+            .map(|name| {
+                let pool_commitment = self.override_for(name).pool_commitment_parsed()?;
+                Ok((name.clone(), pool_commitment))
+            })
+            .collect()
+    }
+
+    /// This validates that the commitments of every override are valid Percentages.
+    fn validate_overrides(&self) -> Result<()> {
+        for (name, thin_override) in &self.overrides {
+            let commitments = [
+                ("poolCommitment", thin_override.pool_commitment.as_deref()),
+                (
+                    "volumeCommitment",
+                    thin_override.volume_commitment.as_deref(),
+                ),
+            ];
+            for (field, value) in commitments {
+                let Some(value) = value else {
+                    continue;
+                };
+                value.parse::<Percentage>().map_err(|_| {
+                    ThinCommitmentOverrideParse {
+                        name: name.as_str(),
+                        field,
+                        value,
+                    }
+                    .build()
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// This is used to deserialize the entries of the yaml object 'agents.core.capacity.overrides'.
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct ThinOverride {
+    /// The pool commitment limit of the pool or pool class. Defaults to the global
+    /// poolCommitment.
+    pool_commitment: Option<String>,
+    /// The volume commitment of the pool or pool class. Defaults to the global volumeCommitment.
+    volume_commitment: Option<String>,
+}
+
+/// This is used to deserialize the yaml object 'agents.core.capacity.thin'.
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct Thin {
+    /// The allowed pool commitment limit when dealing with thin provisioned volumes.
+    pool_commitment: Option<String>,
+    /// The free space percentage of the volume size, that each replica pool must have when
+    /// creating replicas for an existing volume.
+    volume_commitment: Option<String>,
+    /// Same as the volume commitment, but applicable only when creating replicas for a new volume.
+    volume_commitment_initial: Option<String>,
+}
+
+impl Thin {
+    /// This is a getter for poolCommitment.
+    pub(crate) fn pool_commitment(&self) -> Result<&str> {
+        thin_subfield(self.pool_commitment.as_deref(), "poolCommitment")
+    }
+
+    /// This is a getter for volumeCommitment.
+    pub(crate) fn volume_commitment(&self) -> Result<&str> {
+        thin_subfield(self.volume_commitment.as_deref(), "volumeCommitment")
+    }
+
+    /// This is a getter for volumeCommitmentInitial.
+    pub(crate) fn volume_commitment_initial(&self) -> Result<&str> {
+        thin_subfield(
+            self.volume_commitment_initial.as_deref(),
+            "volumeCommitmentInitial",
+        )
+    }
+
+    /// This is a getter for poolCommitment, parsed as a Percentage.
+    pub(crate) fn pool_commitment_parsed(&self) -> Result<Percentage> {
+        parse_thin_commitment("poolCommitment", self.pool_commitment()?)
+    }
+
+    /// This is a getter for volumeCommitment, parsed as a Percentage.
+    pub(crate) fn volume_commitment_parsed(&self) -> Result<Percentage> {
+        parse_thin_commitment("volumeCommitment", self.volume_commitment()?)
+    }
+
+    /// This is a getter for volumeCommitmentInitial, parsed as a Percentage.
+    pub(crate) fn volume_commitment_initial_parsed(&self) -> Result<Percentage> {
+        parse_thin_commitment("volumeCommitmentInitial", self.volume_commitment_initial()?)
+    }
+}
+
+/// This returns the value of a thin-provisioning option, or an error which names the option's yaml
+/// key if it is absent.
+fn thin_subfield<'a>(value: Option<&'a str>, field: &'static str) -> Result<&'a str> {
+    value.ok_or(ThinSubfieldAbsent { field }.build())
+}
+
+/// This parses a thin-provisioning commitment value, and reports the yaml key of the value if it
+/// is not a valid Percentage.
+fn parse_thin_commitment(field: &'static str, value: &str) -> Result<Percentage> {
+    value.parse::<Percentage>().map_err(|_| {
+        ThinCommitmentParse {
+            field,
+            value: value.to_string(),
+        }
+        .build()
+    })
+}
+
+/// This is a non-negative whole number percentage, e.g. the "250%" in a thin-provisioning
+/// commitment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Percentage(u32);
+
+impl FromStr for Percentage {
+    type Err = Error;
+
+    /// This parses percentage strings like "250%" and "250". Surrounding whitespace is ignored.
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        trimmed
+            .strip_suffix('%')
+            .unwrap_or(trimmed)
+            .trim_end()
+            .parse::<u32>()
+            .map(Self)
+            .map_err(|_| {
+                PercentageParse {
+                    value: s.to_string(),
+                }
+                .build()
+            })
+    }
+}
+
+impl Percentage {
+    /// This is the percentage which the numerator is of the denominator, rounded up, e.g. 251%
+    /// for 1001 of 400. This is None if the denominator is 0.
+    pub(crate) fn from_ratio(numerator: u64, denominator: u64) -> Option<Self> {
+        if denominator == 0 {
+            return None;
+        }
+        let (numerator, denominator) = (u128::from(numerator) * 100, u128::from(denominator));
+        let percent = numerator / denominator + u128::from(numerator % denominator != 0);
+        Some(Self(u32::try_from(percent).unwrap_or(u32::MAX)))
+    }
+
+    /// This is the percentage of a number of bytes, rounded down, e.g. 250 for 250% of 100.
+    pub(crate) fn of(&self, bytes: u64) -> u64 {
+        let bytes = u128::from(bytes) * u128::from(self.0) / 100;
+        u64::try_from(bytes).unwrap_or(u64::MAX)
+    }
+}
+
+impl fmt::Display for Percentage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// This is used to deserialize the yaml object 'apis', which contains the configuration for the
+/// control-plane APIs.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct Apis {
+    /// This contains the configuration for the REST API Deployment.
+    #[serde(default)]
+    rest: ApiRest,
+}
+
+impl Apis {
+    /// This is a getter for the number of REST API replicas.
+    pub(crate) fn rest_replica_count(&self) -> Option<u32> {
+        self.rest.replica_count
+    }
+}
+
+/// This is used to deserialize the yaml object 'apis.rest'.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct ApiRest {
+    /// The number of REST API replicas.
+    replica_count: Option<u32>,
+}
+
+/// This is used to deserialize the yaml object 'loki-stack', which contains the configuration for
+/// the bundled loki logging stack.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct LokiStack {
+    /// This enables the loki logging stack.
+    enabled: Option<bool>,
+}
+
+impl LokiStack {
+    /// This is a predicate for the installation setting of the loki logging stack.
+    pub(crate) fn enabled(&self) -> Option<bool> {
+        self.enabled
+    }
+}
+
+/// This is used to deserialize the yaml object 'etcd', which contains the configuration for the
+/// etcd StatefulSet.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct Etcd {
+    /// The number of etcd replicas.
+    #[serde(default, deserialize_with = "deserialize_lenient_u32")]
+    #[schemars(schema_with = "lenient_u32_schema")]
+    replica_count: u32,
+    /// The PersistentVolumeClaim configuration for the etcd StatefulSet.
+    #[serde(default)]
+    persistence: EtcdPersistence,
+}
+
+impl Etcd {
+    /// This is a getter for the number of etcd replicas.
+    pub(crate) fn replica_count(&self) -> u32 {
+        self.replica_count
+    }
+
+    /// This is a getter for the StorageClass of the etcd PersistentVolumeClaims.
+    pub(crate) fn storage_class(&self) -> &str {
+        self.persistence.storage_class()
+    }
+
+    /// This is a getter for the size of the etcd PersistentVolumeClaims.
+    pub(crate) fn persistence_size(&self) -> &str {
+        self.persistence.size()
+    }
+}
+
+/// This is used to deserialize the yaml object 'etcd.persistence'.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub(crate) struct EtcdPersistence {
+    /// The StorageClass for the etcd PersistentVolumeClaims.
+    #[serde(default)]
+    storage_class: String,
+    /// The size of the etcd PersistentVolumeClaims.
+    #[serde(default)]
+    size: String,
+}
+
+impl EtcdPersistence {
+    /// This is a getter for the StorageClass.
+    pub(crate) fn storage_class(&self) -> &str {
+        self.storage_class.as_str()
+    }
+
+    /// This is a getter for the PersistentVolumeClaim size.
+    pub(crate) fn size(&self) -> &str {
+        self.size.as_str()
+    }
+}
+
+/// This is used to deserialize the yaml object 'eventing', v2.3.0 has it disabled by default,
+/// the default thereafter has it enabled.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct Eventing {
+    // This value is defaulted to 'false' when 'Eventing' is absent in the yaml.
+    // This works fine because we don't use the serde deserialized values during
     // the values.yaml merge. The merge is done with 'yq'. These are assumed values,
     // in case the value is absent (usually due to added features). This is used
     // to compare against new values (those bundled with the chart in the upgrade-job's
@@ -199,13 +1793,21 @@ impl Eventing {
 }
 
 /// This is used to deserialize the yaml object 'csi'.
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub(crate) struct Csi {
     /// This contains the image tags for the kubernetes-csi sidecar containers.
     image: CsiImage,
+    /// This contains the configuration for the csi-node DaemonSet.
+    #[serde(default)]
+    node: CsiNode,
 }
 
 impl Csi {
+    /// This is a getter for the nvme initiator settings of the csi-node plugin.
+    pub(crate) fn node_nvme(&self) -> &Nvme {
+        self.node.nvme()
+    }
+
     /// This is a getter for the sig-storage/csi-provisioner image tag.
     pub(crate) fn provisioner_image_tag(&self) -> &str {
         self.image.provisioner_tag()
@@ -232,8 +1834,59 @@ impl Csi {
     }
 }
 
+/// This is used to deserialize the yaml object 'csi.node'.
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct CsiNode {
+    /// This contains the nvme initiator settings.
+    #[serde(default)]
+    nvme: Nvme,
+}
+
+impl CsiNode {
+    /// This is a getter for the nvme initiator settings.
+    pub(crate) fn nvme(&self) -> &Nvme {
+        &self.nvme
+    }
+}
+
+/// This is used to deserialize the yaml object 'csi.node.nvme', which contains the nvme initiator
+/// timeouts. These decide how connections to the io-engine's nvme targets tolerate transport
+/// hiccups, and so how volumes fail over. Any of the timeouts may be absent.
+#[derive(Deserialize, JsonSchema, Debug, Default, PartialEq)]
+pub(crate) struct Nvme {
+    /// The nvme_core module io timeout in seconds.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    io_timeout: Option<String>,
+    /// The controller loss timeout in seconds.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    ctrl_loss_tmo: Option<String>,
+    /// The keep alive timeout in seconds.
+    #[serde(default, deserialize_with = "deserialize_lenient_string")]
+    #[schemars(schema_with = "lenient_string_schema")]
+    keep_alive_tmo: Option<String>,
+}
+
+impl Nvme {
+    /// This is a getter for the nvme_core module io timeout.
+    pub(crate) fn io_timeout(&self) -> Option<&str> {
+        self.io_timeout.as_deref()
+    }
+
+    /// This is a getter for the controller loss timeout.
+    pub(crate) fn ctrl_loss_tmo(&self) -> Option<&str> {
+        self.ctrl_loss_tmo.as_deref()
+    }
+
+    /// This is a getter for the keep alive timeout.
+    pub(crate) fn keep_alive_tmo(&self) -> Option<&str> {
+        self.keep_alive_tmo.as_deref()
+    }
+}
+
 /// This contains the image tags for the CSI sidecar containers.
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct CsiImage {
     /// This is the image tag for the csi-provisioner container.
@@ -276,3 +1929,775 @@ impl CsiImage {
         self.registrar_tag.as_str()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::constants::TARGET_VALUES_SOURCE;
+
+    /// This is the values.yaml of the Core chart in this repository.
+    const CORE_VALUES_YAML: &str = include_str!("../../../../../../chart/values.yaml");
+
+    /// This deserializes the Core chart's values.yaml, with the 'base.imagePullSecrets' replaced.
+    fn core_values_with_pull_secrets(image_pull_secrets: &str) -> CoreValues {
+        core_values_with(|values| {
+            values["base"]["imagePullSecrets"] = serde_yaml::from_str(image_pull_secrets).unwrap();
+        })
+    }
+
+    /// This deserializes the Core chart's values.yaml, after the edit of its yaml.
+    fn core_values_with(edit: impl FnOnce(&mut serde_yaml::Value)) -> CoreValues {
+        let mut values: serde_yaml::Value = serde_yaml::from_str(CORE_VALUES_YAML).unwrap();
+        edit(&mut values);
+        serde_yaml::from_value(values).unwrap()
+    }
+
+    /// This decides if a Masterminds version constraint admits the version.
+    fn admits(constraint: &str, version: &str) -> bool {
+        let version = Version::parse(version).unwrap();
+        masterminds_constraint(constraint)
+            .unwrap()
+            .iter()
+            .any(|range| range.matches(&version))
+    }
+
+    /// This is an Umbrella chart's Chart.yaml, whose Core chart dependency has the alias, if any.
+    fn umbrella_chart(alias: Option<&str>) -> Chart {
+        let alias = alias
+            .map(|alias| format!("alias: {alias}"))
+            .unwrap_or_default();
+        serde_yaml::from_str(
+            format!(
+                "apiVersion: v2\nname: umbrella\nversion: 1.0.0\ndependencies:\n  - name: \
+                {CORE_CHART_NAME}\n    version: 2.5.0\n    {alias}\n"
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    /// This is a Chart.yaml with the name, the version and the appVersion, if any.
+    fn chart(name: &str, version: &str, app_version: Option<&str>) -> Chart {
+        let app_version = app_version
+            .map(|app_version| format!("appVersion: '{app_version}'"))
+            .unwrap_or_default();
+        serde_yaml::from_str(
+            format!("apiVersion: v2\nname: {name}\nversion: {version}\n{app_version}\n").as_str(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn upgrade_summary_has_the_version_and_app_version_transitions() {
+        let installed = chart("mayastor", "2.4.1", Some("2.4.0"));
+        let target = chart("mayastor", "2.6.0", Some("2.6.0"));
+        assert_eq!(
+            installed.summarize_upgrade(&target),
+            "upgrading mayastor 2.4.1 -> 2.6.0 (appVersion 2.4.0 -> 2.6.0)"
+        );
+    }
+
+    #[test]
+    fn upgrade_summary_shows_an_absent_app_version_as_unknown() {
+        let installed = chart("mayastor", "2.4.1", None);
+        let target = chart("mayastor", "2.6.0", Some("2.6.0"));
+        assert_eq!(
+            installed.summarize_upgrade(&target),
+            "upgrading mayastor 2.4.1 -> 2.6.0 (appVersion unknown -> 2.6.0)"
+        );
+
+        // An appVersion which is not a semver, e.g. 'develop', is unknown too.
+        let target = chart("mayastor", "2.6.0", Some("develop"));
+        assert_eq!(
+            chart("mayastor", "2.4.1", Some("2.4.0")).summarize_upgrade(&target),
+            "upgrading mayastor 2.4.1 -> 2.6.0 (appVersion 2.4.0 -> unknown)"
+        );
+    }
+
+    #[test]
+    fn upgrade_summary_without_app_versions() {
+        let installed = chart("mayastor", "2.4.1", None);
+        let target = chart("mayastor", "2.4.2-rc.1", None);
+        assert_eq!(
+            installed.summarize_upgrade(&target),
+            "upgrading mayastor 2.4.1 -> 2.4.2-rc.1"
+        );
+    }
+
+    #[test]
+    fn upgrade_summary_names_a_different_target_chart() {
+        let installed = chart("mayastor", "2.4.1", None);
+        let target = chart("openebs", "4.0.0", None);
+        assert_eq!(
+            installed.summarize_upgrade(&target),
+            "upgrading mayastor 2.4.1 -> openebs 4.0.0"
+        );
+    }
+
+    /// This nests the Core chart's values.yaml under the yaml key, as in an Umbrella chart.
+    fn umbrella_values_yaml(key: &str) -> String {
+        let core_values: serde_yaml::Value = serde_yaml::from_str(CORE_VALUES_YAML).unwrap();
+        let mut values = serde_yaml::Mapping::new();
+        values.insert(key.into(), core_values);
+        serde_yaml::to_string(&values).unwrap()
+    }
+
+    #[test]
+    fn core_values_are_nested_under_the_core_chart_name() {
+        let chart = umbrella_chart(None);
+        assert_eq!(chart.core_values_key(), CORE_CHART_NAME);
+
+        let values = detect_and_load(
+            umbrella_values_yaml(CORE_CHART_NAME).as_str(),
+            chart.core_values_key(),
+        )
+        .unwrap();
+        assert!(matches!(values, LoadedValues::Umbrella(_)));
+    }
+
+    #[test]
+    fn core_values_are_nested_under_the_alias() {
+        let chart = umbrella_chart(Some("storage"));
+        assert_eq!(chart.core_values_key(), "storage");
+
+        let values = detect_and_load(
+            umbrella_values_yaml("storage").as_str(),
+            chart.core_values_key(),
+        )
+        .unwrap();
+        assert!(matches!(values, LoadedValues::Umbrella(_)));
+        assert!(detect_and_load(
+            umbrella_values_yaml(CORE_CHART_NAME).as_str(),
+            chart.core_values_key()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn masterminds_and_ranges_are_normalised() {
+        assert!(admits(">=1.20.0-0 <1.30.0", "1.27.3"));
+        assert!(admits(">= 1.20.0-0, < 1.30.0", "1.27.3"));
+        assert!(admits(">=1.20.0,<1.30.0", "1.20.0"));
+        assert!(!admits(">= 1.20.0, < 1.30.0", "1.30.0"));
+        assert!(!admits(">= 1.20.0 < 1.30.0", "1.19.9"));
+    }
+
+    #[test]
+    fn masterminds_alternatives_and_hyphen_ranges_are_normalised() {
+        assert!(admits(">=1.20.0 <1.25.0 || >=1.27.0", "1.28.1"));
+        assert!(!admits(">=1.20.0 <1.25.0 || >=1.27.0", "1.26.0"));
+        assert!(admits("1.20 - 1.29", "1.29.5"));
+        assert!(!admits("1.20 - 1.29", "1.30.0"));
+        assert!(admits("v1.27.3", "1.27.3"));
+        assert!(!admits("1.27.3", "1.27.4"));
+        assert!(admits("~1.27.0", "1.27.9"));
+    }
+
+    #[test]
+    fn invalid_masterminds_constraint_fails() {
+        assert!(masterminds_constraint(">= foo").is_err());
+        assert!(masterminds_constraint(">=").is_err());
+    }
+
+    #[test]
+    fn chart_pull_secrets_are_disabled() {
+        let values: CoreValues = serde_yaml::from_str(CORE_VALUES_YAML).unwrap();
+        assert!(values.image_pull_secrets().is_empty());
+    }
+
+    #[test]
+    fn enabled_pull_secrets_are_listed() {
+        let values = core_values_with_pull_secrets(
+            "{enabled: true, secrets: [{name: login}, {name: mirror}]}",
+        );
+        assert_eq!(values.image_pull_secrets(), vec!["login", "mirror"]);
+    }
+
+    #[test]
+    fn empty_pull_secrets_are_listed() {
+        let values = core_values_with_pull_secrets("{enabled: true, secrets: []}");
+        assert!(values.image_pull_secrets().is_empty());
+
+        let values = core_values_with_pull_secrets("{enabled: false, secrets: [{name: login}]}");
+        assert!(values.image_pull_secrets().is_empty());
+    }
+
+    #[test]
+    fn thin_provisioning_is_expected_from_the_minimum_version() {
+        for version in ["2.2.0", "2.2.0-rc.1", "2.2.1", "2.5.0", "3.0.0-develop"] {
+            assert!(
+                CoreValues::supports_thin_provisioning(&Version::parse(version).unwrap()),
+                "{version}"
+            );
+        }
+        for version in ["2.1.9", "2.1.0", "2.0.0", "1.0.0"] {
+            assert!(
+                !CoreValues::supports_thin_provisioning(&Version::parse(version).unwrap()),
+                "{version}"
+            );
+        }
+    }
+
+    /// This deserializes the thin-provisioning options from their yaml.
+    fn thin(yaml: &str) -> Thin {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn percentages_round_trip() {
+        for input in ["250%", "250", " 250% ", "250 %", "\t250\n"] {
+            let percentage: Percentage = input.parse().unwrap();
+            assert_eq!(percentage.to_string(), "250%");
+            assert_eq!(
+                percentage.to_string().parse::<Percentage>().unwrap(),
+                percentage
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_percentages_fail() {
+        for input in ["", "%", "-10%", "2.5%", "250%%", "25 0%", "ten%"] {
+            assert!(input.parse::<Percentage>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn percentage_from_ratio_rounds_up() {
+        assert_eq!(Percentage::from_ratio(1000, 400), "250%".parse().ok());
+        assert_eq!(Percentage::from_ratio(1001, 400), "251%".parse().ok());
+        assert_eq!(Percentage::from_ratio(0, 400), "0%".parse().ok());
+        assert_eq!(Percentage::from_ratio(1, 0), None);
+    }
+
+    #[test]
+    fn percentage_of_bytes_rounds_down() {
+        let percentage: Percentage = "250%".parse().unwrap();
+        assert_eq!(percentage.of(100), 250);
+        assert_eq!(percentage.of(3), 7);
+        assert_eq!(percentage.of(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn thin_string_getters_return_the_values_as_set() {
+        let values: CoreValues = serde_yaml::from_str(CORE_VALUES_YAML).unwrap();
+        let version = Version::new(2, 5, 0);
+
+        assert_eq!(
+            values.thin_pool_commitment(&version, "test").unwrap(),
+            "250%"
+        );
+        assert_eq!(
+            values.thin_volume_commitment(&version, "test").unwrap(),
+            "40%"
+        );
+        assert_eq!(
+            values
+                .thin_volume_commitment_initial(&version, "test")
+                .unwrap(),
+            "40%"
+        );
+        assert_eq!(
+            values
+                .thin_pool_commitment_parsed(&version, "test")
+                .unwrap(),
+            Percentage(250)
+        );
+    }
+
+    #[test]
+    fn each_absent_thin_subfield_is_named() {
+        let thin_without_pool_commitment =
+            thin("{volumeCommitment: '40%', volumeCommitmentInitial: '40%'}");
+        assert!(matches!(
+            thin_without_pool_commitment.pool_commitment(),
+            Err(Error::ThinSubfieldAbsent {
+                field: "poolCommitment"
+            })
+        ));
+        assert!(thin_without_pool_commitment.volume_commitment().is_ok());
+
+        let thin_without_volume_commitment =
+            thin("{poolCommitment: '250%', volumeCommitmentInitial: '40%'}");
+        assert!(matches!(
+            thin_without_volume_commitment.volume_commitment_parsed(),
+            Err(Error::ThinSubfieldAbsent {
+                field: "volumeCommitment"
+            })
+        ));
+
+        let thin_without_volume_commitment_initial =
+            thin("{poolCommitment: '250%', volumeCommitment: '40%'}");
+        assert!(matches!(
+            thin_without_volume_commitment_initial.volume_commitment_initial(),
+            Err(Error::ThinSubfieldAbsent {
+                field: "volumeCommitmentInitial"
+            })
+        ));
+    }
+
+    #[test]
+    fn malformed_thin_commitment_names_the_field() {
+        let thin = thin("{poolCommitment: 'lots', volumeCommitment: '40%'}");
+        assert!(matches!(
+            thin.pool_commitment_parsed(),
+            Err(Error::ThinCommitmentParse {
+                field: "poolCommitment",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn etcd_replica_count_may_be_quoted() {
+        for yaml in [
+            "replicaCount: 3",
+            "replicaCount: \"3\"",
+            "replicaCount: ' 3 '",
+        ] {
+            let etcd: Etcd = serde_yaml::from_str(yaml).unwrap();
+            assert_eq!(etcd.replica_count(), 3, "{yaml}");
+        }
+
+        let etcd: Etcd = serde_yaml::from_str("persistence: {}").unwrap();
+        assert_eq!(etcd.replica_count(), 0);
+        assert!(serde_yaml::from_str::<Etcd>("replicaCount: three").is_err());
+    }
+
+    #[test]
+    fn malformed_values_name_the_yaml() {
+        let Err(error) = detect_and_load("image: [", "mayastor") else {
+            panic!("malformed yaml should not load");
+        };
+        assert!(matches!(
+            error,
+            Error::YamlParseFromSlice { input_yaml, .. } if input_yaml == "image: ["
+        ));
+    }
+
+    #[test]
+    fn deprecated_flag_is_read_from_the_chart() {
+        let chart_yaml = include_str!("../../../../../../chart/Chart.yaml");
+        let chart: Chart = serde_yaml::from_str(chart_yaml).unwrap();
+        assert!(!chart.is_deprecated());
+
+        let chart: Chart =
+            serde_yaml::from_str(format!("{chart_yaml}deprecated: true\n").as_str()).unwrap();
+        assert!(chart.is_deprecated());
+    }
+
+    /// This deserializes the yaml object 'agents.core.capacity', with global commitments of
+    /// '250%' and '40%'.
+    fn capacity(overrides: &str) -> Capacity {
+        serde_yaml::from_str(
+            format!(
+                "{{thin: {{poolCommitment: '250%', volumeCommitment: '40%', \
+                 volumeCommitmentInitial: '40%'}}{overrides}}}"
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn global_commitments_apply_without_overrides() {
+        let capacity = capacity("");
+        let thin = capacity.override_for("pool-1");
+
+        assert_eq!(thin.pool_commitment().unwrap(), "250%");
+        assert_eq!(thin.volume_commitment().unwrap(), "40%");
+        assert!(capacity
+            .pool_commitment_overrides_parsed()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn pool_overrides_take_the_place_of_the_global_commitments() {
+        let capacity = capacity(
+            ", overrides: {fast: {poolCommitment: '400%'}, slow: {volumeCommitment: '10%'}}",
+        );
+
+        let fast = capacity.override_for("fast");
+        assert_eq!(fast.pool_commitment().unwrap(), "400%");
+        assert_eq!(fast.volume_commitment().unwrap(), "40%");
+        let slow = capacity.override_for("slow");
+        assert_eq!(slow.pool_commitment().unwrap(), "250%");
+        assert_eq!(slow.volume_commitment().unwrap(), "10%");
+        assert_eq!(
+            capacity.override_for("other").pool_commitment().unwrap(),
+            "250%"
+        );
+
+        let pool_commitments = capacity.pool_commitment_overrides_parsed().unwrap();
+        assert_eq!(pool_commitments["fast"], "400%".parse().unwrap());
+        assert_eq!(pool_commitments["slow"], "250%".parse().unwrap());
+    }
+
+    #[test]
+    fn malformed_override_names_the_pool() {
+        let capacity = capacity(", overrides: {fast: {volumeCommitment: 'lots'}}");
+        assert!(matches!(
+            capacity.pool_commitment_overrides_parsed(),
+            Err(Error::ThinCommitmentOverrideParse {
+                name,
+                field: "volumeCommitment",
+                ..
+            }) if name.eq("fast")
+        ));
+    }
+
+    #[test]
+    fn core_list_overrides_cpu_count() {
+        let pinned = |core_list: &str, cpu_count: &str| {
+            core_values_with(|values| {
+                values["io_engine"]["coreList"] = serde_yaml::from_str(core_list).unwrap();
+                values["io_engine"]["cpuCount"] = serde_yaml::from_str(cpu_count).unwrap();
+            })
+        };
+        let installed = pinned("[30, 31]", "2");
+
+        assert_eq!(
+            installed.io_engine_core_list(),
+            Some(["30".to_string(), "31".to_string()].as_slice())
+        );
+        assert_eq!(installed.io_engine_cpu_count(), Some("2"));
+        assert!(!io_engine_cpu_pinning_changed(
+            &installed,
+            &pinned("[30, 31]", "4")
+        ));
+        assert!(io_engine_cpu_pinning_changed(
+            &installed,
+            &pinned("[30, 32]", "2")
+        ));
+    }
+
+    #[test]
+    fn absent_cpu_pinning_is_unchanged() {
+        let unpinned = core_values_with(|values| {
+            let io_engine = values["io_engine"].as_mapping_mut().unwrap();
+            io_engine.remove("coreList");
+            io_engine.remove("cpuCount");
+        });
+
+        assert_eq!(unpinned.io_engine_core_list(), None);
+        assert_eq!(unpinned.io_engine_cpu_count(), None);
+        assert!(!io_engine_cpu_pinning_changed(&unpinned, &unpinned));
+
+        let counted = core_values_with(|_| {});
+        assert!(io_engine_cpu_pinning_changed(&unpinned, &counted));
+    }
+
+    #[test]
+    fn core_agent_log_level_is_read_when_present() {
+        let values =
+            core_values_with(|values| values["agents"]["core"]["logLevel"] = "debug".into());
+        assert_eq!(values.core_agent_log_level(), Some("debug"));
+        assert_eq!(values.io_engine_log_level(), "info");
+    }
+
+    #[test]
+    fn absent_core_agent_log_level_has_no_default() {
+        let values = core_values_with(|values| {
+            values["agents"]["core"]
+                .as_mapping_mut()
+                .unwrap()
+                .remove("logLevel");
+        });
+        assert_eq!(values.core_agent_log_level(), None);
+    }
+
+    #[test]
+    fn loki_stack_toggle_is_read_when_present() {
+        for enabled in [true, false] {
+            let values =
+                core_values_with(|values| values["loki-stack"]["enabled"] = enabled.into());
+            assert_eq!(values.loki_enabled(), Some(enabled));
+        }
+    }
+
+    #[test]
+    fn absent_loki_stack_has_no_toggle() {
+        let values = core_values_with(|values| {
+            values.as_mapping_mut().unwrap().remove("loki-stack");
+        });
+        assert_eq!(values.loki_enabled(), None);
+    }
+
+    /// This writes the Chart.yaml and the values.yaml of the Core chart in this repository, and a
+    /// malformed Chart.yaml, into a temporary directory.
+    fn chart_files_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("Chart.yaml"),
+            include_str!("../../../../../../chart/Chart.yaml"),
+        )
+        .unwrap();
+        fs::write(dir.path().join("values.yaml"), CORE_VALUES_YAML).unwrap();
+        fs::write(dir.path().join("malformed-Chart.yaml"), "{name: mayastor").unwrap();
+        dir
+    }
+
+    #[test]
+    fn chart_files_are_loaded_from_their_paths() {
+        let dir = chart_files_dir();
+
+        let chart = Chart::from_path(dir.path().join("Chart.yaml").as_path()).unwrap();
+        assert_eq!(chart.name(), CORE_CHART_NAME);
+        let values = CoreValues::from_path(dir.path().join("values.yaml").as_path()).unwrap();
+        assert_eq!(values.io_engine_log_level(), "info");
+    }
+
+    #[test]
+    fn malformed_chart_file_names_the_path() {
+        let dir = chart_files_dir();
+        let path = dir.path().join("malformed-Chart.yaml");
+
+        assert!(matches!(
+            Chart::from_path(path.as_path()),
+            Err(Error::ChartYamlParse { path: error_path, .. }) if error_path == path
+        ));
+    }
+
+    #[test]
+    fn absent_chart_file_names_the_path() {
+        let dir = chart_files_dir();
+        let path = dir.path().join("absent.yaml");
+
+        assert!(matches!(
+            Chart::from_path(path.as_path()),
+            Err(Error::ChartFileRead { path: error_path, .. }) if error_path == path
+        ));
+    }
+
+    #[test]
+    fn missing_nested_field_names_its_key_path() {
+        let dir = chart_files_dir();
+        let path = dir.path().join("values.yaml");
+        let mut values: serde_yaml::Value = serde_yaml::from_str(CORE_VALUES_YAML).unwrap();
+        values["csi"]["image"]
+            .as_mapping_mut()
+            .unwrap()
+            .remove("registrarTag");
+        fs::write(path.as_path(), serde_yaml::to_string(&values).unwrap()).unwrap();
+
+        let Err(error) = CoreValues::from_path(path.as_path()) else {
+            panic!("values without csi.image.registrarTag should not load");
+        };
+        assert!(matches!(&error, Error::ValuesDeserialize { path, .. } if path.eq("csi.image")));
+        assert!(error.to_string().contains("'csi.image'"), "{error}");
+        assert!(error.to_string().contains("registrarTag"), "{error}");
+    }
+
+    #[test]
+    fn full_nvme_block_is_read() {
+        let values = core_values_with(|values| {
+            values["csi"]["node"]["nvme"] = serde_yaml::from_str(
+                "{io_timeout: \"60\", ctrl_loss_tmo: 1980, keep_alive_tmo: \"10\"}",
+            )
+            .unwrap();
+        });
+
+        let nvme = values.csi_node_nvme();
+        assert_eq!(nvme.io_timeout(), Some("60"));
+        assert_eq!(nvme.ctrl_loss_tmo(), Some("1980"));
+        assert_eq!(nvme.keep_alive_tmo(), Some("10"));
+    }
+
+    #[test]
+    fn empty_or_absent_nvme_block_has_no_timeouts() {
+        let empty = core_values_with(|values| {
+            values["csi"]["node"]["nvme"] = serde_yaml::from_str("{}").unwrap();
+        });
+        let absent = core_values_with(|values| {
+            values["csi"].as_mapping_mut().unwrap().remove("node");
+        });
+
+        for values in [&empty, &absent] {
+            assert_eq!(values.csi_node_nvme(), &Nvme::default());
+        }
+        assert!(!nvme_timeouts_changed(&empty, &absent));
+    }
+
+    #[test]
+    fn changed_nvme_timeout_is_flagged() {
+        let installed = core_values_with(|_| {});
+        let target = core_values_with(|values| {
+            values["csi"]["node"]["nvme"]["ctrl_loss_tmo"] = "3600".into();
+        });
+
+        assert!(nvme_timeouts_changed(&installed, &target));
+        assert!(!nvme_timeouts_changed(
+            &installed,
+            &core_values_with(|_| {})
+        ));
+    }
+
+    #[test]
+    fn absent_thin_provisioning_options_name_the_values_source_and_chart_version() {
+        let values = core_values_with(|values| {
+            values["agents"]["core"]
+                .as_mapping_mut()
+                .unwrap()
+                .remove("capacity");
+        });
+        let chart_version = Version::new(2, 3, 0);
+
+        let error = values
+            .thin_pool_commitment_parsed(&chart_version, TARGET_VALUES_SOURCE)
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::ThinProvisioningOptionsAbsent { chart_version, values_source }
+                if chart_version.eq(&Version::new(2, 3, 0))
+                    && values_source.eq(TARGET_VALUES_SOURCE)
+        ));
+        assert_eq!(
+            error.to_string(),
+            "The agents.core.capacity yaml object is absent amongst the helm values from the \
+            target helm chart, of helm chart version 2.3.0"
+        );
+    }
+
+    /// This checks if the io-engine logs more verbosely after an upgrade from the installed
+    /// io-engine log filter to the target one.
+    fn more_verbose(installed: &str, target: &str) -> bool {
+        let with_log_level = |log_level: &str| {
+            core_values_with(|values| values["io_engine"]["logLevel"] = log_level.into())
+        };
+        io_engine_log_level_more_verbose(&with_log_level(installed), &with_log_level(target))
+    }
+
+    #[test]
+    fn log_filter_verbosity_is_the_most_verbose_level() {
+        assert_eq!(log_filter_verbosity("info"), Some(LogLevel::Info));
+        assert_eq!(
+            log_filter_verbosity("info,io_engine=debug"),
+            Some(LogLevel::Debug)
+        );
+        assert_eq!(
+            log_filter_verbosity("TRACE, h2=warn , tower=error,"),
+            Some(LogLevel::Trace)
+        );
+        assert_eq!(log_filter_verbosity("info,io_engine=chatty"), None);
+        assert_eq!(log_filter_verbosity(""), None);
+    }
+
+    #[test]
+    fn more_verbose_log_level_transitions_are_flagged() {
+        for (installed, target) in [
+            ("info", "debug"),
+            ("debug", "trace"),
+            ("error", "warn"),
+            ("info", "info,io_engine=trace"),
+        ] {
+            assert!(
+                more_verbose(installed, target),
+                "'{installed}' -> '{target}'"
+            );
+        }
+    }
+
+    #[test]
+    fn less_or_equally_verbose_log_level_transitions_are_not_flagged() {
+        for (installed, target) in [
+            ("debug", "info"),
+            ("info", "info"),
+            ("info,io_engine=debug", "debug"),
+            ("trace", "error"),
+        ] {
+            assert!(
+                !more_verbose(installed, target),
+                "'{installed}' -> '{target}'"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_log_levels_are_not_ordered() {
+        assert!(!more_verbose("verbose", "trace"));
+        assert!(!more_verbose("info", "io_engine=loud"));
+    }
+
+    /// This is the Core chart's values, with the io-engine env and envcontext yaml.
+    fn core_values_with_env(env: &str, envcontext: &str) -> CoreValues {
+        core_values_with(|values| {
+            values["io_engine"]["env"] = serde_yaml::from_str(env).unwrap();
+            values["io_engine"]["envcontext"] = envcontext.into();
+        })
+    }
+
+    #[test]
+    fn io_engine_env_includes_the_envcontext_options() {
+        let values = core_values_with_env(
+            "{NVME_QPAIR_CONNECT_ASYNC: true, NVMF_TCP_MAX_QUEUE_DEPTH: 32}",
+            "iova-mode=pa --no-huge",
+        );
+        assert_eq!(
+            values.io_engine_env(),
+            BTreeMap::from(
+                [
+                    ("--iova-mode", "pa"),
+                    ("--no-huge", ""),
+                    ("NVME_QPAIR_CONNECT_ASYNC", "true"),
+                    ("NVMF_TCP_MAX_QUEUE_DEPTH", "32"),
+                ]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn absent_io_engine_env_is_empty() {
+        let values = core_values_with(|values| {
+            values["io_engine"]
+                .as_mapping_mut()
+                .unwrap()
+                .remove("envcontext");
+        });
+        assert!(values.io_engine_env().is_empty());
+    }
+
+    #[test]
+    fn io_engine_env_changes_list_removed_and_changed_entries() {
+        let installed = core_values_with_env(
+            "{NVMF_TCP_MAX_QUEUE_DEPTH: 32, NVME_TIMEOUT_US: 5000000, KEPT: x}",
+            "iova-mode=pa",
+        );
+        let target = core_values_with_env(
+            "{NVMF_TCP_MAX_QUEUE_DEPTH: 64, KEPT: x, NVME_QPAIR_CONNECT_ASYNC: true}",
+            "",
+        );
+
+        let changes = io_engine_env_changes(&installed, &target);
+        assert_eq!(
+            changes,
+            vec![
+                IoEngineEnvChange::Removed {
+                    key: "--iova-mode".to_string(),
+                    old: "pa".to_string()
+                },
+                IoEngineEnvChange::Removed {
+                    key: "NVME_TIMEOUT_US".to_string(),
+                    old: "5000000".to_string()
+                },
+                IoEngineEnvChange::Changed {
+                    key: "NVMF_TCP_MAX_QUEUE_DEPTH".to_string(),
+                    old: "32".to_string(),
+                    new: "64".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            changes[2].to_string(),
+            "NVMF_TCP_MAX_QUEUE_DEPTH: '32' -> '64'"
+        );
+    }
+
+    #[test]
+    fn added_io_engine_env_entries_are_not_changes() {
+        let installed = core_values_with_env("{}", "");
+        let target = core_values_with_env("{NVME_QPAIR_CONNECT_ASYNC: true}", "iova-mode=pa");
+        assert!(io_engine_env_changes(&installed, &target).is_empty());
+    }
+}
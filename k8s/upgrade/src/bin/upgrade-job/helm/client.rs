@@ -1,13 +1,14 @@
 use crate::{
     common::{
+        constants::{HELM_OUTPUT_LOG_TARGET, HELM_OUTPUT_TAIL_LINES},
         error::{
-            CollectDirEntries, CreateCrd, HelmClientNs, HelmCommand, HelmGetValuesCommand,
-            HelmListCommand, HelmRelease, HelmUpgradeCommand, InvalidHelmChartCrdDir,
-            ReadingDirectoryContents, ReadingFile, Result, U8VectorToString, YamlParseFromFile,
-            YamlParseFromSlice,
+            CreateCrd, Error, HelmClientNs, HelmCommand, HelmGetValuesCommand, HelmListCommand,
+            HelmRelease, HelmRollbackCommand, HelmTemplateCommand, HelmUpgradeFailed, Result,
+            U8VectorToString, UpgradeHookFailed, YamlParseFromSlice,
         },
         kube_client::KubeClientSet,
     },
+    helm::crd::load_chart_crds,
     vec_to_strings,
 };
 use k8s_openapi::{
@@ -18,12 +19,13 @@ use kube_client::{api::PostParams, Api};
 use serde::Deserialize;
 use snafu::{ensure, IntoError, ResultExt};
 use std::{
-    fs,
+    collections::VecDeque,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     str,
 };
-use tracing::{debug, info};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+use tracing::{debug, info, warn};
 
 /// This struct is used to deserialize the output of `helm list -n <namespace> --deployed -o yaml`.
 #[derive(Clone, Deserialize)]
@@ -191,6 +193,12 @@ impl HelmReleaseClient {
     }
 
     /// Runs command `helm upgrade -n <namespace> <release_name> <chart_dir>`.
+    ///
+    /// The helm chart's pre-upgrade and post-upgrade hooks are left to helm, which runs the hook
+    /// Jobs in weight order, waits for them and applies their hook-delete-policy. The upgrade-job
+    /// does not run the hook manifests itself, as that would run every hook twice. It only reports
+    /// a failed hook Job as UpgradeHookFailed, instead of HelmUpgradeFailed. The hooks are not run
+    /// for data-plane only upgrades, which skip the helm upgrade.
     pub(crate) async fn upgrade<A, B>(
         &self,
         release_name: A,
@@ -233,6 +241,116 @@ impl HelmReleaseClient {
         );
 
         debug!(%command, ?args, "Helm upgrade command");
+        let mut child = tokio::process::Command::new(command)
+            .args(args.clone())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(HelmCommand {
+                command: command.to_string(),
+                args: args.clone(),
+            })?;
+
+        // The output is logged as it is printed, so that a slow or stuck helm upgrade can be
+        // followed in the job's logs. The last lines are kept for the error, if helm fails.
+        let dry_run = args.iter().any(|arg| arg.eq("--dry-run"));
+        let last_lines = stream_output(child.stdout.take(), child.stderr.take(), dry_run).await;
+
+        let status = child.wait().await.context(HelmCommand {
+            command: command.to_string(),
+            args,
+        })?;
+        // Helm runs the helm chart's pre-upgrade and post-upgrade hook Jobs, and applies their
+        // hook-delete-policy. A failed hook Job fails the helm upgrade.
+        if !status.success() {
+            if let Some(error) = hook_failure(&last_lines) {
+                return Err(error);
+            }
+        }
+        ensure!(
+            status.success(),
+            HelmUpgradeFailed {
+                status,
+                last_lines: Vec::from(last_lines),
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Runs command `helm template -n <namespace> <release_name> <chart_dir> --show-only
+    /// <template>`, and returns the rendered manifest of the template.
+    pub(crate) fn template<A, B>(
+        &self,
+        release_name: A,
+        chart_dir: &Path,
+        template: &str,
+        maybe_extra_args: Option<Vec<B>>,
+    ) -> Result<Vec<u8>>
+    where
+        A: ToString,
+        B: ToString,
+    {
+        let command: &str = "helm";
+        let mut args: Vec<String> = vec_to_strings![
+            "template",
+            release_name,
+            chart_dir.to_string_lossy(),
+            "-n",
+            self.namespace.as_str(),
+            "--show-only",
+            template
+        ];
+
+        // Extra args
+        args.extend(
+            maybe_extra_args
+                .unwrap_or_default()
+                .iter()
+                .map(ToString::to_string),
+        );
+
+        debug!(%command, ?args, "Helm template command");
+        let output = Command::new(command)
+            .args(args.clone())
+            .output()
+            .context(HelmCommand {
+                command: command.to_string(),
+                args: args.clone(),
+            })?;
+
+        ensure!(
+            output.status.success(),
+            HelmTemplateCommand {
+                command: command.to_string(),
+                args,
+                std_err: str::from_utf8(output.stderr.as_slice())
+                    .context(U8VectorToString)?
+                    .to_string()
+            }
+        );
+
+        Ok(output.stdout)
+    }
+
+    /// Runs command `helm rollback -n <namespace> <release_name> <revision>`.
+    pub(crate) fn rollback<A>(&self, release_name: A, revision: u32) -> Result<()>
+    where
+        A: ToString,
+    {
+        let command: &str = "helm";
+        let args: Vec<String> = vec_to_strings![
+            "rollback",
+            release_name,
+            revision,
+            "-n",
+            self.namespace.as_str(),
+            "--wait",
+            "--timeout",
+            "15m"
+        ];
+
+        debug!(%command, ?args, "Helm rollback command");
         let output = Command::new(command)
             .args(args.clone())
             .output()
@@ -242,10 +360,10 @@ impl HelmReleaseClient {
             })?;
 
         let stdout_str = str::from_utf8(output.stdout.as_slice()).context(U8VectorToString)?;
-        debug!(stdout=%stdout_str, "Helm upgrade command standard output");
+        debug!(stdout=%stdout_str, "Helm rollback command standard output");
         ensure!(
             output.status.success(),
-            HelmUpgradeCommand {
+            HelmRollbackCommand {
                 command: command.to_string(),
                 args,
                 std_err: str::from_utf8(output.stderr.as_slice())
@@ -285,60 +403,231 @@ impl HelmReleaseClient {
 /// Installs CRDs which are missing from the target helm chart cluster which are missing
 /// from the cluster.
 async fn install_missing_crds(crd_client: &Api<Crd>, crd_dir_path: PathBuf) -> Result<()> {
-    ensure!(
-        crd_dir_path.is_dir(),
-        InvalidHelmChartCrdDir { path: crd_dir_path }
-    );
-    // List the entries in the 'crds' directory.
-    let entries = fs::read_dir(crd_dir_path.as_path())
-        .context(ReadingDirectoryContents {
-            path: crd_dir_path.clone(),
-        })?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, std::io::Error>>()
-        .context(CollectDirEntries { path: crd_dir_path })?;
-
-    // Walk through the entries in the directory and create the CRDs.
-    // This errors out if a file is not a CRD, but that is okay because the 'crds' directory
-    // is meant for use with CRDs only.
-    for entry in entries {
-        if entry.is_file() {
-            let crd_yaml = fs::read(entry.as_path()).context(ReadingFile {
-                filepath: entry.clone(),
-            })?;
+    // Walk through the CRDs in the directory and create them.
+    for crd in load_chart_crds(crd_dir_path.as_path())? {
+        // Create CRDs, and ignore creation failures due to the CRD already
+        // existing in the cluster.
+        let pp = PostParams::default();
+        let creation_result = crd_client.create(&pp, &crd).await;
+        if let Err(err) = creation_result {
+            match err {
+                // Return early if creation has failed due to the CRD already existing in the
+                // cluster.
+                // Ref: https://github.com/kubernetes/apimachinery/blob/v0.27.3/pkg/apis/meta/v1/types.go#L846
+                // The existing CRD's versions are validated against the ones bundled with the
+                // target helm chart ahead of the upgrade.
+                kube::Error::Api(response) if response.reason.eq("AlreadyExists") => {
+                    info!(
+                        "CustomResourceDefinition '{}' already exists",
+                        crd.name_any()
+                    );
+                    continue;
+                }
+                _ => {
+                    return Err(CreateCrd {
+                        name: crd.name_any(),
+                    }
+                    .into_error(err))
+                }
+            }
+        }
+        info!("Created CustomResourceDefinition '{}'", crd.name_any());
+    }
+    Ok(())
+}
 
-            let crd: Crd = serde_yaml::from_slice(crd_yaml.as_slice())
-                .context(YamlParseFromFile { filepath: entry })?;
-
-            // Create CRDs, and ignore creation failures due to the CRD already
-            // existing in the cluster.
-            let pp = PostParams::default();
-            let creation_result = crd_client.create(&pp, &crd).await;
-            if let Err(err) = creation_result {
-                match err {
-                    // Return early if creation has failed due to the CRD already existing in the
-                    // cluster.
-                    // Ref: https://github.com/kubernetes/apimachinery/blob/v0.27.3/pkg/apis/meta/v1/types.go#L846
-                    // TODO: It could be that the existing CRD registers a CR of a different version
-                    //       than the one bundled with the target helm chart. This needs to be
-                    // handled.
-                    kube::Error::Api(response) if response.reason.eq("AlreadyExists") => {
-                        info!(
-                            "CustomResourceDefinition '{}' already exists",
-                            crd.name_any()
-                        );
-                        continue;
+/// This logs the output of a helm command as it is printed, and returns its last lines. The
+/// standard output is only logged at debug level, because it carries the rendered manifest of the
+/// helm release, e.g. with the data of its Secrets, which is not subject to '--redact'. The
+/// standard output of a dry-run is the manifest alone, so it is dropped.
+async fn stream_output<O, E>(
+    stdout: Option<O>,
+    stderr: Option<E>,
+    dry_run: bool,
+) -> VecDeque<String>
+where
+    O: AsyncRead + Unpin,
+    E: AsyncRead + Unpin,
+{
+    let mut last_lines: VecDeque<String> = VecDeque::with_capacity(HELM_OUTPUT_TAIL_LINES);
+    let mut stdout_lines = stdout.map(|out| BufReader::new(out).lines());
+    let mut stderr_lines = stderr.map(|err| BufReader::new(err).lines());
+    while stdout_lines.is_some() || stderr_lines.is_some() {
+        tokio::select! {
+            line = next_line(&mut stdout_lines), if stdout_lines.is_some() => {
+                match line {
+                    Some(_) if dry_run => {}
+                    Some(line) => {
+                        debug!(target: HELM_OUTPUT_LOG_TARGET, "{line}");
+                        push_line(&mut last_lines, line);
                     }
-                    _ => {
-                        return Err(CreateCrd {
-                            name: crd.name_any(),
-                        }
-                        .into_error(err))
+                    None => stdout_lines = None,
+                }
+            }
+            line = next_line(&mut stderr_lines), if stderr_lines.is_some() => {
+                match line {
+                    Some(line) => {
+                        warn!(target: HELM_OUTPUT_LOG_TARGET, "{line}");
+                        push_line(&mut last_lines, line);
                     }
+                    None => stderr_lines = None,
                 }
             }
-            info!("Created CustomResourceDefinition '{}'", crd.name_any());
         }
     }
-    Ok(())
+
+    last_lines
+}
+
+/// This reads the next line of a child process's output. This returns None once the output is
+/// closed, or if it cannot be read.
+async fn next_line<R>(lines: &mut Option<Lines<BufReader<R>>>) -> Option<String>
+where
+    R: AsyncRead + Unpin,
+{
+    match lines.as_mut()?.next_line().await {
+        Ok(line) => line,
+        Err(error) => {
+            debug!(%error, "Failed to read helm command output");
+            None
+        }
+    }
+}
+
+/// This picks out a failed helm chart hook from the tail of the 'helm upgrade' output, e.g.
+/// 'Error: UPGRADE FAILED: pre-upgrade hooks failed: 1 error occurred: * job mayastor-pre-upgrade
+/// failed: BackoffLimitExceeded'. Older helm versions leave out the name of the Job.
+fn hook_failure(last_lines: &VecDeque<String>) -> Option<Error> {
+    let position = last_lines
+        .iter()
+        .position(|line| line.contains(" hooks failed: "))?;
+    let (head, tail) = last_lines[position].split_once(" hooks failed: ")?;
+    let hook = head.rsplit([' ', ':']).next().unwrap_or(head).to_string();
+
+    // Helm may list the errors on the lines which follow, e.g. '\t* job <name> failed: <reason>'.
+    let mut failure = tail.trim().to_string();
+    if failure.ends_with("error occurred:") || failure.ends_with("errors occurred:") {
+        if let Some(next) = last_lines.get(position + 1) {
+            failure = next.trim().trim_start_matches('*').trim().to_string();
+        }
+    }
+
+    let (job, reason) = match failure.strip_prefix("job ") {
+        Some(job_failure) => match job_failure.split_once(" failed: ") {
+            Some((job, reason)) => (job.to_string(), reason.to_string()),
+            None => (
+                "unknown".to_string(),
+                job_failure.trim_start_matches("failed: ").to_string(),
+            ),
+        },
+        None => ("unknown".to_string(), failure),
+    };
+
+    Some(UpgradeHookFailed { hook, job, reason }.build())
+}
+
+/// This adds a line to the tail of the helm command output, dropping the oldest line if the tail
+/// is full.
+fn push_line(last_lines: &mut VecDeque<String>, line: String) {
+    if last_lines.len() == HELM_OUTPUT_TAIL_LINES {
+        last_lines.pop_front();
+    }
+    last_lines.push_back(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This runs a fake helm command which prints a manifest with a Secret to its standard output
+    /// and an error to its standard error, and returns the last lines of its output.
+    async fn fake_helm_output(dry_run: bool) -> Vec<String> {
+        let mut child = tokio::process::Command::new("sh")
+            .args([
+                "-c",
+                "echo 'MANIFEST:'; echo 'data:'; echo '  password: c2VjcmV0'; \
+                echo 'Error: UPGRADE FAILED' >&2",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let last_lines = stream_output(child.stdout.take(), child.stderr.take(), dry_run).await;
+        child.wait().await.unwrap();
+        Vec::from(last_lines)
+    }
+
+    #[tokio::test]
+    async fn dry_run_output_drops_the_manifest() {
+        assert_eq!(
+            fake_helm_output(true).await,
+            vec!["Error: UPGRADE FAILED".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn upgrade_output_keeps_the_tail() {
+        let last_lines = fake_helm_output(false).await;
+        assert_eq!(last_lines.len(), 4);
+        assert!(last_lines.contains(&"Error: UPGRADE FAILED".to_string()));
+        assert!(last_lines.contains(&"  password: c2VjcmV0".to_string()));
+    }
+
+    /// This is the tail of the output of a 'helm upgrade' whose pre-upgrade hook passed.
+    const PASSING_HOOK_OUTPUT: [&str; 3] = [
+        "client.go:428: [debug] Starting delete for \"mayastor-pre-upgrade\" Job",
+        "client.go:540: [debug] Watching for changes to Job mayastor-pre-upgrade",
+        "Error: UPGRADE FAILED: timed out waiting for the condition",
+    ];
+
+    /// This collects the lines of helm output into a tail of the output.
+    fn tail(lines: &[&str]) -> VecDeque<String> {
+        lines.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn passing_hook_job_is_not_a_hook_failure() {
+        assert!(hook_failure(&tail(PASSING_HOOK_OUTPUT.as_slice())).is_none());
+    }
+
+    #[test]
+    fn failing_hook_job_is_a_hook_failure() {
+        let last_lines = tail(&[
+            "client.go:568: [debug] Job mayastor-pre-upgrade: Jobs active: 0, jobs failed: 1",
+            "Error: UPGRADE FAILED: pre-upgrade hooks failed: 1 error occurred:",
+            "\t* job mayastor-pre-upgrade failed: BackoffLimitExceeded",
+        ]);
+
+        assert!(matches!(
+            hook_failure(&last_lines),
+            Some(Error::UpgradeHookFailed { hook, job, reason })
+                if hook == "pre-upgrade"
+                    && job == "mayastor-pre-upgrade"
+                    && reason == "BackoffLimitExceeded"
+        ));
+    }
+
+    #[test]
+    fn failing_hook_job_without_a_job_name() {
+        let last_lines = tail(&[
+            "Error: UPGRADE FAILED: post-upgrade hooks failed: job failed: \
+            DeadlineExceeded",
+        ]);
+
+        assert!(matches!(
+            hook_failure(&last_lines),
+            Some(Error::UpgradeHookFailed { hook, job, reason })
+                if hook == "post-upgrade" && job == "unknown" && reason == "DeadlineExceeded"
+        ));
+    }
+
+    #[test]
+    fn tail_keeps_the_last_lines() {
+        let mut last_lines = VecDeque::new();
+        for line in 0 .. HELM_OUTPUT_TAIL_LINES + 2 {
+            push_line(&mut last_lines, line.to_string());
+        }
+        assert_eq!(last_lines.len(), HELM_OUTPUT_TAIL_LINES);
+        assert_eq!(last_lines.front(), Some(&"2".to_string()));
+    }
 }
@@ -0,0 +1,204 @@
+use crate::common::error::{
+    CollectDirEntries, CrdVersionRegression, GetCrd, InvalidHelmChartCrdDir,
+    ReadingDirectoryContents, ReadingFile, Result, YamlParseFromFile,
+};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition as Crd;
+use kube::{core::Version, Api, ResourceExt};
+use snafu::{ensure, ResultExt};
+use std::{fs, path::Path};
+use tracing::info;
+
+/// This reads the CRDs from the files in the 'crds' directory of a helm chart. This errors out if
+/// a file is not a CRD, but that is okay because the 'crds' directory is meant for use with CRDs
+/// only.
+pub(crate) fn load_chart_crds(crd_dir_path: &Path) -> Result<Vec<Crd>> {
+    ensure!(
+        crd_dir_path.is_dir(),
+        InvalidHelmChartCrdDir {
+            path: crd_dir_path.to_path_buf()
+        }
+    );
+    // List the entries in the 'crds' directory.
+    let entries = fs::read_dir(crd_dir_path)
+        .context(ReadingDirectoryContents {
+            path: crd_dir_path.to_path_buf(),
+        })?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, std::io::Error>>()
+        .context(CollectDirEntries {
+            path: crd_dir_path.to_path_buf(),
+        })?;
+
+    let mut crds: Vec<Crd> = Vec::new();
+    for entry in entries.into_iter().filter(|entry| entry.is_file()) {
+        let crd_yaml = fs::read(entry.as_path()).context(ReadingFile {
+            filepath: entry.clone(),
+        })?;
+
+        crds.push(
+            serde_yaml::from_slice(crd_yaml.as_slice())
+                .context(YamlParseFromFile { filepath: entry })?,
+        );
+    }
+
+    Ok(crds)
+}
+
+/// This fails if the target helm chart bundles a CRD whose latest served version is older than
+/// the latest served version of the CRD installed in the cluster, e.g. v1beta1 over v1. The CRD
+/// versions which the target helm chart adds are only logged. Helm charts without a 'crds'
+/// directory have nothing to check.
+pub(crate) async fn check_crd_versions(crd_api: &Api<Crd>, crd_dir_path: &Path) -> Result<()> {
+    if !crd_dir_path.is_dir() {
+        return Ok(());
+    }
+
+    for target_crd in load_chart_crds(crd_dir_path)? {
+        let name = target_crd.name_any();
+        let Some(installed_crd) = crd_api
+            .get_opt(name.as_str())
+            .await
+            .context(GetCrd { name: name.clone() })?
+        else {
+            info!(crd.name = %name, "CustomResourceDefinition will be created by the upgrade");
+            continue;
+        };
+
+        let added_versions = compare_crd_versions(&installed_crd, &target_crd)?;
+        if !added_versions.is_empty() {
+            info!(
+                crd.name = %name,
+                "CustomResourceDefinition versions {} are added by the upgrade",
+                added_versions.join(", ")
+            );
+        }
+    }
+
+    info!("Validated the CustomResourceDefinition versions of the target helm chart");
+    Ok(())
+}
+
+/// This fails if the latest served version of the target CRD is older than the latest served
+/// version of the installed CRD. Otherwise, this returns the served versions which the target CRD
+/// adds.
+fn compare_crd_versions<'a>(installed_crd: &Crd, target_crd: &'a Crd) -> Result<Vec<&'a str>> {
+    let installed_versions = served_versions(installed_crd);
+    let target_versions = served_versions(target_crd);
+
+    if let (Some(installed), Some(target)) = (
+        latest_version(installed_versions.as_slice()),
+        latest_version(target_versions.as_slice()),
+    ) {
+        ensure!(
+            Version::parse(target).priority() >= Version::parse(installed).priority(),
+            CrdVersionRegression {
+                crd: target_crd.name_any(),
+                installed,
+                target,
+            }
+        );
+    }
+
+    Ok(target_versions
+        .into_iter()
+        .filter(|version| !installed_versions.contains(version))
+        .collect())
+}
+
+/// This lists the names of the served versions of a CRD, e.g. 'v1' and 'v1beta1'.
+fn served_versions(crd: &Crd) -> Vec<&str> {
+    crd.spec
+        .versions
+        .iter()
+        .filter(|version| version.served)
+        .map(|version| version.name.as_str())
+        .collect()
+}
+
+/// This picks the latest of the CRD versions, as per the Kubernetes version priority.
+fn latest_version<'a>(versions: &[&'a str]) -> Option<&'a str> {
+    versions
+        .iter()
+        .max_by_key(|version| Version::parse(version).priority())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This is a CRD fixture, which serves the versions with 'served' set to true.
+    fn crd(versions: &[(&str, bool)]) -> Crd {
+        let versions: String = versions
+            .iter()
+            .map(|(name, served)| {
+                format!("  - name: {name}\n    served: {served}\n    storage: {served}\n")
+            })
+            .collect();
+        serde_yaml::from_str(
+            format!(
+                "apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: diskpools.openebs.io
+spec:
+  group: openebs.io
+  names:
+    kind: DiskPool
+    plural: diskpools
+  scope: Namespaced
+  versions:
+{versions}"
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn older_target_version_is_a_regression() {
+        let installed = crd(&[("v1beta1", false), ("v1beta2", true)]);
+        let target = crd(&[("v1alpha1", true), ("v1beta1", true)]);
+
+        let error = compare_crd_versions(&installed, &target).unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::CrdVersionRegression { crd, installed, target }
+                if crd == "diskpools.openebs.io" && installed == "v1beta2" && target == "v1beta1"
+        ));
+    }
+
+    #[test]
+    fn added_versions_are_returned() {
+        let installed = crd(&[("v1beta1", true)]);
+        let target = crd(&[("v1beta1", true), ("v1beta2", true), ("v1", true)]);
+
+        assert_eq!(
+            compare_crd_versions(&installed, &target).unwrap(),
+            vec!["v1beta2", "v1"]
+        );
+    }
+
+    #[test]
+    fn unchanged_chart_crd_is_compatible() {
+        let chart_crd: Crd = serde_yaml::from_str(include_str!(
+            "../../../../../../chart/crds/csi-volume-snapshot.yaml"
+        ))
+        .unwrap();
+
+        assert!(compare_crd_versions(&chart_crd, &chart_crd)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn versions_which_are_not_served_are_ignored() {
+        let installed = crd(&[("v1beta1", true), ("v1", false)]);
+        let target = crd(&[("v1beta1", true)]);
+
+        assert!(compare_crd_versions(&installed, &target)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    common::{
+        constants::{
+            CHART_VERSION_LABEL_KEY, INSTALLED_VALUES_SOURCE, REDACTED_VALUE, TARGET_VALUES_SOURCE,
+            UNIFIED_DIFF_CONTEXT_LINES,
+        },
+        error::{Result, YamlParseFromSlice},
+    },
+    helm::{chart::CoreValues, redact::is_redacted},
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{value::TaggedValue, Mapping, Value};
+use snafu::ResultExt;
+
+/// This is a change in the value of a helm values option, between the installed values and the
+/// upgrade values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct FieldChange {
+    /// The yaml path of the helm values option, e.g. '.image.tag'.
+    path: String,
+    /// The value of the option in the installed values.
+    old: Option<String>,
+    /// The value of the option in the upgrade values.
+    new: Option<String>,
+}
+
+impl FieldChange {
+    /// This is a getter for the yaml path of the helm values option.
+    pub(crate) fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// This is a getter for the installed value of the option.
+    pub(crate) fn old_value(&self) -> Option<&str> {
+        self.old.as_deref()
+    }
+
+    /// This is a getter for the upgrade value of the option.
+    pub(crate) fn new_value(&self) -> Option<&str> {
+        self.new.as_deref()
+    }
+}
+
+/// This is the list of helm values options which change during the upgrade.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct UpgradeValuesDiff {
+    changes: Vec<FieldChange>,
+}
+
+impl UpgradeValuesDiff {
+    /// This adds a FieldChange to the diff, if the installed value and the upgrade value differ.
+    fn record<J>(&mut self, path: J, old: Option<String>, new: Option<String>)
+    where
+        J: ToString,
+    {
+        if old.ne(&new) {
+            self.changes.push(FieldChange {
+                path: path.to_string(),
+                old,
+                new,
+            });
+        }
+    }
+
+    /// This is a getter for the list of changes.
+    pub(crate) fn changes(&self) -> &[FieldChange] {
+        self.changes.as_slice()
+    }
+
+    /// This replaces the installed and the target values of the options, whose yaml paths match
+    /// any of the redacted key paths, with "***".
+    pub(crate) fn redact<S: AsRef<str>>(&mut self, paths: &[S]) {
+        for change in self.changes.iter_mut() {
+            let key_path: Vec<&str> = change.path.trim_start_matches('.').split('.').collect();
+            if is_redacted(key_path.as_slice(), paths) {
+                for value in [&mut change.old, &mut change.new].into_iter().flatten() {
+                    *value = REDACTED_VALUE.to_string();
+                }
+            }
+        }
+    }
+
+    /// This decides if the upgrade moves the container images to another registry or repository,
+    /// in which case every node has to pull the images afresh. On air-gapped clusters, the images
+    /// may have to be made available ahead of the upgrade.
+    pub(crate) fn requires_image_prepull(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|change| IMAGE_LOCATION_PATHS.contains(&change.path()))
+    }
+
+    /// This is a predicate for an empty diff.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// These are the yaml paths of the options which set where the container images are pulled from.
+const IMAGE_LOCATION_PATHS: [&str; 2] = [".image.registry", ".image.repo"];
+
+/// This compares the installed values and the upgrade values, and lists the changes to the image
+/// registry, repository and tag, the io-engine and core agent log levels and the thin-provisioning
+/// commitment options.
+pub(crate) fn diff_values(
+    installed: &CoreValues,
+    installed_version: &Version,
+    target: &CoreValues,
+    target_version: &Version,
+) -> UpgradeValuesDiff {
+    let mut diff = UpgradeValuesDiff::default();
+
+    diff.record(
+        ".image.registry",
+        installed.image_registry().map(ToString::to_string),
+        target.image_registry().map(ToString::to_string),
+    );
+    diff.record(
+        ".image.repo",
+        installed.image_repo().map(ToString::to_string),
+        target.image_repo().map(ToString::to_string),
+    );
+    diff.record(
+        ".image.tag",
+        Some(installed.image_tag().to_string()),
+        Some(target.image_tag().to_string()),
+    );
+    diff.record(
+        ".image.repoTags.dataPlane",
+        Some(installed.data_plane_repotag().to_string()),
+        Some(target.data_plane_repotag().to_string()),
+    );
+    diff.record(
+        ".io_engine.logLevel",
+        Some(installed.io_engine_log_level().to_string()),
+        Some(target.io_engine_log_level().to_string()),
+    );
+    diff.record(
+        ".agents.core.logLevel",
+        installed.core_agent_log_level().map(ToString::to_string),
+        target.core_agent_log_level().map(ToString::to_string),
+    );
+    diff.record(
+        ".agents.core.capacity.thin.poolCommitment",
+        installed
+            .thin_pool_commitment_parsed(installed_version, INSTALLED_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+        target
+            .thin_pool_commitment_parsed(target_version, TARGET_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+    );
+    diff.record(
+        ".agents.core.capacity.thin.volumeCommitment",
+        installed
+            .thin_volume_commitment_parsed(installed_version, INSTALLED_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+        target
+            .thin_volume_commitment_parsed(target_version, TARGET_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+    );
+    diff.record(
+        ".agents.core.capacity.thin.volumeCommitmentInitial",
+        installed
+            .thin_volume_commitment_initial_parsed(installed_version, INSTALLED_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+        target
+            .thin_volume_commitment_initial_parsed(target_version, TARGET_VALUES_SOURCE)
+            .ok()
+            .map(|p| p.to_string()),
+    );
+
+    diff
+}
+
+/// This decides if the io-engine Pod template changes between the rendered io-engine DaemonSet
+/// manifests of the installed release and of the upgrade. The io-engine DaemonSet's update
+/// strategy is OnDelete, so the io-engine Pods only pick up a changed Pod template once they are
+/// restarted. The helm chart version label is not compared, as it changes with every upgrade
+/// without changing the io-engine. This is true if either of the manifests has no DaemonSet, as
+/// the Pod template cannot be compared then.
+pub(crate) fn io_engine_pod_template_changed(
+    installed_manifest: &[u8],
+    target_manifest: &[u8],
+) -> Result<bool> {
+    let installed = daemonset_pod_template(installed_manifest)?;
+    let target = daemonset_pod_template(target_manifest)?;
+    Ok(match (installed, target) {
+        (Some(installed), Some(target)) => installed.ne(&target),
+        _ => true,
+    })
+}
+
+/// This is the Pod template of the first DaemonSet in a rendered manifest, if any. The helm chart
+/// version label is removed from it.
+fn daemonset_pod_template(manifest: &[u8]) -> Result<Option<Value>> {
+    for document in serde_yaml::Deserializer::from_slice(manifest) {
+        let document = Value::deserialize(document).context(YamlParseFromSlice {
+            input_yaml: String::from_utf8_lossy(manifest).to_string(),
+        })?;
+        if document.get("kind").and_then(Value::as_str) == Some("DaemonSet") {
+            let mut template = document
+                .get("spec")
+                .and_then(|spec| spec.get("template"))
+                .cloned();
+            if let Some(labels) = template
+                .as_mut()
+                .and_then(|template| template.get_mut("metadata"))
+                .and_then(|metadata| metadata.get_mut("labels"))
+                .and_then(Value::as_mapping_mut)
+            {
+                labels.remove(CHART_VERSION_LABEL_KEY);
+            }
+            return Ok(template);
+        }
+    }
+
+    Ok(None)
+}
+
+/// This is a line of a line-by-line diff.
+enum DiffLine<'a> {
+    /// The line is in both texts.
+    Equal(&'a str),
+    /// The line is only in the old text.
+    Delete(&'a str),
+    /// The line is only in the new text.
+    Insert(&'a str),
+}
+
+/// This produces a git-style unified diff of the installed values and the effective values, i.e.
+/// the values which helm upgrade would be run with. Both are serialized with their mapping keys
+/// sorted, so that keys which only moved do not show up in the diff. This is empty if the values
+/// are the same.
+pub(crate) fn unified(installed: &Value, effective: &Value) -> String {
+    // Serializing a serde_yaml::Value does not fail, as its mapping keys are yaml values too.
+    let to_yaml = |value: &Value| serde_yaml::to_string(&sort_keys(value)).unwrap_or_default();
+    let (installed_yaml, effective_yaml) = (to_yaml(installed), to_yaml(effective));
+    let old: Vec<&str> = installed_yaml.lines().collect();
+    let new: Vec<&str> = effective_yaml.lines().collect();
+
+    let diff = diff_lines(old.as_slice(), new.as_slice());
+    if diff.iter().all(|line| matches!(line, DiffLine::Equal(_))) {
+        return String::new();
+    }
+
+    // The line numbers of the old and the new text, ahead of each line of the diff.
+    let mut positions: Vec<(usize, usize)> = Vec::with_capacity(diff.len() + 1);
+    let (mut old_line, mut new_line) = (0, 0);
+    for line in diff.iter() {
+        positions.push((old_line, new_line));
+        match line {
+            DiffLine::Equal(_) => (old_line, new_line) = (old_line + 1, new_line + 1),
+            DiffLine::Delete(_) => old_line += 1,
+            DiffLine::Insert(_) => new_line += 1,
+        }
+    }
+    positions.push((old_line, new_line));
+
+    // The hunks are the ranges of the diff with changes, with context lines around them. Hunks
+    // whose context lines overlap are merged.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, line) in diff.iter().enumerate() {
+        if matches!(line, DiffLine::Equal(_)) {
+            continue;
+        }
+        let start = index.saturating_sub(UNIFIED_DIFF_CONTEXT_LINES);
+        let end = (index + UNIFIED_DIFF_CONTEXT_LINES + 1).min(diff.len());
+        match hunks.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = String::from("--- installed values\n+++ upgrade values\n");
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        // Empty ranges start at the line before them, as with diff -u.
+        let range = |start: usize, len: usize| {
+            format!("{},{len}", if len == 0 { start } else { start + 1 })
+        };
+        output.push_str(
+            format!(
+                "@@ -{} +{} @@\n",
+                range(old_start, old_end - old_start),
+                range(new_start, new_end - new_start)
+            )
+            .as_str(),
+        );
+        for line in &diff[start .. end] {
+            let (marker, text) = match line {
+                DiffLine::Equal(text) => (' ', text),
+                DiffLine::Delete(text) => ('-', text),
+                DiffLine::Insert(text) => ('+', text),
+            };
+            output.push(marker);
+            output.push_str(text);
+            output.push('\n');
+        }
+    }
+
+    output
+}
+
+/// This copies a yaml value with the keys of all of its mappings sorted.
+fn sort_keys(value: &Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries: Vec<(&Value, &Value)> = mapping.iter().collect();
+            entries.sort_by_key(|(key, _)| serde_yaml::to_string(key).unwrap_or_default());
+            Value::Mapping(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), sort_keys(value)))
+                    .collect::<Mapping>(),
+            )
+        }
+        Value::Sequence(sequence) => Value::Sequence(sequence.iter().map(sort_keys).collect()),
+        Value::Tagged(tagged) => Value::Tagged(Box::new(TaggedValue {
+            tag: tagged.tag.clone(),
+            value: sort_keys(&tagged.value),
+        })),
+        value => value.clone(),
+    }
+}
+
+/// This computes the line-by-line diff of the old and the new lines, from their longest common
+/// subsequence. Helm values files are small enough for the quadratic table.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut common = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0 .. old.len()).rev() {
+        for j in (0 .. new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Equal(old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            diff.push(DiffLine::Delete(old[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Insert(new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i ..].iter().map(|line| DiffLine::Delete(line)));
+    diff.extend(new[j ..].iter().map(|line| DiffLine::Insert(line)));
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::chart::FromPath;
+    use std::path::Path;
+
+    /// This is the Core helm chart's values, with the image block's registry, repo and tag set.
+    fn core_values_with_image(registry: &str, repo: &str, tag: &str) -> CoreValues {
+        let mut values: Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        values["image"]["registry"] = Value::from(registry);
+        values["image"]["repo"] = Value::from(repo);
+        values["image"]["tag"] = Value::from(tag);
+        CoreValues::from_slice(
+            Path::new("values.yaml"),
+            serde_yaml::to_string(&values).unwrap().as_bytes(),
+        )
+        .unwrap()
+    }
+
+    /// This diffs the installed values and the target values, for an upgrade from 2.4.0 to 2.5.0.
+    fn diff_images(installed: &CoreValues, target: &CoreValues) -> UpgradeValuesDiff {
+        diff_values(
+            installed,
+            &Version::new(2, 4, 0),
+            target,
+            &Version::new(2, 5, 0),
+        )
+    }
+
+    #[test]
+    fn registry_change_requires_image_prepull() {
+        let installed = core_values_with_image("docker.io", "openebs", "v2.4.0");
+        let target = core_values_with_image("registry.local:5000", "openebs", "v2.5.0");
+
+        let diff = diff_images(&installed, &target);
+        assert!(diff.requires_image_prepull());
+        assert!(diff.changes().contains(&FieldChange {
+            path: ".image.registry".to_string(),
+            old: Some("docker.io".to_string()),
+            new: Some("registry.local:5000".to_string()),
+        }));
+    }
+
+    #[test]
+    fn repo_change_requires_image_prepull() {
+        let installed = core_values_with_image("docker.io", "openebs", "v2.4.0");
+        let target = core_values_with_image("docker.io", "mirror/openebs", "v2.4.0");
+        assert!(diff_images(&installed, &target).requires_image_prepull());
+    }
+
+    #[test]
+    fn tag_only_change_does_not_require_image_prepull() {
+        let installed = core_values_with_image("docker.io", "openebs", "v2.4.0");
+        let target = core_values_with_image("docker.io", "openebs", "v2.5.0");
+
+        let diff = diff_images(&installed, &target);
+        assert!(!diff.requires_image_prepull());
+        assert_eq!(
+            diff.changes()
+                .iter()
+                .map(FieldChange::path)
+                .collect::<Vec<_>>(),
+            vec![".image.tag"]
+        );
+    }
+
+    /// This renders a minimal io-engine DaemonSet manifest, like helm template does.
+    fn io_engine_manifest(chart_version: &str, image_tag: &str) -> Vec<u8> {
+        r#"---
+# Source: mayastor/templates/mayastor/io/io-engine-daemonset.yaml
+apiVersion: apps/v1
+kind: DaemonSet
+metadata:
+  name: mayastor-io-engine
+  labels: {openebs.io/version: CHART_VERSION}
+spec:
+  updateStrategy: {type: OnDelete}
+  template:
+    metadata:
+      labels: {openebs.io/version: CHART_VERSION}
+    spec:
+      containers:
+      - {name: io-engine, image: "docker.io/openebs/mayastor-io-engine:IMAGE_TAG"}
+"#
+        .replace("CHART_VERSION", chart_version)
+        .replace("IMAGE_TAG", image_tag)
+        .into_bytes()
+    }
+
+    #[test]
+    fn control_plane_only_change_keeps_io_engine_pod_template() {
+        let installed = io_engine_manifest("2.4.0", "v2.4.0");
+        let target = io_engine_manifest("2.5.0", "v2.4.0");
+        assert!(!io_engine_pod_template_changed(&installed, &target).unwrap());
+    }
+
+    #[test]
+    fn image_tag_change_changes_io_engine_pod_template() {
+        let installed = io_engine_manifest("2.4.0", "v2.4.0");
+        let target = io_engine_manifest("2.5.0", "v2.5.0");
+        assert!(io_engine_pod_template_changed(&installed, &target).unwrap());
+    }
+
+    #[test]
+    fn io_engine_option_change_changes_io_engine_pod_template() {
+        let installed = io_engine_manifest("2.4.0", "v2.4.0");
+        let target = String::from_utf8(io_engine_manifest("2.5.0", "v2.4.0"))
+            .unwrap()
+            .replace(
+                "image: \"docker.io/openebs/mayastor-io-engine:v2.4.0\"}",
+                "image: \"docker.io/openebs/mayastor-io-engine:v2.4.0\", args: [-l2]}",
+            )
+            .into_bytes();
+        assert!(io_engine_pod_template_changed(&installed, &target).unwrap());
+    }
+
+    #[test]
+    fn missing_daemonset_counts_as_changed() {
+        let installed = io_engine_manifest("2.4.0", "v2.4.0");
+        assert!(io_engine_pod_template_changed(&installed, b"").unwrap());
+    }
+
+    #[test]
+    fn unified_diff_shows_the_changed_image_tag() {
+        let installed: Value = serde_yaml::from_str(
+            "image: {registry: docker.io, repo: openebs, tag: v2.4.0}\nio_engine: {logLevel: info}",
+        )
+        .unwrap();
+        let effective: Value = serde_yaml::from_str(
+            "io_engine: {logLevel: info}\nimage: {tag: v2.5.0, repo: openebs, registry: docker.io}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            unified(&installed, &effective),
+            "--- installed values\n+++ upgrade values\n@@ -1,6 +1,6 @@\n image:\n   \
+             registry: docker.io\n   repo: openebs\n-  tag: v2.4.0\n+  tag: v2.5.0\n \
+             io_engine:\n   logLevel: info\n"
+        );
+    }
+
+    #[test]
+    fn reordered_keys_have_no_unified_diff() {
+        let installed: Value = serde_yaml::from_str("{a: 1, b: {c: 2, d: 3}}").unwrap();
+        let effective: Value = serde_yaml::from_str("{b: {d: 3, c: 2}, a: 1}").unwrap();
+        assert!(unified(&installed, &effective).is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    common::error::{ChartFileRead, Error, MultiLoadErrors, Result},
+    helm::chart::{Chart, CoreValues, FromPath},
+};
+use futures::future::join_all;
+use snafu::ResultExt;
+use std::path::{Path, PathBuf};
+
+/// These are the kinds of helm chart files which may be loaded together.
+#[derive(Clone, Copy)]
+pub(crate) enum ChartFileKind {
+    /// A Chart.yaml file.
+    Chart,
+    /// A values.yaml file of the Core helm chart.
+    CoreValues,
+}
+
+/// This is a loaded helm chart file.
+pub(crate) enum ChartFile {
+    /// A deserialized Chart.yaml file.
+    Chart(Chart),
+    /// A deserialized values.yaml file of the Core helm chart.
+    CoreValues(Box<CoreValues>),
+}
+
+/// This reads and deserializes the helm chart files concurrently. The loaded files are returned in
+/// the order of the inputs. If more than one of the files fails to load, all of their errors are
+/// returned together, so that they may all be fixed at once.
+pub(crate) async fn load_chart_files(
+    inputs: Vec<(PathBuf, ChartFileKind)>,
+) -> Result<Vec<ChartFile>> {
+    let results = join_all(
+        inputs
+            .iter()
+            .map(|(path, kind)| load_chart_file(path.as_path(), *kind)),
+    )
+    .await;
+
+    let mut files: Vec<ChartFile> = Vec::with_capacity(inputs.len());
+    let mut failures: Vec<(PathBuf, Error)> = Vec::new();
+    for ((path, _), result) in inputs.into_iter().zip(results) {
+        match result {
+            Ok(file) => files.push(file),
+            Err(error) => failures.push((path, error)),
+        }
+    }
+
+    match failures.len() {
+        0 => Ok(files),
+        // A single error is returned as is.
+        1 => Err(failures.remove(0).1),
+        _ => MultiLoadErrors { failures }.fail(),
+    }
+}
+
+/// This reads and deserializes a helm chart file.
+async fn load_chart_file(path: &Path, kind: ChartFileKind) -> Result<ChartFile> {
+    let yaml = tokio::fs::read(path).await.context(ChartFileRead {
+        path: path.to_path_buf(),
+    })?;
+
+    Ok(match kind {
+        ChartFileKind::Chart => ChartFile::Chart(Chart::from_slice(path, yaml.as_slice())?),
+        ChartFileKind::CoreValues => {
+            ChartFile::CoreValues(Box::new(CoreValues::from_slice(path, yaml.as_slice())?))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const CHART_YAML: &str = include_str!("../../../../../../chart/Chart.yaml");
+    const VALUES_YAML: &str = include_str!("../../../../../../chart/values.yaml");
+
+    /// This writes the helm chart file fixtures into the directory, and returns the path of each
+    /// fixture with the kind it is loaded as.
+    fn fixtures(
+        dir: &Path,
+        files: &[(&str, &str, ChartFileKind)],
+    ) -> Vec<(PathBuf, ChartFileKind)> {
+        files
+            .iter()
+            .map(|(name, contents, kind)| {
+                let path = dir.join(name);
+                fs::write(path.as_path(), contents).unwrap();
+                (path, *kind)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn valid_files_are_loaded_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = fixtures(
+            dir.path(),
+            &[
+                ("values.yaml", VALUES_YAML, ChartFileKind::CoreValues),
+                ("Chart.yaml", CHART_YAML, ChartFileKind::Chart),
+            ],
+        );
+
+        let files = load_chart_files(inputs).await.unwrap();
+        assert!(matches!(
+            files.as_slice(),
+            [ChartFile::CoreValues(_), ChartFile::Chart(chart)] if chart.name() == "mayastor"
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_files_are_reported_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut inputs = fixtures(
+            dir.path(),
+            &[
+                ("Chart.yaml", CHART_YAML, ChartFileKind::Chart),
+                ("broken-Chart.yaml", "name: [mayastor", ChartFileKind::Chart),
+                ("values.yaml", VALUES_YAML, ChartFileKind::CoreValues),
+            ],
+        );
+        let missing = dir.path().join("missing-values.yaml");
+        inputs.push((missing.clone(), ChartFileKind::CoreValues));
+
+        let error = load_chart_files(inputs).await.err().unwrap();
+        let Error::MultiLoadErrors { failures } = error else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].0, dir.path().join("broken-Chart.yaml"));
+        assert!(matches!(failures[0].1, Error::ChartYamlParse { .. }));
+        assert_eq!(failures[1].0, missing);
+        assert!(matches!(failures[1].1, Error::ChartFileRead { .. }));
+    }
+
+    #[tokio::test]
+    async fn single_invalid_file_is_reported_as_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = fixtures(
+            dir.path(),
+            &[
+                ("Chart.yaml", CHART_YAML, ChartFileKind::Chart),
+                ("values.yaml", "image: [", ChartFileKind::CoreValues),
+            ],
+        );
+
+        let error = load_chart_files(inputs).await.err().unwrap();
+        assert!(matches!(error, Error::ChartYamlParse { .. }), "{error}");
+    }
+}
@@ -0,0 +1,196 @@
+use serde_yaml::Value;
+
+/// This merges the 'overrides' yaml on top of the 'base' yaml. Maps are merged key by key,
+/// recursively. Everything else, i.e. scalars and sequences, is replaced by the value in
+/// 'overrides'. If the type of a value differs between 'base' and 'overrides', e.g. a map in
+/// 'base' and a scalar in 'overrides', the value in 'overrides' wins as a whole. An empty
+/// 'overrides' document, i.e. a null, leaves 'base' as it is. A null value at a key inside the
+/// 'overrides' maps still replaces the value in 'base', like with helm.
+pub(crate) fn deep_merge(base: Value, overrides: Value) -> Value {
+    if overrides.is_null() {
+        return base;
+    }
+    merge_values(base, overrides)
+}
+
+/// This merges the 'overrides' yaml value on top of the 'base' yaml value, recursively.
+fn merge_values(base: Value, overrides: Value) -> Value {
+    match (base, overrides) {
+        (Value::Mapping(mut base), Value::Mapping(overrides)) => {
+            for (key, override_value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => {
+                        *base_value = merge_values(std::mem::take(base_value), override_value);
+                    }
+                    None => {
+                        base.insert(key, override_value);
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (_, overrides) => overrides,
+    }
+}
+
+/// This lists the dot-separated key paths of the maps in 'source' which are absent in 'merged',
+/// e.g. 'customKey' or 'io_engine.customKey'. Keys of maps are compared recursively, as long as
+/// the value is a map in both. Values are not compared, so a changed value is not reported.
+pub(crate) fn missing_key_paths(source: &Value, merged: &Value) -> Vec<String> {
+    let mut missing = Vec::new();
+    collect_missing_key_paths(source, merged, "", &mut missing);
+    missing
+}
+
+/// This adds the key paths of 'source' which are absent in 'merged' to 'missing'. The key paths
+/// are prefixed with the key path of 'source'.
+fn collect_missing_key_paths(
+    source: &Value,
+    merged: &Value,
+    prefix: &str,
+    missing: &mut Vec<String>,
+) {
+    let (Value::Mapping(source), Value::Mapping(merged)) = (source, merged) else {
+        return;
+    };
+
+    for (key, source_value) in source {
+        let Some(key_str) = key.as_str() else {
+            continue;
+        };
+        let path = if prefix.is_empty() {
+            key_str.to_string()
+        } else {
+            format!("{prefix}.{key_str}")
+        };
+        match merged.get(key) {
+            Some(merged_value) => {
+                collect_missing_key_paths(source_value, merged_value, path.as_str(), missing)
+            }
+            None => missing.push(path),
+        }
+    }
+}
+
+/// This lists the dot-separated key paths at which 'base' and 'overrides' both have a value, but
+/// of different types, e.g. a number in 'base' and a string in 'overrides'. Null values and keys
+/// which are only in one of the two do not conflict. Maps are compared recursively.
+pub(crate) fn conflicting_type_paths(base: &Value, overrides: &Value) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    collect_conflicting_type_paths(base, overrides, "", &mut conflicts);
+    conflicts
+}
+
+/// This adds the key paths at which 'base' and 'overrides' have values of different types to
+/// 'conflicts'. The key paths are prefixed with the key path of 'base'.
+fn collect_conflicting_type_paths(
+    base: &Value,
+    overrides: &Value,
+    prefix: &str,
+    conflicts: &mut Vec<String>,
+) {
+    match (base, overrides) {
+        (Value::Null, _) | (_, Value::Null) => {}
+        (Value::Mapping(base), Value::Mapping(overrides)) => {
+            for (key, override_value) in overrides {
+                let (Some(base_value), Some(key_str)) = (base.get(key), key.as_str()) else {
+                    continue;
+                };
+                let path = if prefix.is_empty() {
+                    key_str.to_string()
+                } else {
+                    format!("{prefix}.{key_str}")
+                };
+                collect_conflicting_type_paths(
+                    base_value,
+                    override_value,
+                    path.as_str(),
+                    conflicts,
+                );
+            }
+        }
+        (base, overrides) if std::mem::discriminant(base) != std::mem::discriminant(overrides) => {
+            conflicts.push(prefix.to_string())
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This parses the yaml.
+    fn yaml(input: &str) -> Value {
+        serde_yaml::from_str(input).unwrap()
+    }
+
+    #[test]
+    fn deep_merge_merges_maps_recursively() {
+        let merged = deep_merge(
+            yaml("io_engine: {logLevel: info, cpuCount: 2}\nimage: {tag: v1}"),
+            yaml("io_engine: {logLevel: debug}"),
+        );
+        assert_eq!(
+            merged,
+            yaml("io_engine: {logLevel: debug, cpuCount: 2}\nimage: {tag: v1}")
+        );
+    }
+
+    #[test]
+    fn deep_merge_replaces_sequences() {
+        let merged = deep_merge(
+            yaml("io_engine: {coreList: [1, 2, 3]}\nbase: {imagePullSecrets: {secrets: [a]}}"),
+            yaml("io_engine: {coreList: [4]}"),
+        );
+        assert_eq!(
+            merged,
+            yaml("io_engine: {coreList: [4]}\nbase: {imagePullSecrets: {secrets: [a]}}")
+        );
+    }
+
+    #[test]
+    fn deep_merge_replaces_values_whose_type_changes() {
+        let merged = deep_merge(
+            yaml("etcd: {persistence: {size: 2Gi, storageClass: manual}}\nimage: {tag: v1}"),
+            yaml("etcd: {persistence: 2Gi}\nimage: [v2]"),
+        );
+        assert_eq!(merged, yaml("etcd: {persistence: 2Gi}\nimage: [v2]"));
+
+        let merged = deep_merge(yaml("csi: enabled"), yaml("csi: {node: {nvme: {}}}"));
+        assert_eq!(merged, yaml("csi: {node: {nvme: {}}}"));
+    }
+
+    #[test]
+    fn deep_merge_keeps_base_for_empty_overrides() {
+        let base = yaml("io_engine: {logLevel: info}");
+        assert_eq!(deep_merge(base.clone(), Value::Null), base);
+    }
+
+    #[test]
+    fn deep_merge_replaces_values_with_nested_nulls() {
+        let merged = deep_merge(
+            yaml("io_engine: {logLevel: info}"),
+            yaml("io_engine: {logLevel: null}"),
+        );
+        assert_eq!(merged, yaml("io_engine: {logLevel: null}"));
+    }
+
+    #[test]
+    fn conflicting_type_paths_lists_type_changes() {
+        let conflicts = conflicting_type_paths(
+            &yaml("etcd: {replicaCount: 3, persistence: {size: 2Gi}}\nimage: {tag: v1}"),
+            &yaml("etcd: {replicaCount: three, persistence: 2Gi}\nimage: {tag: null}"),
+        );
+        assert_eq!(conflicts, vec!["etcd.replicaCount", "etcd.persistence"]);
+    }
+
+    #[test]
+    fn missing_key_paths_lists_dropped_keys() {
+        let missing = missing_key_paths(
+            &yaml("io_engine: {logLevel: info, customKey: 1}\ncustomTop: true"),
+            &yaml("io_engine: {logLevel: info}"),
+        );
+        assert_eq!(missing, vec!["io_engine.customKey", "customTop"]);
+    }
+}
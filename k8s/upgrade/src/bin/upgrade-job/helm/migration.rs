@@ -0,0 +1,225 @@
+use crate::{
+    common::{
+        constants::{CORE_CHART_NAME, TWO_DOT_ONE},
+        error::{IrreversibleMigration, Result, SemverParse},
+    },
+    helm::merge::deep_merge,
+};
+use semver::Version;
+use serde_yaml::Value;
+use snafu::ResultExt;
+use tracing::info;
+
+/// This is a transformation of the helm values, which is required to move the values of an older
+/// helm chart into the shape which a newer helm chart accepts.
+pub(crate) trait Migration {
+    /// This is a short description of the migration, for logging.
+    fn description(&self) -> &str;
+
+    /// This is a predicate which decides if the migration is required for an upgrade from the
+    /// 'from' version to the 'to' version.
+    fn applies_to(&self, from: &Version, to: &Version) -> Result<bool>;
+
+    /// This transforms the helm values.
+    fn apply(&self, values: &mut Value) -> Result<()>;
+
+    /// This is a predicate for when a rollback to the 'from' version may restore the helm values
+    /// from before the migration.
+    fn is_reversible(&self) -> bool {
+        true
+    }
+}
+
+/// This is the ordered list of all of the migrations. The migrations are applied in this order.
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![Box::new(HoistCoreChartValues)]
+}
+
+/// This applies all of the migrations which are required for an upgrade from the 'from' version
+/// to the 'to' version, in order.
+pub(crate) fn apply_migrations(from: &Version, to: &Version, values: &mut Value) -> Result<()> {
+    for migration in migrations() {
+        if migration.applies_to(from, to)? {
+            info!("Migrating helm values: {}", migration.description());
+            migration.apply(values)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// This fails if a rollback from the 'to' version to the 'from' version would have to reverse a
+/// migration which is not reversible.
+pub(crate) fn validate_reversible(from: &Version, to: &Version) -> Result<()> {
+    validate_reversible_for(migrations().as_slice(), from, to)
+}
+
+/// This is validate_reversible(), for a given list of migrations.
+fn validate_reversible_for(
+    migrations: &[Box<dyn Migration>],
+    from: &Version,
+    to: &Version,
+) -> Result<()> {
+    for migration in migrations {
+        if migration.applies_to(from, to)? && !migration.is_reversible() {
+            return IrreversibleMigration {
+                migration: migration.description(),
+            }
+            .fail();
+        }
+    }
+
+    Ok(())
+}
+
+/// Helm charts older than 2.1.0 could be installed as a dependency of the Umbrella chart, with the
+/// Core chart's values nested under the Core chart's name. The Core chart expects its values at
+/// the top level. This moves the nested values to the top level. Values already set at the top
+/// level take precedence over the nested values.
+struct HoistCoreChartValues;
+
+impl Migration for HoistCoreChartValues {
+    fn description(&self) -> &str {
+        "moving nested Core chart values to the top level"
+    }
+
+    fn applies_to(&self, from: &Version, _to: &Version) -> Result<bool> {
+        let two_dot_one = Version::parse(TWO_DOT_ONE).context(SemverParse {
+            version_string: TWO_DOT_ONE.to_string(),
+        })?;
+
+        Ok(from.lt(&two_dot_one))
+    }
+
+    // A helm rollback restores the helm values of the older helm release revision as they were
+    // stored, i.e. still nested under the Core chart's name.
+    fn is_reversible(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, values: &mut Value) -> Result<()> {
+        let Some(values) = values.as_mapping_mut() else {
+            return Ok(());
+        };
+
+        if !values
+            .get(CORE_CHART_NAME)
+            .is_some_and(|nested| nested.is_mapping())
+        {
+            return Ok(());
+        }
+
+        if let Some(nested) = values.remove(CORE_CHART_NAME) {
+            let top_level = Value::Mapping(std::mem::take(values));
+            if let Value::Mapping(merged) = deep_merge(nested, top_level) {
+                *values = merged;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This is a migration for upgrades to 3.0.0 or later, which cannot be reversed.
+    struct IrreversibleToThree;
+
+    impl Migration for IrreversibleToThree {
+        fn description(&self) -> &str {
+            "rewriting pools"
+        }
+
+        fn applies_to(&self, from: &Version, to: &Version) -> Result<bool> {
+            Ok(from.major < 3 && to.major >= 3)
+        }
+
+        fn apply(&self, _values: &mut Value) -> Result<()> {
+            Ok(())
+        }
+
+        fn is_reversible(&self) -> bool {
+            false
+        }
+    }
+
+    /// This validates a rollback against the real migrations and the irreversible one.
+    fn validate(from: &str, to: &str) -> Result<()> {
+        let mut migrations = migrations();
+        migrations.push(Box::new(IrreversibleToThree));
+        validate_reversible_for(
+            migrations.as_slice(),
+            &Version::parse(from).unwrap(),
+            &Version::parse(to).unwrap(),
+        )
+    }
+
+    #[test]
+    fn tag_only_rollback_is_reversible() {
+        assert!(validate("2.5.0", "2.5.1").is_ok());
+        assert!(validate_reversible(
+            &Version::parse("2.5.0").unwrap(),
+            &Version::parse("2.5.1").unwrap()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn rollback_across_hoisted_values_is_reversible() {
+        assert!(validate("2.0.1", "2.5.0").is_ok());
+    }
+
+    #[test]
+    fn rollback_across_irreversible_migration_is_blocked() {
+        assert!(matches!(
+            validate("2.5.0", "3.0.0"),
+            Err(Error::IrreversibleMigration { migration }) if migration == "rewriting pools"
+        ));
+    }
+
+    /// This migrates the helm values for an upgrade from the 'from' version to 2.5.0.
+    fn migrate(from: &str, values: &str) -> Value {
+        let mut values: Value = serde_yaml::from_str(values).unwrap();
+        apply_migrations(
+            &Version::parse(from).unwrap(),
+            &Version::new(2, 5, 0),
+            &mut values,
+        )
+        .unwrap();
+        values
+    }
+
+    #[test]
+    fn nested_core_chart_values_are_hoisted() {
+        let migrated = migrate(
+            "2.0.1",
+            "mayastor: {io_engine: {logLevel: debug, cpuCount: 2}, image: {tag: v2.0.1}}\n\
+             io_engine: {logLevel: info}",
+        );
+        let expected: Value =
+            serde_yaml::from_str("io_engine: {logLevel: info, cpuCount: 2}\nimage: {tag: v2.0.1}")
+                .unwrap();
+        assert_eq!(migrated, expected);
+    }
+
+    #[test]
+    fn top_level_values_are_not_migrated() {
+        let values = "io_engine: {logLevel: info}\nimage: {tag: v2.0.1}";
+        assert_eq!(
+            migrate("2.0.1", values),
+            serde_yaml::from_str::<Value>(values).unwrap()
+        );
+    }
+
+    #[test]
+    fn values_from_two_dot_one_are_not_migrated() {
+        let values = "mayastor: {io_engine: {logLevel: debug}}";
+        assert_eq!(
+            migrate("2.1.0", values),
+            serde_yaml::from_str::<Value>(values).unwrap()
+        );
+    }
+}
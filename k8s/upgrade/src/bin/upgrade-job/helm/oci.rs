@@ -0,0 +1,687 @@
+use crate::{
+    common::{
+        constants::{HELM_CHART_LAYER_MEDIA_TYPE, OCI_MANIFEST_MEDIA_TYPE, OCI_MAX_REDIRECTS},
+        error::{
+            DockerConfigJsonParse, Error, GetPullSecret, HelmRepoHttpsConnector, HelmRepoUriParse,
+            OciChartExtract, OciChartLayerAbsent, OciLayerDigestMismatch, OciManifestParse,
+            OciReferenceParse, OciRegistryAuthChallenge, OciRegistryHttpStatus, OciRegistryRequest,
+            OciRegistryTokenParse, OciRegistryUnauthorized, OciRequestBuild, OciTagAbsent, Result,
+            TempFileCreation,
+        },
+        kube_client::KubeClientSet,
+    },
+    helm::{
+        chart::{Chart, FromPath},
+        release::load_installed_values,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use flate2::read::GzDecoder;
+use hyper::{
+    body, client::HttpConnector, header, Body, Client, Request, Response, StatusCode, Uri,
+};
+use hyper_openssl::HttpsConnector;
+use openssl::sha::sha256;
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tar::Archive;
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+/// This is a reference to a helm chart in an OCI registry, e.g.
+/// 'oci://registry.example.com/charts/mayastor:2.5.0'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OciReference {
+    registry: String,
+    repository: String,
+    tag: String,
+}
+
+impl FromStr for OciReference {
+    type Err = Error;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let (registry, repository_and_tag) = reference
+            .strip_prefix("oci://")
+            .and_then(|rest| rest.split_once('/'))
+            .context(OciReferenceParse { reference })?;
+        let (repository, tag) = repository_and_tag
+            .rsplit_once(':')
+            .context(OciReferenceParse { reference })?;
+        ensure!(
+            !registry.is_empty() && !repository.is_empty() && !tag.is_empty() && !tag.contains('/'),
+            OciReferenceParse { reference }
+        );
+
+        Ok(Self {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for OciReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "oci://{}/{}:{}",
+            self.registry, self.repository, self.tag
+        )
+    }
+}
+
+/// This is a helm chart pulled from an OCI registry, extracted into a temporary directory. The
+/// directory is removed when this is dropped.
+pub(crate) struct PulledChart {
+    _temp_dir: TempDir,
+    chart_dir: PathBuf,
+}
+
+impl PulledChart {
+    /// This is a getter for the directory of the extracted helm chart.
+    pub(crate) fn chart_dir(&self) -> &Path {
+        self.chart_dir.as_path()
+    }
+}
+
+/// This is used to deserialize the OCI image manifest of a helm chart.
+#[derive(Deserialize)]
+struct OciManifest {
+    layers: Vec<OciDescriptor>,
+}
+
+/// This is used to deserialize a layer of an OCI image manifest.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OciDescriptor {
+    media_type: String,
+    digest: String,
+}
+
+/// This is used to deserialize the bearer token of an OCI registry's token service.
+#[derive(Deserialize)]
+struct OciToken {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// This is used to deserialize the '.dockerconfigjson' of a container image pull secret.
+#[derive(Deserialize)]
+struct DockerConfigJson {
+    auths: HashMap<String, DockerAuth>,
+}
+
+/// This is used to deserialize the credentials of a registry in a '.dockerconfigjson'.
+#[derive(Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// This pulls a helm chart from an OCI registry, and extracts it into a temporary directory. The
+/// registry credentials are read from the container image pull secrets set in the helm values of
+/// the installed release.
+pub(crate) async fn pull_chart(
+    reference: &OciReference,
+    namespace: &str,
+    release_name: &str,
+) -> Result<PulledChart> {
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(namespace)
+        .build()
+        .await?;
+    let installed_values = load_installed_values(&k8s_client, release_name, namespace).await?;
+    let credentials = registry_credentials(
+        &k8s_client,
+        installed_values.image_pull_secrets().as_slice(),
+        reference.registry.as_str(),
+    )
+    .await?;
+
+    let pulled_chart = OciClient::new(reference, credentials)?.pull().await?;
+    let chart = Chart::from_path(pulled_chart.chart_dir().join("Chart.yaml").as_path())?;
+    info!(
+        %reference,
+        chart.name = chart.name(),
+        chart.version = %chart.version(),
+        "Pulled helm chart from OCI registry"
+    );
+
+    Ok(pulled_chart)
+}
+
+/// This is an HTTPS client for the OCI distribution API of a registry. The authorization is set
+/// up when the registry first responds with a challenge.
+struct OciClient<'a> {
+    client: Client<HttpsConnector<HttpConnector>>,
+    reference: &'a OciReference,
+    /// This is the URL of the registry, e.g. 'https://registry.example.com'.
+    base_url: String,
+    /// These are the base64 encoded 'username:password' credentials for the registry, if any.
+    credentials: Option<String>,
+    /// This is the Authorization header value for the registry, once it is known.
+    authorization: Option<String>,
+}
+
+impl<'a> OciClient<'a> {
+    /// This creates an OciClient for the registry of the reference.
+    fn new(reference: &'a OciReference, credentials: Option<String>) -> Result<Self> {
+        let base_url = format!("https://{}", reference.registry);
+        Self::with_base_url(reference, credentials, base_url)
+    }
+
+    /// This creates an OciClient which sends its requests to the registry at 'base_url'.
+    fn with_base_url(
+        reference: &'a OciReference,
+        credentials: Option<String>,
+        base_url: String,
+    ) -> Result<Self> {
+        let connector = HttpsConnector::new().context(HelmRepoHttpsConnector)?;
+        Ok(Self {
+            client: Client::builder().build::<_, Body>(connector),
+            reference,
+            base_url,
+            credentials,
+            authorization: None,
+        })
+    }
+
+    /// This pulls the helm chart layer of the reference, verifies its digest, and extracts it.
+    async fn pull(&mut self) -> Result<PulledChart> {
+        let reference = self.reference;
+        let manifest_url = format!(
+            "{}/v2/{}/manifests/{}",
+            self.base_url, reference.repository, reference.tag
+        );
+        let response = self
+            .get(manifest_url.as_str(), Some(OCI_MANIFEST_MEDIA_TYPE))
+            .await?;
+        ensure!(
+            response.status().ne(&StatusCode::NOT_FOUND),
+            OciTagAbsent {
+                reference: reference.to_string(),
+            }
+        );
+        let manifest = read_body(response, manifest_url.as_str()).await?;
+        let manifest: OciManifest =
+            serde_json::from_slice(manifest.as_slice()).context(OciManifestParse {
+                reference: reference.to_string(),
+            })?;
+
+        let layer = manifest
+            .layers
+            .into_iter()
+            .find(|layer| layer.media_type.eq(HELM_CHART_LAYER_MEDIA_TYPE))
+            .context(OciChartLayerAbsent {
+                reference: reference.to_string(),
+            })?;
+
+        let blob_url = format!(
+            "{}/v2/{}/blobs/{}",
+            self.base_url, reference.repository, layer.digest
+        );
+        let chart_archive = self.get_blob(blob_url).await?;
+        verify_digest(chart_archive.as_slice(), layer.digest.as_str())?;
+
+        extract_chart(chart_archive.as_slice(), reference)
+    }
+
+    /// This sends a GET request to the registry. If the registry responds with an authorization
+    /// challenge, the request is sent again with the authorization.
+    async fn get(&mut self, url: &str, accept: Option<&str>) -> Result<Response<Body>> {
+        let response = self
+            .send(url, accept, self.authorization.clone().as_deref())
+            .await?;
+        if response.status().ne(&StatusCode::UNAUTHORIZED) || self.authorization.is_some() {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let authorization = self.authorize(challenge.as_str(), url).await?;
+        self.authorization = Some(authorization);
+
+        self.send(url, accept, self.authorization.clone().as_deref())
+            .await
+    }
+
+    /// This downloads a blob from the registry. Registries commonly redirect blob downloads to a
+    /// storage service, which is not sent the registry's authorization.
+    async fn get_blob(&mut self, blob_url: String) -> Result<Vec<u8>> {
+        let mut response = self.get(blob_url.as_str(), None).await?;
+        let mut url = blob_url;
+        for _ in 0 .. OCI_MAX_REDIRECTS {
+            if !response.status().is_redirection() {
+                break;
+            }
+            let Some(location) = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|value| value.to_str().ok())
+            else {
+                break;
+            };
+            // The location may be relative to the URL which was redirected.
+            url = url::Url::parse(url.as_str())
+                .and_then(|redirected| redirected.join(location))
+                .map(|location| location.to_string())
+                .unwrap_or_else(|_| location.to_string());
+            response = self.send(url.as_str(), None, None).await?;
+        }
+
+        read_body(response, url.as_str()).await
+    }
+
+    /// This sends a single GET request.
+    async fn send(
+        &self,
+        url: &str,
+        accept: Option<&str>,
+        authorization: Option<&str>,
+    ) -> Result<Response<Body>> {
+        let uri: Uri = url.parse().context(HelmRepoUriParse { url })?;
+        let mut request = Request::get(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let request = request
+            .body(Body::empty())
+            .context(OciRequestBuild { url })?;
+
+        self.client
+            .request(request)
+            .await
+            .context(OciRegistryRequest { url })
+    }
+
+    /// This answers a registry's authorization challenge. 'Basic' challenges are answered with
+    /// the credentials. 'Bearer' challenges are answered with a token from the registry's token
+    /// service, which is requested with the credentials, if there are any.
+    async fn authorize(&self, challenge: &str, url: &str) -> Result<String> {
+        if challenge.starts_with("Basic") {
+            let credentials = self
+                .credentials
+                .as_ref()
+                .context(OciRegistryUnauthorized { url })?;
+            return Ok(format!("Basic {credentials}"));
+        }
+
+        let params = challenge
+            .strip_prefix("Bearer ")
+            .map(challenge_params)
+            .context(OciRegistryAuthChallenge { url, challenge })?;
+        let realm = params
+            .get("realm")
+            .context(OciRegistryAuthChallenge { url, challenge })?;
+        let mut query: Vec<(&str, String)> = vec![(
+            "scope",
+            format!("repository:{}:pull", self.reference.repository),
+        )];
+        if let Some(service) = params.get("service") {
+            query.push(("service", service.clone()));
+        }
+        let token_url = url::Url::parse_with_params(realm, query)
+            .ok()
+            .context(OciRegistryAuthChallenge { url, challenge })?;
+
+        let basic_authorization = self
+            .credentials
+            .as_ref()
+            .map(|credentials| format!("Basic {credentials}"));
+        let response = self
+            .send(token_url.as_str(), None, basic_authorization.as_deref())
+            .await?;
+        ensure!(
+            response.status().ne(&StatusCode::UNAUTHORIZED),
+            OciRegistryUnauthorized { url }
+        );
+        let token = read_body(response, token_url.as_str()).await?;
+        let token: OciToken =
+            serde_json::from_slice(token.as_slice()).context(OciRegistryTokenParse {
+                url: token_url.to_string(),
+            })?;
+        let token = token
+            .token
+            .or(token.access_token)
+            .context(OciRegistryUnauthorized { url })?;
+
+        Ok(format!("Bearer {token}"))
+    }
+}
+
+/// This reads the body of a successful response.
+async fn read_body(response: Response<Body>, url: &str) -> Result<Vec<u8>> {
+    ensure!(
+        response.status().is_success(),
+        OciRegistryHttpStatus {
+            url,
+            status: response.status().as_u16(),
+        }
+    );
+
+    let bytes = body::to_bytes(response.into_body())
+        .await
+        .context(OciRegistryRequest { url })?;
+
+    Ok(bytes.to_vec())
+}
+
+/// This parses the comma-separated key="value" parameters of an authorization challenge. The
+/// values may contain commas, e.g. scope="repository:charts/mayastor:pull,push".
+fn challenge_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value_and_rest)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, remaining) = match value_and_rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value_and_rest
+                .split_once(',')
+                .unwrap_or((value_and_rest, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = remaining.trim();
+    }
+    parsed
+}
+
+/// This verifies the sha256 digest of a downloaded blob. Digests of other algorithms are not
+/// verified.
+fn verify_digest(blob: &[u8], digest: &str) -> Result<()> {
+    let Some(expected) = digest.strip_prefix("sha256:") else {
+        warn!(
+            digest,
+            "Skipping verification of a non-sha256 OCI layer digest"
+        );
+        return Ok(());
+    };
+
+    let actual: String = sha256(blob)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    ensure!(
+        actual.eq(expected),
+        OciLayerDigestMismatch {
+            digest,
+            actual: format!("sha256:{actual}"),
+        }
+    );
+
+    Ok(())
+}
+
+/// This extracts a helm chart .tgz into a temporary directory. Helm chart archives have the chart
+/// inside of a top-level directory named after the chart.
+fn extract_chart(chart_archive: &[u8], reference: &OciReference) -> Result<PulledChart> {
+    let temp_dir = TempDir::new().context(TempFileCreation)?;
+    Archive::new(GzDecoder::new(chart_archive))
+        .unpack(temp_dir.path())
+        .context(OciChartExtract {
+            reference: reference.to_string(),
+        })?;
+
+    let chart_dir = fs::read_dir(temp_dir.path())
+        .context(OciChartExtract {
+            reference: reference.to_string(),
+        })?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.join("Chart.yaml").is_file())
+        .unwrap_or_else(|| temp_dir.path().to_path_buf());
+
+    Ok(PulledChart {
+        _temp_dir: temp_dir,
+        chart_dir,
+    })
+}
+
+/// This returns the base64 encoded 'username:password' credentials for a registry from a
+/// '.dockerconfigjson', if it has any. The servers of a '.dockerconfigjson' may be URLs.
+fn docker_config_credentials(docker_config: DockerConfigJson, registry: &str) -> Option<String> {
+    docker_config
+        .auths
+        .into_iter()
+        .find(|(server, _)| {
+            server
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .is_some_and(|host| host.eq(registry))
+        })
+        .and_then(
+            |(_, auth)| match (auth.auth, auth.username, auth.password) {
+                (Some(auth), _, _) => Some(auth),
+                (None, Some(username), Some(password)) => {
+                    Some(STANDARD.encode(format!("{username}:{password}")))
+                }
+                _ => None,
+            },
+        )
+}
+
+/// This returns the base64 encoded 'username:password' credentials for a registry, from the
+/// '.dockerconfigjson' of the first of the container image pull secrets which has them.
+async fn registry_credentials(
+    k8s_client: &KubeClientSet,
+    secret_names: &[&str],
+    registry: &str,
+) -> Result<Option<String>> {
+    for &name in secret_names {
+        let Some(secret) = k8s_client
+            .secrets_api()
+            .get_opt(name)
+            .await
+            .context(GetPullSecret { name })?
+        else {
+            warn!(secret.name = %name, "Container image pull secret not found");
+            continue;
+        };
+
+        let Some(docker_config) = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(".dockerconfigjson"))
+        else {
+            continue;
+        };
+        let docker_config: DockerConfigJson = serde_json::from_slice(docker_config.0.as_slice())
+            .context(DockerConfigJsonParse { name })?;
+
+        let credentials = docker_config_credentials(docker_config, registry);
+        if credentials.is_some() {
+            info!(secret.name = %name, registry, "Using OCI registry credentials from pull secret");
+            return Ok(credentials);
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Server,
+    };
+    use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+    /// These are the base64 encoded credentials of the mocked registry, i.e. 'user:pass'.
+    const CREDENTIALS: &str = "dXNlcjpwYXNz";
+
+    /// This is the Chart.yaml of the helm chart in the mocked registry.
+    const CHART_YAML: &str = "apiVersion: v2\nname: mayastor\nversion: 2.5.0\n";
+
+    /// This is a mocked OCI registry, which serves a helm chart at 'charts/mayastor:2.5.0' behind
+    /// 'Basic' authorization, and redirects its blob to a storage path.
+    struct MockRegistry {
+        chart_archive: Vec<u8>,
+        digest: String,
+    }
+
+    impl MockRegistry {
+        /// This creates a MockRegistry with a small helm chart .tgz.
+        fn new() -> Self {
+            let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(CHART_YAML.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, "mayastor/Chart.yaml", CHART_YAML.as_bytes())
+                .unwrap();
+            let chart_archive = builder.into_inner().unwrap().finish().unwrap();
+            let digest: String = sha256(chart_archive.as_slice())
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            Self {
+                chart_archive,
+                digest: format!("sha256:{digest}"),
+            }
+        }
+
+        /// This responds to a request to the registry.
+        fn respond(&self, request: Request<Body>) -> Response<Body> {
+            let path = request.uri().path();
+            if path.eq("/storage/chart.tgz") {
+                return Response::new(Body::from(self.chart_archive.clone()));
+            }
+
+            let authorized = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .is_some_and(|value| value.eq(format!("Basic {CREDENTIALS}").as_str()));
+            if !authorized {
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Basic realm=\"mock\"")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+
+            if path.eq("/v2/charts/mayastor/manifests/2.5.0") {
+                let manifest = serde_json::json!({
+                    "layers": [{
+                        "mediaType": HELM_CHART_LAYER_MEDIA_TYPE,
+                        "digest": self.digest,
+                    }]
+                });
+                return Response::new(Body::from(manifest.to_string()));
+            }
+            if path.eq(format!("/v2/charts/mayastor/blobs/{}", self.digest).as_str()) {
+                return Response::builder()
+                    .status(StatusCode::TEMPORARY_REDIRECT)
+                    .header(header::LOCATION, "/storage/chart.tgz")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+
+    /// This serves a MockRegistry on a local port, and returns its URL.
+    fn serve_mock_registry() -> String {
+        let registry = Arc::new(MockRegistry::new());
+        let make_service = make_service_fn(move |_| {
+            let registry = registry.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = registry.respond(request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn chart_is_pulled_with_credentials() {
+        let base_url = serve_mock_registry();
+        let reference: OciReference = "oci://registry.example.com/charts/mayastor:2.5.0"
+            .parse()
+            .unwrap();
+
+        let pulled_chart =
+            OciClient::with_base_url(&reference, Some(CREDENTIALS.to_string()), base_url)
+                .unwrap()
+                .pull()
+                .await
+                .unwrap();
+        assert_eq!(
+            fs::read_to_string(pulled_chart.chart_dir().join("Chart.yaml")).unwrap(),
+            CHART_YAML
+        );
+    }
+
+    #[tokio::test]
+    async fn absent_tag_is_a_clear_error() {
+        let base_url = serve_mock_registry();
+        let reference: OciReference = "oci://registry.example.com/charts/mayastor:9.9.9"
+            .parse()
+            .unwrap();
+
+        let result = OciClient::with_base_url(&reference, Some(CREDENTIALS.to_string()), base_url)
+            .unwrap()
+            .pull()
+            .await;
+        assert!(matches!(result, Err(Error::OciTagAbsent { .. })));
+    }
+
+    #[tokio::test]
+    async fn pull_without_credentials_is_unauthorized() {
+        let base_url = serve_mock_registry();
+        let reference: OciReference = "oci://registry.example.com/charts/mayastor:2.5.0"
+            .parse()
+            .unwrap();
+
+        let result = OciClient::with_base_url(&reference, None, base_url)
+            .unwrap()
+            .pull()
+            .await;
+        assert!(matches!(result, Err(Error::OciRegistryUnauthorized { .. })));
+    }
+
+    #[test]
+    fn pull_secret_credentials_match_the_registry() {
+        let docker_config = r#"{"auths": {
+            "https://registry.example.com/v1/": {"username": "user", "password": "pass"},
+            "other.example.com": {"auth": "b3RoZXI6b3RoZXI="}
+        }}"#;
+
+        let parse = || serde_json::from_str::<DockerConfigJson>(docker_config).unwrap();
+        assert_eq!(
+            docker_config_credentials(parse(), "registry.example.com").as_deref(),
+            Some(CREDENTIALS)
+        );
+        assert_eq!(
+            docker_config_credentials(parse(), "other.example.com").as_deref(),
+            Some("b3RoZXI6b3RoZXI=")
+        );
+        assert_eq!(docker_config_credentials(parse(), "ghcr.io"), None);
+    }
+}
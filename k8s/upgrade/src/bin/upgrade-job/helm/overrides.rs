@@ -0,0 +1,363 @@
+use crate::{
+    common::error::{
+        Error, ReadingFile, Result, SerializeValuesYaml, SetValueParse, ValuesDocumentsConflict,
+        YamlParseFromFile, YamlParseFromSlice,
+    },
+    helm::{
+        merge::{conflicting_type_paths, deep_merge},
+        redact::redact,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::info;
+
+/// This is a 'key=value' pair from the --set option. The key is a dot-separated path into the
+/// helm values, e.g. 'etcd.persistence.size=4Gi'. Dots which are part of a key are escaped with a
+/// backslash, e.g. 'nodeSelector.kubernetes\.io/arch=amd64'.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct SetValue {
+    path: Vec<String>,
+    value: Value,
+}
+
+impl FromStr for SetValue {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (path, value) = Self::parse_key_value(input)?;
+
+        // Like helm, scalars are typed, e.g. 'true' is a boolean and '3' is an integer. Anything
+        // which isn't a scalar, and the empty value, is a string.
+        let value = match serde_yaml::from_str::<Value>(value) {
+            Ok(scalar @ (Value::Bool(_) | Value::Number(_))) => scalar,
+            Ok(Value::Null) if !value.is_empty() => Value::Null,
+            Ok(_) | Err(_) => Value::String(value.to_string()),
+        };
+
+        Ok(Self { path, value })
+    }
+}
+
+impl SetValue {
+    /// This parses a 'key=value' pair from the --set-string option. Unlike with --set, the value
+    /// is always a string, e.g. the image tag in 'image.tag=2024'.
+    pub(crate) fn parse_string(input: &str) -> Result<Self> {
+        let (path, value) = Self::parse_key_value(input)?;
+
+        Ok(Self {
+            path,
+            value: Value::String(value.to_string()),
+        })
+    }
+
+    /// This splits a 'key=value' pair into the key's path and the unparsed value.
+    fn parse_key_value(input: &str) -> Result<(Vec<String>, &str)> {
+        let (key, value) = input.split_once('=').context(SetValueParse { input })?;
+
+        let mut path: Vec<String> = Vec::new();
+        let mut segment = String::new();
+        let mut chars = key.trim().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('.') => segment.push('.'),
+                    Some(other) => segment.extend(['\\', other]),
+                    None => segment.push('\\'),
+                },
+                '.' => path.push(std::mem::take(&mut segment)),
+                other => segment.push(other),
+            }
+        }
+        path.push(segment);
+        ensure!(
+            path.iter().all(|segment| !segment.is_empty()),
+            SetValueParse { input }
+        );
+
+        Ok((path, value))
+    }
+
+    /// This redacts the value, if the key's path matches any of the redacted key paths.
+    fn redact<S: AsRef<str>>(&mut self, paths: &[S]) {
+        let mut yaml = self.to_yaml();
+        redact(&mut yaml, paths);
+        if let Some(value) = self
+            .path
+            .iter()
+            .try_fold(&yaml, |value, key| value.get(key.as_str()))
+        {
+            self.value = value.clone();
+        }
+    }
+
+    /// This builds the yaml map which sets the value at the key's path.
+    fn to_yaml(&self) -> Value {
+        self.path
+            .iter()
+            .rev()
+            .fold(self.value.clone(), |value, key| {
+                let mut map = Mapping::new();
+                map.insert(Value::String(key.clone()), value);
+                Value::Mapping(map)
+            })
+    }
+}
+
+/// These are the helm values which the operator sets at upgrade time, with --values-file, --set
+/// and --set-string. Like with helm, later values files win over earlier ones, --set wins over
+/// all of the values files, and --set-string wins over --set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct ValuesOverrides {
+    values_files: Vec<PathBuf>,
+    set_values: Vec<SetValue>,
+    set_string_values: Vec<SetValue>,
+}
+
+impl ValuesOverrides {
+    /// This creates the overrides from the values files, the --set values and the --set-string
+    /// values, in the order they were specified in.
+    pub(crate) fn new(
+        values_files: Vec<PathBuf>,
+        set_values: Vec<SetValue>,
+        set_string_values: Vec<SetValue>,
+    ) -> Self {
+        Self {
+            values_files,
+            set_values,
+            set_string_values,
+        }
+    }
+
+    /// This redacts the --set and the --set-string values whose keys match any of the redacted key
+    /// paths. The values files are only referred to by their paths, and are left as is.
+    pub(crate) fn redact<S: AsRef<str>>(&mut self, paths: &[S]) {
+        for set_value in self
+            .set_values
+            .iter_mut()
+            .chain(self.set_string_values.iter_mut())
+        {
+            set_value.redact(paths);
+        }
+    }
+
+    /// This deep-merges the overrides on top of the values yaml.
+    pub(crate) fn apply(&self, values_yaml: Vec<u8>) -> Result<Vec<u8>> {
+        if self.values_files.is_empty()
+            && self.set_values.is_empty()
+            && self.set_string_values.is_empty()
+        {
+            return Ok(values_yaml);
+        }
+
+        let parse = |yaml: &[u8]| -> Result<Value> {
+            serde_yaml::from_slice(yaml).context(YamlParseFromSlice {
+                input_yaml: String::from_utf8_lossy(yaml).to_string(),
+            })
+        };
+
+        let mut values = parse(values_yaml.as_slice())?;
+        for filepath in self.values_files.iter() {
+            values = deep_merge(values, load_values_file(filepath)?);
+            info!(filepath = %filepath.display(), "Applied helm values overrides from file");
+        }
+        for set_value in self.set_values.iter() {
+            values = deep_merge(values, set_value.to_yaml());
+            info!(key = %set_value.path.join("."), "Applied helm values override from --set");
+        }
+        for set_value in self.set_string_values.iter() {
+            values = deep_merge(values, set_value.to_yaml());
+            info!(
+                key = %set_value.path.join("."),
+                "Applied helm values override from --set-string"
+            );
+        }
+
+        serde_yaml::to_string(&values)
+            .map(String::into_bytes)
+            .context(SerializeValuesYaml)
+    }
+}
+
+/// This reads a helm values file, which may be a stream of yaml documents. The documents are
+/// deep-merged in order, so later documents win over earlier ones. Documents which set values of
+/// different types at the same key path are rejected, as that is more likely to be a mistake than
+/// an intended override. Empty documents, e.g. after a trailing '---', are skipped.
+pub(crate) fn load_values_file(filepath: &Path) -> Result<Value> {
+    let yaml = fs::read(filepath).context(ReadingFile {
+        filepath: filepath.to_path_buf(),
+    })?;
+
+    let mut values = Value::Null;
+    for document in serde_yaml::Deserializer::from_slice(yaml.as_slice()) {
+        let document = Value::deserialize(document).context(YamlParseFromFile {
+            filepath: filepath.to_path_buf(),
+        })?;
+        if document.is_null() {
+            continue;
+        }
+
+        let paths = conflicting_type_paths(&values, &document);
+        ensure!(
+            paths.is_empty(),
+            ValuesDocumentsConflict {
+                filepath: filepath.to_path_buf(),
+                paths,
+            }
+        );
+        values = deep_merge(values, document);
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::chart::Image;
+    use std::io::Write;
+
+    /// This writes a values file with the contents, which is removed when it is dropped.
+    fn values_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    /// This parses a yaml value.
+    fn yaml(input: &str) -> Value {
+        serde_yaml::from_str(input).unwrap()
+    }
+
+    #[test]
+    fn load_values_file_merges_two_documents() {
+        let file = values_file(
+            "io_engine: {logLevel: info, cpuCount: 2}\n---\nio_engine: {logLevel: debug}\n",
+        );
+        let values = load_values_file(file.path()).unwrap();
+        assert_eq!(values, yaml("io_engine: {logLevel: debug, cpuCount: 2}"));
+    }
+
+    #[test]
+    fn load_values_file_skips_empty_documents() {
+        let file = values_file("io_engine: {logLevel: debug}\n---\n# comment\n---\n");
+        let values = load_values_file(file.path()).unwrap();
+        assert_eq!(values, yaml("io_engine: {logLevel: debug}"));
+    }
+
+    #[test]
+    fn load_values_file_rejects_conflicting_types() {
+        let file = values_file("etcd: {replicaCount: 3}\n---\netcd: {replicaCount: [3]}\n");
+        let error = load_values_file(file.path()).unwrap_err();
+        let Error::ValuesDocumentsConflict { paths, .. } = error else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(paths, vec!["etcd.replicaCount"]);
+    }
+
+    #[test]
+    fn apply_keeps_values_for_empty_values_file() {
+        let file = values_file("---\n");
+        let overrides = ValuesOverrides::new(vec![file.path().to_path_buf()], vec![], vec![]);
+        let values = overrides.apply(b"image: {tag: v2.4.0}\n".to_vec()).unwrap();
+        assert_eq!(
+            serde_yaml::from_slice::<Value>(values.as_slice()).unwrap(),
+            yaml("image: {tag: v2.4.0}")
+        );
+    }
+
+    #[test]
+    fn later_values_files_win() {
+        let first = values_file("etcd: {replicaCount: 3, persistence: {size: 2Gi}}\n");
+        let second = values_file("etcd: {persistence: {size: 4Gi}}\n");
+        let overrides = ValuesOverrides::new(
+            vec![first.path().to_path_buf(), second.path().to_path_buf()],
+            vec!["etcd.persistence.size=8Gi".parse().unwrap()],
+            vec![],
+        );
+        let values = overrides
+            .apply(b"etcd: {replicaCount: 1}\n".to_vec())
+            .unwrap();
+        assert_eq!(
+            serde_yaml::from_slice::<Value>(values.as_slice()).unwrap(),
+            yaml("etcd: {replicaCount: 3, persistence: {size: 8Gi}}")
+        );
+    }
+
+    #[test]
+    fn set_value_parses_nested_keys() {
+        let set_value: SetValue = "mayastor.image.tag=x".parse().unwrap();
+        assert_eq!(set_value.to_yaml(), yaml("mayastor: {image: {tag: x}}"));
+
+        let set_value: SetValue = "nodeSelector.kubernetes\\.io/arch=amd64".parse().unwrap();
+        assert_eq!(
+            set_value.to_yaml(),
+            yaml("nodeSelector: {kubernetes.io/arch: amd64}")
+        );
+
+        let set_value: SetValue = "etcd.replicaCount=3".parse().unwrap();
+        assert_eq!(set_value.to_yaml(), yaml("etcd: {replicaCount: 3}"));
+        let set_value = SetValue::parse_string("image.tag=2024").unwrap();
+        assert_eq!(set_value.to_yaml(), yaml("image: {tag: '2024'}"));
+    }
+
+    /// This applies the --set and the --set-string values to the chart's values, and reads the
+    /// image block from the re-serialized values yaml.
+    fn apply_to_chart_image(
+        set_values: &[&str],
+        set_string_values: &[&str],
+    ) -> (String, serde_yaml::Result<Image>) {
+        let overrides = ValuesOverrides::new(
+            vec![],
+            set_values
+                .iter()
+                .map(|input| input.parse().unwrap())
+                .collect(),
+            set_string_values
+                .iter()
+                .map(|input| SetValue::parse_string(input).unwrap())
+                .collect(),
+        );
+        let values = overrides
+            .apply(include_bytes!("../../../../../../chart/values.yaml").to_vec())
+            .unwrap();
+        let values_yaml = String::from_utf8(values).unwrap();
+        let values: Value = serde_yaml::from_str(values_yaml.as_str()).unwrap();
+        (values_yaml, serde_yaml::from_value(values["image"].clone()))
+    }
+
+    #[test]
+    fn set_string_numeric_tag_stays_a_string() {
+        let (values_yaml, image) = apply_to_chart_image(&[], &["image.tag=2024"]);
+
+        assert!(values_yaml.contains("tag: '2024'"), "{values_yaml}");
+        assert_eq!(image.unwrap().tag(), "2024");
+    }
+
+    #[test]
+    fn set_numeric_tag_is_an_integer() {
+        let (values_yaml, image) = apply_to_chart_image(&["image.tag=2024"], &[]);
+
+        assert!(values_yaml.contains("tag: 2024\n"), "{values_yaml}");
+        assert!(image.is_err());
+    }
+
+    #[test]
+    fn set_string_wins_over_set() {
+        let (_, image) = apply_to_chart_image(&["image.tag=2023"], &["image.tag=2024"]);
+        assert_eq!(image.unwrap().tag(), "2024");
+    }
+
+    #[test]
+    fn set_value_rejects_empty_segments() {
+        for input in ["image..tag=x", ".image=x", "image.=x", "=x", "image.tag"] {
+            assert!(input.parse::<SetValue>().is_err(), "{input}");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::common::constants::REDACTED_VALUE;
+use serde_yaml::Value;
+
+/// This replaces the values at the key paths which match any of the patterns with "***", in
+/// place. A pattern is a dot-separated key path, e.g. 'image.pullSecrets', and a '*' segment in it
+/// matches any number of keys, e.g. '*.password' matches a 'password' key at any depth. The items
+/// of a sequence are at the key path of the sequence.
+pub(crate) fn redact<S: AsRef<str>>(values: &mut Value, paths: &[S]) {
+    redact_at(values, &mut Vec::new(), paths);
+}
+
+/// This redacts the values nested in the value, whose key path is 'path'.
+fn redact_at<S: AsRef<str>>(value: &mut Value, path: &mut Vec<String>, patterns: &[S]) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, nested) in mapping.iter_mut() {
+                let Some(key) = key.as_str() else {
+                    continue;
+                };
+                path.push(key.to_string());
+                if is_redacted(path.as_slice(), patterns) {
+                    *nested = Value::String(REDACTED_VALUE.to_string());
+                } else {
+                    redact_at(nested, path, patterns);
+                }
+                path.pop();
+            }
+        }
+        Value::Sequence(sequence) => {
+            for item in sequence.iter_mut() {
+                redact_at(item, path, patterns);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// This is a predicate for a key path which matches any of the patterns.
+pub(crate) fn is_redacted<K: AsRef<str>, S: AsRef<str>>(path: &[K], patterns: &[S]) -> bool {
+    let path: Vec<&str> = path.iter().map(AsRef::as_ref).collect();
+    patterns.iter().any(|pattern| {
+        let pattern: Vec<&str> = pattern.as_ref().split('.').collect();
+        matches(pattern.as_slice(), path.as_slice())
+    })
+}
+
+/// This matches the key path against the pattern's segments. A '*' segment matches any number of
+/// keys, including none.
+fn matches(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((&"*", rest)), _) => {
+            matches(rest, path) || (!path.is_empty() && matches(pattern, &path[1 ..]))
+        }
+        (Some((segment, rest)), Some((key, path_rest))) => {
+            segment.eq(key) && matches(rest, path_rest)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::constants::DEFAULT_REDACTED_VALUES_PATHS;
+
+    /// This parses a yaml value.
+    fn yaml(input: &str) -> Value {
+        serde_yaml::from_str(input).unwrap()
+    }
+
+    #[test]
+    fn default_paths_hide_passwords_and_keep_the_structure() {
+        let mut values = yaml(
+            "etcd:
+  auth:
+    rbac:
+      create: true
+      rootPassword: hunter2
+  replicaCount: 3
+image:
+  registry: docker.io
+  pullSecrets: [regcred]
+loki:
+  stores:
+    - name: s3
+      password: s3cret
+",
+        );
+        redact(&mut values, &DEFAULT_REDACTED_VALUES_PATHS);
+
+        assert_eq!(
+            values,
+            yaml(
+                "etcd:
+  auth:
+    rbac:
+      create: true
+      rootPassword: '***'
+  replicaCount: 3
+image:
+  registry: docker.io
+  pullSecrets: '***'
+loki:
+  stores:
+    - name: s3
+      password: '***'
+"
+            )
+        );
+    }
+
+    #[test]
+    fn overridden_paths_replace_the_defaults() {
+        let mut values = yaml("etcd: {auth: {token: abc}}\nimage: {registry: registry.local}\n");
+        redact(&mut values, &["image.registry"]);
+
+        assert_eq!(
+            values,
+            yaml("etcd: {auth: {token: abc}}\nimage: {registry: '***'}\n")
+        );
+    }
+
+    #[test]
+    fn wildcard_matches_any_number_of_keys() {
+        for path in [
+            &["password"][..],
+            &["etcd", "password"],
+            &["a", "b", "password"],
+        ] {
+            assert!(is_redacted(path, &["*.password"]), "{path:?}");
+        }
+        assert!(!is_redacted(&["password", "hint"], &["*.password"]));
+        assert!(!is_redacted(&["etcd", "passwordHint"], &["*.password"]));
+        assert!(!is_redacted(&["pullSecrets"], &["image.pullSecrets"]));
+    }
+}
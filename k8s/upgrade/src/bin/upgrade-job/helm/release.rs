@@ -0,0 +1,505 @@
+use crate::{
+    common::{
+        constants::{HELM_RELEASE_OWNER_LABEL, HELM_RELEASE_VERSION_LABEL_KEY},
+        error::{
+            Base64DecodeHelmRelease, GzipDecodeHelmRelease, HelmReleaseSecretAbsent,
+            HelmReleaseSecretDataAbsent, JsonParseHelmRelease, ListSecretsWithLabel,
+            NoPreviousHelmRelease, ReleaseNotFound, Result,
+        },
+        kube_client::KubeClientSet,
+    },
+    helm::{
+        chart::{deserialize_with_key_path, Chart, CoreValues},
+        merge::deep_merge,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use flate2::read::GzDecoder;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{api::ListParams, ResourceExt};
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::{collections::BTreeSet, io::Read};
+
+/// This is the key in the Kubernetes Secret's data which holds the helm release payload.
+const HELM_RELEASE_SECRET_DATA_KEY: &str = "release";
+
+/// This is the label on a helm release Secret which carries the helm release name.
+const HELM_RELEASE_NAME_LABEL_KEY: &str = "name";
+
+/// These are the states of the helm release revisions which may be the current revision, i.e. the
+/// revision which an upgrade starts from.
+const CURRENT_RELEASE_STATUSES: [&str; 2] = ["deployed", "failed"];
+
+/// This is the number of helm release names in the namespace to suggest, when the helm release
+/// is not found.
+const MAX_RELEASE_NAME_SUGGESTIONS: usize = 3;
+
+/// This is used to deserialize the helm release payload, which helm stores in a Kubernetes Secret
+/// for every revision of a helm release.
+#[derive(Deserialize)]
+struct ReleasePayload {
+    /// This contains the helm chart which was installed or upgraded to in this revision.
+    chart: ReleaseChart,
+    /// These are the helm values which were set by the user in this revision.
+    #[serde(default)]
+    config: serde_yaml::Value,
+}
+
+/// This is used to deserialize the helm chart inside of the helm release payload.
+#[derive(Deserialize)]
+struct ReleaseChart {
+    /// This is the Chart.yaml file of the helm chart.
+    metadata: Chart,
+    /// These are the default helm values of the helm chart, i.e. its values.yaml.
+    #[serde(default)]
+    values: serde_yaml::Value,
+}
+
+/// This is a revision of a helm release, which may be rolled back to.
+pub(crate) struct ReleaseRevision {
+    /// The helm release revision number.
+    revision: u32,
+    /// The Chart.yaml of the helm chart which was installed or upgraded to in this revision.
+    chart: Chart,
+}
+
+impl ReleaseRevision {
+    /// This is a getter for the helm release revision number.
+    pub(crate) fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// This is a getter for the Chart.yaml of the helm chart of this revision.
+    pub(crate) fn chart(&self) -> &Chart {
+        &self.chart
+    }
+}
+
+/// This returns the most recent revision of the helm release which was deployed before the
+/// deployed revision, i.e. the revision which a rollback would restore.
+pub(crate) async fn load_previous_release(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<ReleaseRevision> {
+    let deployed_label_selector =
+        format!("{HELM_RELEASE_OWNER_LABEL},name={release_name},status=deployed");
+    let deployed_revision =
+        list_release_secrets(k8s_client, deployed_label_selector.as_str(), namespace)
+            .await?
+            .iter()
+            .map(release_revision)
+            .max()
+            .unwrap_or_default();
+
+    // Helm marks the revisions which were deployed once, and then upgraded from, as superseded.
+    let superseded_label_selector =
+        format!("{HELM_RELEASE_OWNER_LABEL},name={release_name},status=superseded");
+    let secret = list_release_secrets(k8s_client, superseded_label_selector.as_str(), namespace)
+        .await?
+        .into_iter()
+        .filter(|secret| release_revision(secret) < deployed_revision)
+        .max_by_key(release_revision)
+        .ok_or(
+            NoPreviousHelmRelease {
+                release_name: release_name.to_string(),
+                namespace: namespace.to_string(),
+            }
+            .build(),
+        )?;
+
+    let revision = release_revision(&secret);
+    let payload = decode_release_payload(&secret, namespace)?;
+
+    Ok(ReleaseRevision {
+        revision,
+        chart: payload.chart.metadata,
+    })
+}
+
+/// This returns the Chart.yaml of the helm chart which is installed as the current revision of
+/// the helm release, see current_release_secret.
+pub(crate) async fn load_installed_chart(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<Chart> {
+    let payload = current_release_payload(k8s_client, release_name, namespace).await?;
+
+    Ok(payload.chart.metadata)
+}
+
+/// This returns the helm values of the current revision of the helm release, i.e. the user's
+/// values merged on top of the helm chart's default values. This is the same as the output of
+/// 'helm get values --all', read without the helm CLI.
+pub(crate) async fn load_installed_values(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<CoreValues> {
+    let payload = current_release_payload(k8s_client, release_name, namespace).await?;
+
+    release_values(payload)
+}
+
+/// This merges the user's helm values of a helm release revision on top of the default values of
+/// its helm chart.
+fn release_values(payload: ReleasePayload) -> Result<CoreValues> {
+    let values = match payload.config {
+        // There are no user values if the release was installed without any.
+        serde_yaml::Value::Null => payload.chart.values,
+        config => deep_merge(payload.chart.values, config),
+    };
+
+    deserialize_with_key_path(values)
+}
+
+/// This finds the Kubernetes Secret for the current revision of the helm release, and decodes
+/// the helm release payload inside of it.
+async fn current_release_payload(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<ReleasePayload> {
+    let secret = current_release_secret(k8s_client, release_name, namespace).await?;
+
+    decode_release_payload(&secret, namespace)
+}
+
+/// This finds the Kubernetes Secret for the current revision of the helm release, i.e. the most
+/// recent revision which is either deployed or failed. Helm keeps the release object of each
+/// revision in a Secret. A failed upgrade leaves the failed revision as the most recent one, and
+/// helm upgrades from it, so it is the current revision until an upgrade or a rollback succeeds.
+pub(crate) async fn current_release_secret(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<Secret> {
+    let label_selector = format!(
+        "{HELM_RELEASE_OWNER_LABEL},name={release_name},status in ({})",
+        CURRENT_RELEASE_STATUSES.join(",")
+    );
+    let secrets = list_release_secrets(k8s_client, label_selector.as_str(), namespace).await?;
+
+    if let Some(secret) = latest_revision(secrets) {
+        return Ok(secret);
+    }
+
+    ensure_release_exists(k8s_client, release_name, namespace).await?;
+
+    // The helm release exists, but none of its revisions is deployed or failed, e.g. the install
+    // is still pending.
+    HelmReleaseSecretAbsent {
+        release_name: release_name.to_string(),
+        namespace: namespace.to_string(),
+    }
+    .fail()
+}
+
+/// This fails with a ReleaseNotFound error if there is no helm release Secret, in any state, for
+/// the helm release. The error suggests the helm releases in the namespace with the closest names,
+/// to catch typos in the release name.
+async fn ensure_release_exists(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<()> {
+    let label_selector = format!("{HELM_RELEASE_OWNER_LABEL},name={release_name}");
+    if !list_release_secrets(k8s_client, label_selector.as_str(), namespace)
+        .await?
+        .is_empty()
+    {
+        return Ok(());
+    }
+
+    let release_names: BTreeSet<String> =
+        list_release_secrets(k8s_client, HELM_RELEASE_OWNER_LABEL, namespace)
+            .await?
+            .iter()
+            .filter_map(|secret| secret.labels().get(HELM_RELEASE_NAME_LABEL_KEY).cloned())
+            .collect();
+
+    let candidates = closest_release_names(release_name, release_names);
+    let suggestion = if candidates.is_empty() {
+        "there are no helm releases in this namespace".to_string()
+    } else {
+        format!("did you mean one of: {}?", candidates.join(", "))
+    };
+
+    ReleaseNotFound {
+        name: release_name.to_string(),
+        namespace: namespace.to_string(),
+        suggestion,
+    }
+    .fail()
+}
+
+/// This lists the helm release names which are closest to the release name, closest first.
+fn closest_release_names(release_name: &str, release_names: BTreeSet<String>) -> Vec<String> {
+    let mut candidates: Vec<String> = release_names.into_iter().collect();
+    // Stable sort, so that names at the same distance stay in alphabetical order.
+    candidates.sort_by_key(|name| edit_distance(release_name, name));
+    candidates.truncate(MAX_RELEASE_NAME_SUGGESTIONS);
+    candidates
+}
+
+/// This is the Levenshtein distance between two strings, i.e. the number of single character
+/// insertions, deletions or substitutions which turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0 ..= b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous_row[j] + usize::from(a_char != *b_char);
+            row.push(substitution.min(previous_row[j + 1] + 1).min(row[j] + 1));
+        }
+        previous_row = row;
+    }
+
+    previous_row[b.len()]
+}
+
+/// This lists the helm release Secrets which match a label selector.
+async fn list_release_secrets(
+    k8s_client: &KubeClientSet,
+    label_selector: &str,
+    namespace: &str,
+) -> Result<Vec<Secret>> {
+    let secrets = k8s_client
+        .secrets_api()
+        .list(&ListParams::default().labels(label_selector))
+        .await
+        .context(ListSecretsWithLabel {
+            label: label_selector.to_string(),
+            namespace: namespace.to_string(),
+        })?;
+
+    Ok(secrets.items)
+}
+
+/// This decodes the helm release payload inside of a helm release Secret. Helm stores the payload
+/// as base64 encoded, gzip compressed JSON.
+fn decode_release_payload(secret: &Secret, namespace: &str) -> Result<ReleasePayload> {
+    let secret_name = secret.name_any();
+
+    let encoded_payload = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(HELM_RELEASE_SECRET_DATA_KEY))
+        .ok_or(
+            HelmReleaseSecretDataAbsent {
+                secret_name: secret_name.clone(),
+                namespace: namespace.to_string(),
+            }
+            .build(),
+        )?;
+
+    let compressed_payload =
+        STANDARD
+            .decode(encoded_payload.0.as_slice())
+            .context(Base64DecodeHelmRelease {
+                secret_name: secret_name.clone(),
+            })?;
+
+    let mut payload: Vec<u8> = Vec::new();
+    GzDecoder::new(compressed_payload.as_slice())
+        .read_to_end(&mut payload)
+        .context(GzipDecodeHelmRelease {
+            secret_name: secret_name.clone(),
+        })?;
+
+    serde_json::from_slice(payload.as_slice()).context(JsonParseHelmRelease { secret_name })
+}
+
+/// This picks the helm release Secret with the most recent helm release revision.
+fn latest_revision(secrets: Vec<Secret>) -> Option<Secret> {
+    secrets.into_iter().max_by_key(release_revision)
+}
+
+/// This is the helm release revision of a helm release Secret, as found in its labels.
+fn release_revision(secret: &Secret) -> u32 {
+    secret
+        .labels()
+        .get(HELM_RELEASE_VERSION_LABEL_KEY)
+        .and_then(|version| version.parse::<u32>().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::common::constants::CORE_CHART_NAME;
+    use flate2::{write::GzEncoder, Compression};
+    use k8s_openapi::{apimachinery::pkg::apis::meta::v1::ObjectMeta, ByteString};
+    use std::io::Write;
+
+    /// This is the Chart.yaml of the helm chart, as it is stored in a helm release payload.
+    const CHART_YAML: &str = include_str!("../../../../../../chart/Chart.yaml");
+
+    /// This is the values.yaml of the helm chart, as it is stored in a helm release payload.
+    const VALUES_YAML: &str = include_str!("../../../../../../chart/values.yaml");
+
+    /// This builds a helm release Secret for a helm release revision.
+    pub(crate) fn release_secret(revision: u32, status: &str) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(format!("sh.helm.release.v1.mayastor.v{revision}")),
+                labels: Some(
+                    [
+                        (HELM_RELEASE_VERSION_LABEL_KEY, revision.to_string()),
+                        ("status", status.to_string()),
+                    ]
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// This encodes a helm release payload the way helm stores it in a helm release Secret, i.e.
+    /// as gzip compressed JSON, and base64 encodes it.
+    fn encoded_release_payload(config: serde_json::Value) -> ByteString {
+        let chart: serde_json::Value = serde_yaml::from_str(CHART_YAML).unwrap();
+        encode_release_payload(chart, config)
+    }
+
+    /// This builds a helm release Secret for a helm release revision, with the payload of the
+    /// helm chart version and of the user's values.
+    pub(crate) fn release_secret_with_payload(
+        revision: u32,
+        status: &str,
+        chart_version: &str,
+        config: serde_json::Value,
+    ) -> Secret {
+        let mut chart: serde_json::Value = serde_yaml::from_str(CHART_YAML).unwrap();
+        chart["version"] = chart_version.into();
+
+        let mut secret = release_secret(revision, status);
+        secret.data = Some(
+            [(
+                HELM_RELEASE_SECRET_DATA_KEY.to_string(),
+                encode_release_payload(chart, config),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        secret
+    }
+
+    /// This is encoded_release_payload(), for a given Chart.yaml.
+    fn encode_release_payload(chart: serde_json::Value, config: serde_json::Value) -> ByteString {
+        let values: serde_json::Value = serde_yaml::from_str(VALUES_YAML).unwrap();
+        let payload = serde_json::json!({
+            "name": "mayastor",
+            "version": 1,
+            "chart": { "metadata": chart, "values": values },
+            "config": config,
+        });
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(serde_json::to_vec(&payload).unwrap().as_slice())
+            .unwrap();
+        ByteString(STANDARD.encode(encoder.finish().unwrap()).into_bytes())
+    }
+
+    #[test]
+    fn release_payload_decodes_to_user_values_over_chart_values() {
+        let mut secret = release_secret(1, "deployed");
+        secret.data = Some(
+            [(
+                HELM_RELEASE_SECRET_DATA_KEY.to_string(),
+                encoded_release_payload(serde_json::json!({
+                    "image": { "tag": "v2.5.0" },
+                })),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let payload = decode_release_payload(&secret, "mayastor").unwrap();
+        assert_eq!(payload.chart.metadata.name(), CORE_CHART_NAME);
+
+        let values = release_values(payload).unwrap();
+        assert_eq!(values.image_tag(), "v2.5.0");
+        // The values which the user did not set are the helm chart's defaults.
+        assert_eq!(values.io_engine_log_level(), "info");
+    }
+
+    #[test]
+    fn release_payload_without_user_values_decodes_to_chart_values() {
+        let mut secret = release_secret(1, "deployed");
+        secret.data = Some(
+            [(
+                HELM_RELEASE_SECRET_DATA_KEY.to_string(),
+                encoded_release_payload(serde_json::Value::Null),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let values = release_values(decode_release_payload(&secret, "mayastor").unwrap()).unwrap();
+        assert_eq!(values.image_tag(), "develop");
+    }
+
+    #[test]
+    fn release_secret_without_payload_fails() {
+        assert!(matches!(
+            decode_release_payload(&release_secret(1, "deployed"), "mayastor"),
+            Err(crate::common::error::Error::HelmReleaseSecretDataAbsent { .. })
+        ));
+    }
+
+    #[test]
+    fn failed_revision_after_deployed_revision_is_current() {
+        let secrets = vec![release_secret(2, "deployed"), release_secret(3, "failed")];
+
+        let current = latest_revision(secrets).unwrap();
+        assert_eq!(release_revision(&current), 3);
+    }
+
+    #[test]
+    fn deployed_revision_after_failed_revision_is_current() {
+        let secrets = vec![release_secret(4, "deployed"), release_secret(3, "failed")];
+
+        let current = latest_revision(secrets).unwrap();
+        assert_eq!(release_revision(&current), 4);
+    }
+
+    #[test]
+    fn no_revisions_have_no_current_revision() {
+        assert!(latest_revision(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("mayastor", "mayastor"), 0);
+        assert_eq!(edit_distance("mayastor", "maystor"), 1);
+        assert_eq!(edit_distance("mayastor", "mayastro"), 2);
+        assert_eq!(edit_distance("", "openebs"), 7);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn misspelled_release_suggests_the_closest_names() {
+        let release_names = ["openebs", "loki", "mayastor", "mayastor-test"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let candidates = closest_release_names("maystor", release_names);
+
+        assert_eq!(candidates.first().map(String::as_str), Some("mayastor"));
+        assert!(candidates.len() <= MAX_RELEASE_NAME_SUGGESTIONS);
+    }
+
+    #[test]
+    fn empty_namespace_has_no_suggestions() {
+        assert!(closest_release_names("mayastor", BTreeSet::new()).is_empty());
+    }
+}
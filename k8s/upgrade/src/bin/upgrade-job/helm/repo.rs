@@ -0,0 +1,129 @@
+use crate::{
+    common::error::{
+        HelmRepoChartAbsent, HelmRepoFetch, HelmRepoHttpStatus, HelmRepoHttpsConnector,
+        HelmRepoIndexParse, HelmRepoUriParse, Result,
+    },
+    helm::chart::Chart,
+};
+use hyper::{body, Client, Uri};
+use hyper_openssl::HttpsConnector;
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// This is used to deserialize the index.yaml of a helm repository.
+#[derive(Deserialize)]
+struct RepoIndex {
+    /// These are the published versions of every helm chart in the helm repository, keyed by the
+    /// helm chart name. Every entry is the Chart.yaml of a published version. The entries are
+    /// left as yaml, so that only the entries of the helm chart which is looked up have to be
+    /// valid Chart.yaml files.
+    entries: HashMap<String, Vec<serde_yaml::Value>>,
+}
+
+/// This fetches the index.yaml of a helm repository over HTTP(S).
+pub(crate) async fn fetch_index(repo_url: &str) -> Result<Vec<u8>> {
+    let index_url = format!("{}/index.yaml", repo_url.trim_end_matches('/'));
+    let uri: Uri = index_url.parse().context(HelmRepoUriParse {
+        url: index_url.clone(),
+    })?;
+
+    let connector = HttpsConnector::new().context(HelmRepoHttpsConnector)?;
+    let client = Client::builder().build::<_, hyper::Body>(connector);
+    let response = client.get(uri).await.context(HelmRepoFetch {
+        url: index_url.clone(),
+    })?;
+    ensure!(
+        response.status().is_success(),
+        HelmRepoHttpStatus {
+            url: index_url.clone(),
+            status: response.status().as_u16(),
+        }
+    );
+
+    let index = body::to_bytes(response.into_body())
+        .await
+        .context(HelmRepoFetch { url: index_url })?;
+
+    Ok(index.to_vec())
+}
+
+/// This returns the published versions of a helm chart in a helm repository index.yaml, the
+/// latest version first.
+pub(crate) fn chart_versions(
+    index_yaml: &[u8],
+    chart_name: &str,
+    repo_url: &str,
+) -> Result<Vec<Chart>> {
+    let mut index: RepoIndex = serde_yaml::from_slice(index_yaml).context(HelmRepoIndexParse {
+        url: repo_url.to_string(),
+    })?;
+
+    let entries = index.entries.remove(chart_name).ok_or(
+        HelmRepoChartAbsent {
+            chart_name: chart_name.to_string(),
+            url: repo_url.to_string(),
+        }
+        .build(),
+    )?;
+    // A published version with a malformed entry, e.g. with a version which is not a semver,
+    // cannot be upgraded to, so it is skipped instead of failing the lookup.
+    let mut versions: Vec<Chart> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            serde_yaml::from_value(entry)
+                .map_err(|error| {
+                    warn!(%error, chart_name, "Skipping a malformed helm repository index entry");
+                })
+                .ok()
+        })
+        .collect();
+    versions.sort_by(|a, b| b.version().cmp(a.version()));
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This is a helm repository index.yaml with a malformed entry of the 'mayastor' helm chart,
+    /// and an entry of another helm chart which is not a valid Chart.yaml.
+    const INDEX_YAML: &str = r#"
+apiVersion: v1
+entries:
+  mayastor:
+    - apiVersion: v2
+      name: mayastor
+      version: 2.4.0
+    - apiVersion: v2
+      name: mayastor
+      version: 2.5.0
+    - apiVersion: v2
+      name: mayastor
+      version: latest
+  other:
+    - name: other
+"#;
+
+    #[test]
+    fn malformed_entries_are_skipped_and_latest_is_first() {
+        let versions = chart_versions(INDEX_YAML.as_bytes(), "mayastor", "repo").unwrap();
+
+        let versions: Vec<String> = versions
+            .iter()
+            .map(|chart| chart.version().to_string())
+            .collect();
+        assert_eq!(versions, vec!["2.5.0", "2.4.0"]);
+    }
+
+    #[test]
+    fn absent_chart_fails() {
+        assert!(matches!(
+            chart_versions(INDEX_YAML.as_bytes(), "absent", "repo"),
+            Err(Error::HelmRepoChartAbsent { .. })
+        ));
+    }
+}
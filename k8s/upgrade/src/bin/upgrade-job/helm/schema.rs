@@ -0,0 +1,172 @@
+use crate::common::{
+    constants::HELM_VALUES_SCHEMA_FILENAME,
+    error::{
+        JsonParseValuesSchema, ReadingFile, Result, ValuesSchemaCompile, ValuesSchemaViolations,
+        ValuesToJson,
+    },
+};
+use jsonschema::JSONSchema;
+use snafu::ResultExt;
+use std::{fs, path::Path};
+use tracing::debug;
+
+/// This validates the helm values against the JSON schema of a helm chart. All of the violations
+/// are collected into the error, so that they may be fixed in one go.
+pub(crate) fn validate(values: &serde_yaml::Value, schema: &serde_json::Value) -> Result<()> {
+    let compiled_schema = JSONSchema::compile(schema).map_err(|error| {
+        ValuesSchemaCompile {
+            error: error.to_string(),
+        }
+        .build()
+    })?;
+    let values = serde_json::to_value(values).context(ValuesToJson)?;
+
+    let errors: Vec<String> = match compiled_schema.validate(&values) {
+        Ok(()) => return Ok(()),
+        Err(violations) => violations
+            .map(|violation| format!("'{}': {}", violation.instance_path, violation))
+            .collect(),
+    };
+
+    ValuesSchemaViolations { errors }.fail()
+}
+
+/// This validates the helm values file against the chart's values.schema.json. Helm charts are not
+/// required to ship a schema, the validation is skipped for those which don't.
+pub(crate) fn validate_against_chart_schema(
+    chart_dir: &Path,
+    values_filepath: &Path,
+) -> Result<()> {
+    let values_bytes = fs::read(values_filepath).context(ReadingFile {
+        filepath: values_filepath.to_path_buf(),
+    })?;
+    let values: serde_yaml::Value = serde_yaml::from_slice(values_bytes.as_slice())?;
+
+    validate_values_against_chart_schema(chart_dir, &values)
+}
+
+/// This validates the helm values against the chart's values.schema.json, if the chart ships one.
+pub(crate) fn validate_values_against_chart_schema(
+    chart_dir: &Path,
+    values: &serde_yaml::Value,
+) -> Result<()> {
+    let schema_filepath = chart_dir.join(HELM_VALUES_SCHEMA_FILENAME);
+    if !schema_filepath.is_file() {
+        return Ok(());
+    }
+
+    let schema_bytes = fs::read(schema_filepath.as_path()).context(ReadingFile {
+        filepath: schema_filepath.clone(),
+    })?;
+    let schema: serde_json::Value =
+        serde_json::from_slice(schema_bytes.as_slice()).context(JsonParseValuesSchema {
+            filepath: schema_filepath.clone(),
+        })?;
+
+    validate(values, &schema)?;
+    debug!(
+        "Validated helm values against the schema {}",
+        schema_filepath.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This is a schema which requires mayastor.image.tag to be a string, and
+    /// mayastor.base.logLevel, if present, to be a string too.
+    fn schema_requiring_image_tag() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "required": ["mayastor"],
+            "properties": {
+                "mayastor": {
+                    "type": "object",
+                    "required": ["image"],
+                    "properties": {
+                        "image": {
+                            "type": "object",
+                            "required": ["tag"],
+                            "properties": { "tag": { "type": "string" } }
+                        },
+                        "base": {
+                            "type": "object",
+                            "properties": { "logLevel": { "type": "string" } }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// This parses the yaml of helm values.
+    fn values(yaml: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn values_with_the_image_tag_are_valid() {
+        let values = values("mayastor: {image: {registry: docker.io, tag: v2.5.0}}");
+        assert!(validate(&values, &schema_requiring_image_tag()).is_ok());
+    }
+
+    #[test]
+    fn values_missing_the_image_tag_are_a_violation() {
+        let values = values("mayastor: {image: {registry: docker.io}}");
+
+        let result = validate(&values, &schema_requiring_image_tag());
+        let Err(Error::ValuesSchemaViolations { errors }) = result else {
+            panic!("expected ValuesSchemaViolations, got {result:?}");
+        };
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("'/mayastor/image'"), "{errors:?}");
+        assert!(
+            errors[0].contains("\"tag\" is a required property"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn all_violations_are_collected() {
+        let values = values("mayastor: {image: {registry: docker.io}, base: {logLevel: 3}}");
+
+        let result = validate(&values, &schema_requiring_image_tag());
+        let Err(Error::ValuesSchemaViolations { mut errors }) = result else {
+            panic!("expected ValuesSchemaViolations, got {result:?}");
+        };
+        errors.sort();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(
+            errors[0].starts_with("'/mayastor/base/logLevel'"),
+            "{errors:?}"
+        );
+        assert!(errors[1].starts_with("'/mayastor/image'"), "{errors:?}");
+    }
+
+    #[test]
+    fn chart_without_a_schema_is_not_validated() {
+        let chart_dir = tempfile::tempdir().unwrap();
+        let values = values("mayastor: {image: {registry: docker.io}}");
+        assert!(validate_values_against_chart_schema(chart_dir.path(), &values).is_ok());
+    }
+
+    #[test]
+    fn chart_schema_is_read_from_the_chart() {
+        let chart_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            chart_dir.path().join(HELM_VALUES_SCHEMA_FILENAME),
+            schema_requiring_image_tag().to_string(),
+        )
+        .unwrap();
+
+        let values = values("mayastor: {image: {registry: docker.io}}");
+        assert!(matches!(
+            validate_values_against_chart_schema(chart_dir.path(), &values),
+            Err(Error::ValuesSchemaViolations { .. })
+        ));
+    }
+}
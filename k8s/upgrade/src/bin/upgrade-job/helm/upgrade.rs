@@ -1,21 +1,39 @@
 use crate::{
     common::{
-        constants::{CORE_CHART_NAME, TO_UMBRELLA_SEMVER, UMBRELLA_CHART_NAME},
+        constants::{
+            CORE_CHART_NAME, IO_ENGINE_DAEMONSET_TEMPLATE, TO_UMBRELLA_SEMVER, UMBRELLA_CHART_NAME,
+        },
         error::{
             CoreChartUpgradeNoneChartDir, HelmUpgradeOptionsAbsent, InvalidHelmUpgrade,
             InvalidUpgradePath, NoInputHelmChartDir, NotAKnownHelmChart, RegexCompile, Result,
-            RollbackForbidden, UmbrellaChartNotUpgraded,
+            TempFileCreation, UmbrellaChartNotUpgraded, UnsupportedUpgradePair, WriteToTempFile,
+        },
+        kube_client::KubeClientSet,
+    },
+    helm::{
+        chart::{
+            detect_and_load, validate_chart_name_match, validate_kube_version, Chart, CoreValues,
+            FromPath,
         },
+        client::HelmReleaseClient,
+        diff::{io_engine_pod_template_changed, UpgradeValuesDiff},
+        overrides::ValuesOverrides,
+        release::load_installed_chart,
+        values::{check_thin_defaults, generate_values_yaml_file},
     },
-    helm::{client::HelmReleaseClient, values::generate_values_yaml_file},
     upgrade, vec_to_strings,
 };
 use regex::Regex;
 use semver::Version;
 use snafu::{ensure, ResultExt};
-use std::{future::Future, path::PathBuf, pin::Pin};
+use std::{
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+};
 use tempfile::NamedTempFile as TempFile;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 /// This is the helm chart variant of the helm chart installed in the cluster.
 /// The PRODUCT may be installed using either of these options, but never both.
@@ -33,8 +51,13 @@ pub(crate) struct HelmUpgradeBuilder {
     namespace: Option<String>,
     core_chart_dir: Option<PathBuf>,
     skip_upgrade_path_validation: bool,
+    force_upgrade: bool,
+    allow_prerelease: bool,
+    no_thin_defaults: bool,
     helm_args_set: Option<String>,
     helm_args_set_file: Option<String>,
+    values_overrides: ValuesOverrides,
+    values_dir: Option<PathBuf>,
 }
 
 impl HelmUpgradeBuilder {
@@ -75,6 +98,28 @@ impl HelmUpgradeBuilder {
         self
     }
 
+    /// This sets the flag to run the helm upgrade even if the target version is already installed.
+    #[must_use]
+    pub(crate) fn with_force_upgrade(mut self, force_upgrade: bool) -> Self {
+        self.force_upgrade = force_upgrade;
+        self
+    }
+
+    /// This sets the flag to allow upgrades from a release to a pre-release.
+    #[must_use]
+    pub(crate) fn with_allow_prerelease(mut self, allow_prerelease: bool) -> Self {
+        self.allow_prerelease = allow_prerelease;
+        self
+    }
+
+    /// This sets the flag to fail upgrades from helm charts which predate thin-provisioning,
+    /// instead of using the target helm chart's default thin-provisioning options.
+    #[must_use]
+    pub(crate) fn with_no_thin_defaults(mut self, no_thin_defaults: bool) -> Self {
+        self.no_thin_defaults = no_thin_defaults;
+        self
+    }
+
     /// This is a builder option to add set flags set during upgrade.
     #[must_use]
     pub(crate) fn with_helm_args_set<J>(mut self, helm_args_set: J) -> Self
@@ -95,6 +140,21 @@ impl HelmUpgradeBuilder {
         self
     }
 
+    /// This is a builder option to set helm values on top of the installed release's values.
+    #[must_use]
+    pub(crate) fn with_values_overrides(mut self, values_overrides: ValuesOverrides) -> Self {
+        self.values_overrides = values_overrides;
+        self
+    }
+
+    /// This is a builder option to set the directory which the helm values files are written to.
+    /// Defaults to the core helm chart directory.
+    #[must_use]
+    pub(crate) fn with_values_dir(mut self, values_dir: PathBuf) -> Self {
+        self.values_dir = Some(values_dir);
+        self
+    }
+
     /// This builds the HelmUpgrade object.
     pub(crate) async fn build(self) -> Result<HelmUpgrade> {
         ensure!(
@@ -127,10 +187,11 @@ impl HelmUpgradeBuilder {
             .build(),
         )?;
         let chart_yaml_path = chart_dir.join("Chart.yaml");
-        let to_version: Version = upgrade::path::version_from_chart_yaml_file(chart_yaml_path)?;
+        let to_chart = Chart::from_path(chart_yaml_path.as_path())?;
+        let to_version: Version = to_chart.version().clone();
 
         // Check if already upgraded.
-        let already_upgraded = to_version.eq(&from_version);
+        let mut already_upgraded = to_version.eq(&from_version);
 
         // Define regular expression to pick out the chart name from the
         // <chart-name>-<chart-version> string.
@@ -159,6 +220,8 @@ impl HelmUpgradeBuilder {
         let mut core_chart_dir: Option<PathBuf> = None;
         let mut core_chart_extra_args: Option<Vec<String>> = None;
         let mut upgrade_values_file: Option<TempFile> = None;
+        let mut values_diff = UpgradeValuesDiff::default();
+        let mut io_engine_template_changed = false;
 
         if Regex::new(umbrella_chart_regex.as_str()) // Case: HelmChart::Umbrella.
             .context(RegexCompile {
@@ -168,6 +231,45 @@ impl HelmUpgradeBuilder {
         {
             chart_variant = HelmChart::Umbrella;
             ensure!(already_upgraded, UmbrellaChartNotUpgraded);
+
+            let umbrella_values_yaml =
+                client.get_values_as_yaml::<String, String>(release_name.clone(), None)?;
+            // The Umbrella chart's values are only logged, they are not required for the
+            // upgrade to proceed. The Core chart's values are nested under the alias of the
+            // Core chart dependency.
+            let k8s_client = KubeClientSet::builder()
+                .with_namespace(namespace.as_str())
+                .build()
+                .await?;
+            let core_values_key =
+                match load_installed_chart(&k8s_client, release_name.as_str(), namespace.as_str())
+                    .await
+                {
+                    Ok(chart) => chart.core_values_key().to_string(),
+                    Err(error) => {
+                        warn!(
+                            %error,
+                            "Failed to load the installed {UMBRELLA_CHART_NAME} helm chart, \
+                            assuming the {CORE_CHART_NAME} chart's values are nested under \
+                            '{CORE_CHART_NAME}'"
+                        );
+                        CORE_CHART_NAME.to_string()
+                    }
+                };
+            if let Ok(umbrella_values) = detect_and_load(
+                &String::from_utf8_lossy(umbrella_values_yaml.as_slice()),
+                core_values_key.as_str(),
+            ) {
+                debug!(
+                    "Installed {UMBRELLA_CHART_NAME} helm chart uses image tag '{}', container \
+                    image '{}', pullPolicy '{}', pullSecrets {:?} and io-engine logLevel '{}'",
+                    umbrella_values.image_tag(),
+                    umbrella_values.image_full_reference(),
+                    umbrella_values.image_pull_policy().unwrap_or_default(),
+                    umbrella_values.image_pull_secrets(),
+                    umbrella_values.io_engine_log_level()
+                );
+            }
         } else if Regex::new(core_chart_regex.as_str()) // Case: HelmChart::Core.
             .context(RegexCompile {
                 expression: core_chart_regex.clone(),
@@ -176,31 +278,108 @@ impl HelmUpgradeBuilder {
         {
             chart_variant = HelmChart::Core;
 
+            // The installed helm chart and the helm chart to upgrade to must be the same chart.
+            let k8s_client = KubeClientSet::builder()
+                .with_namespace(namespace.as_str())
+                .build()
+                .await?;
+            let installed_chart =
+                load_installed_chart(&k8s_client, release_name.as_str(), namespace.as_str())
+                    .await?;
+            info!("{}", installed_chart.summarize_upgrade(&to_chart));
+            validate_chart_name_match(&installed_chart, &to_chart)?;
+            validate_kube_version(&to_chart, &k8s_client.kubernetes_version().await?)?;
+
+            // The helm upgrade is re-run for the same version, if forced to.
+            already_upgraded =
+                core_chart_already_upgraded(&from_version, &to_version, self.force_upgrade);
+
             // Skip upgrade-path validation and allow all upgrades for the Core helm chart, if the
             // flag is set.
-            if !self.skip_upgrade_path_validation {
-                // Rollbacks not supported.
+            if !self.skip_upgrade_path_validation && !already_upgraded {
+                upgrade::path::validate(&from_version, &to_version, self.allow_prerelease)?;
+
+                let upgrade_path_is_valid = upgrade::path::is_valid_for_core_chart(&from_version)?;
+                ensure!(upgrade_path_is_valid, InvalidUpgradePath);
+
                 ensure!(
-                    to_version.ge(&from_version),
-                    RollbackForbidden {
-                        from_version: from_version.to_string(),
-                        to_version: to_version.to_string()
+                    upgrade::path::is_supported_pair(&from_version, &to_version)?,
+                    UnsupportedUpgradePair {
+                        from: from_version.to_string(),
+                        to: to_version.to_string(),
                     }
                 );
 
-                let upgrade_path_is_valid = upgrade::path::is_valid_for_core_chart(&from_version)?;
-                ensure!(upgrade_path_is_valid, InvalidUpgradePath);
+                // The io-engine binaries are versioned by the appVersion, not by the chart version.
+                upgrade::path::validate_minimum_from(
+                    installed_chart.app_version_or_chart_version(),
+                )?;
             }
 
+            let intermediate_versions = upgrade::path::intermediate_versions(
+                &from_version,
+                &to_version,
+                upgrade::path::known_versions()?.as_slice(),
+            );
+            if !intermediate_versions.is_empty() {
+                info!(
+                    "Upgrade from {from_version} to {to_version} passes over releases {}, \
+                    refer to their release notes",
+                    intermediate_versions
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+            }
+
+            check_thin_defaults(
+                &from_version,
+                &to_version,
+                chart_dir.as_path(),
+                &client,
+                release_name.clone(),
+                &self.values_overrides,
+                !self.no_thin_defaults,
+            )?;
+
             // Generate values yaml file for upgrade
-            let _upgrade_values_file = generate_values_yaml_file(
+            let values_dir = self.values_dir.clone().unwrap_or_else(|| chart_dir.clone());
+            let (_upgrade_values_file, _values_diff) = generate_values_yaml_file(
                 &from_version,
                 &to_version,
                 chart_dir.as_path(),
+                values_dir.as_path(),
                 &client,
                 release_name.clone(),
+                &self.values_overrides,
             )?;
 
+            // The io-engine Pods only have to be restarted if the io-engine Pod template changes,
+            // e.g. with the io-engine image tag or with the io-engine's options. If it does not,
+            // only the control-plane is upgraded. The io-engine Pods of an already upgraded helm
+            // release are restarted regardless, so that an interrupted upgrade is carried through.
+            let template_changed = !already_upgraded
+                && io_engine_template_changes(
+                    &client,
+                    release_name.as_str(),
+                    chart_dir.as_path(),
+                    values_dir.as_path(),
+                    _upgrade_values_file.path(),
+                    [helm_args_set.as_str(), helm_args_set_file.as_str()],
+                )?;
+            // The io-engine Pods are picked for restarts by the helm chart version label, so a
+            // forced upgrade to the same helm chart version cannot restart them.
+            if template_changed && from_version.eq(&to_version) {
+                warn!(
+                    "The io-engine Pod template changes, but the helm chart version stays at \
+                    {to_version}, so the io-engine Pods are not restarted. The io-engine \
+                    DaemonSet only applies the change to io-engine Pods which are deleted"
+                );
+            }
+            io_engine_template_changed =
+                io_engine_template_requires_restart(template_changed, &from_version, &to_version);
+
             core_chart_dir = Some(chart_dir);
 
             // helm upgrade .. -f <values-yaml> --atomic
@@ -213,7 +392,8 @@ impl HelmUpgradeBuilder {
                 helm_args_set_file,
                 "--atomic"
             ]);
-            upgrade_values_file = Some(_upgrade_values_file)
+            upgrade_values_file = Some(_upgrade_values_file);
+            values_diff = _values_diff;
         } else {
             // Case: Helm chart release is not a known helm chart installation.
             return NotAKnownHelmChart { chart_name: chart }.fail();
@@ -228,11 +408,96 @@ impl HelmUpgradeBuilder {
             core_chart_extra_args,
             from_version,
             to_version,
+            to_app_version: to_chart.app_version().cloned(),
             upgrade_values_file,
+            values_diff,
+            io_engine_template_changed,
         })
     }
 }
 
+/// This renders the io-engine DaemonSet with the installed values and with the upgrade values, and
+/// decides if the io-engine Pod template changes. The installed values are written to the values
+/// directory for helm template, and are removed once it is done.
+fn io_engine_template_changes(
+    client: &HelmReleaseClient,
+    release_name: &str,
+    chart_dir: &Path,
+    values_dir: &Path,
+    upgrade_values_file: &Path,
+    [helm_args_set, helm_args_set_file]: [&str; 2],
+) -> Result<bool> {
+    let installed_values_yaml = client.get_values_as_yaml::<&str, String>(release_name, None)?;
+    let mut installed_values_file = TempFile::new_in(values_dir).context(TempFileCreation)?;
+    installed_values_file
+        .write_all(installed_values_yaml.as_slice())
+        .context(WriteToTempFile {
+            filepath: installed_values_file.path().to_path_buf(),
+        })?;
+
+    let installed_manifest = client.template(
+        release_name,
+        chart_dir,
+        IO_ENGINE_DAEMONSET_TEMPLATE,
+        Some(vec_to_strings![
+            "-f",
+            installed_values_file.path().to_string_lossy()
+        ]),
+    )?;
+    let target_manifest = client.template(
+        release_name,
+        chart_dir,
+        IO_ENGINE_DAEMONSET_TEMPLATE,
+        Some(vec_to_strings![
+            "-f",
+            upgrade_values_file.to_string_lossy(),
+            "--set",
+            helm_args_set,
+            "--set-file",
+            helm_args_set_file
+        ]),
+    )?;
+
+    let changed =
+        io_engine_pod_template_changed(installed_manifest.as_slice(), target_manifest.as_slice())?;
+    info!(
+        changed,
+        "Compared the rendered io-engine Pod templates of the installed release and the upgrade"
+    );
+    Ok(changed)
+}
+
+/// This decides if a change to the io-engine Pod template restarts the io-engine Pods. The
+/// io-engine Pods are picked for restarts by the helm chart version label, so this is false if the
+/// helm chart version stays the same.
+fn io_engine_template_requires_restart(
+    template_changed: bool,
+    from_version: &Version,
+    to_version: &Version,
+) -> bool {
+    template_changed && from_version.ne(to_version)
+}
+
+/// This decides if the installed Core helm chart is already at the version to upgrade to, so that
+/// the helm upgrade is skipped. The versions are compared as semvers, so a pre-release is never
+/// the same version as its release. A forced upgrade is never already upgraded.
+fn core_chart_already_upgraded(
+    from_version: &Version,
+    to_version: &Version,
+    force_upgrade: bool,
+) -> bool {
+    !force_upgrade && from_version.eq(to_version)
+}
+
+/// This is the extra arguments of the 'helm upgrade --dry-run' command. The '--dry-run' argument
+/// goes last, so that helm only renders and validates the upgrade, whatever the other extra
+/// arguments are.
+fn dry_run_extra_args(extra_args: Option<&[String]>) -> Vec<String> {
+    let mut dry_run_extra_args = extra_args.map(<[String]>::to_vec).unwrap_or_default();
+    dry_run_extra_args.push("--dry-run".to_string());
+    dry_run_extra_args
+}
+
 /// This type can generate and execute the `helm upgrade` command.
 pub(crate) struct HelmUpgrade {
     chart_variant: HelmChart,
@@ -243,8 +508,11 @@ pub(crate) struct HelmUpgrade {
     core_chart_extra_args: Option<Vec<String>>,
     from_version: Version,
     to_version: Version,
+    to_app_version: Option<Version>,
     #[allow(dead_code)]
     upgrade_values_file: Option<TempFile>,
+    values_diff: UpgradeValuesDiff,
+    io_engine_template_changed: bool,
 }
 
 impl HelmUpgrade {
@@ -291,16 +559,13 @@ impl HelmUpgrade {
                     .core_chart_dir
                     .ok_or(CoreChartUpgradeNoneChartDir.build())?;
 
-                // Running 'helm upgrade --dry-run'.
-                let mut dry_run_extra_args: Vec<String> =
-                    self.core_chart_extra_args.clone().unwrap_or_default();
-                dry_run_extra_args.push("--dry-run".to_string());
+                // Running 'helm upgrade --dry-run'. The CRDs are not installed for the dry-run.
                 info!("Running helm upgrade dry-run...");
                 self.client
                     .upgrade(
                         self.release_name.as_str(),
                         chart_dir.as_path(),
-                        Some(dry_run_extra_args),
+                        Some(dry_run_extra_args(self.core_chart_extra_args.as_deref())),
                         false,
                     )
                     .await?;
@@ -332,10 +597,63 @@ impl HelmUpgrade {
         }
     }
 
+    /// This is a predicate for when the helm chart release is already at the target version, and
+    /// helm upgrade is skipped.
+    pub(crate) fn already_upgraded(&self) -> bool {
+        self.already_upgraded
+    }
+
+    /// This decides if the io-engine Pods have to be restarted for the upgrade. The io-engine Pods
+    /// of an already upgraded helm release are restarted, so that an interrupted data-plane
+    /// upgrade is carried through. Otherwise, this is true if the io-engine Pod template, minus the
+    /// helm chart version label, changes, e.g. with the io-engine image tag.
+    pub(crate) fn requires_io_engine_restart(&self) -> bool {
+        self.already_upgraded || self.io_engine_template_changed
+    }
+
+    /// This is a getter for the changes between the installed values and the upgrade values.
+    pub(crate) fn values_diff(&self) -> &UpgradeValuesDiff {
+        &self.values_diff
+    }
+
+    /// This reads the helm values which the helm upgrade would be run with. This is None if the
+    /// helm chart isn't a known helm chart installation which values are generated for.
+    pub(crate) fn upgrade_values(&self) -> Result<Option<CoreValues>> {
+        self.upgrade_values_file
+            .as_ref()
+            .map(|file| CoreValues::from_path(file.path()))
+            .transpose()
+    }
+
+    /// This reads the helm values which the helm upgrade would be run with, as yaml. This is None
+    /// if the helm chart isn't a known helm chart installation which values are generated for.
+    pub(crate) fn upgrade_values_yaml(&self) -> Result<Option<serde_yaml::Value>> {
+        self.upgrade_values_file
+            .as_ref()
+            .map(|file| serde_yaml::Value::from_path(file.path()))
+            .transpose()
+    }
+
     pub(crate) fn upgrade_from_version(&self) -> String {
         self.from_version.to_string()
     }
 
+    /// This is a getter for the version of the installed helm chart.
+    pub(crate) fn installed_version(&self) -> &Version {
+        &self.from_version
+    }
+
+    /// This is a getter for the version of the helm chart to upgrade to.
+    pub(crate) fn target_version(&self) -> &Version {
+        &self.to_version
+    }
+
+    /// This is a getter for the appVersion of the helm chart to upgrade to. This is None if the
+    /// appVersion is absent or is not a valid semver.
+    pub(crate) fn target_app_version(&self) -> Option<&Version> {
+        self.to_app_version.as_ref()
+    }
+
     pub(crate) fn upgrade_to_version(&self) -> String {
         self.to_version.to_string()
     }
@@ -344,3 +662,103 @@ impl HelmUpgrade {
 /// HelmUpgradeRunner is returned after an upgrade is validated and dry-run-ed. Running
 /// it carries out helm upgrade.
 pub(crate) type HelmUpgradeRunner = Pin<Box<dyn Future<Output = Result<()>>>>;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// This is a HelmUpgrade of the core helm chart from the 'from' version to the 'to' version,
+    /// without generated upgrade values, as if the helm release were read with the helm CLI.
+    pub(crate) fn core_chart_upgrade(
+        from: &str,
+        to: &str,
+        io_engine_template_changed: bool,
+    ) -> HelmUpgrade {
+        HelmUpgrade {
+            chart_variant: HelmChart::Core,
+            already_upgraded: false,
+            core_chart_dir: None,
+            release_name: "mayastor".to_string(),
+            client: HelmReleaseClient::builder()
+                .with_namespace("mayastor")
+                .build()
+                .unwrap(),
+            core_chart_extra_args: None,
+            from_version: Version::parse(from).unwrap(),
+            to_version: Version::parse(to).unwrap(),
+            to_app_version: None,
+            upgrade_values_file: None,
+            values_diff: UpgradeValuesDiff::default(),
+            io_engine_template_changed,
+        }
+    }
+
+    #[test]
+    fn dry_run_args_keep_the_extra_args() {
+        let extra_args = vec_to_strings!["--set", "image.tag=v2.5.0", "--dry-run=false"];
+        assert_eq!(
+            dry_run_extra_args(Some(extra_args.as_slice())),
+            vec_to_strings!["--set", "image.tag=v2.5.0", "--dry-run=false", "--dry-run"]
+        );
+    }
+
+    /// This decides if an upgrade from the 'from' version to the 'to' version is already done.
+    fn already_upgraded(from: &str, to: &str, force_upgrade: bool) -> bool {
+        core_chart_already_upgraded(
+            &Version::parse(from).unwrap(),
+            &Version::parse(to).unwrap(),
+            force_upgrade,
+        )
+    }
+
+    #[test]
+    fn equal_versions_are_already_upgraded() {
+        assert!(already_upgraded("2.5.0", "2.5.0", false));
+        assert!(already_upgraded("2.5.0-rc.1", "2.5.0-rc.1", false));
+    }
+
+    #[test]
+    fn different_versions_are_not_already_upgraded() {
+        assert!(!already_upgraded("2.4.0", "2.5.0", false));
+        assert!(!already_upgraded("2.5.0-rc.1", "2.5.0", false));
+        assert!(!already_upgraded("2.5.0-rc.1", "2.5.0-rc.2", false));
+    }
+
+    #[test]
+    fn forced_upgrade_is_never_already_upgraded() {
+        assert!(!already_upgraded("2.5.0", "2.5.0", true));
+        assert!(!already_upgraded("2.4.0", "2.5.0", true));
+    }
+
+    #[test]
+    fn control_plane_only_upgrade_does_not_restart_the_io_engine() {
+        assert!(!io_engine_template_requires_restart(
+            false,
+            &Version::new(2, 4, 0),
+            &Version::new(2, 5, 0)
+        ));
+    }
+
+    #[test]
+    fn io_engine_template_change_restarts_the_io_engine() {
+        assert!(io_engine_template_requires_restart(
+            true,
+            &Version::new(2, 4, 0),
+            &Version::new(2, 5, 0)
+        ));
+    }
+
+    #[test]
+    fn forced_upgrade_to_the_same_version_does_not_restart_the_io_engine() {
+        assert!(!io_engine_template_requires_restart(
+            true,
+            &Version::new(2, 5, 0),
+            &Version::new(2, 5, 0)
+        ));
+    }
+
+    #[test]
+    fn dry_run_args_without_extra_args() {
+        assert_eq!(dry_run_extra_args(None), vec_to_strings!["--dry-run"]);
+    }
+}
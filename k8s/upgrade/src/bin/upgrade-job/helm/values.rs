@@ -1,68 +1,217 @@
 use crate::{
     common::{
-        constants::{TWO_DOT_FOUR, TWO_DOT_ONE, TWO_DOT_O_RC_ONE, TWO_DOT_THREE},
+        constants::{
+            INSTALLED_VALUES_SOURCE, TARGET_VALUES_SOURCE, TWO_DOT_FOUR, TWO_DOT_ONE,
+            TWO_DOT_O_RC_ONE, TWO_DOT_THREE, UPGRADE_VALUES_SOURCE,
+        },
         error::{
-            ReadingFile, Result, SemverParse, TempFileCreation, U8VectorToString, WriteToTempFile,
-            YamlParseFromFile, YamlParseFromSlice,
+            ReadingFile, Result, SemverParse, SerializeValuesYaml, TempFileCreation,
+            ThinProvisioningOptionsAbsent, U8VectorToString, ValuesKeysDropped, WriteToTempFile,
+            YamlParseFromSlice,
         },
     },
     helm::{
-        chart::CoreValues,
+        chart::{
+            io_engine_cpu_pinning_changed, io_engine_env_changes, io_engine_log_level_more_verbose,
+            log_filter_verbosity, nvme_timeouts_changed, resources_changed, CoreValues, FromPath,
+            Nvme, Resources,
+        },
         client::HelmReleaseClient,
+        diff::{diff_values, UpgradeValuesDiff},
+        merge::missing_key_paths,
+        migration::apply_migrations,
+        overrides::ValuesOverrides,
+        schema::validate_against_chart_schema,
+        values_validation::ThinCommitmentValues,
         yaml::yq::{YamlKey, YqV4},
     },
 };
 use semver::Version;
-use snafu::ResultExt;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
 use std::{fs, io::Write, path::Path, str};
 use tempfile::NamedTempFile as TempFile;
+use tracing::{debug, info, warn};
+
+/// This is the change in the maximum logical capacity which may be provisioned on the storage
+/// pools, with thin-provisioning, when the poolCommitment changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CommitmentDelta {
+    /// The maximum logical capacity with the installed poolCommitment, in bytes.
+    installed_bytes: u64,
+    /// The maximum logical capacity with the target poolCommitment, in bytes.
+    target_bytes: u64,
+}
+
+impl CommitmentDelta {
+    /// This is a getter for the maximum logical capacity with the installed poolCommitment.
+    pub(crate) fn installed_bytes(&self) -> u64 {
+        self.installed_bytes
+    }
+
+    /// This is a getter for the maximum logical capacity with the target poolCommitment.
+    pub(crate) fn target_bytes(&self) -> u64 {
+        self.target_bytes
+    }
+
+    /// This is the change in the maximum logical capacity, in bytes. This is negative if the
+    /// poolCommitment is lowered.
+    pub(crate) fn delta_bytes(&self) -> i128 {
+        i128::from(self.target_bytes) - i128::from(self.installed_bytes)
+    }
+}
+
+/// This computes the maximum logical capacity which may be provisioned on storage pools of the
+/// physical capacity, with the installed and with the target poolCommitment.
+pub(crate) fn commitment_capacity_delta(
+    installed: &ThinCommitmentValues,
+    target: &ThinCommitmentValues,
+    physical_bytes: u64,
+) -> CommitmentDelta {
+    CommitmentDelta {
+        installed_bytes: installed.pool_commitment().of(physical_bytes),
+        target_bytes: target.pool_commitment().of(physical_bytes),
+    }
+}
 
-/// This compiles all of the helm values options to be passed during the helm chart upgrade.
+/// This checks the installed helm values on upgrades from a helm chart which predates
+/// thin-provisioning to one which has it. The installed values have no agents.core.capacity yaml
+/// object then. The target helm chart's defaults from its values.yaml are used for it, unless
+/// 'use_thin_defaults' is false, in which case this fails with ThinProvisioningOptionsAbsent.
+pub(crate) fn check_thin_defaults(
+    from_version: &Version,
+    to_version: &Version,
+    chart_dir: &Path,
+    client: &HelmReleaseClient,
+    release_name: String,
+    overrides: &ValuesOverrides,
+    use_thin_defaults: bool,
+) -> Result<()> {
+    if CoreValues::supports_thin_provisioning(from_version)
+        || !CoreValues::supports_thin_provisioning(to_version)
+    {
+        return Ok(());
+    }
+
+    let installed_values_yaml =
+        overrides.apply(client.get_values_as_yaml::<String, String>(release_name, None)?)?;
+    let installed_values: serde_yaml::Value =
+        serde_yaml::from_slice(installed_values_yaml.as_slice()).context(YamlParseFromSlice {
+            input_yaml: String::from_utf8_lossy(installed_values_yaml.as_slice()).to_string(),
+        })?;
+    let to_values = CoreValues::from_path(chart_dir.join("values.yaml").as_path())?;
+    if let Some(thin_defaults) = thin_defaults_for(
+        from_version,
+        to_version,
+        &installed_values,
+        &to_values,
+        use_thin_defaults,
+    )? {
+        info!(
+            "Helm chart version {from_version} predates thin-provisioning, the defaults of helm \
+            chart version {to_version} will be used, poolCommitment: {}",
+            thin_defaults.pool_commitment()
+        );
+    }
+
+    Ok(())
+}
+
+/// This is like check_thin_defaults, for the installed values and the target helm chart's values
+/// which are already read. This returns the target helm chart's thin-provisioning options, if
+/// they are used for installed values without an agents.core.capacity yaml object.
+fn thin_defaults_for(
+    from_version: &Version,
+    to_version: &Version,
+    installed_values: &serde_yaml::Value,
+    to_values: &CoreValues,
+    use_thin_defaults: bool,
+) -> Result<Option<ThinCommitmentValues>> {
+    if CoreValues::supports_thin_provisioning(from_version)
+        || !CoreValues::supports_thin_provisioning(to_version)
+        || !installed_values["agents"]["core"]["capacity"].is_null()
+    {
+        return Ok(None);
+    }
+
+    ensure!(
+        use_thin_defaults,
+        ThinProvisioningOptionsAbsent {
+            chart_version: from_version.clone(),
+            values_source: INSTALLED_VALUES_SOURCE,
+        }
+    );
+
+    // The target helm chart's values are merged under the installed values, so the defaults are
+    // carried into the upgrade values as they are.
+    ThinCommitmentValues::try_from_values(to_values, to_version, TARGET_VALUES_SOURCE).map(Some)
+}
+
+/// This compiles all of the helm values options to be passed during the helm chart upgrade. This
+/// also returns the changes between the installed values and the upgrade values. The
+/// overrides are merged on top of the installed values, so they are migrated and validated too,
+/// and again on top of the values which the upgrade always sets, e.g. the image tag.
+/// The values files are written to the values directory, and are only readable by their owner.
+/// They are removed when the returned TempFile is dropped, and on any failure.
 pub(crate) fn generate_values_yaml_file(
     from_version: &Version,
     to_version: &Version,
     chart_dir: &Path,
+    values_dir: &Path,
     client: &HelmReleaseClient,
     release_name: String,
-) -> Result<TempFile> {
+    overrides: &ValuesOverrides,
+) -> Result<(TempFile, UpgradeValuesDiff)> {
     // Serde object for to_values yaml.
     let to_values_filepath = chart_dir.join("values.yaml");
-    let to_values_yaml = fs::read(to_values_filepath.as_path()).context(ReadingFile {
-        filepath: to_values_filepath.clone(),
-    })?;
-    let to_values: CoreValues =
-        serde_yaml::from_slice(to_values_yaml.as_slice()).context(YamlParseFromFile {
-            filepath: to_values_filepath.clone(),
-        })?;
+    let to_values = CoreValues::from_path(to_values_filepath.as_path())?;
 
     // Write from_values_yaml to a file, and also parse it and build a serde object.
-    let from_values_yaml = client.get_values_as_yaml::<String, String>(release_name, None)?;
+    let installed_values_yaml = client.get_values_as_yaml::<String, String>(release_name, None)?;
+    // The installed values are also read without the overrides, and are compared with the merged
+    // values of the upgrade, so that the changes which the overrides make are reported too.
+    let installed_values = parse_core_values(
+        migrate_values_yaml(from_version, to_version, installed_values_yaml.clone())?.as_slice(),
+    )?;
+    let from_values_yaml = overrides.apply(installed_values_yaml)?;
+    // Migrate the source values into the shape which the target helm chart accepts.
+    let from_values_yaml = migrate_values_yaml(from_version, to_version, from_values_yaml)?;
     // File
-    let mut from_values_file = TempFile::new_in(chart_dir).context(TempFileCreation)?;
-    from_values_file
-        .write(from_values_yaml.as_slice())
-        .context(WriteToTempFile {
-            filepath: from_values_file.path().to_path_buf(),
-        })?;
+    let from_values_file = write_values_file(values_dir, from_values_yaml.as_slice())?;
     // Serde object
-    let from_values: CoreValues =
-        serde_yaml::from_slice(from_values_yaml.as_slice()).context(YamlParseFromSlice {
-            input_yaml: str::from_utf8(from_values_yaml.as_slice())
-                .context(U8VectorToString)?
-                .to_string(),
-        })?;
+    let from_values = parse_core_values(from_values_yaml.as_slice())?;
+
+    // Log the container image references, so that a change in the image registry or repository
+    // does not go unnoticed.
+    info!(
+        "Container images will be upgraded from '{}' to '{}'",
+        from_values.image_full_reference(),
+        to_values.image_full_reference()
+    );
+
+    // The pull policy decides if cached container images are re-pulled during the rolling restart
+    // of the PRODUCT's Pods.
+    if from_values
+        .image_pull_policy()
+        .ne(&to_values.image_pull_policy())
+    {
+        info!(
+            "Container image pullPolicy differs between the installed release ({}) and \
+            the target helm chart ({}), this affects whether cached images are re-pulled",
+            from_values.image_pull_policy().unwrap_or_default(),
+            to_values.image_pull_policy().unwrap_or_default()
+        );
+    }
 
     // Resultant values yaml for helm upgrade command.
     // Merge the source values with the target values.
-    let mut upgrade_values_file = TempFile::new_in(chart_dir).context(TempFileCreation)?;
     let yq = YqV4::new()?;
     let upgrade_values_yaml =
         yq.merge_files(from_values_file.path(), to_values_filepath.as_path())?;
-    upgrade_values_file
-        .write(upgrade_values_yaml.as_slice())
-        .context(WriteToTempFile {
-            filepath: upgrade_values_file.path().to_path_buf(),
-        })?;
+    // The merge is done on the yaml, not on the typed values, so keys which CoreValues does not
+    // model, e.g. custom user keys, are carried over too. This makes sure none were dropped.
+    ensure_no_keys_dropped(from_values_yaml.as_slice(), upgrade_values_yaml.as_slice())?;
+    let upgrade_values_file = write_values_file(values_dir, upgrade_values_yaml.as_slice())?;
 
     // Not using semver::VersionReq because expressions like '>=2.1.0' don't include
     // 2.3.0-rc.0. 2.3.0, 2.4.0, etc. are supported. So, not using VersionReq in the
@@ -170,5 +319,768 @@ pub(crate) fn generate_values_yaml_file(
     // helm upgrade .. --set image.tag=<version> --set image.repoTags.controlPlane= --set
     // image.repoTags.dataPlane= --set image.repoTags.extensions=
 
-    Ok(upgrade_values_file)
+    // The overrides win over the values which are set above, e.g. --set image.tag=<tag>.
+    reapply_overrides(overrides, upgrade_values_file.path())?;
+
+    validate_upgrade_values(upgrade_values_file.path(), to_version, chart_dir)?;
+
+    // More verbose io-engine logs take up more disk space on the storage nodes. The merged values
+    // are compared, because the installed log level is carried over unless a migration changes it.
+    let upgrade_values = CoreValues::from_path(upgrade_values_file.path())?;
+    if io_engine_log_level_more_verbose(&from_values, &upgrade_values) {
+        warn!(
+            "io-engine logLevel will change from '{}' to '{}' (most verbose level: {}), this may \
+            increase the log volume and the disk pressure on the storage nodes",
+            from_values.io_engine_log_level(),
+            upgrade_values.io_engine_log_level(),
+            log_filter_verbosity(upgrade_values.io_engine_log_level())
+                .map(|level| level.to_string())
+                .unwrap_or_default()
+        );
+    }
+
+    warn_of_upgrade_values_changes(&installed_values, &upgrade_values);
+
+    // The changes are those which the helm upgrade makes, i.e. the installed values which the
+    // merge keeps are not listed, and the changes which the overrides make are.
+    let values_diff = diff_values(&installed_values, from_version, &upgrade_values, to_version);
+    if !values_diff.is_empty() {
+        info!("Helm values which the upgrade changes:");
+        for change in values_diff.changes() {
+            info!(
+                "  {}: '{}' -> '{}'",
+                change.path(),
+                change.old_value().unwrap_or_default(),
+                change.new_value().unwrap_or_default()
+            );
+        }
+    }
+
+    Ok((upgrade_values_file, values_diff))
+}
+
+/// This parses the Core helm chart's values from the values yaml.
+fn parse_core_values(values_yaml: &[u8]) -> Result<CoreValues> {
+    serde_yaml::from_slice(values_yaml).context(YamlParseFromSlice {
+        input_yaml: str::from_utf8(values_yaml)
+            .context(U8VectorToString)?
+            .to_string(),
+    })
+}
+
+/// This warns of the changes to the installed values, which are risky during an upgrade. The
+/// installed values are compared with the merged values which helm upgrade is run with, i.e. the
+/// installed values are only changed by the migrations, the overrides and the values which the
+/// upgrade always sets.
+fn warn_of_upgrade_values_changes(installed_values: &CoreValues, upgrade_values: &CoreValues) {
+    // The io-engine's runtime tunables are carried over from the installed values, unless a
+    // migration or an override drops or changes them.
+    for change in io_engine_env_changes(installed_values, upgrade_values) {
+        warn!("io-engine runtime tunable will change, {change}");
+    }
+
+    // Changing the cores the io-engine is pinned to in the middle of an upgrade may degrade IO.
+    if io_engine_cpu_pinning_changed(installed_values, upgrade_values) {
+        warn!(
+            "io-engine CPU configuration will change from (coreList: {:?}, cpuCount: {}) to \
+            (coreList: {:?}, cpuCount: {})",
+            installed_values.io_engine_core_list().unwrap_or_default(),
+            installed_values.io_engine_cpu_count().unwrap_or_default(),
+            upgrade_values.io_engine_core_list().unwrap_or_default(),
+            upgrade_values.io_engine_cpu_count().unwrap_or_default()
+        );
+    }
+
+    // Changes to the io-engine's resources interact with node capacity and scheduling.
+    if resources_changed(installed_values, upgrade_values) {
+        let describe = |resources: Option<&Resources>| -> String {
+            let resources = resources.map(|r| (r.requests(), r.limits()));
+            match resources {
+                Some((requests, limits)) => format!(
+                    "requests(cpu: {}, memory: {}, hugepages2Mi: {}), \
+                    limits(cpu: {}, memory: {}, hugepages2Mi: {})",
+                    requests.cpu().unwrap_or_default(),
+                    requests.memory().unwrap_or_default(),
+                    requests.hugepages_2mi().unwrap_or_default(),
+                    limits.cpu().unwrap_or_default(),
+                    limits.memory().unwrap_or_default(),
+                    limits.hugepages_2mi().unwrap_or_default()
+                ),
+                None => "none".to_string(),
+            }
+        };
+        info!(
+            "io-engine resources will change from [{}] to [{}]",
+            describe(installed_values.io_engine_resources()),
+            describe(upgrade_values.io_engine_resources())
+        );
+    }
+
+    // The etcd persistence has to survive the upgrade. Changing these is almost always a mistake.
+    if etcd_changed(installed_values, upgrade_values) {
+        warn!(
+            "etcd configuration will change from (replicaCount: {}, storageClass: '{}', \
+            size: '{}') to (replicaCount: {}, storageClass: '{}', size: '{}')",
+            installed_values.etcd_replica_count(),
+            installed_values.etcd_storage_class(),
+            installed_values.etcd_persistence_size(),
+            upgrade_values.etcd_replica_count(),
+            upgrade_values.etcd_storage_class(),
+            upgrade_values.etcd_persistence_size()
+        );
+    }
+
+    // A drop in the number of control-plane replicas reduces fault tolerance.
+    if let Some((from_replicas, to_replicas)) = replica_count_drop(
+        installed_values.api_rest_replica_count(),
+        upgrade_values.api_rest_replica_count(),
+    ) {
+        warn!(
+            "REST API replicaCount would drop from {from_replicas} to {to_replicas}, this \
+            reduces the fault tolerance of the control-plane"
+        );
+    }
+
+    // The nvme initiator timeouts decide how volumes fail over when io-engine Pods restart.
+    if nvme_timeouts_changed(installed_values, upgrade_values) {
+        let describe = |nvme: &Nvme| -> String {
+            format!(
+                "io_timeout: '{}', ctrl_loss_tmo: '{}', keep_alive_tmo: '{}'",
+                nvme.io_timeout().unwrap_or_default(),
+                nvme.ctrl_loss_tmo().unwrap_or_default(),
+                nvme.keep_alive_tmo().unwrap_or_default()
+            )
+        };
+        warn!(
+            "csi-node nvme timeouts will change from [{}] to [{}], this alters volume failover \
+            behaviour",
+            describe(installed_values.csi_node_nvme()),
+            describe(upgrade_values.csi_node_nvme())
+        );
+    }
+
+    // Deployments may have the bundled logging stack disabled on purpose, it should not flip
+    // silently.
+    if let Some((from_enabled, to_enabled)) = loki_enabled_change(installed_values, upgrade_values)
+    {
+        warn!(
+            "The loki logging stack would be {} by the upgrade, the installed release has \
+            loki-stack.enabled={from_enabled}",
+            if to_enabled { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+/// This returns the installed and the upgrade loki-stack.enabled, if they differ. Absent values
+/// are not compared.
+fn loki_enabled_change(
+    installed_values: &CoreValues,
+    upgrade_values: &CoreValues,
+) -> Option<(bool, bool)> {
+    match (
+        installed_values.loki_enabled(),
+        upgrade_values.loki_enabled(),
+    ) {
+        (Some(from_enabled), Some(to_enabled)) if from_enabled != to_enabled => {
+            Some((from_enabled, to_enabled))
+        }
+        _ => None,
+    }
+}
+
+/// This is a predicate for a change to the etcd replicaCount or storageClass.
+fn etcd_changed(installed_values: &CoreValues, upgrade_values: &CoreValues) -> bool {
+    installed_values
+        .etcd_replica_count()
+        .ne(&upgrade_values.etcd_replica_count())
+        || installed_values
+            .etcd_storage_class()
+            .ne(upgrade_values.etcd_storage_class())
+}
+
+/// This writes the helm values yaml to a new file in the values directory. The file is only
+/// readable by its owner, and is removed when the returned TempFile is dropped.
+fn write_values_file(values_dir: &Path, values_yaml: &[u8]) -> Result<TempFile> {
+    let mut values_file = TempFile::new_in(values_dir).context(TempFileCreation)?;
+    values_file
+        .write_all(values_yaml)
+        .context(WriteToTempFile {
+            filepath: values_file.path().to_path_buf(),
+        })?;
+
+    Ok(values_file)
+}
+
+/// This merges the overrides on top of the values file again, after the upgrade has set the
+/// values which it always sets, so that the operator's overrides of those values are kept.
+fn reapply_overrides(overrides: &ValuesOverrides, values_filepath: &Path) -> Result<()> {
+    let values_yaml = fs::read(values_filepath).context(ReadingFile {
+        filepath: values_filepath.to_path_buf(),
+    })?;
+    fs::write(values_filepath, overrides.apply(values_yaml)?).context(WriteToTempFile {
+        filepath: values_filepath.to_path_buf(),
+    })
+}
+
+/// This fails if any of the keys of the installed values is absent in the merged values.
+fn ensure_no_keys_dropped(installed_values_yaml: &[u8], upgrade_values_yaml: &[u8]) -> Result<()> {
+    let parse = |yaml: &[u8]| -> Result<serde_yaml::Value> {
+        serde_yaml::from_slice(yaml).context(YamlParseFromSlice {
+            input_yaml: String::from_utf8_lossy(yaml).to_string(),
+        })
+    };
+
+    let keys = missing_key_paths(&parse(installed_values_yaml)?, &parse(upgrade_values_yaml)?);
+    ensure!(keys.is_empty(), ValuesKeysDropped { keys });
+
+    Ok(())
+}
+
+/// This validates the merged values yaml file for the helm upgrade.
+fn validate_upgrade_values(
+    upgrade_values_filepath: &Path,
+    to_version: &Version,
+    chart_dir: &Path,
+) -> Result<()> {
+    validate_against_chart_schema(chart_dir, upgrade_values_filepath)?;
+
+    let upgrade_values = CoreValues::from_path(upgrade_values_filepath)?;
+
+    // The thin-provisioning options are absent in older helm charts.
+    if let Some(thin_commitment) = ThinCommitmentValues::try_from_values_if_present(
+        &upgrade_values,
+        to_version,
+        UPGRADE_VALUES_SOURCE,
+    )? {
+        thin_commitment.validate()?;
+        // The options are logged as they are set in the helm values.
+        debug!(
+            "Validated thin-provisioning commitment options, poolCommitment: '{}', \
+            volumeCommitment: '{}', volumeCommitmentInitial: '{}'",
+            upgrade_values.thin_pool_commitment(to_version, UPGRADE_VALUES_SOURCE)?,
+            upgrade_values.thin_volume_commitment(to_version, UPGRADE_VALUES_SOURCE)?,
+            upgrade_values.thin_volume_commitment_initial(to_version, UPGRADE_VALUES_SOURCE)?
+        );
+    }
+
+    Ok(())
+}
+
+/// This returns the installed and the target replicaCount, if the target is lower than the
+/// installed one. Absent replicaCounts are not compared.
+fn replica_count_drop(from: Option<u32>, to: Option<u32>) -> Option<(u32, u32)> {
+    match (from, to) {
+        (Some(from), Some(to)) if to < from => Some((from, to)),
+        _ => None,
+    }
+}
+
+/// This applies the helm values migrations required for the upgrade to the values yaml.
+fn migrate_values_yaml(
+    from_version: &Version,
+    to_version: &Version,
+    values_yaml: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let mut values: serde_yaml::Value =
+        serde_yaml::from_slice(values_yaml.as_slice()).context(YamlParseFromSlice {
+            input_yaml: String::from_utf8_lossy(values_yaml.as_slice()).to_string(),
+        })?;
+
+    apply_migrations(from_version, to_version, &mut values)?;
+
+    serde_yaml::to_string(&values)
+        .map(String::into_bytes)
+        .context(SerializeValuesYaml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::error::Error,
+        helm::{
+            chart::{Agents, IoEngineEnvChange},
+            merge::deep_merge,
+        },
+    };
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+    #[test]
+    fn overrides_win_over_the_forced_values() {
+        let mut values_file = TempFile::new().unwrap();
+        values_file
+            .write_all(b"image: {tag: v2.5.0, pullPolicy: IfNotPresent}\n")
+            .unwrap();
+        let overrides =
+            ValuesOverrides::new(vec![], vec!["image.tag=custom".parse().unwrap()], vec![]);
+
+        reapply_overrides(&overrides, values_file.path()).unwrap();
+        let values: serde_yaml::Value =
+            serde_yaml::from_slice(fs::read(values_file.path()).unwrap().as_slice()).unwrap();
+        assert_eq!(values["image"]["tag"], "custom");
+        assert_eq!(values["image"]["pullPolicy"], "IfNotPresent");
+    }
+
+    /// This is the Core helm chart's values, with keys which CoreValues does not model.
+    fn installed_values_with_custom_keys() -> serde_yaml::Value {
+        let mut values: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        values["customKey"] = serde_yaml::from_str("{team: storage, tier: 1}").unwrap();
+        values["io_engine"]["customTunable"] = serde_yaml::Value::from(true);
+        values
+    }
+
+    #[test]
+    fn unmodeled_keys_survive_merge_and_re_serialization() {
+        let installed = installed_values_with_custom_keys();
+        let target: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        let installed_yaml = serde_yaml::to_string(&installed).unwrap();
+        let merged_yaml =
+            ValuesOverrides::new(vec![], vec!["image.tag=v2.5.0".parse().unwrap()], vec![])
+                .apply(
+                    serde_yaml::to_string(&deep_merge(target, installed))
+                        .unwrap()
+                        .into_bytes(),
+                )
+                .unwrap();
+
+        ensure_no_keys_dropped(installed_yaml.as_bytes(), merged_yaml.as_slice()).unwrap();
+        let merged: serde_yaml::Value = serde_yaml::from_slice(merged_yaml.as_slice()).unwrap();
+        assert_eq!(merged["customKey"]["team"], "storage");
+        assert_eq!(merged["customKey"]["tier"], 1);
+        assert_eq!(merged["io_engine"]["customTunable"], true);
+
+        // The typed values are only read from the merged yaml, they do not re-emit it.
+        let values =
+            CoreValues::from_slice(Path::new("values.yaml"), merged_yaml.as_slice()).unwrap();
+        assert_eq!(values.image_tag(), "v2.5.0");
+    }
+
+    #[test]
+    fn dropped_unmodeled_keys_fail_the_upgrade() {
+        let installed_yaml = serde_yaml::to_string(&installed_values_with_custom_keys()).unwrap();
+        let merged_yaml = include_str!("../../../../../../chart/values.yaml");
+
+        let error =
+            ensure_no_keys_dropped(installed_yaml.as_bytes(), merged_yaml.as_bytes()).unwrap_err();
+        let Error::ValuesKeysDropped { keys } = error else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(keys, vec!["io_engine.customTunable", "customKey"]);
+    }
+
+    /// This lists the files in the directory.
+    fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect()
+    }
+
+    #[test]
+    fn values_file_is_private_and_removed_after_success() {
+        let values_dir = tempfile::tempdir().unwrap();
+
+        let values_file = write_values_file(values_dir.path(), b"image: {tag: v2.5.0}\n").unwrap();
+        assert_eq!(dir_entries(values_dir.path()), vec![values_file.path()]);
+        assert_eq!(
+            fs::read(values_file.path()).unwrap(),
+            b"image: {tag: v2.5.0}\n"
+        );
+        let mode = fs::metadata(values_file.path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(values_file);
+        assert!(dir_entries(values_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn values_file_is_removed_after_a_failure() {
+        let values_dir = tempfile::tempdir().unwrap();
+
+        // Like with generate_values_yaml_file, a failure after the values file is written returns
+        // early and drops the file.
+        let result = (|| -> Result<TempFile> {
+            let values_file = write_values_file(values_dir.path(), b"customKey: true\n")?;
+            ensure_no_keys_dropped(b"customKey: true\n", b"image: {tag: v2.5.0}\n")?;
+            Ok(values_file)
+        })();
+        assert!(matches!(result, Err(Error::ValuesKeysDropped { .. })));
+        assert!(dir_entries(values_dir.path()).is_empty());
+    }
+
+    #[test]
+    fn values_file_in_a_missing_dir_fails() {
+        let values_dir = tempfile::tempdir().unwrap();
+        let missing_dir = values_dir.path().join("missing");
+
+        let result = write_values_file(missing_dir.as_path(), b"image: {tag: v2.5.0}\n");
+        assert!(matches!(result, Err(Error::TempFileCreation { .. })));
+        assert!(dir_entries(values_dir.path()).is_empty());
+    }
+
+    /// This is the Core helm chart's values, as the target values of an upgrade.
+    fn chart_values() -> CoreValues {
+        serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap()
+    }
+
+    /// These are installed values of a helm chart which predates thin-provisioning.
+    fn pre_thin_values() -> serde_yaml::Value {
+        serde_yaml::from_str("agents: {core: {logLevel: info}}\nimage: {tag: v2.1.0}\n").unwrap()
+    }
+
+    #[test]
+    fn pre_thin_upgrade_uses_the_target_thin_defaults() {
+        let defaults = thin_defaults_for(
+            &Version::new(2, 1, 0),
+            &Version::new(2, 5, 0),
+            &pre_thin_values(),
+            &chart_values(),
+            true,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            defaults.pool_commitment(),
+            thin_commitment("250%").pool_commitment()
+        );
+    }
+
+    #[test]
+    fn pre_thin_upgrade_with_no_thin_defaults_fails() {
+        let result = thin_defaults_for(
+            &Version::new(2, 1, 0),
+            &Version::new(2, 5, 0),
+            &pre_thin_values(),
+            &chart_values(),
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::ThinProvisioningOptionsAbsent { chart_version, values_source })
+                if chart_version == Version::new(2, 1, 0)
+                    && values_source == INSTALLED_VALUES_SOURCE
+        ));
+    }
+
+    #[test]
+    fn thin_defaults_are_not_used_outside_the_pre_thin_transition() {
+        let installed_thin_values: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        for (from, to, installed) in [
+            (
+                Version::new(2, 4, 0),
+                Version::new(2, 5, 0),
+                pre_thin_values(),
+            ),
+            (
+                Version::new(2, 0, 0),
+                Version::new(2, 1, 0),
+                pre_thin_values(),
+            ),
+            (
+                Version::new(2, 1, 0),
+                Version::new(2, 5, 0),
+                installed_thin_values,
+            ),
+        ] {
+            assert!(
+                thin_defaults_for(&from, &to, &installed, &chart_values(), false)
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    /// This is the Core helm chart's values, with the edit made to them.
+    fn chart_values_with(edit: impl FnOnce(&mut serde_yaml::Value)) -> CoreValues {
+        let mut values: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        edit(&mut values);
+        serde_yaml::from_value(values).unwrap()
+    }
+
+    /// This returns the installed values, i.e. the Core helm chart's values with the edit made to
+    /// them, and the upgrade values. Like with the upgrade, the installed values are merged over
+    /// the chart's values, and the --set values are merged over that.
+    fn installed_and_upgrade_values(
+        edit: impl FnOnce(&mut serde_yaml::Value),
+        set_values: &[&str],
+    ) -> (CoreValues, CoreValues) {
+        let chart_values: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        let mut installed_values = chart_values.clone();
+        edit(&mut installed_values);
+
+        let merged = deep_merge(chart_values, installed_values.clone());
+        let overrides = ValuesOverrides::new(
+            vec![],
+            set_values
+                .iter()
+                .map(|input| input.parse().unwrap())
+                .collect(),
+            vec![],
+        );
+        let upgrade_values = overrides
+            .apply(serde_yaml::to_string(&merged).unwrap().into_bytes())
+            .unwrap();
+
+        (
+            serde_yaml::from_value(installed_values).unwrap(),
+            parse_core_values(upgrade_values.as_slice()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn custom_registry_kept_by_the_merge_does_not_require_an_image_prepull() {
+        let version = Version::new(2, 5, 0);
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["image"]["registry"] = serde_yaml::Value::from("registry.example.com"),
+            &[],
+        );
+        assert!(
+            diff_values(&installed, &version, &chart_values(), &version).requires_image_prepull()
+        );
+        assert!(!diff_values(&installed, &version, &upgrade, &version).requires_image_prepull());
+    }
+
+    #[test]
+    fn registry_override_requires_an_image_prepull() {
+        let version = Version::new(2, 5, 0);
+        let (installed, upgrade) =
+            installed_and_upgrade_values(|_| {}, &["image.registry=registry.example.com"]);
+        let diff = diff_values(&installed, &version, &upgrade, &version);
+        assert!(diff.requires_image_prepull());
+        assert_eq!(diff.changes()[0].new_value(), Some("registry.example.com"));
+    }
+
+    #[test]
+    fn custom_io_engine_env_kept_by_the_merge_is_not_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| {
+                values["io_engine"]["env"] =
+                    serde_yaml::from_str("{NVMF_TCP_MAX_QUEUE_DEPTH: 32}").unwrap()
+            },
+            &[],
+        );
+        assert!(io_engine_env_changes(&installed, &upgrade).is_empty());
+    }
+
+    #[test]
+    fn io_engine_env_override_is_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| {
+                values["io_engine"]["env"] =
+                    serde_yaml::from_str("{NVMF_TCP_MAX_QUEUE_DEPTH: 32}").unwrap()
+            },
+            &["io_engine.env.NVMF_TCP_MAX_QUEUE_DEPTH=64"],
+        );
+        assert_eq!(
+            io_engine_env_changes(&installed, &upgrade),
+            vec![IoEngineEnvChange::Changed {
+                key: "NVMF_TCP_MAX_QUEUE_DEPTH".to_string(),
+                old: "32".to_string(),
+                new: "64".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn custom_cpu_pinning_kept_by_the_merge_is_not_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["io_engine"]["coreList"] = serde_yaml::from_str("[1, 2]").unwrap(),
+            &[],
+        );
+        assert!(io_engine_cpu_pinning_changed(&installed, &chart_values()));
+        assert!(!io_engine_cpu_pinning_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn cpu_pinning_override_is_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(|_| {}, &["io_engine.cpuCount=4"]);
+        assert!(io_engine_cpu_pinning_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn custom_resources_kept_by_the_merge_are_not_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| {
+                values["io_engine"]["resources"]["limits"]["memory"] =
+                    serde_yaml::Value::from("2Gi")
+            },
+            &[],
+        );
+        assert!(resources_changed(&installed, &chart_values()));
+        assert!(!resources_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn resources_override_is_a_change() {
+        let (installed, upgrade) =
+            installed_and_upgrade_values(|_| {}, &["io_engine.resources.limits.cpu=4"]);
+        assert!(resources_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn custom_etcd_settings_kept_by_the_merge_are_not_a_change() {
+        let custom_etcd = |values: &mut serde_yaml::Value| {
+            values["etcd"]["replicaCount"] = serde_yaml::Value::from(5);
+            values["etcd"]["persistence"]["storageClass"] = serde_yaml::Value::from("fast");
+        };
+        let installed = chart_values_with(custom_etcd);
+        let upgrade = chart_values_with(custom_etcd);
+        assert!(!etcd_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn etcd_override_is_a_change() {
+        let installed = chart_values();
+        let upgrade =
+            chart_values_with(|values| values["etcd"]["replicaCount"] = serde_yaml::Value::from(1));
+        assert!(etcd_changed(&installed, &upgrade));
+
+        let upgrade = chart_values_with(|values| {
+            values["etcd"]["persistence"]["storageClass"] = serde_yaml::Value::from("slow")
+        });
+        assert!(etcd_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn replica_count_drop_is_detected() {
+        assert_eq!(replica_count_drop(Some(3), Some(1)), Some((3, 1)));
+    }
+
+    #[test]
+    fn replica_count_increase_or_unchanged_is_not_a_drop() {
+        assert_eq!(replica_count_drop(Some(1), Some(3)), None);
+        assert_eq!(replica_count_drop(Some(3), Some(3)), None);
+        assert_eq!(replica_count_drop(None, Some(1)), None);
+        assert_eq!(replica_count_drop(Some(3), None), None);
+    }
+
+    #[test]
+    fn custom_nvme_timeouts_kept_by_the_merge_are_not_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["csi"]["node"]["nvme"]["io_timeout"] = serde_yaml::Value::from("60"),
+            &[],
+        );
+        assert!(nvme_timeouts_changed(&installed, &chart_values()));
+        assert!(!nvme_timeouts_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn nvme_timeout_override_is_a_change() {
+        let (installed, upgrade) =
+            installed_and_upgrade_values(|_| {}, &["csi.node.nvme.ctrl_loss_tmo=3600"]);
+        assert!(nvme_timeouts_changed(&installed, &upgrade));
+    }
+
+    #[test]
+    fn disabled_loki_kept_by_the_merge_is_not_a_change() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["loki-stack"]["enabled"] = serde_yaml::Value::from(false),
+            &[],
+        );
+        assert_eq!(
+            loki_enabled_change(&installed, &chart_values()),
+            Some((false, true))
+        );
+        assert_eq!(loki_enabled_change(&installed, &upgrade), None);
+    }
+
+    #[test]
+    fn loki_override_is_a_change() {
+        let (installed, upgrade) =
+            installed_and_upgrade_values(|_| {}, &["loki-stack.enabled=false"]);
+        assert_eq!(
+            loki_enabled_change(&installed, &upgrade),
+            Some((true, false))
+        );
+    }
+
+    #[test]
+    fn custom_replica_count_kept_by_the_merge_is_not_a_drop() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["apis"]["rest"]["replicaCount"] = serde_yaml::Value::from(3),
+            &[],
+        );
+        assert_eq!(
+            replica_count_drop(
+                installed.api_rest_replica_count(),
+                chart_values().api_rest_replica_count()
+            ),
+            Some((3, 1))
+        );
+        assert_eq!(
+            replica_count_drop(
+                installed.api_rest_replica_count(),
+                upgrade.api_rest_replica_count()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn replica_count_override_is_a_drop() {
+        let (installed, upgrade) = installed_and_upgrade_values(
+            |values| values["apis"]["rest"]["replicaCount"] = serde_yaml::Value::from(3),
+            &["apis.rest.replicaCount=2"],
+        );
+        assert_eq!(
+            replica_count_drop(
+                installed.api_rest_replica_count(),
+                upgrade.api_rest_replica_count()
+            ),
+            Some((3, 2))
+        );
+    }
+
+    #[test]
+    fn rest_replica_count_is_read_from_the_chart() {
+        let values: CoreValues =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        assert_eq!(values.api_rest_replica_count(), Some(1));
+    }
+
+    /// This parses the thin-provisioning commitments, with the poolCommitment.
+    fn thin_commitment(pool_commitment: &str) -> ThinCommitmentValues {
+        let agents: Agents = serde_yaml::from_str(
+            format!(
+                "{{core: {{capacity: {{thin: {{poolCommitment: '{pool_commitment}', \
+                 volumeCommitment: '40%', volumeCommitmentInitial: '40%'}}}}}}}}"
+            )
+            .as_str(),
+        )
+        .unwrap();
+        ThinCommitmentValues::try_from_agents(&agents, &Version::new(2, 5, 0), "test").unwrap()
+    }
+
+    #[test]
+    fn raised_commitment_increases_capacity() {
+        let delta =
+            commitment_capacity_delta(&thin_commitment("250%"), &thin_commitment("400%"), 1000);
+        assert_eq!(delta.installed_bytes(), 2500);
+        assert_eq!(delta.target_bytes(), 4000);
+        assert_eq!(delta.delta_bytes(), 1500);
+    }
+
+    #[test]
+    fn lowered_commitment_decreases_capacity() {
+        let delta =
+            commitment_capacity_delta(&thin_commitment("250%"), &thin_commitment("100%"), 1000);
+        assert_eq!(delta.target_bytes(), 1000);
+        assert_eq!(delta.delta_bytes(), -1500);
+    }
+
+    #[test]
+    fn unchanged_commitment_keeps_capacity() {
+        let delta =
+            commitment_capacity_delta(&thin_commitment("250%"), &thin_commitment("250%"), 1000);
+        assert_eq!(delta.installed_bytes(), delta.target_bytes());
+        assert_eq!(delta.delta_bytes(), 0);
+    }
 }
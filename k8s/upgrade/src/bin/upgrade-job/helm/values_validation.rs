@@ -0,0 +1,164 @@
+use crate::{
+    common::error::{Result, ThinVolumeCommitmentInverted},
+    helm::chart::{Agents, CoreValues, Percentage},
+};
+use semver::Version;
+use snafu::ensure;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// This contains the parsed thin-provisioning commitment percentages of the core agent.
+pub(crate) struct ThinCommitmentValues {
+    /// The pool commitment percentage.
+    pool_commitment: Percentage,
+    /// The volume commitment percentage.
+    volume_commitment: Percentage,
+    /// The initial volume commitment percentage.
+    volume_commitment_initial: Percentage,
+    /// The pool commitment percentages of individual pools or pool classes, keyed by the pool or
+    /// class name.
+    pool_commitment_overrides: BTreeMap<String, Percentage>,
+}
+
+impl ThinCommitmentValues {
+    /// This parses the thin-provisioning commitment percentages from helm values, and validates
+    /// the per-pool and per-class overrides. The chart version and the source of the helm values
+    /// are for error messages.
+    pub(crate) fn try_from_values(
+        values: &CoreValues,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Self> {
+        Self::try_from_agents(values.agents(), chart_version, values_source)
+    }
+
+    /// This is like try_from_values, with only the configuration of the control-plane agents.
+    pub(crate) fn try_from_agents(
+        agents: &Agents,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            pool_commitment: agents
+                .core_thin_pool_commitment_parsed(chart_version, values_source)?,
+            volume_commitment: agents
+                .core_thin_volume_commitment_parsed(chart_version, values_source)?,
+            volume_commitment_initial: agents
+                .core_thin_volume_commitment_initial_parsed(chart_version, values_source)?,
+            pool_commitment_overrides: agents.core_thin_pool_commitment_overrides_parsed()?,
+        })
+    }
+
+    /// This is like try_from_values, but this is None if the thin-provisioning options are absent.
+    pub(crate) fn try_from_values_if_present(
+        values: &CoreValues,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Option<Self>> {
+        Self::try_from_agents_if_present(values.agents(), chart_version, values_source)
+    }
+
+    /// This is like try_from_agents, but this is None if the thin-provisioning options are absent.
+    /// The helm charts from THIN_PROVISIONING_MIN_VERSION on are expected to have them, but that
+    /// version is not verified against every released helm chart, so their absence is warned
+    /// about instead of failing.
+    pub(crate) fn try_from_agents_if_present(
+        agents: &Agents,
+        chart_version: &Version,
+        values_source: &str,
+    ) -> Result<Option<Self>> {
+        if agents.has_thin_provisioning_options() {
+            return Self::try_from_agents(agents, chart_version, values_source).map(Some);
+        }
+
+        if CoreValues::supports_thin_provisioning(chart_version) {
+            warn!(
+                %chart_version,
+                values_source,
+                "The thin-provisioning options are absent amongst the helm values, skipping the \
+                thin-provisioning checks"
+            );
+        }
+        Ok(None)
+    }
+
+    /// This validates that the commitment percentages are consistent with each other. The initial
+    /// volume commitment applies to new volumes, and may not be larger than the volume
+    /// commitment which applies to existing volumes.
+    pub(crate) fn validate(&self) -> Result<()> {
+        ensure!(
+            self.volume_commitment_initial <= self.volume_commitment,
+            ThinVolumeCommitmentInverted {
+                volume_commitment_initial: self.volume_commitment_initial,
+                volume_commitment: self.volume_commitment,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// This is a getter for the pool commitment percentage.
+    pub(crate) fn pool_commitment(&self) -> Percentage {
+        self.pool_commitment
+    }
+
+    /// This is the pool commitment percentage of the pool with the given name. This is the global
+    /// pool commitment, unless the pool has an override.
+    pub(crate) fn pool_commitment_for(&self, pool: &str) -> Percentage {
+        self.pool_commitment_overrides
+            .get(pool)
+            .copied()
+            .unwrap_or(self.pool_commitment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This deserializes the configuration of the control-plane agents from its yaml.
+    fn agents(yaml: &str) -> Agents {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn absent_thin_provisioning_options_are_skipped_for_every_version() {
+        let agents = agents("{core: {logLevel: info}}");
+
+        for version in [Version::new(2, 1, 0), Version::new(2, 5, 0)] {
+            assert!(
+                ThinCommitmentValues::try_from_agents_if_present(&agents, &version, "test")
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn present_thin_provisioning_options_are_parsed_for_every_version() {
+        let agents = agents(
+            "{core: {capacity: {thin: {poolCommitment: '300%', volumeCommitment: '40%', \
+            volumeCommitmentInitial: '40%'}}}}",
+        );
+
+        for version in [Version::new(2, 1, 0), Version::new(2, 5, 0)] {
+            let thin_commitment =
+                ThinCommitmentValues::try_from_agents_if_present(&agents, &version, "test")
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(thin_commitment.pool_commitment().to_string(), "300%");
+        }
+    }
+
+    #[test]
+    fn incomplete_thin_provisioning_options_fail() {
+        let agents = agents("{core: {capacity: {thin: {poolCommitment: '300%'}}}}");
+
+        assert!(ThinCommitmentValues::try_from_agents_if_present(
+            &agents,
+            &Version::new(2, 5, 0),
+            "test"
+        )
+        .is_err());
+    }
+}
@@ -1,17 +1,33 @@
+// The error variants carry their (kube, helm, openapi) sources by value. Boxing them would touch
+// every variant and context selector, for no benefit on this cold error path.
+#![allow(clippy::result_large_err)]
+
 use crate::{
-    common::{constants::PRODUCT, error::Result},
+    common::{
+        constants::PRODUCT,
+        error::{ClusterArgumentsMissing, DryRunRollback, Error, Result},
+    },
+    helm::oci::pull_chart,
     opts::validators::{
         validate_helm_chart_dir, validate_helm_release, validate_helmv3_in_path,
-        validate_namespace, validate_rest_endpoint,
+        validate_namespace, validate_rest_endpoint, validate_values_dir,
     },
     upgrade::upgrade,
 };
 use clap::Parser;
-use opts::CliArgs;
-use tracing::{error, info};
+use opts::{CliArgs, LogFormat};
+use snafu::ensure;
+use std::io;
+use tracing::{error, info, warn, Subscriber};
+use tracing_subscriber::{
+    fmt::{writer::BoxMakeWriter, MakeWriter},
+    util::SubscriberInitExt,
+    EnvFilter,
+};
 use utils::{
-    print_package_info, raw_version_str,
+    package_description, print_package_info, raw_version_str,
     tracing_telemetry::{default_tracing_tags, flush_traces, init_tracing},
+    version_info_str,
 };
 
 mod common;
@@ -22,41 +38,224 @@ mod upgrade;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    print_package_info!();
-    init_logging();
+    let mut opts = CliArgs::parse();
+    if opts.stdout_is_document() {
+        eprintln!("{} {}", package_description!(), version_info_str!());
+    } else {
+        print_package_info!();
+    }
+    init_logging(&opts);
 
-    let opts = parse_cli_args().await.map_err(|error| {
-        error!(%error, "Failed to upgrade {PRODUCT}");
-        error
-    })?;
-
-    upgrade(&opts).await.map_err(|error| {
-        error!(%error, "Failed to upgrade {PRODUCT}");
+    if let Err(error) = validate_cli_args(&mut opts).await {
+        log_error(&error);
         flush_traces();
-        error
-    })
+        return Err(error);
+    }
+
+    let result = upgrade(&opts).await;
+    if let Err(error) = &result {
+        log_error(error);
+    }
+    // The spans of the upgrade phases are exported in batches, this exports the last batch.
+    flush_traces();
+
+    result
 }
 
-/// Initialize logging components -- tracing.
-fn init_logging() {
-    let tags = default_tracing_tags(raw_version_str(), env!("CARGO_PKG_VERSION"));
+/// This logs the error which failed the upgrade, with its error code and category.
+fn log_error(error: &Error) {
+    error!(
+        %error,
+        error.code = error.error_code(),
+        error.category = %error.category(),
+        "Failed to upgrade {PRODUCT}"
+    );
+}
+
+/// Initialize logging components -- tracing. The spans are exported to the Jaeger endpoint agent,
+/// if one is set, and are only logged otherwise. With the json log format, each log line is a
+/// JSON object which carries its fields and the fields of its spans, for log pipelines. The logs
+/// are written to stderr if stdout carries a document, e.g. the JSON output, and the spans are
+/// not exported then.
+fn init_logging(opts: &CliArgs) {
+    let stdout_is_document = opts.stdout_is_document();
+    if opts.log_format() == LogFormat::Text && !stdout_is_document {
+        let tags = default_tracing_tags(raw_version_str(), env!("CARGO_PKG_VERSION"));
+        init_tracing("upgrade-job", tags, opts.jaeger());
+        return;
+    }
+
+    let writer = if stdout_is_document {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    match opts.log_format() {
+        LogFormat::Json => json_logs(filter, writer).init(),
+        LogFormat::Text => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .init(),
+    }
 
-    init_tracing("upgrade-job", tags, None);
+    if opts.jaeger().is_some() {
+        warn!("The tracing spans are not exported to Jaeger, as stdout carries the output");
+    }
 }
 
-/// This function handles the following tasks -- 1. Argument parsing, 2. Validating arguments whose
-/// validation depends on other arguments.
-pub(crate) async fn parse_cli_args() -> Result<CliArgs> {
-    let opts = CliArgs::parse();
+/// This is the JSON log formatter. Each log line is a JSON object with the fields of the event,
+/// and the list of its spans with their fields.
+fn json_logs<W>(filter: EnvFilter, writer: W) -> impl Subscriber + Send + Sync
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(true)
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .finish()
+}
+
+/// This function validates the arguments, including those whose validation depends on other
+/// arguments. The core helm chart is pulled first, if it is referenced in an OCI registry. There
+/// is nothing to validate for the validate-values, schema and list-versions subcommands.
+pub(crate) async fn validate_cli_args(opts: &mut CliArgs) -> Result<()> {
+    // Values files are validated, the values schema is printed and the helm chart versions are
+    // listed without a cluster. None of the inputs need to be reachable.
+    if !opts.uses_cluster() {
+        return Ok(());
+    }
+
+    // The dry-run is checked ahead of the rollback, so a rollback with --dry-run set, e.g. from the
+    // environment, would print an upgrade plan instead of rolling back.
+    ensure!(!(opts.dry_run() && opts.rollback()), DryRunRollback);
+
+    let missing_args = opts.missing_cluster_args();
+    ensure!(
+        missing_args.is_empty(),
+        ClusterArgumentsMissing {
+            arguments: missing_args.join(", ")
+        }
+    );
 
     validate_namespace(opts.namespace()).await?;
-    validate_rest_endpoint(opts.rest_endpoint()).await?;
+    validate_rest_endpoint(opts.rest_endpoint(), opts.rest_tls()).await?;
 
     validate_helmv3_in_path()?;
     validate_helm_release(opts.release_name(), opts.namespace())?;
-    validate_helm_chart_dir(opts.core_chart_dir())?;
+    if let Some(reference) = opts.chart_ref() {
+        let pulled_chart = pull_chart(
+            &reference,
+            opts.namespace().as_str(),
+            opts.release_name().as_str(),
+        )
+        .await?;
+        opts.set_pulled_chart(pulled_chart);
+    }
+    validate_helm_chart_dir(opts.core_chart_dir(), opts.fail_on_deprecated()).await?;
+    if let Some(values_dir) = opts.values_dir_opt() {
+        validate_values_dir(values_dir)?;
+    }
 
     info!("Validated all inputs");
 
-    Ok(opts)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::NodeReadyTimeout;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tracing::info_span;
+
+    /// This captures the logs which are written to it.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_conflicts_with_the_rollback() {
+        let mut opts = crate::opts::tests::parse(&["--dry-run", "rollback"]).unwrap();
+        assert!(matches!(
+            validate_cli_args(&mut opts).await,
+            Err(Error::DryRunRollback)
+        ));
+
+        let mut opts =
+            crate::opts::tests::parse_with_env(&["rollback"], &[("UPGRADE_DRY_RUN", "true")])
+                .unwrap();
+        assert!(matches!(
+            validate_cli_args(&mut opts).await,
+            Err(Error::DryRunRollback)
+        ));
+    }
+
+    #[test]
+    fn json_error_log_carries_the_error_code_and_the_span_fields() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = json_logs(EnvFilter::new("info"), move || writer.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "node_restart",
+                node.name = "node-1",
+                upgrade.phase = "restart",
+                upgrade.to_version = "2.5.0"
+            );
+            let _entered = span.enter();
+            log_error(
+                &NodeReadyTimeout {
+                    node: "node-1",
+                    elapsed: Duration::from_secs(600),
+                    phase: "Pending",
+                }
+                .build(),
+            );
+        });
+
+        let logs = logs.0.lock().unwrap();
+        let lines: Vec<serde_json::Value> = logs
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+
+        let line = &lines[0];
+        assert_eq!(line["level"], "ERROR");
+        assert_eq!(line["message"], format!("Failed to upgrade {PRODUCT}"));
+        assert_eq!(line["error.code"], "E-K8S-018");
+        assert_eq!(line["error.category"], "Kubernetes");
+        assert!(line["error"]
+            .as_str()
+            .is_some_and(|error| error.contains("node-1")));
+        assert_eq!(
+            line["spans"],
+            serde_json::json!([{
+                "name": "node_restart",
+                "node.name": "node-1",
+                "upgrade.phase": "restart",
+                "upgrade.to_version": "2.5.0"
+            }])
+        );
+    }
 }
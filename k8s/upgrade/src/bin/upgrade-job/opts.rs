@@ -1,80 +1,650 @@
-use crate::common::constants::PRODUCT;
-use clap::Parser;
-use std::path::PathBuf;
+use crate::{
+    common::{
+        constants::{
+            API_REST_HTTP_PORT, API_REST_SERVICE_NAME_SUFFIX, AUDIT_RECORD_CONFIGMAP_MAX_RECORDS,
+            DEFAULT_REDACTED_VALUES_PATHS, HELM_REPO_URL, PRODUCT,
+        },
+        error::{DrainGracePeriodParse, Error, MaxUnavailableParse, NodeLabelParse},
+        rest_client::RestTls,
+    },
+    helm::{
+        oci::{OciReference, PulledChart},
+        overrides::{SetValue, ValuesOverrides},
+    },
+};
+use clap::{builder::BoolishValueParser, Parser, Subcommand, ValueEnum};
+use snafu::{ensure, OptionExt};
+use std::{
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 use utils::{package_description, version_info_str};
 
 /// Validate input whose validation depends on other inputs.
 pub(crate) mod validators;
 
+/// These are the output formats for the upgrade plan and the rendered helm values.
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human-readable text, in the logs. Rendered helm values are printed as yaml, on stdout.
+    Text,
+    /// A single JSON document, on stdout. The logs are written to stderr.
+    Json,
+}
+
+/// These are the formats of the upgrade-job's logs.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// A JSON object per line, with the fields of the log line and of its spans, e.g. the
+    /// upgrade.phase, node.name and error.code.
+    Json,
+}
+
+/// These are the components of the PRODUCT installation which are upgraded.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Component {
+    /// The helm release, i.e. the control-plane, and then the io-engine Pods.
+    All,
+    /// Only the helm release. The io-engine Pods are not restarted.
+    ControlPlane,
+    /// Only the io-engine Pods, after an earlier control-plane only upgrade.
+    DataPlane,
+}
+
+/// This is the maximum number of io-engine Pods which may be restarted at the same time. This
+/// follows the semantics of the Kubernetes maxUnavailable, i.e. it is either an absolute number or
+/// a percentage of the total number of Pods.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum MaxUnavailable {
+    /// An absolute number of Pods.
+    Count(usize),
+    /// A percentage of the total number of Pods.
+    Percent(usize),
+}
+
+impl MaxUnavailable {
+    /// This returns the number of Pods out of a total, rounded down. At least one Pod may be
+    /// restarted at a time, so that the upgrade makes progress.
+    pub(crate) fn resolve(&self, total: usize) -> usize {
+        let count = match self {
+            Self::Count(count) => *count,
+            Self::Percent(percent) => total * percent / 100,
+        };
+        count.max(1)
+    }
+}
+
+impl FromStr for MaxUnavailable {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let parse = |number: &str| {
+            number.parse::<usize>().ok().context(MaxUnavailableParse {
+                value: value.to_string(),
+            })
+        };
+
+        match value.strip_suffix('%') {
+            Some(percent) => {
+                let percent = parse(percent)?;
+                ensure!(
+                    percent <= 100,
+                    MaxUnavailableParse {
+                        value: value.to_string()
+                    }
+                );
+                Ok(Self::Percent(percent))
+            }
+            None => Ok(Self::Count(parse(value)?)),
+        }
+    }
+}
+
+/// This is how long the drain of a storage node may take before its io-engine Pod is restarted
+/// regardless. It is a positive duration, e.g. '90s' or '5m'.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DrainGracePeriod(Duration);
+
+impl FromStr for DrainGracePeriod {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let duration = value
+            .trim()
+            .parse::<humantime::Duration>()
+            .ok()
+            .filter(|duration| !duration.is_zero())
+            .context(DrainGracePeriodParse {
+                value: value.to_string(),
+            })?;
+
+        Ok(Self(*duration))
+    }
+}
+
+/// This is a 'key=value' Kubernetes Node label, e.g. 'node-role.example.com/maintenance=true'.
+#[derive(Clone, Debug)]
+pub(crate) struct NodeLabel {
+    key: String,
+    value: String,
+}
+
+impl FromStr for NodeLabel {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (key, value) = input
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .context(NodeLabelParse { input })?;
+        ensure!(!key.is_empty(), NodeLabelParse { input });
+
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for NodeLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// These are the operations other than upgrade.
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Rolls back the helm release to the previously deployed revision, and restarts the
+    /// io-engine DaemonSet Pods.
+    Rollback,
+    /// Prints the helm values which the upgrade would pass to helm, without upgrading. These are
+    /// the installed release's values, migrated and merged with the target helm chart's values.
+    RenderValues,
+    /// Lists the versions of the helm chart which are published in a helm repository, the latest
+    /// version first.
+    ListVersions {
+        /// This is the URL of the helm repository.
+        #[arg(long, default_value = HELM_REPO_URL)]
+        repo_url: String,
+    },
+    /// Prints an audit record of the requested upgrade as JSON, without upgrading. The record
+    /// carries the helm chart versions, the helm values overrides, the upgrade plan, who
+    /// requested the upgrade, the upgrade-job's ServiceAccount and a timestamp.
+    AuditExport {
+        /// If set, the audit record is also stored in this ConfigMap, in the upgrade-job's
+        /// namespace. Each audit record is a separate key of the ConfigMap.
+        #[arg(long)]
+        configmap: Option<String>,
+        /// This is the number of audit records which the ConfigMap retains, the oldest records
+        /// are removed first.
+        #[arg(long, default_value_t = AUDIT_RECORD_CONFIGMAP_MAX_RECORDS, requires = "configmap")]
+        max_records: usize,
+        /// This is the identity of the person or the system which requested the upgrade, e.g.
+        /// the Kubernetes user. The upgrade-job only knows its own ServiceAccount, which is the
+        /// same for every upgrade.
+        #[arg(long, value_name = "IDENTITY")]
+        requested_by: Option<String>,
+    },
+    /// Prints the JSON schema of the Core chart's helm values which the upgrade-job reads. This
+    /// documents the keys which the upgrade-job understands, and their types.
+    Schema {
+        /// If set, the Core chart's values are nested under this key in the schema, as in the
+        /// values of the Umbrella chart, e.g. 'mayastor'.
+        #[arg(long, value_name = "KEY")]
+        core_values_key: Option<String>,
+    },
+    /// Checks that the cluster matches the installed helm release's values, without upgrading.
+    /// The io-engine Pods' container image tags and log levels, and the number of etcd replicas
+    /// are compared. Each difference is reported, and the command fails if there is any.
+    Verify,
+    /// Validates a values file against a helm chart, without a cluster. The values file is merged
+    /// on top of the helm chart's values, and is checked against the helm chart's values schema
+    /// and for consistent thin-provisioning options. All of the problems are reported.
+    ValidateValues {
+        /// This is the directory of the helm chart.
+        #[arg(value_name = "CHART_DIR")]
+        chart_dir: PathBuf,
+        /// This is the values file to validate.
+        #[arg(value_name = "VALUES_FILE")]
+        values_file: PathBuf,
+    },
+}
+
 /// These are the supported cli configuration options for upgrade.
 #[derive(Parser)]
 #[command(name = package_description!(), version = version_info_str!())]
 #[command(about = format!("Upgrades {}", PRODUCT), long_about = None)]
+#[command(
+    after_help = "Options which show an [env: ...] variable may also be set with that \
+environment variable. A command-line argument takes precedence over the environment variable, \
+which takes precedence over the default value. Boolean environment variables accept \
+true/false, 1/0 and yes/no."
+)]
+// The subcommands which do not use the cluster need none of the cluster's arguments. The
+// subcommands which do use the cluster are checked for them with missing_cluster_args().
+#[command(subcommand_negates_reqs = true)]
 pub(crate) struct CliArgs {
-    /// This is the URL for the storage REST API server.
+    /// This is the URL for the storage REST API server. If not set, this is the http port of the
+    /// helm release's api-rest Service, e.g. 'http://mayastor-api-rest:8081'.
     #[arg(short = 'e', long)]
-    rest_endpoint: String,
+    rest_endpoint: Option<String>,
+
+    /// This is a PEM file with the CA certificate(s) which verify the storage REST API's server
+    /// certificate, if the REST API is served over https, along with the system's trusted CAs,
+    /// e.g. /var/run/secrets/kubernetes.io/serviceaccount/ca.crt for a certificate signed by the
+    /// cluster's CA.
+    #[arg(long, value_name = "FILE_PATH")]
+    control_plane_ca: Option<PathBuf>,
+
+    /// If set then the storage REST API's server certificate is not verified. This is meant for
+    /// testing.
+    #[arg(long, default_value_t = false, conflicts_with = "control_plane_ca")]
+    control_plane_insecure: bool,
 
     /// This is the Kubernetes Namespace for the Helm release.
-    #[arg(short, long)]
-    namespace: String,
+    #[arg(short, long, env = "UPGRADE_NAMESPACE", required = true)]
+    namespace: Option<String>,
 
     /// This is the release name of the installed Helm chart.
-    #[arg(long)]
-    release_name: String,
+    #[arg(long, env = "UPGRADE_RELEASE_NAME", required = true)]
+    release_name: Option<String>,
 
     /// This is the Helm chart directory filepath for the core Helm chart variant.
-    #[arg(long, env = "CORE_CHART_DIR", value_name = "DIR_PATH")]
-    core_chart_dir: PathBuf,
+    #[arg(
+        long,
+        env = "CORE_CHART_DIR",
+        value_name = "DIR_PATH",
+        required_unless_present = "chart_ref"
+    )]
+    core_chart_dir: Option<PathBuf>,
+
+    /// This is a reference to the core Helm chart in an OCI registry, e.g.
+    /// oci://registry.example.com/charts/mayastor:2.5.0. If set, the chart is pulled and is used
+    /// instead of the chart in --core-chart-dir. The registry credentials are read from the image
+    /// pull secrets in the installed release's helm values.
+    #[arg(long, env = "UPGRADE_CHART_REF", value_name = "OCI_REF")]
+    chart_ref: Option<OciReference>,
+
+    /// This is the helm chart pulled from the --chart-ref OCI reference.
+    #[arg(skip)]
+    pulled_chart: Option<PulledChart>,
+
+    /// This is the directory which the helm values files for 'helm upgrade -f' are written to,
+    /// e.g. a memory-backed emptyDir volume. Defaults to the core Helm chart directory. The files
+    /// are only readable by the upgrade-job, and are removed once the helm upgrade is done with,
+    /// whether it succeeds or fails.
+    #[arg(long, value_name = "DIR_PATH")]
+    values_dir: Option<PathBuf>,
 
     /// If not set, this skips the Kubernetes Pod restarts for the io-engine DaemonSet.
     #[arg(long, default_value_t = false)]
     skip_data_plane_restart: bool,
 
-    /// If set then this skips the upgrade path validation.
+    /// This is the component to upgrade. A control-plane only upgrade runs the helm upgrade, and
+    /// leaves the io-engine Pods at the installed version. A data-plane only upgrade restarts the
+    /// io-engine Pods of an already upgraded helm release.
+    #[arg(long, value_enum, default_value_t = Component::All)]
+    component: Component,
+
+    /// If set then this skips the upgrade path validation, including the check against the
+    /// upgrade compatibility matrix.
     #[arg(long, default_value_t = false)]
     skip_upgrade_path_validation: bool,
 
+    /// If set then the upgrade is not aborted if there are volumes or pools which are not Online.
+    /// This is meant for emergencies.
+    #[arg(
+        long,
+        env = "UPGRADE_SKIP_HEALTH_CHECK",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false
+    )]
+    skip_health_check: bool,
+
+    /// If set then the upgrade is not aborted if a node which runs an io-engine Pod has fewer
+    /// allocatable hugepages than the upgraded io-engine requests.
+    #[arg(long, default_value_t = false)]
+    skip_hugepages_check: bool,
+
+    /// This is the free disk space which each io-engine node needs for pulling the upgraded
+    /// container image, e.g. '2Gi'. The Node status has no disk usage, so this is only compared
+    /// with the node's allocatable ephemeral-storage, which the free disk space cannot exceed.
+    /// Nodes with DiskPressure are reported whether or not this is set.
+    #[arg(long, value_name = "QUANTITY")]
+    min_free_disk: Option<String>,
+
+    /// If set then the upgrade is aborted if an io-engine node has DiskPressure, or has less
+    /// allocatable ephemeral-storage than '--min-free-disk'. Without this, a warning is logged.
+    #[arg(long, default_value_t = false)]
+    fail_on_low_disk: bool,
+
+    /// If set then helm upgrade is run even if the helm chart version is already installed.
+    /// Without this, an upgrade whose helm release and io-engine Pods are already at the
+    /// target version exits without making any changes.
+    #[arg(long, default_value_t = false)]
+    force_upgrade: bool,
+
+    /// If set then upgrades from a release to a pre-release are allowed.
+    #[arg(long, default_value_t = false)]
+    allow_prerelease: bool,
+
+    /// If set then upgrades from a helm chart which predates thin-provisioning fail if the
+    /// installed helm values have no thin-provisioning options. By default, the target helm
+    /// chart's defaults are used for them.
+    #[arg(long, default_value_t = false)]
+    no_thin_defaults: bool,
+
+    /// If set then the upgrade is validated and the upgrade plan is printed, without making any
+    /// changes to the cluster. This cannot be used with the rollback subcommand.
+    #[arg(
+        long,
+        env = "UPGRADE_DRY_RUN",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false
+    )]
+    dry_run: bool,
+
+    /// If set then the upgrade goes ahead without asking for confirmation. Otherwise the upgrade
+    /// plan is printed and the upgrade has to be confirmed at a prompt, which needs an
+    /// interactive terminal. The upgrade fails if there is no terminal and this is not set.
+    #[arg(
+        short = 'y',
+        long,
+        env = "UPGRADE_YES",
+        value_parser = BoolishValueParser::new(),
+        default_value_t = false
+    )]
+    yes: bool,
+
+    /// If set then the storage nodes are not drained of volume targets before their io-engine
+    /// Pods are restarted. This is meant for single-node or test clusters.
+    #[arg(long, default_value_t = false)]
+    no_drain: bool,
+
+    /// This is how long to wait for the volume targets to be moved off a storage node, before its
+    /// io-engine Pod is restarted regardless, e.g. '5m'. The control-plane then fails the
+    /// remaining targets over, as with an unplanned restart. Without this, the drain is waited
+    /// on until it completes.
+    #[arg(long, value_name = "DURATION", conflicts_with = "no_drain")]
+    drain_grace_period: Option<DrainGracePeriod>,
+
+    /// If set then the upgrade is aborted if the only replica of a volume is on a node whose
+    /// io-engine Pod is to be restarted, as the volume is unavailable during the restart. Without
+    /// this, a warning is logged for each node with such volumes.
+    #[arg(long, default_value_t = false)]
+    fail_on_single_replica: bool,
+
+    /// If set then the upgrade is aborted if the io-engine Pods run different container image
+    /// tags, e.g. midway through a manual rollout. Without this, a warning is logged and all of
+    /// the io-engine Pods are upgraded to the same tag.
+    #[arg(long, default_value_t = false)]
+    fail_on_mixed_tags: bool,
+
+    /// If set then the io-engine Pod on only the first node is restarted, and the data-plane
+    /// upgrade is paused once that canary node and its pools are Online again. The upgrade is
+    /// continued on the rest of the nodes once an operator resumes it, by setting the 'paused'
+    /// key of the upgrade state ConfigMap to "false". The upgrade is aborted if the canary node
+    /// fails the verification.
+    #[arg(long, default_value_t = false)]
+    canary: bool,
+
+    /// This is the maximum number of io-engine Pods which may be restarted at the same time, as
+    /// an integer or as a percentage of all of the io-engine Pods, e.g. 2 or 25%. io-engine Pods
+    /// on nodes which host the target or a replica of the same volume are never restarted at the
+    /// same time.
+    #[arg(long, default_value = "1")]
+    max_unavailable: MaxUnavailable,
+
+    /// If set, the io-engine Pods on the nodes with this label are not restarted, e.g.
+    /// 'node-role.example.com/maintenance=true'. The io-engine Pods on these nodes are left at
+    /// the installed version, and are left out of the post-upgrade verification.
+    #[arg(long, value_name = "KEY=VALUE")]
+    skip_node_label: Option<NodeLabel>,
+
+    /// This is the maximum time to wait for an io-engine Pod to become Ready, after it is
+    /// restarted, on each node.
+    #[arg(long, default_value = "10m")]
+    node_ready_timeout: humantime::Duration,
+
+    /// If set, after the io-engine Pod on a node is Ready, the upgrade waits for this long and
+    /// then until the volumes on the node are Online and are not rebuilding, before moving on to
+    /// the next node, e.g. '30s'. The wait for the volumes is bounded by --node-ready-timeout.
+    #[arg(long, value_name = "DURATION")]
+    post_node_wait: Option<humantime::Duration>,
+
+    /// This is the maximum time for the whole upgrade. Once it is exceeded, the upgrade is
+    /// aborted ahead of the next batch of io-engine Pod restarts, and a re-run of the upgrade-job
+    /// resumes from there. The time for which the upgrade is paused does not count, the time spent
+    /// by earlier runs of the same upgrade does.
+    #[arg(long)]
+    overall_timeout: Option<humantime::Duration>,
+
+    /// This is the time at which the arguments were parsed, i.e. when the upgrade-job started.
+    #[arg(skip = Instant::now())]
+    started_at: Instant,
+
+    /// This is the Jaeger endpoint agent to export the upgrade's tracing spans to. Spans are only
+    /// logged if this is not set.
+    #[arg(long, env = "JAEGER_ENDPOINT")]
+    jaeger: Option<String>,
+
+    /// If set, the upgrade plan is POSTed as JSON to this URL before the helm upgrade, and the
+    /// upgrade only proceeds if the response has a 2xx status.
+    #[arg(long, value_name = "URL")]
+    pre_upgrade_webhook: Option<String>,
+
+    /// This is the maximum time to wait for the pre-upgrade webhook to respond.
+    #[arg(long, default_value = "30s")]
+    pre_upgrade_webhook_timeout: humantime::Duration,
+
+    /// If set, the upgrade metrics are served in the Prometheus format on this port.
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// This is how long the metrics are served for after the upgrade has succeeded or failed,
+    /// so that the outcome is scraped before the upgrade-job exits.
+    #[arg(long, default_value = "1m", requires = "metrics_port")]
+    metrics_linger: humantime::Duration,
+
+    /// This is the format of the upgrade-job's logs. Tracing spans are not exported to Jaeger
+    /// with the json log format.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, conflicts_with = "jaeger")]
+    log_format: LogFormat,
+
+    /// This is the output format of the upgrade plan, printed with --dry-run, of the rendered
+    /// helm values, and of the data-plane upgrade progress. The logs are written to stderr with
+    /// the json output format.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// If set then upgrade fails if the helm chart to upgrade to is deprecated.
+    #[arg(long, default_value_t = false)]
+    fail_on_deprecated: bool,
+
     /// The name of the Kubernetes Job Pod. The Job object will be used to post upgrade event.
-    #[arg(env = "POD_NAME")]
-    pod_name: String,
+    #[arg(env = "POD_NAME", required = true)]
+    pod_name: Option<String>,
 
     /// The set values specified by the user for upgrade
     /// (can specify multiple or separate values with commas: key1=val1,key2=val2).
-    #[arg(long)]
+    #[arg(long, default_value = "")]
     helm_args_set: String,
 
     /// The set file values specified by the user for upgrade
     /// (can specify multiple or separate values with commas: key1=path1,key2=path2).
-    #[arg(long)]
+    #[arg(long, default_value = "")]
     helm_args_set_file: String,
+
+    /// This is a helm values file to merge on top of the installed release's values, before they
+    /// are migrated and validated. This may be specified more than once, later files win.
+    #[arg(long = "values-file", value_name = "FILE_PATH")]
+    values_files: Vec<PathBuf>,
+
+    /// This is a helm value to set on top of the installed release's values and the values
+    /// files, e.g. 'etcd.persistence.size=4Gi'. This may be specified more than once.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set_values: Vec<SetValue>,
+
+    /// This is like --set, except that the value is always a string, e.g. 'image.tag=2024'. This
+    /// may be specified more than once, and wins over --set.
+    #[arg(long = "set-string", value_name = "KEY=VALUE", value_parser = SetValue::parse_string)]
+    set_string_values: Vec<SetValue>,
+
+    /// This is the dot-separated key path of a helm value to redact from the upgrade plan and the
+    /// audit records, e.g. 'etcd.auth.rootPassword'. A '*' matches any number of keys, e.g.
+    /// '*.password'. This may be specified more than once, and replaces the default key paths.
+    #[arg(long = "redact", value_name = "KEY_PATH")]
+    redact_paths: Vec<String>,
+
+    /// This is a container image name prefix which is available to the cluster, e.g.
+    /// 'registry.example.com/openebs'. If set, an upgrade which moves the container images to
+    /// another registry or repository fails before any changes are made, unless the new images
+    /// match one of the prefixes. This may be specified more than once.
+    #[arg(long = "image-allowlist", value_name = "IMAGE_PREFIX")]
+    image_allowlist: Vec<String>,
+
+    /// If set then the upgrade fails if the container image tag of the upgrade is not the target
+    /// helm chart's appVersion, e.g. 'v2.6.0' for appVersion 2.6.0. This is meant for official
+    /// releases, a development image tag is allowed by default.
+    #[arg(long, default_value_t = false)]
+    enforce_tag_matches_appversion: bool,
+
+    /// This is the operation to run instead of upgrade, if any.
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
 impl CliArgs {
     /// This returns the URL to the storage REST API.
     pub(crate) fn rest_endpoint(&self) -> String {
-        self.rest_endpoint.clone()
+        self.rest_endpoint.clone().unwrap_or_else(|| {
+            format!(
+                "http://{}{API_REST_SERVICE_NAME_SUFFIX}:{API_REST_HTTP_PORT}",
+                self.release_name()
+            )
+        })
     }
 
-    /// This returns the Kubernetes Namespace for the Helm chart release.
+    /// This returns how the storage REST API's server certificate is verified. The system's
+    /// trusted CAs are used, along with the --control-plane-ca, if one is set.
+    pub(crate) fn rest_tls(&self) -> RestTls {
+        if self.control_plane_insecure {
+            return RestTls::Insecure;
+        }
+
+        match self.control_plane_ca.clone() {
+            Some(ca_path) => RestTls::Ca(ca_path),
+            None => RestTls::SystemRoots,
+        }
+    }
+
+    /// This returns the Kubernetes Namespace for the Helm chart release. This is empty for the
+    /// subcommands which do not use the cluster.
     pub(crate) fn namespace(&self) -> String {
-        self.namespace.clone()
+        self.namespace.clone().unwrap_or_default()
     }
 
-    /// This returns the Helm release name for the installed Helm chart.
+    /// This returns the Helm release name for the installed Helm chart. This is empty for the
+    /// subcommands which do not use the cluster.
     pub(crate) fn release_name(&self) -> String {
-        self.release_name.clone()
+        self.release_name.clone().unwrap_or_default()
     }
 
     /// This returns the Helm chart directory filepath for a crate::helm::upgrade::HelmChart::Core.
     pub(crate) fn core_chart_dir(&self) -> PathBuf {
-        self.core_chart_dir.clone()
+        match self.pulled_chart.as_ref() {
+            Some(pulled_chart) => pulled_chart.chart_dir().to_path_buf(),
+            // One of --core-chart-dir and --chart-ref is required.
+            None => self.core_chart_dir.clone().unwrap_or_default(),
+        }
+    }
+
+    /// This returns the directory to write the helm values files for the upgrade to.
+    pub(crate) fn values_dir(&self) -> PathBuf {
+        self.values_dir
+            .clone()
+            .unwrap_or_else(|| self.core_chart_dir())
+    }
+
+    /// This returns the directory to write the helm values files to, if it was set.
+    pub(crate) fn values_dir_opt(&self) -> Option<PathBuf> {
+        self.values_dir.clone()
+    }
+
+    /// This returns the OCI reference of the core helm chart to pull, if any.
+    pub(crate) fn chart_ref(&self) -> Option<OciReference> {
+        self.chart_ref.clone()
+    }
+
+    /// This sets the helm chart pulled from the OCI registry, to be used as the core chart.
+    pub(crate) fn set_pulled_chart(&mut self, pulled_chart: PulledChart) {
+        self.pulled_chart = Some(pulled_chart);
     }
 
     /// This is a predicate to decide if <release-name>-io-engine Kubernetes DaemonSet Pods should
     /// be restarted as a part of the data-plane upgrade.
+    /// The io-engine Pods are never restarted in a control-plane only upgrade.
     pub(crate) fn skip_data_plane_restart(&self) -> bool {
-        self.skip_data_plane_restart
+        self.skip_data_plane_restart || self.component.eq(&Component::ControlPlane)
+    }
+
+    /// This returns the component of the PRODUCT installation to upgrade.
+    pub(crate) fn component(&self) -> Component {
+        self.component
+    }
+
+    /// This decides to skip draining the storage nodes before restarting io-engine Pods or not.
+    pub(crate) fn no_drain(&self) -> bool {
+        self.no_drain
+    }
+
+    /// This decides to fail, instead of warning, if a volume has its only replica on a node whose
+    /// io-engine Pod is to be restarted.
+    pub(crate) fn fail_on_single_replica(&self) -> bool {
+        self.fail_on_single_replica
+    }
+
+    /// This decides to fail, instead of warning, if the io-engine Pods run different container
+    /// image tags.
+    pub(crate) fn fail_on_mixed_tags(&self) -> bool {
+        self.fail_on_mixed_tags
+    }
+
+    /// This is how long the drain of a storage node may take, if it is limited.
+    pub(crate) fn drain_grace_period(&self) -> Option<Duration> {
+        self.drain_grace_period.map(|grace_period| grace_period.0)
+    }
+
+    /// This decides to upgrade a canary node and wait for approval, before the rest of the nodes.
+    pub(crate) fn canary(&self) -> bool {
+        self.canary
+    }
+
+    /// This returns the maximum number of io-engine Pods which may be restarted at the same time.
+    pub(crate) fn max_unavailable(&self) -> MaxUnavailable {
+        self.max_unavailable
+    }
+
+    /// This returns the label of the nodes whose io-engine Pods are not to be restarted, if any.
+    pub(crate) fn skip_node_label(&self) -> Option<&NodeLabel> {
+        self.skip_node_label.as_ref()
+    }
+
+    /// This returns the maximum time to wait for a restarted io-engine Pod to become Ready.
+    pub(crate) fn node_ready_timeout(&self) -> Duration {
+        *self.node_ready_timeout
+    }
+
+    /// This is the settling time after the restart of an io-engine Pod on a node, if the volumes
+    /// on the node are to be waited on.
+    pub(crate) fn post_node_wait(&self) -> Option<Duration> {
+        self.post_node_wait.map(|wait| *wait)
     }
 
     /// This decides to skip upgrade path validation or not.
@@ -82,9 +652,116 @@ impl CliArgs {
         self.skip_upgrade_path_validation
     }
 
+    /// This decides to skip the pre-upgrade storage health check or not.
+    pub(crate) fn skip_health_check(&self) -> bool {
+        self.skip_health_check
+    }
+
+    /// This decides to skip the io-engine hugepages capacity check or not.
+    pub(crate) fn skip_hugepages_check(&self) -> bool {
+        self.skip_hugepages_check
+    }
+
+    /// This is the free disk space which each io-engine node needs, as a Kubernetes quantity.
+    pub(crate) fn min_free_disk(&self) -> Option<&str> {
+        self.min_free_disk.as_deref()
+    }
+
+    /// This decides to fail, instead of warning, if an io-engine node is low on disk space.
+    pub(crate) fn fail_on_low_disk(&self) -> bool {
+        self.fail_on_low_disk
+    }
+
+    /// This decides to re-run helm upgrade for an already installed version or not.
+    pub(crate) fn force_upgrade(&self) -> bool {
+        self.force_upgrade
+    }
+
+    /// This decides to allow upgrades from a release to a pre-release or not.
+    pub(crate) fn allow_prerelease(&self) -> bool {
+        self.allow_prerelease
+    }
+
+    /// This decides to fail, instead of using the target helm chart's thin-provisioning defaults,
+    /// on upgrades from helm charts which predate thin-provisioning.
+    pub(crate) fn no_thin_defaults(&self) -> bool {
+        self.no_thin_defaults
+    }
+
+    /// This decides to only print the upgrade plan or not.
+    pub(crate) fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// This decides to skip the confirmation prompt before upgrading or not.
+    pub(crate) fn yes(&self) -> bool {
+        self.yes
+    }
+
+    /// This returns the maximum time for the whole upgrade, if any.
+    pub(crate) fn overall_timeout(&self) -> Option<Duration> {
+        self.overall_timeout.map(|timeout| *timeout)
+    }
+
+    /// This returns the time at which the upgrade-job started.
+    pub(crate) fn started_at(&self) -> Instant {
+        self.started_at
+    }
+
+    /// This returns the Jaeger endpoint agent to export tracing spans to, if any.
+    pub(crate) fn jaeger(&self) -> Option<String> {
+        self.jaeger.clone()
+    }
+
+    /// This returns the URL of the pre-upgrade webhook, if any.
+    pub(crate) fn pre_upgrade_webhook(&self) -> Option<String> {
+        self.pre_upgrade_webhook.clone()
+    }
+
+    /// This returns the maximum time to wait for the pre-upgrade webhook to respond.
+    pub(crate) fn pre_upgrade_webhook_timeout(&self) -> Duration {
+        *self.pre_upgrade_webhook_timeout
+    }
+
+    /// This returns the port to serve the upgrade metrics on, if they are enabled.
+    pub(crate) fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// This returns how long the metrics are served for after the upgrade has ended.
+    pub(crate) fn metrics_linger(&self) -> Duration {
+        *self.metrics_linger
+    }
+
+    /// This returns the output format of the upgrade plan and the data-plane upgrade progress.
+    pub(crate) fn output(&self) -> OutputFormat {
+        self.output
+    }
+
+    /// This returns the format of the upgrade-job's logs.
+    pub(crate) fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    /// This decides if stdout carries a document for other programs to parse, i.e. the JSON
+    /// output, the rendered helm values, the helm chart versions, the audit record or the values
+    /// schema. The logs are written to stderr then, so as to keep the document parsable.
+    pub(crate) fn stdout_is_document(&self) -> bool {
+        matches!(self.output, OutputFormat::Json)
+            || self.render_values()
+            || self.audit_export()
+            || self.values_schema().is_some()
+            || self.list_versions_repo_url().is_some()
+    }
+
+    /// This decides to abort upgrade to a deprecated helm chart or not.
+    pub(crate) fn fail_on_deprecated(&self) -> bool {
+        self.fail_on_deprecated
+    }
+
     /// This returns the name of the Kubernetes Pod where this binary will be running.
     pub(crate) fn pod_name(&self) -> String {
-        self.pod_name.clone()
+        self.pod_name.clone().unwrap_or_default()
     }
 
     /// This returns the set values passed during upgrade.
@@ -96,4 +773,361 @@ impl CliArgs {
     pub(crate) fn helm_args_set_file(&self) -> String {
         self.helm_args_set_file.clone()
     }
+
+    /// This returns the helm values set at upgrade time, with --values-file, --set and
+    /// --set-string.
+    pub(crate) fn values_overrides(&self) -> ValuesOverrides {
+        ValuesOverrides::new(
+            self.values_files.clone(),
+            self.set_values.clone(),
+            self.set_string_values.clone(),
+        )
+    }
+
+    /// This returns the key paths of the helm values to redact, from --redact, or the default key
+    /// paths if none were set.
+    pub(crate) fn redact_paths(&self) -> Vec<String> {
+        if self.redact_paths.is_empty() {
+            DEFAULT_REDACTED_VALUES_PATHS
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        } else {
+            self.redact_paths.clone()
+        }
+    }
+
+    /// This returns the allowlisted container image name prefixes.
+    pub(crate) fn image_allowlist(&self) -> &[String] {
+        self.image_allowlist.as_slice()
+    }
+
+    /// This decides to fail if the container image tag is not the target helm chart's appVersion.
+    pub(crate) fn enforce_tag_matches_appversion(&self) -> bool {
+        self.enforce_tag_matches_appversion
+    }
+
+    /// This decides to roll back instead of upgrading or not.
+    pub(crate) fn rollback(&self) -> bool {
+        matches!(self.command, Some(Command::Rollback))
+    }
+
+    /// This decides to only print the helm values for the upgrade or not.
+    pub(crate) fn render_values(&self) -> bool {
+        matches!(self.command, Some(Command::RenderValues))
+    }
+
+    /// This decides to only check the cluster for drift from the installed helm values or not.
+    pub(crate) fn verify(&self) -> bool {
+        matches!(self.command, Some(Command::Verify))
+    }
+
+    /// This decides to only export an audit record of the upgrade or not.
+    pub(crate) fn audit_export(&self) -> bool {
+        matches!(self.command, Some(Command::AuditExport { .. }))
+    }
+
+    /// This returns the name of the ConfigMap to store the audit record in, if any, and the
+    /// number of audit records which it retains.
+    pub(crate) fn audit_configmap(&self) -> Option<(String, usize)> {
+        match &self.command {
+            Some(Command::AuditExport {
+                configmap,
+                max_records,
+                ..
+            }) => configmap.clone().map(|configmap| (configmap, *max_records)),
+            _ => None,
+        }
+    }
+
+    /// This returns the identity of whoever requested the upgrade, for the audit record, if set.
+    pub(crate) fn audit_requested_by(&self) -> Option<String> {
+        match &self.command {
+            Some(Command::AuditExport { requested_by, .. }) => requested_by.clone(),
+            _ => None,
+        }
+    }
+
+    /// This returns the helm chart directory and the values file to validate, if a values file is
+    /// to be validated instead of upgrading.
+    pub(crate) fn validate_values(&self) -> Option<(PathBuf, PathBuf)> {
+        match &self.command {
+            Some(Command::ValidateValues {
+                chart_dir,
+                values_file,
+            }) => Some((chart_dir.clone(), values_file.clone())),
+            _ => None,
+        }
+    }
+
+    /// This returns the key to nest the Core chart's values under, if the helm values schema is to
+    /// be printed instead of upgrading. The key is None if the values are not nested.
+    pub(crate) fn values_schema(&self) -> Option<Option<String>> {
+        match &self.command {
+            Some(Command::Schema { core_values_key }) => Some(core_values_key.clone()),
+            _ => None,
+        }
+    }
+
+    /// This returns the helm repository URL to list the helm chart versions from, if the versions
+    /// are to be listed instead of upgrading.
+    pub(crate) fn list_versions_repo_url(&self) -> Option<String> {
+        match &self.command {
+            Some(Command::ListVersions { repo_url }) => Some(repo_url.clone()),
+            _ => None,
+        }
+    }
+
+    /// This decides if the operation uses the cluster. Values files are validated, the values
+    /// schema is printed and the helm chart versions are listed without a cluster.
+    pub(crate) fn uses_cluster(&self) -> bool {
+        self.validate_values().is_none()
+            && self.values_schema().is_none()
+            && self.list_versions_repo_url().is_none()
+    }
+
+    /// This lists the arguments which the operation on the cluster needs and which are not set.
+    /// The upgrade and the rollback publish Events for the upgrade-job's Pod, and so need its
+    /// name.
+    pub(crate) fn missing_cluster_args(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.namespace.is_none() {
+            missing.push("--namespace");
+        }
+        if self.release_name.is_none() {
+            missing.push("--release-name");
+        }
+        if self.core_chart_dir.is_none() && self.chart_ref.is_none() {
+            missing.push("--core-chart-dir");
+        }
+        if self.pod_name.is_none() && matches!(self.command, None | Some(Command::Rollback)) {
+            missing.push("<POD_NAME>");
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Mutex, PoisonError};
+
+    /// This is held while the arguments are parsed, as the environment variables which the
+    /// arguments fall back to are shared by the tests which run concurrently.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// This parses the arguments, after the binary name.
+    pub(crate) fn parse(args: &[&str]) -> Result<CliArgs, clap::Error> {
+        parse_with_env(args, &[])
+    }
+
+    /// This parses the arguments with the environment variables set, and removes them after.
+    pub(crate) fn parse_with_env(
+        args: &[&str],
+        env: &[(&str, &str)],
+    ) -> Result<CliArgs, clap::Error> {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        for (name, value) in env {
+            std::env::set_var(name, value);
+        }
+        let result =
+            CliArgs::try_parse_from(std::iter::once("upgrade-job").chain(args.iter().copied()));
+        for (name, _) in env {
+            std::env::remove_var(name);
+        }
+        result
+    }
+
+    /// These are the environment variables for the arguments which an upgrade needs.
+    const CLUSTER_ENV: [(&str, &str); 4] = [
+        ("UPGRADE_NAMESPACE", "mayastor"),
+        ("UPGRADE_RELEASE_NAME", "mayastor"),
+        ("CORE_CHART_DIR", "chart"),
+        ("POD_NAME", "upgrade-job-pod"),
+    ];
+
+    /// This parses the upgrade's arguments, with the extra arguments.
+    fn parse_upgrade(extra_args: &[&str]) -> Result<CliArgs, clap::Error> {
+        let mut args = extra_args.to_vec();
+        args.extend([
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "chart",
+            "upgrade-job-pod",
+        ]);
+        parse(args.as_slice())
+    }
+
+    #[test]
+    fn drain_grace_period_is_parsed() {
+        let opts = parse_upgrade(&["--drain-grace-period", "90s"]).unwrap();
+        assert_eq!(opts.drain_grace_period(), Some(Duration::from_secs(90)));
+
+        let opts = parse_upgrade(&["--drain-grace-period", "1m 30s"]).unwrap();
+        assert_eq!(opts.drain_grace_period(), Some(Duration::from_secs(90)));
+
+        assert_eq!(parse_upgrade(&[]).unwrap().drain_grace_period(), None);
+    }
+
+    #[test]
+    fn invalid_drain_grace_periods_are_rejected() {
+        for value in ["0s", "0", "-5m", "soon", ""] {
+            assert!(
+                matches!(
+                    value.parse::<DrainGracePeriod>(),
+                    Err(Error::DrainGracePeriodParse { value: invalid }) if invalid == value
+                ),
+                "'{value}' should be rejected"
+            );
+            assert!(
+                parse_upgrade(&["--drain-grace-period", value]).is_err(),
+                "'{value}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn drain_grace_period_conflicts_with_no_drain() {
+        assert!(parse_upgrade(&["--drain-grace-period", "5m", "--no-drain"]).is_err());
+    }
+
+    #[test]
+    fn env_vars_configure_the_upgrade() {
+        let mut env = CLUSTER_ENV.to_vec();
+        env.extend([
+            ("UPGRADE_DRY_RUN", "true"),
+            ("UPGRADE_SKIP_HEALTH_CHECK", "1"),
+        ]);
+
+        let opts = parse_with_env(&[], env.as_slice()).unwrap();
+        assert!(opts.missing_cluster_args().is_empty());
+        assert_eq!(opts.namespace(), "mayastor");
+        assert_eq!(opts.release_name(), "mayastor");
+        assert_eq!(opts.core_chart_dir(), PathBuf::from("chart"));
+        assert_eq!(opts.pod_name(), "upgrade-job-pod");
+        assert!(opts.dry_run());
+        assert!(opts.skip_health_check());
+        assert!(!opts.yes());
+    }
+
+    #[test]
+    fn boolean_env_vars_parse_consistently() {
+        for (value, expected) in [
+            ("true", true),
+            ("1", true),
+            ("yes", true),
+            ("false", false),
+            ("0", false),
+            ("no", false),
+        ] {
+            let mut env = CLUSTER_ENV.to_vec();
+            env.extend([
+                ("UPGRADE_DRY_RUN", value),
+                ("UPGRADE_SKIP_HEALTH_CHECK", value),
+                ("UPGRADE_YES", value),
+            ]);
+
+            let opts = parse_with_env(&[], env.as_slice()).unwrap();
+            assert_eq!(opts.dry_run(), expected, "{value}");
+            assert_eq!(opts.skip_health_check(), expected, "{value}");
+            assert_eq!(opts.yes(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn cli_args_take_precedence_over_env_vars() {
+        let mut env = CLUSTER_ENV.to_vec();
+        env.extend([("UPGRADE_DRY_RUN", "false")]);
+
+        let opts = parse_with_env(
+            &[
+                "--namespace",
+                "openebs",
+                "--release-name",
+                "openebs",
+                "--dry-run",
+                "other-pod",
+            ],
+            env.as_slice(),
+        )
+        .unwrap();
+        assert_eq!(opts.namespace(), "openebs");
+        assert_eq!(opts.release_name(), "openebs");
+        assert_eq!(opts.pod_name(), "other-pod");
+        assert_eq!(opts.core_chart_dir(), PathBuf::from("chart"));
+        assert!(opts.dry_run());
+    }
+
+    #[test]
+    fn validate_values_needs_no_cluster_args() {
+        let opts = parse(&["validate-values", "chart", "values.yaml"]).unwrap();
+        assert!(!opts.uses_cluster());
+        assert!(opts.validate_values().is_some());
+    }
+
+    #[test]
+    fn schema_needs_no_cluster_args() {
+        let opts = parse(&["schema"]).unwrap();
+        assert!(!opts.uses_cluster());
+    }
+
+    #[test]
+    fn upgrade_needs_the_cluster_args() {
+        assert!(parse(&[]).is_err());
+
+        let opts = parse(&[
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "chart",
+            "upgrade-job-pod",
+        ])
+        .unwrap();
+        assert!(opts.uses_cluster());
+        assert!(opts.missing_cluster_args().is_empty());
+        assert_eq!(opts.helm_args_set(), "");
+    }
+
+    #[test]
+    fn yes_skips_the_confirmation() {
+        let args = [
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "chart",
+            "upgrade-job-pod",
+        ];
+        assert!(!parse(&args).unwrap().yes());
+
+        let mut yes_args = vec!["--yes"];
+        yes_args.extend(args);
+        assert!(parse(yes_args.as_slice()).unwrap().yes());
+    }
+
+    #[test]
+    fn cluster_subcommand_lists_the_missing_cluster_args() {
+        let opts = parse(&["rollback"]).unwrap();
+        assert_eq!(
+            opts.missing_cluster_args(),
+            vec![
+                "--namespace",
+                "--release-name",
+                "--core-chart-dir",
+                "<POD_NAME>"
+            ]
+        );
+
+        let opts = parse(&["--namespace", "mayastor", "verify"]).unwrap();
+        assert_eq!(
+            opts.missing_cluster_args(),
+            vec!["--release-name", "--core-chart-dir"]
+        );
+    }
 }
@@ -1,29 +1,47 @@
 use crate::{
     common::{
-        constants::CORE_CHART_NAME,
+        constants::{APPLICATION_CHART_TYPE, CORE_CHART_NAME},
         error::{
-            FindingHelmChart, GetNamespace, HelmCommand, HelmListCommand, HelmRelease, HelmVersion,
-            HelmVersionCommand, ListStorageNodes, NotADirectory, NotAFile, ReadingFile,
-            RegexCompile, Result, U8VectorToString, ValidateDirPath, ValidateFilePath,
-            YamlParseFromFile,
+            CollectDirEntries, ControlPlaneTls, ControlPlaneUnreachable, DeprecatedHelmChart,
+            FindingHelmChart, GetNamespace, HelmChartDependencyAbsent, HelmCommand,
+            HelmListCommand, HelmRelease, HelmVersion, HelmVersionCommand, NotADirectory, NotAFile,
+            NotAnApplicationHelmChart, ReadingDirectoryContents, RegexCompile, Result,
+            U8VectorToString, ValidateDirPath, ValidateFilePath,
         },
         kube_client::KubeClientSet,
-        rest_client::RestClientSet,
+        rest_client::{tls_handshake_fails, RestClientSet, RestTls},
+    },
+    helm::{
+        chart::{Chart, FromPath},
+        load::{load_chart_files, ChartFile, ChartFileKind},
     },
-    helm::chart::Chart,
     vec_to_strings,
 };
 use regex::bytes::Regex;
-use snafu::{ensure, ResultExt};
-use std::{fs, path::PathBuf, process::Command, str};
-use tracing::debug;
+use semver::Version;
+use snafu::{ensure, IntoError, ResultExt};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+};
+use tracing::{debug, info, warn};
 
 /// Validate that the helm release specified in the CLI options exists in the namespace,
-/// which is also specified in the CLI options.
+/// which is also specified in the CLI options. A helm release whose last upgrade failed may be
+/// upgraded again, so failed helm releases are listed as well as deployed ones.
 pub(crate) fn validate_helm_release(name: String, namespace: String) -> Result<()> {
     let command: &str = "helm";
-    let args: Vec<String> =
-        vec_to_strings!["list", "-n", namespace.as_str(), "--deployed", "--short"];
+    let args: Vec<String> = vec_to_strings![
+        "list",
+        "-n",
+        namespace.as_str(),
+        "--deployed",
+        "--failed",
+        "--short"
+    ];
 
     debug!(%command, ?args, "Helm list command");
 
@@ -108,8 +126,11 @@ pub(crate) fn validate_helmv3_in_path() -> Result<()> {
 }
 
 /// Validate the input helm chart directory path.
-pub(crate) fn validate_helm_chart_dir(core_dir: PathBuf) -> Result<()> {
-    validate_core_helm_chart_variant_in_dir(core_dir)
+pub(crate) async fn validate_helm_chart_dir(
+    core_dir: PathBuf,
+    fail_on_deprecated: bool,
+) -> Result<()> {
+    validate_core_helm_chart_variant_in_dir(core_dir, fail_on_deprecated).await
 }
 
 /// Validate the input helm chart directory path:
@@ -117,7 +138,14 @@ pub(crate) fn validate_helm_chart_dir(core_dir: PathBuf) -> Result<()> {
 /// - validate if the expected directory structure is present.
 /// - validate if the expected helm chart files are present.
 /// - validate if the chart name if the chart name in the Chart.yaml file is correct.
-fn validate_core_helm_chart_variant_in_dir(dir_path: PathBuf) -> Result<()> {
+/// - validate if the Chart.yaml and the values.yaml files can be loaded.
+/// - validate if the chart in the Chart.yaml file is an 'application' chart.
+/// - validate if the sub-chart dependencies of the chart are present.
+/// - warn about, or fail validation for, a deprecated chart.
+async fn validate_core_helm_chart_variant_in_dir(
+    dir_path: PathBuf,
+    fail_on_deprecated: bool,
+) -> Result<()> {
     let path_exists_and_is_dir = |path: PathBuf| -> Result<bool> {
         fs::metadata(path.as_path())
             .map(|m| m.is_dir())
@@ -145,18 +173,63 @@ fn validate_core_helm_chart_variant_in_dir(dir_path: PathBuf) -> Result<()> {
         }
     );
 
-    let chart_yaml_file = fs::read(chart_yaml_path.as_path()).context(ReadingFile {
-        filepath: chart_yaml_path.clone(),
-    })?;
-    let chart_yaml: Chart =
-        serde_yaml::from_slice(chart_yaml_file.as_slice()).context(YamlParseFromFile {
-            filepath: chart_yaml_path.clone(),
-        })?;
+    // Validate values.yaml file.
+    let mut values_yaml_path = dir_path.clone();
+    values_yaml_path.push("values.yaml");
+    ensure!(
+        path_exists_and_is_file(values_yaml_path.clone())?,
+        NotAFile {
+            path: values_yaml_path.clone()
+        }
+    );
+
+    // The Chart.yaml and the values.yaml files are loaded together, so that the errors in either
+    // of them are reported at once.
+    let mut files = load_chart_files(vec![
+        (chart_yaml_path, ChartFileKind::Chart),
+        (values_yaml_path, ChartFileKind::CoreValues),
+    ])
+    .await?
+    .into_iter();
+    let (chart_yaml, values_yaml) = match (files.next(), files.next()) {
+        (Some(ChartFile::Chart(chart)), Some(ChartFile::CoreValues(values))) => (chart, values),
+        _ => return FindingHelmChart { path: dir_path }.fail(),
+    };
 
     ensure!(
         chart_yaml.name().eq(CORE_CHART_NAME),
         FindingHelmChart { path: dir_path }
     );
+    debug!(
+        api_version = chart_yaml.api_version(),
+        app_version = %chart_yaml.app_version_or_chart_version(),
+        image_tag = values_yaml.image_tag(),
+        "Found {CORE_CHART_NAME} helm chart"
+    );
+
+    // Library charts cannot be installed, so they cannot be upgraded to.
+    ensure!(
+        chart_yaml.chart_type().eq(APPLICATION_CHART_TYPE),
+        NotAnApplicationHelmChart {
+            path: dir_path,
+            chart_type: chart_yaml.chart_type().to_string()
+        }
+    );
+
+    if chart_yaml.is_deprecated() {
+        ensure!(
+            !fail_on_deprecated,
+            DeprecatedHelmChart {
+                name: chart_yaml.name().to_string(),
+                version: chart_yaml.version().to_string()
+            }
+        );
+        warn!(
+            "The {} helm chart version {} is deprecated",
+            chart_yaml.name(),
+            chart_yaml.version()
+        );
+    }
 
     // Validate charts directory, it should exist if `helm dependency update` has been executed.
     let charts_dir_path = dir_path.join("charts");
@@ -166,16 +239,7 @@ fn validate_core_helm_chart_variant_in_dir(dir_path: PathBuf) -> Result<()> {
             path: charts_dir_path
         }
     );
-
-    // Validate values.yaml file.
-    let mut values_yaml_path = dir_path.clone();
-    values_yaml_path.push("values.yaml");
-    ensure!(
-        path_exists_and_is_file(values_yaml_path.clone())?,
-        NotAFile {
-            path: values_yaml_path.clone()
-        }
-    );
+    validate_helm_chart_dependencies(&chart_yaml, charts_dir_path)?;
 
     // Validate README.md file.
     let mut readme_md_path = dir_path.clone();
@@ -210,6 +274,74 @@ fn validate_core_helm_chart_variant_in_dir(dir_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Validate that all of the sub-charts which the helm chart depends on are present in the 'charts'
+/// directory, and that their versions satisfy the version requirements in the Chart.yaml.
+fn validate_helm_chart_dependencies(chart: &Chart, charts_dir_path: PathBuf) -> Result<()> {
+    let entries = fs::read_dir(charts_dir_path.as_path())
+        .context(ReadingDirectoryContents {
+            path: charts_dir_path.clone(),
+        })?
+        .map(|res| res.map(|e| e.path()))
+        .collect::<Result<Vec<_>, std::io::Error>>()
+        .context(CollectDirEntries {
+            path: charts_dir_path.clone(),
+        })?;
+
+    let mut vendored_dependencies: HashSet<&str> = HashSet::new();
+    for (name, version) in entries
+        .iter()
+        .filter_map(|entry| vendored_chart_name_and_version(entry.as_path()))
+    {
+        // Archives of sub-chart versions which are no longer required may be left over in the
+        // directory.
+        if let Some(dependency) = chart.dependency(name.as_str()) {
+            if dependency.version_matches(&version)? {
+                vendored_dependencies.insert(dependency.name());
+            }
+        }
+    }
+
+    for dependency in chart.dependencies() {
+        debug!(
+            name = dependency.name(),
+            version = dependency.version(),
+            repository = ?dependency.repository(),
+            condition = ?dependency.condition(),
+            "Validating helm chart dependency"
+        );
+        ensure!(
+            vendored_dependencies.contains(dependency.name()),
+            HelmChartDependencyAbsent {
+                name: dependency.name().to_string(),
+                version_req: dependency.version().to_string(),
+                path: charts_dir_path.clone()
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// `helm dependency update` vendors sub-charts as '<chart-name>-<chart-version>.tgz' archives,
+/// and sub-charts may also be unpacked into a directory with a Chart.yaml file. This picks out
+/// the chart name and the chart version of either of them.
+fn vendored_chart_name_and_version(path: &Path) -> Option<(String, Version)> {
+    if path.is_dir() {
+        let chart = Chart::from_path(path.join("Chart.yaml").as_path()).ok()?;
+        return Some((chart.name().to_string(), chart.version().clone()));
+    }
+
+    let name_and_version = path.file_name()?.to_str()?.strip_suffix(".tgz")?;
+
+    // The chart name may contain '-' characters as well, so the version is the first suffix
+    // after a '-' which is a valid semver.
+    name_and_version.match_indices('-').find_map(|(index, _)| {
+        Version::parse(&name_and_version[index + 1 ..])
+            .ok()
+            .map(|version| (name_and_version[.. index].to_string(), version))
+    })
+}
+
 /// This checks for 2 things:
 /// - if the kubernetes API is reachable.
 /// - if the input namespace exists.
@@ -228,15 +360,195 @@ pub(crate) async fn validate_namespace(ns: String) -> Result<()> {
     Ok(())
 }
 
-/// This checks if the storage API is reachable and usable.
-pub(crate) async fn validate_rest_endpoint(rest_endpoint: String) -> Result<()> {
-    let rest_client = RestClientSet::new_with_url(rest_endpoint)?;
+/// This validates that the directory for the helm values files exists.
+pub(crate) fn validate_values_dir(dir_path: PathBuf) -> Result<()> {
+    let is_dir = fs::metadata(dir_path.as_path())
+        .map(|m| m.is_dir())
+        .context(ValidateDirPath {
+            path: dir_path.clone(),
+        })?;
+    ensure!(is_dir, NotADirectory { path: dir_path });
 
-    rest_client
-        .nodes_api()
-        .get_nodes(None)
-        .await
-        .context(ListStorageNodes)?;
+    Ok(())
+}
+
+/// This checks if the storage API is reachable and usable, so that an unreachable control-plane
+/// fails the upgrade before it starts, rather than in the middle of it.
+pub(crate) async fn validate_rest_endpoint(rest_endpoint: String, tls: RestTls) -> Result<()> {
+    let rest_client = RestClientSet::new_with_url(rest_endpoint.clone(), &tls)?;
+
+    if let Err(error) = rest_client.nodes_api().get_nodes(None).await {
+        // A CA which does not match the server certificate is a misconfiguration, and not a
+        // connectivity problem.
+        let endpoint = rest_endpoint.clone();
+        return Err(if tls_handshake_fails(rest_endpoint.as_str(), &tls).await {
+            ControlPlaneTls { endpoint }.into_error(error)
+        } else {
+            ControlPlaneUnreachable { endpoint }.into_error(error)
+        });
+    }
 
+    info!(endpoint = %rest_endpoint, "The storage REST API is reachable");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+    use std::{
+        convert::Infallible,
+        net::{SocketAddr, TcpListener},
+        sync::{Arc, Mutex},
+    };
+
+    /// This serves a mock storage REST API, which lists no nodes, and returns its endpoint and the
+    /// paths of the requests it receives.
+    fn serve_mock_rest_api() -> (String, Arc<Mutex<Vec<String>>>) {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let received = paths.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(request.uri().path().to_string());
+                    async { Ok::<_, Infallible>(Response::new(Body::from("[]"))) }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        (endpoint, paths)
+    }
+
+    #[tokio::test]
+    async fn reachable_control_plane_passes() {
+        let (endpoint, paths) = serve_mock_rest_api();
+
+        validate_rest_endpoint(endpoint, RestTls::SystemRoots)
+            .await
+            .unwrap();
+        assert_eq!(*paths.lock().unwrap(), vec!["/v0/nodes"]);
+    }
+
+    #[tokio::test]
+    async fn unreachable_control_plane_fails_fast() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let result = validate_rest_endpoint(endpoint.clone(), RestTls::SystemRoots).await;
+        assert!(matches!(
+            result,
+            Err(Error::ControlPlaneUnreachable { endpoint: unreachable, .. })
+                if unreachable == endpoint
+        ));
+    }
+
+    /// This deserializes a Chart.yaml with the dependencies 'etcd' and 'loki-stack'.
+    fn chart_with_dependencies(etcd_version: &str, loki_version: &str) -> Chart {
+        serde_yaml::from_str(
+            format!(
+                "apiVersion: v2\nname: {CORE_CHART_NAME}\nversion: 2.5.0\ndependencies:\n  \
+                 - name: etcd\n    version: '{etcd_version}'\n  \
+                 - name: loki-stack\n    version: '{loki_version}'\n"
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    /// This vendors the sub-charts into a temporary 'charts' directory, 'etcd' as an archive and
+    /// 'loki-stack' as an unpacked chart directory.
+    fn vendored_charts_dir() -> tempfile::TempDir {
+        let charts_dir = tempfile::tempdir().unwrap();
+        fs::write(charts_dir.path().join("etcd-8.6.0.tgz"), []).unwrap();
+        let loki_dir = charts_dir.path().join("loki-stack");
+        fs::create_dir(loki_dir.as_path()).unwrap();
+        fs::write(
+            loki_dir.join("Chart.yaml"),
+            "apiVersion: v2\nname: loki-stack\nversion: 2.6.4\n",
+        )
+        .unwrap();
+        charts_dir
+    }
+
+    /// This lays out a Core helm chart directory, whose Chart.yaml has 'deprecated: true'.
+    fn deprecated_chart_dir() -> tempfile::TempDir {
+        let chart_dir = tempfile::tempdir().unwrap();
+        fs::write(
+            chart_dir.path().join("Chart.yaml"),
+            format!(
+                "apiVersion: v2\nname: {CORE_CHART_NAME}\ntype: application\nversion: 2.5.0\n\
+                 deprecated: true\n"
+            ),
+        )
+        .unwrap();
+        fs::write(
+            chart_dir.path().join("values.yaml"),
+            include_str!("../../../../../../chart/values.yaml"),
+        )
+        .unwrap();
+        fs::write(chart_dir.path().join("README.md"), []).unwrap();
+        for dir in ["charts", "crds", "templates"] {
+            fs::create_dir(chart_dir.path().join(dir)).unwrap();
+        }
+        chart_dir
+    }
+
+    #[tokio::test]
+    async fn deprecated_chart_is_accepted_with_a_warning() {
+        let chart_dir = deprecated_chart_dir();
+        validate_core_helm_chart_variant_in_dir(chart_dir.path().to_path_buf(), false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn deprecated_chart_fails_with_fail_on_deprecated() {
+        let chart_dir = deprecated_chart_dir();
+        assert!(matches!(
+            validate_core_helm_chart_variant_in_dir(chart_dir.path().to_path_buf(), true).await,
+            Err(Error::DeprecatedHelmChart { version, .. }) if version.eq("2.5.0")
+        ));
+    }
+
+    #[test]
+    fn archived_and_unpacked_dependencies_match_masterminds_constraints() {
+        let charts_dir = vendored_charts_dir();
+        let chart = chart_with_dependencies("~8.6.0", ">= 2.6.0 < 2.7.0");
+
+        validate_helm_chart_dependencies(&chart, charts_dir.path().to_path_buf()).unwrap();
+    }
+
+    #[test]
+    fn dependency_version_mismatch_fails() {
+        let charts_dir = vendored_charts_dir();
+        let chart = chart_with_dependencies("8.6.0", "2.5.x || 2.7.x");
+
+        assert!(matches!(
+            validate_helm_chart_dependencies(&chart, charts_dir.path().to_path_buf()),
+            Err(Error::HelmChartDependencyAbsent { name, .. }) if name.eq("loki-stack")
+        ));
+    }
+
+    #[test]
+    fn invalid_dependency_version_fails() {
+        let charts_dir = vendored_charts_dir();
+        let chart = chart_with_dependencies(">= foo", "2.6.4");
+
+        assert!(matches!(
+            validate_helm_chart_dependencies(&chart, charts_dir.path().to_path_buf()),
+            Err(Error::DependencyVersionConstraintParse { .. })
+        ));
+    }
+}
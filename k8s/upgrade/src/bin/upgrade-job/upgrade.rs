@@ -1,49 +1,618 @@
 use crate::{
-    common::{constants::PRODUCT, error::Result},
+    common::{
+        constants::{MAX_DATA_PLANE_MINOR_VERSION_SKEW, PRODUCT, UPGRADE_VALUES_SOURCE},
+        error::{
+            ControlPlaneNotUpgraded, DataPlaneVersionSkewUnsupported, ImageNotInAllowlist,
+            ImageTagAppVersionMismatch, OverallUpgradeTimeout, Result, SemverParse,
+        },
+        kube_client::KubeClientSet,
+        rest_client::RestClientSet,
+    },
     events::event_recorder::{EventAction, EventRecorder},
-    helm::upgrade::{HelmUpgrade, HelmUpgradeRunner},
-    opts::CliArgs,
+    helm::{
+        crd::check_crd_versions,
+        release::current_release_secret,
+        upgrade::{HelmUpgrade, HelmUpgradeRunner},
+        values_validation::ThinCommitmentValues,
+    },
+    opts::{CliArgs, Component, OutputFormat},
 };
 use data_plane::upgrade_data_plane;
+use k8s_openapi::api::core::v1::ObjectReference;
+use metrics::{start_metrics, MetricsProgressReporter, UpgradeMetrics, UpgradePhase};
+use progress::{
+    CountingProgressReporter, JsonLinesProgressReporter, LogProgressReporter, ProgressReporter,
+};
+use semver::Version;
+use snafu::{ensure, ResultExt};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{info, info_span, warn, Instrument, Span};
 
 /// Contains the data-plane upgrade logic.
 pub(crate) mod data_plane;
 
+/// Contains the storage Node drain logic, to move volume targets off of a Node.
+pub(crate) mod drain;
+
 /// Contains upgrade utilities.
 pub(crate) mod utils;
 
+/// Contains the pre-upgrade storage health checks.
+pub(crate) mod health;
+
+/// Contains the export of audit records of upgrades.
+pub(crate) mod audit;
+
+/// Contains the verification of the canary node, for canary upgrades.
+pub(crate) mod canary;
+
+/// Contains the pre-upgrade node capacity checks for the upgraded io-engine.
+pub(crate) mod capacity;
+
+/// Contains the pre-upgrade check of the upgrade-job's RBAC permissions.
+pub(crate) mod rbac;
+
+/// Contains the post-upgrade verification of the data-plane.
+pub(crate) mod verify;
+
 /// Tools to validate upgrade path.
 pub(crate) mod path;
 
+/// Contains the upgrade plan, for dry-runs.
+pub(crate) mod plan;
+
+/// Contains the Prometheus metrics of the upgrade.
+pub(crate) mod metrics;
+
+/// Contains the progress reporters for the data-plane upgrade.
+pub(crate) mod progress;
+
+/// Contains the detection of the io-engine DaemonSet's rollout state, for interrupted upgrades.
+pub(crate) mod reconcile;
+
+/// Contains the persisted data-plane upgrade progress, for resuming after a restart.
+pub(crate) mod state;
+
+/// Contains the rendering of the helm values for the upgrade, without upgrading.
+pub(crate) mod render;
+
+/// Contains the validation of values files against a helm chart, without a cluster.
+pub(crate) mod lint;
+
+/// Contains the listing of the published helm chart versions.
+pub(crate) mod versions;
+
+/// Contains the JSON schema export of the helm values which the upgrade-job reads.
+pub(crate) mod values_schema;
+
+/// Contains the rollback to the previously deployed helm release revision.
+pub(crate) mod rollback;
+
+/// Contains the user-provided webhook which approves the upgrade plan, before upgrading.
+pub(crate) mod webhook;
+
+/// Contains the interactive confirmation prompt, before upgrading.
+pub(crate) mod confirm;
+
 /// This function starts and sees upgrade through to the end.
 pub(crate) async fn upgrade(opts: &CliArgs) -> Result<()> {
+    // Dry-runs, value renders and validations, schema exports, version listings, audit exports
+    // and drift checks do not publish events, as they make no changes to the installation.
+    if let Some((chart_dir, values_file)) = opts.validate_values() {
+        return lint::validate_values(chart_dir.as_path(), values_file.as_path());
+    }
+    if let Some(core_values_key) = opts.values_schema() {
+        return values_schema::print_values_schema(core_values_key.as_deref());
+    }
+    if opts.dry_run() {
+        return plan::dry_run(opts).await;
+    }
+    if opts.render_values() {
+        return render::render_values(opts).await;
+    }
+    if let Some(repo_url) = opts.list_versions_repo_url() {
+        return versions::list_versions(repo_url.as_str(), opts.output()).await;
+    }
+    if opts.audit_export() {
+        return audit::audit_export(opts).await;
+    }
+    if opts.verify() {
+        return verify::verify_installed(opts).await;
+    }
+
     let mut event = EventRecorder::builder()
-        .with_pod_name(&opts.pod_name())
-        .with_namespace(&opts.namespace())
+        .with_pod_name(opts.pod_name())
+        .with_namespace(opts.namespace())
         .build()
         .await?;
 
-    let result = upgrade_product(opts, &mut event).await;
+    let maybe_metrics = match start_metrics(opts) {
+        Ok(maybe_metrics) => maybe_metrics,
+        Err(error) => {
+            event.publish_unrecoverable(&error, true).await;
+            event.shutdown_worker().await;
+            return Err(error);
+        }
+    };
+
+    let result = if opts.rollback() {
+        rollback::rollback(opts, &mut event, maybe_metrics.as_ref()).await
+    } else {
+        upgrade_product(opts, &mut event, maybe_metrics.as_ref()).await
+    };
+    event
+        .publish_summary(
+            result.as_ref().map(|_| ()),
+            helm_release_reference(opts).await,
+        )
+        .await;
+
+    if let Some(metrics) = maybe_metrics.as_ref() {
+        metrics.record_outcome(&result);
+    }
 
     // This makes sure that the event worker attempts to publish
     // all of its events. It waits for the event worker to exit.
     event.shutdown_worker().await;
 
+    // The metrics are served for a while longer, so that the outcome is scraped before the
+    // upgrade-job exits.
+    if maybe_metrics.is_some() && !opts.metrics_linger().is_zero() {
+        info!(
+            linger = %humantime::format_duration(opts.metrics_linger()),
+            "Serving the upgrade metrics before exiting"
+        );
+        tokio::time::sleep(opts.metrics_linger()).await;
+    }
+
     result
 }
 
-/// This carries out the helm upgrade validation, actual helm upgrade, and the io-engine Pod
-/// restarts.
-async fn upgrade_product(opts: &CliArgs, event: &mut EventRecorder) -> Result<()> {
-    let helm_upgrade = HelmUpgrade::builder()
+/// This returns a reference to the helm release object, i.e. the Secret of the current helm
+/// release revision, for the Events of the upgrade. This is None if the helm release Secret
+/// cannot be found.
+async fn helm_release_reference(opts: &CliArgs) -> Option<ObjectReference> {
+    let namespace = opts.namespace();
+    let secret = async {
+        let k8s_client = KubeClientSet::builder()
+            .with_namespace(namespace.as_str())
+            .build()
+            .await?;
+        current_release_secret(
+            &k8s_client,
+            opts.release_name().as_str(),
+            namespace.as_str(),
+        )
+        .await
+    }
+    .await;
+
+    match secret {
+        Ok(secret) => Some(ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Secret".to_string()),
+            name: secret.metadata.name,
+            namespace: Some(namespace),
+            uid: secret.metadata.uid,
+            field_path: None,
+            resource_version: secret.metadata.resource_version,
+        }),
+        Err(error) => {
+            warn!(%error, "Failed to find the helm release Secret for the upgrade summary event");
+            None
+        }
+    }
+}
+
+/// This validates the helm upgrade and builds the HelmUpgrade, from the CLI options.
+pub(crate) async fn build_helm_upgrade(opts: &CliArgs) -> Result<HelmUpgrade> {
+    HelmUpgrade::builder()
         .with_namespace(opts.namespace())
         .with_release_name(opts.release_name())
         .with_core_chart_dir(opts.core_chart_dir())
         .with_skip_upgrade_path_validation(opts.skip_upgrade_path_validation())
+        .with_force_upgrade(opts.force_upgrade())
+        .with_allow_prerelease(opts.allow_prerelease())
+        .with_no_thin_defaults(opts.no_thin_defaults())
         .with_helm_args_set(opts.helm_args_set())
         .with_helm_args_set_file(opts.helm_args_set_file())
+        .with_values_overrides(opts.values_overrides())
+        .with_values_dir(opts.values_dir())
+        .build()
+        .await
+}
+
+/// This checks that all of the storage volumes and pools are Online, unless the check is skipped.
+#[tracing::instrument(name = "validation", skip_all, fields(upgrade.phase = "validation"))]
+pub(crate) async fn check_storage_health(opts: &CliArgs) -> Result<()> {
+    if opts.skip_health_check() {
+        info!("Skipping the pre-upgrade storage health check");
+        return Ok(());
+    }
+
+    let rest_client = RestClientSet::new_with_url(opts.rest_endpoint(), &opts.rest_tls())?;
+    health::check_volumes(&rest_client).await?;
+    health::check_pools(&rest_client).await
+}
+
+/// This checks for volumes whose only replica is on a node whose io-engine Pod is to be restarted.
+/// These are warned about, or fail the upgrade if --fail-on-single-replica is set. There is no
+/// check if the io-engine Pods are not restarted by the upgrade.
+pub(crate) async fn check_single_replica_volumes(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    steps: &UpgradeSteps,
+) -> Result<()> {
+    if !steps.io_engine_restart {
+        return Ok(());
+    }
+
+    let skipped_nodes = utils::skipped_nodes(k8s_client, opts.skip_node_label()).await?;
+    let rest_client = RestClientSet::new_with_url(opts.rest_endpoint(), &opts.rest_tls())?;
+    health::check_single_replica_volumes(
+        &rest_client,
+        &skipped_nodes,
+        opts.fail_on_single_replica(),
+    )
+    .await
+}
+
+/// This checks that the upgrade-job has the RBAC permissions which the upgrade needs.
+pub(crate) async fn check_rbac(opts: &CliArgs, k8s_client: &KubeClientSet) -> Result<()> {
+    rbac::check_permissions(k8s_client, opts.namespace().as_str()).await
+}
+
+/// This checks that the nodes which run io-engine Pods have enough hugepages for the upgraded
+/// io-engine, unless the check is skipped or the io-engine Pods are not restarted.
+pub(crate) async fn check_node_capacity(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    helm_upgrade: &HelmUpgrade,
+    steps: &UpgradeSteps,
+) -> Result<()> {
+    if !steps.io_engine_restart {
+        return Ok(());
+    }
+    if opts.skip_hugepages_check() {
+        info!("Skipping the pre-upgrade hugepages check");
+        return Ok(());
+    }
+    let Some(upgrade_values) = helm_upgrade.upgrade_values()? else {
+        return Ok(());
+    };
+
+    capacity::check_hugepages(k8s_client, opts.namespace(), &upgrade_values).await
+}
+
+/// This checks the free disk space of the io-engine nodes, ahead of pulling the upgraded io-engine
+/// image. There is no check if the io-engine Pods are not restarted by the upgrade.
+pub(crate) async fn check_node_disk_space(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    steps: &UpgradeSteps,
+) -> Result<()> {
+    if !steps.io_engine_restart {
+        return Ok(());
+    }
+
+    capacity::check_node_disk_space(
+        k8s_client,
+        opts.namespace(),
+        opts.min_free_disk(),
+        opts.fail_on_low_disk(),
+    )
+    .await
+}
+
+/// This checks that none of the storage pools is committed beyond the upgraded thin-provisioning
+/// poolCommitment.
+pub(crate) async fn check_pool_commitment(
+    opts: &CliArgs,
+    helm_upgrade: &HelmUpgrade,
+) -> Result<()> {
+    let to_version = helm_upgrade.target_version();
+    let Some(upgrade_values) = helm_upgrade.upgrade_values()? else {
+        return Ok(());
+    };
+    // The thin-provisioning options are absent in older helm charts.
+    let Some(thin_commitment) = ThinCommitmentValues::try_from_values_if_present(
+        &upgrade_values,
+        to_version,
+        UPGRADE_VALUES_SOURCE,
+    )?
+    else {
+        return Ok(());
+    };
+
+    let rest_client = RestClientSet::new_with_url(opts.rest_endpoint(), &opts.rest_tls())?;
+    capacity::check_pool_commitment(&rest_client, &thin_commitment).await
+}
+
+/// This fails if the upgrade moves the container images to another registry or repository, and
+/// the new image is not in the --image-allowlist. This surfaces images which are unavailable on
+/// air-gapped clusters before any Pod is restarted. There is no check if the allowlist is empty.
+pub(crate) fn check_image_allowlist(opts: &CliArgs, helm_upgrade: &HelmUpgrade) -> Result<()> {
+    let allowlist = opts.image_allowlist();
+    if allowlist.is_empty() || !helm_upgrade.values_diff().requires_image_prepull() {
+        return Ok(());
+    }
+    let Some(upgrade_values) = helm_upgrade.upgrade_values()? else {
+        return Ok(());
+    };
+
+    let image = upgrade_values.image_name();
+    ensure!(
+        allowlist
+            .iter()
+            .any(|allowed| image.starts_with(allowed.as_str())),
+        ImageNotInAllowlist {
+            image,
+            allowlist: allowlist.to_vec()
+        }
+    );
+
+    info!(image, "The container images of the upgrade are allowlisted");
+    Ok(())
+}
+
+/// This fails if --enforce-tag-matches-appversion is set, and the container image tag of the
+/// upgrade is not the target helm chart's appVersion. A leading 'v' of the tag is ignored. There
+/// is nothing to compare against if the target helm chart has no semver appVersion.
+pub(crate) fn check_image_tag_app_version(
+    opts: &CliArgs,
+    helm_upgrade: &HelmUpgrade,
+) -> Result<()> {
+    if !opts.enforce_tag_matches_appversion() {
+        return Ok(());
+    }
+    let Some(upgrade_values) = helm_upgrade.upgrade_values()? else {
+        return Ok(());
+    };
+
+    ensure_tag_matches_app_version(
+        upgrade_values.image_tag(),
+        helm_upgrade.target_app_version(),
+    )
+}
+
+/// This is like check_image_tag_app_version, for the image tag of the upgrade and the target helm
+/// chart's appVersion, if it is a semver.
+fn ensure_tag_matches_app_version(tag: &str, app_version: Option<&Version>) -> Result<()> {
+    let Some(app_version) = app_version else {
+        warn!("The target helm chart has no semver appVersion, the image tag is not checked");
+        return Ok(());
+    };
+
+    let tag_version = Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok();
+    ensure!(
+        tag_version.as_ref() == Some(app_version),
+        ImageTagAppVersionMismatch {
+            tag,
+            app_version: app_version.clone()
+        }
+    );
+
+    info!(
+        tag,
+        "The container image tag matches the target helm chart's appVersion"
+    );
+    Ok(())
+}
+
+/// This logs a warning, and returns it, if the io-engine Pods run more than one container image
+/// tag. The upgrade goes ahead, and all of the io-engine Pods are upgraded to the same tag, unless
+/// --fail-on-mixed-tags is set.
+pub(crate) async fn check_installed_image_tags(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+) -> Result<Option<state::ClusterNotUniform>> {
+    let tags = state::installed_image_tags(k8s_client, opts.namespace().as_str()).await?;
+
+    let warning = state::check_uniform(&tags, opts.fail_on_mixed_tags())?;
+    if let Some(warning) = warning.as_ref() {
+        warn!("{warning}");
+    }
+    Ok(warning)
+}
+
+/// This fails if the upgrade has taken longer than the --overall-timeout. This is only checked
+/// where the upgrade may safely stop, i.e. never in the middle of an io-engine Pod restart. The
+/// elapsed time leaves out the time for which the upgrade was paused, see UpgradeClock.
+pub(crate) fn check_overall_timeout(timeout: Option<Duration>, elapsed: Duration) -> Result<()> {
+    let Some(timeout) = timeout else {
+        return Ok(());
+    };
+
+    ensure!(elapsed < timeout, OverallUpgradeTimeout { elapsed });
+
+    Ok(())
+}
+
+/// This checks that the target helm chart's CRDs do not regress the versions of the CRDs which
+/// are installed in the cluster.
+pub(crate) async fn check_crds(opts: &CliArgs, k8s_client: &KubeClientSet) -> Result<()> {
+    check_crd_versions(
+        k8s_client.crd_api(),
+        opts.core_chart_dir().join("crds").as_path(),
+    )
+    .await
+}
+
+/// This prepares the update strategy of the io-engine DaemonSet, ahead of the helm upgrade which
+/// changes its Pod template.
+async fn prepare_io_engine_rollout(opts: &CliArgs, steps: &UpgradeSteps) -> Result<()> {
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(opts.namespace())
         .build()
         .await?;
+    let controller_rollout = reconcile::controller_rollout(opts, steps.io_engine_restart);
+    reconcile::prepare_rollout(&k8s_client, opts.namespace().as_str(), controller_rollout).await?;
+    Ok(())
+}
+
+/// This is true if the helm release and the io-engine Pods are already at the target version, e.g.
+/// when the upgrade-job is re-applied after it has succeeded. A forced upgrade is never complete.
+async fn upgrade_is_complete(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    helm_upgrade: &HelmUpgrade,
+) -> Result<bool> {
+    // The HelmUpgrade is never 'already upgraded' if the upgrade is forced.
+    if !helm_upgrade.already_upgraded() {
+        return Ok(false);
+    }
+    if opts.skip_data_plane_restart() {
+        return Ok(true);
+    }
+
+    let pending_nodes = plan::io_engine_nodes_to_restart(
+        k8s_client,
+        opts.namespace().as_str(),
+        helm_upgrade.upgrade_to_version().as_str(),
+        opts.skip_node_label(),
+    )
+    .await?;
+    Ok(pending_nodes.is_empty())
+}
+
+/// This restarts the io-engine DaemonSet Pods which are not at the 'to' version, and verifies
+/// that they run the container image of the helm release afterwards.
+pub(crate) async fn restart_data_plane(
+    opts: &CliArgs,
+    to_version: String,
+    nodes_upgraded: Arc<AtomicUsize>,
+    maybe_metrics: Option<&Arc<UpgradeMetrics>>,
+) -> Result<()> {
+    let reporter: Box<dyn ProgressReporter> = match opts.output() {
+        OutputFormat::Text => Box::new(LogProgressReporter),
+        OutputFormat::Json => Box::new(JsonLinesProgressReporter),
+    };
+    let mut reporter: Box<dyn ProgressReporter> =
+        Box::new(CountingProgressReporter::new(reporter, nodes_upgraded));
+
+    if let Some(metrics) = maybe_metrics {
+        metrics.set_phase(UpgradePhase::DataPlane);
+        reporter = Box::new(MetricsProgressReporter::new(reporter, metrics.clone()));
+    }
+
+    async {
+        upgrade_data_plane(opts, to_version, reporter.as_ref()).await?;
+
+        // This detects a partially-applied data-plane upgrade.
+        verify::verify_data_plane(
+            opts.namespace(),
+            opts.release_name(),
+            opts.skip_node_label(),
+        )
+        .instrument(info_span!("verify", upgrade.phase = "verify"))
+        .await
+    }
+    .await
+}
+
+/// This validates that the components to upgrade may be upgraded on their own. A data-plane only
+/// upgrade follows a control-plane only upgrade, so the helm release has to be at the target
+/// version already. A control-plane only upgrade leaves the io-engine at the installed version,
+/// which the upgraded control-plane has to support.
+pub(crate) fn validate_component(
+    component: Component,
+    from_version: &str,
+    to_version: &str,
+) -> Result<()> {
+    let parse = |version: &str| {
+        Version::parse(version).context(SemverParse {
+            version_string: version.to_string(),
+        })
+    };
+
+    match component {
+        Component::All => Ok(()),
+        Component::DataPlane => {
+            ensure!(
+                from_version.eq(to_version),
+                ControlPlaneNotUpgraded {
+                    installed: from_version,
+                    target: to_version,
+                }
+            );
+            Ok(())
+        }
+        Component::ControlPlane => {
+            let data_plane = parse(from_version)?;
+            let control_plane = parse(to_version)?;
+            ensure!(
+                data_plane.major.eq(&control_plane.major)
+                    && control_plane.minor.saturating_sub(data_plane.minor)
+                        <= MAX_DATA_PLANE_MINOR_VERSION_SKEW,
+                DataPlaneVersionSkewUnsupported {
+                    control_plane,
+                    data_plane,
+                }
+            );
+            Ok(())
+        }
+    }
+}
+
+/// These are the steps of an upgrade which change the PRODUCT installation, for the component to
+/// upgrade.
+#[derive(Debug, PartialEq)]
+pub(crate) struct UpgradeSteps {
+    /// The helm upgrade of the helm release, i.e. the control-plane upgrade.
+    helm_upgrade: bool,
+    /// The restart of the io-engine DaemonSet Pods, i.e. the data-plane upgrade.
+    io_engine_restart: bool,
+}
+
+impl UpgradeSteps {
+    /// This decides the steps of the upgrade. Upgrades which do not change the helm chart version
+    /// only upgrade the control-plane. Data-plane only upgrades always restart the io-engine Pods,
+    /// and control-plane only upgrades never do.
+    pub(crate) fn for_upgrade(opts: &CliArgs, requires_io_engine_restart: bool) -> Self {
+        let component = opts.component();
+        Self {
+            helm_upgrade: component.ne(&Component::DataPlane),
+            io_engine_restart: !opts.skip_data_plane_restart()
+                && (requires_io_engine_restart || component.eq(&Component::DataPlane)),
+        }
+    }
+
+    /// This is the reason the io-engine Pods are not restarted, if they are not restarted.
+    fn io_engine_restart_skip_reason(&self, opts: &CliArgs) -> Option<&'static str> {
+        if self.io_engine_restart {
+            None
+        } else if opts.component().eq(&Component::ControlPlane) {
+            Some("only the control-plane is upgraded with --component control-plane")
+        } else if opts.skip_data_plane_restart() {
+            Some("the io-engine Pod restarts are skipped with --skip-data-plane-restart")
+        } else {
+            Some("the upgrade does not change the io-engine Pod template")
+        }
+    }
+}
+
+/// This carries out the helm upgrade validation, actual helm upgrade, and the io-engine Pod
+/// restarts. This is the outermost span of the upgrade, and the only one which records the error,
+/// so that a failure is logged once. The outcome is published by the caller, as the upgrade summary
+/// Event, so the failures are only marked on the EventRecorder here.
+#[tracing::instrument(
+    name = "upgrade",
+    skip_all,
+    fields(upgrade.from_version, upgrade.to_version),
+    err
+)]
+async fn upgrade_product(
+    opts: &CliArgs,
+    event: &mut EventRecorder,
+    maybe_metrics: Option<&Arc<UpgradeMetrics>>,
+) -> Result<()> {
+    let helm_upgrade = build_helm_upgrade(opts).await?;
 
     let from_version = helm_upgrade.upgrade_from_version();
     let to_version = helm_upgrade.upgrade_to_version();
@@ -54,15 +623,162 @@ async fn upgrade_product(opts: &CliArgs, event: &mut EventRecorder) -> Result<()
     event.set_from_version(from_version.clone());
     event.set_to_version(to_version.clone());
 
-    // Dry-run helm upgrade.
-    let dry_run_result: Result<HelmUpgradeRunner> = helm_upgrade.dry_run().await;
-    let run_helm_upgrade = match dry_run_result {
+    Span::current()
+        .record("upgrade.from_version", from_version.as_str())
+        .record("upgrade.to_version", to_version.as_str());
+
+    let k8s_client = match KubeClientSet::builder()
+        .with_namespace(opts.namespace())
+        .build()
+        .await
+    {
+        Ok(k8s_client) => k8s_client,
         Err(error) => {
-            event.publish_unrecoverable(&error, true).await;
-            Err(error)
+            event.set_validation_failed();
+            return Err(error);
+        }
+    };
+
+    // A completed upgrade is not carried out again, unless it is forced.
+    match upgrade_is_complete(opts, &k8s_client, &helm_upgrade).await {
+        Ok(true) => {
+            info!("{PRODUCT} is already at the target version {to_version}, skipping the upgrade");
+            return Ok(());
         }
-        Ok(run_helm_upgrade) => Ok(run_helm_upgrade),
-    }?;
+        Ok(false) => {}
+        Err(error) => {
+            event.set_validation_failed();
+            return Err(error);
+        }
+    }
+
+    if let Err(error) = check_rbac(opts, &k8s_client).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = validate_component(opts.component(), &from_version, &to_version) {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_storage_health(opts).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    let steps = UpgradeSteps::for_upgrade(opts, helm_upgrade.requires_io_engine_restart());
+
+    if let Err(error) = check_single_replica_volumes(opts, &k8s_client, &steps).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_node_capacity(opts, &k8s_client, &helm_upgrade, &steps).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_node_disk_space(opts, &k8s_client, &steps).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_pool_commitment(opts, &helm_upgrade).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_image_allowlist(opts, &helm_upgrade) {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_image_tag_app_version(opts, &helm_upgrade) {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    if let Err(error) = check_crds(opts, &k8s_client).await {
+        event.set_validation_failed();
+        return Err(error);
+    }
+
+    match check_installed_image_tags(opts, &k8s_client).await {
+        Ok(Some(warning)) => {
+            event
+                .publish_warning(warning.to_string(), EventAction::UpgradingDP)
+                .await?
+        }
+        Ok(None) => {}
+        Err(error) => {
+            event.set_validation_failed();
+            return Err(error);
+        }
+    }
+
+    // An upgrade which is not confirmed with '--yes' is confirmed at a prompt, which fails closed
+    // if there is no terminal to prompt on.
+    if !opts.yes() {
+        if let Err(error) = confirm::ensure_interactive() {
+            event.set_validation_failed();
+            return Err(error);
+        }
+    }
+
+    // The upgrade plan for the pre-upgrade webhook and the confirmation prompt is computed ahead
+    // of the helm dry-run, which consumes the HelmUpgrade.
+    let maybe_plan = if opts.pre_upgrade_webhook().is_some() || !opts.yes() {
+        match plan::UpgradePlan::for_upgrade(opts, &k8s_client, &helm_upgrade).await {
+            Ok(plan) => Some(plan),
+            Err(error) => {
+                event.set_validation_failed();
+                return Err(error);
+            }
+        }
+    } else {
+        None
+    };
+
+    // The helm upgrade is skipped for data-plane only upgrades, the helm release is already at the
+    // target version.
+    let maybe_run_helm_upgrade = if steps.helm_upgrade {
+        // Dry-run helm upgrade.
+        let dry_run_result: Result<HelmUpgradeRunner> = helm_upgrade.dry_run().await;
+        if dry_run_result.is_err() {
+            event.set_validation_failed();
+        }
+        Some(dry_run_result?)
+    } else {
+        None
+    };
+
+    if !opts.yes() {
+        if let Some(plan) = maybe_plan.as_ref() {
+            plan.log();
+        }
+        if let Err(error) = confirm::confirm("Proceed?") {
+            event.set_validation_failed();
+            return Err(error);
+        }
+    }
+
+    if let (Some(url), Some(plan)) = (opts.pre_upgrade_webhook(), maybe_plan.as_ref()) {
+        let approval = webhook::call_pre_upgrade_webhook(
+            url.as_str(),
+            opts.pre_upgrade_webhook_timeout(),
+            plan,
+        );
+        if let Err(error) = approval.await {
+            event.set_validation_failed();
+            return Err(error);
+        }
+    }
+
+    if let Err(error) = check_overall_timeout(opts.overall_timeout(), opts.started_at().elapsed()) {
+        event.set_validation_failed();
+        return Err(error);
+    }
 
     event
         .publish_normal(
@@ -71,28 +787,47 @@ async fn upgrade_product(opts: &CliArgs, event: &mut EventRecorder) -> Result<()
         )
         .await?;
 
-    event
-        .publish_normal(
-            format!("Upgrading {PRODUCT} control-plane"),
-            EventAction::UpgradingCP,
-        )
-        .await?;
+    if let Some(run_helm_upgrade) = maybe_run_helm_upgrade {
+        if let Some(metrics) = maybe_metrics {
+            metrics.set_phase(UpgradePhase::ControlPlane);
+        }
 
-    // Control plane containers are updated in this step.
-    if let Err(error) = run_helm_upgrade.await {
-        event.publish_unrecoverable(&error, false).await;
-        return Err(error);
+        // The DaemonSet controller may only replace the io-engine Pods by itself when the helm
+        // upgrade changes their Pod template, if the upgrade restarts all of them.
+        if let Err(error) = prepare_io_engine_rollout(opts, &steps).await {
+            event.set_validation_failed();
+            return Err(error);
+        }
+
+        event
+            .publish_normal(
+                format!("Upgrading {PRODUCT} control-plane"),
+                EventAction::UpgradingCP,
+            )
+            .await?;
+
+        // Control plane containers are updated in this step.
+        run_helm_upgrade
+            .instrument(info_span!(
+                "control_plane_upgrade",
+                upgrade.phase = "control-plane"
+            ))
+            .await?;
+
+        event
+            .publish_normal(
+                format!("Upgraded {PRODUCT} control-plane"),
+                EventAction::UpgradedCP,
+            )
+            .await?;
     }
 
-    event
-        .publish_normal(
-            format!("Upgraded {PRODUCT} control-plane"),
-            EventAction::UpgradedCP,
-        )
-        .await?;
+    if let Some(reason) = steps.io_engine_restart_skip_reason(opts) {
+        info!("Skipping the data-plane upgrade: {reason}, the io-engine Pods keep running");
+    }
 
     // Data plane containers are updated in this step.
-    if !opts.skip_data_plane_restart() {
+    if steps.io_engine_restart {
         event
             .publish_normal(
                 format!("Upgrading {PRODUCT} data-plane"),
@@ -100,12 +835,11 @@ async fn upgrade_product(opts: &CliArgs, event: &mut EventRecorder) -> Result<()
             )
             .await?;
 
-        if let Err(error) =
-            upgrade_data_plane(opts.namespace(), opts.rest_endpoint(), to_version).await
-        {
-            event.publish_unrecoverable(&error, false).await;
-            return Err(error);
-        }
+        let nodes_upgraded = Arc::new(AtomicUsize::new(0));
+        let result =
+            restart_data_plane(opts, to_version, nodes_upgraded.clone(), maybe_metrics).await;
+        event.set_nodes_upgraded(nodes_upgraded.load(Ordering::Relaxed));
+        result?;
 
         event
             .publish_normal(
@@ -115,12 +849,198 @@ async fn upgrade_product(opts: &CliArgs, event: &mut EventRecorder) -> Result<()
             .await?;
     }
 
-    event
-        .publish_normal(
-            format!("Successfully upgraded {PRODUCT}"),
-            EventAction::Successful,
-        )
-        .await?;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use std::sync::Mutex;
+    use tracing::{span, Event, Level, Subscriber};
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    /// These are the names of the recorded spans, with the names of their parent spans.
+    type SpanNames = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+    /// This is an in-memory span exporter, which records the spans with the name of their parent
+    /// span, and the spans of the error events.
+    #[derive(Clone, Default)]
+    struct SpanRecorder {
+        spans: SpanNames,
+        error_spans: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl<S> Layer<S> for SpanRecorder
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            if let Some(span) = ctx.span(id) {
+                let parent = span.parent().map(|parent| parent.name().to_string());
+                self.spans
+                    .lock()
+                    .unwrap()
+                    .push((span.name().to_string(), parent));
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            if event.metadata().level().eq(&Level::ERROR) {
+                if let Some(span) = ctx.event_span(event) {
+                    self.error_spans
+                        .lock()
+                        .unwrap()
+                        .push(span.name().to_string());
+                }
+            }
+        }
+    }
+
+    /// This parses the upgrade-job's arguments, with the extra arguments.
+    fn cli_args(extra_args: &[&str]) -> CliArgs {
+        let mut args = vec![
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "chart",
+        ];
+        args.extend(extra_args);
+        args.push("upgrade-job-pod");
+        crate::opts::tests::parse(args.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn control_plane_only_upgrade_does_not_touch_the_io_engine_daemonset() {
+        let opts = cli_args(&["--component", "control-plane"]);
+        for requires_io_engine_restart in [true, false] {
+            assert_eq!(
+                UpgradeSteps::for_upgrade(&opts, requires_io_engine_restart),
+                UpgradeSteps {
+                    helm_upgrade: true,
+                    io_engine_restart: false
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn data_plane_only_upgrade_skips_the_helm_upgrade() {
+        let opts = cli_args(&["--component", "data-plane"]);
+        assert_eq!(
+            UpgradeSteps::for_upgrade(&opts, false),
+            UpgradeSteps {
+                helm_upgrade: false,
+                io_engine_restart: true
+            }
+        );
+    }
+
+    #[test]
+    fn full_upgrade_restarts_the_io_engine_if_required() {
+        let opts = cli_args(&[]);
+        for requires_io_engine_restart in [true, false] {
+            assert_eq!(
+                UpgradeSteps::for_upgrade(&opts, requires_io_engine_restart),
+                UpgradeSteps {
+                    helm_upgrade: true,
+                    io_engine_restart: requires_io_engine_restart
+                }
+            );
+        }
+
+        let opts = cli_args(&["--skip-data-plane-restart"]);
+        assert!(!UpgradeSteps::for_upgrade(&opts, true).io_engine_restart);
+    }
+
+    #[test]
+    fn io_engine_restart_skip_reason_names_the_cause() {
+        let skip_reason = |args: &[&str], requires_io_engine_restart: bool| {
+            let opts = cli_args(args);
+            UpgradeSteps::for_upgrade(&opts, requires_io_engine_restart)
+                .io_engine_restart_skip_reason(&opts)
+        };
+
+        assert_eq!(skip_reason(&[], true), None);
+        assert_eq!(skip_reason(&["--component", "data-plane"], false), None);
+        assert!(skip_reason(&["--component", "control-plane"], true)
+            .is_some_and(|reason| reason.contains("--component control-plane")));
+        assert!(skip_reason(&["--skip-data-plane-restart"], true)
+            .is_some_and(|reason| reason.contains("--skip-data-plane-restart")));
+        assert!(skip_reason(&[], false)
+            .is_some_and(|reason| reason.contains("does not change the io-engine Pod template")));
+    }
+
+    #[tokio::test]
+    async fn data_plane_preflights_are_skipped_without_an_io_engine_restart() {
+        // The REST endpoint and the Kubernetes API are never reached, the checks return early.
+        let opts = cli_args(&[
+            "--component",
+            "control-plane",
+            "--rest-endpoint",
+            "not a url",
+        ]);
+        let steps = UpgradeSteps::for_upgrade(&opts, true);
+        let service = tower::service_fn(|_: hyper::Request<hyper::Body>| async {
+            Err::<hyper::Response<hyper::Body>, _>(std::io::Error::from(std::io::ErrorKind::Other))
+        });
+        let k8s_client =
+            KubeClientSet::with_client(kube::Client::new(service, "mayastor"), "mayastor");
+
+        assert!(check_single_replica_volumes(&opts, &k8s_client, &steps)
+            .await
+            .is_ok());
+        assert!(check_node_disk_space(&opts, &k8s_client, &steps)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn validation_span_is_nested_and_leaves_the_error_to_the_upgrade_span() {
+        let recorder = SpanRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let opts = cli_args(&["--rest-endpoint", "not a url"]);
+
+        let result = check_storage_health(&opts)
+            .instrument(info_span!("upgrade"))
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            *recorder.spans.lock().unwrap(),
+            vec![
+                ("upgrade".to_string(), None),
+                ("validation".to_string(), Some("upgrade".to_string()))
+            ]
+        );
+        assert!(recorder.error_spans.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn image_tag_matching_the_app_version_passes() {
+        let app_version = Version::new(2, 5, 0);
+        for tag in ["v2.5.0", "2.5.0"] {
+            assert!(ensure_tag_matches_app_version(tag, Some(&app_version)).is_ok());
+        }
+    }
+
+    #[test]
+    fn image_tag_not_matching_the_app_version_fails() {
+        let app_version = Version::new(2, 5, 0);
+        for tag in ["v2.4.0", "develop", "v2.5.0-rc.1"] {
+            assert!(matches!(
+                ensure_tag_matches_app_version(tag, Some(&app_version)),
+                Err(Error::ImageTagAppVersionMismatch { tag: mismatched, app_version })
+                    if mismatched == tag && app_version == Version::new(2, 5, 0)
+            ));
+        }
+    }
+
+    #[test]
+    fn absent_app_version_is_not_checked() {
+        assert!(ensure_tag_matches_app_version("develop", None).is_ok());
+    }
+}
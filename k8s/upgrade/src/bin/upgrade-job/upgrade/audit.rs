@@ -0,0 +1,257 @@
+use crate::{
+    common::{
+        constants::AUDIT_RECORD_CONFIGMAP_KEY_PREFIX,
+        error::{GetAuditConfigMap, Result, SerializeAuditRecord, StoreAuditRecord},
+        kube_client::KubeClientSet,
+    },
+    helm::overrides::ValuesOverrides,
+    opts::CliArgs,
+    upgrade::plan::{plan_upgrade, UpgradePlan},
+};
+use k8s_openapi::{api::core::v1::ConfigMap, chrono::Utc};
+use kube::{
+    api::{Patch, PatchParams, PostParams},
+    core::ObjectMeta,
+};
+use serde::Serialize;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+/// This is an auditable record of a requested upgrade.
+#[derive(Serialize)]
+struct AuditRecord {
+    /// The time of the audit export, in RFC 3339 format.
+    timestamp: String,
+    /// The identity which requested the upgrade, as set with --requested-by.
+    requested_by: Option<String>,
+    /// The identity which the upgrade-job runs as, i.e. its ServiceAccount.
+    service_account: Option<String>,
+    /// The version of the installed helm chart.
+    from_version: Option<String>,
+    /// The version of the helm chart to upgrade to.
+    to_version: Option<String>,
+    /// The helm values set at upgrade time, with --values-file, --set and --set-string. The
+    /// sensitive values are redacted.
+    values_overrides: ValuesOverrides,
+    /// The upgrade plan, including the validation errors, if any.
+    plan: UpgradePlan,
+}
+
+/// This validates the upgrade and prints an audit record of it, as JSON, to stdout. The record is
+/// also stored in the audit ConfigMap, if one is set. Like with dry-runs, the record is printed
+/// even if the validation fails, and the validation error is returned after.
+pub(crate) async fn audit_export(opts: &CliArgs) -> Result<()> {
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(opts.namespace())
+        .build()
+        .await?;
+
+    let now = Utc::now();
+    let mut values_overrides = opts.values_overrides();
+    values_overrides.redact(opts.redact_paths().as_slice());
+    let (plan, result) = plan_upgrade(opts).await;
+    let record = AuditRecord {
+        timestamp: now.to_rfc3339(),
+        requested_by: opts.audit_requested_by(),
+        service_account: service_account(opts, &k8s_client).await,
+        from_version: plan.installed_version().map(ToString::to_string),
+        to_version: plan.target_version().map(ToString::to_string),
+        values_overrides,
+        plan,
+    };
+    let record_json = serde_json::to_string(&record).context(SerializeAuditRecord)?;
+    println!("{record_json}");
+
+    if let Some((name, max_records)) = opts.audit_configmap() {
+        let key = format!(
+            "{AUDIT_RECORD_CONFIGMAP_KEY_PREFIX}{}.json",
+            now.format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        store_audit_record(
+            &k8s_client,
+            name.as_str(),
+            key.as_str(),
+            record_json,
+            max_records,
+        )
+        .await?;
+        info!(configmap.name = %name, key, "Stored the upgrade audit record");
+    }
+
+    result
+}
+
+/// This returns the ServiceAccount of the upgrade-job's Pod, in the form of its Kubernetes
+/// username, e.g. 'system:serviceaccount:mayastor:mayastor-upgrade-service-account'. The audit
+/// export may run outside of a Pod, in which case there is no ServiceAccount to record.
+async fn service_account(opts: &CliArgs, k8s_client: &KubeClientSet) -> Option<String> {
+    let pod_name = opts.pod_name();
+    if pod_name.is_empty() {
+        return None;
+    }
+
+    let namespace = opts.namespace();
+    match k8s_client.pods_api().get(pod_name.as_str()).await {
+        Ok(pod) => pod
+            .spec
+            .and_then(|spec| spec.service_account_name)
+            .map(|service_account| format!("system:serviceaccount:{namespace}:{service_account}")),
+        Err(error) => {
+            warn!(
+                pod.name = %pod_name,
+                pod.namespace = %namespace,
+                %error,
+                "Failed to get the upgrade-job's Pod, the audit record has no ServiceAccount"
+            );
+            None
+        }
+    }
+}
+
+/// This returns the audit record keys which have to be removed from the audit ConfigMap, so that
+/// it retains at most 'max_records' records, including the one being added. The keys carry the
+/// time of the audit export, so the oldest records sort first.
+fn pruned_record_keys<'a>(
+    existing_keys: impl Iterator<Item = &'a String>,
+    max_records: usize,
+) -> Vec<String> {
+    let mut record_keys: Vec<&String> = existing_keys
+        .filter(|key| key.starts_with(AUDIT_RECORD_CONFIGMAP_KEY_PREFIX))
+        .collect();
+    record_keys.sort();
+
+    let retained = max_records.saturating_sub(1);
+    let pruned = record_keys.len().saturating_sub(retained);
+    record_keys
+        .into_iter()
+        .take(pruned)
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// This adds the audit record to the audit ConfigMap, and creates the ConfigMap if it does not
+/// exist. The oldest audit records are removed, so that the ConfigMap retains at most
+/// 'max_records' records.
+async fn store_audit_record(
+    k8s_client: &KubeClientSet,
+    name: &str,
+    key: &str,
+    record_json: String,
+    max_records: usize,
+) -> Result<()> {
+    let existing = k8s_client
+        .configmaps_api()
+        .get_opt(name)
+        .await
+        .context(GetAuditConfigMap { name })?;
+    match existing {
+        Some(configmap) => {
+            let pruned = configmap
+                .data
+                .as_ref()
+                .map(|data| pruned_record_keys(data.keys(), max_records))
+                .unwrap_or_default();
+            if !pruned.is_empty() {
+                info!(
+                    configmap.name = %name,
+                    keys = ?pruned,
+                    "Removing the oldest upgrade audit records"
+                );
+            }
+
+            // A merge patch removes the keys which are set to null.
+            let mut data: serde_json::Map<String, serde_json::Value> = pruned
+                .into_iter()
+                .map(|key| (key, serde_json::Value::Null))
+                .collect();
+            data.insert(key.to_string(), serde_json::Value::String(record_json));
+            let patch = serde_json::json!({ "data": data });
+
+            k8s_client
+                .configmaps_api()
+                .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await
+                .map(|_| ())
+        }
+        None => {
+            let configmap = ConfigMap {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                },
+                data: Some(BTreeMap::from([(key.to_string(), record_json)])),
+                ..Default::default()
+            };
+            k8s_client
+                .configmaps_api()
+                .create(&PostParams::default(), &configmap)
+                .await
+                .map(|_| ())
+        }
+    }
+    .context(StoreAuditRecord { name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::overrides::SetValue;
+    use std::{path::PathBuf, str::FromStr};
+
+    #[test]
+    fn audit_record_has_the_overrides_and_both_versions() {
+        let record = AuditRecord {
+            timestamp: "2024-05-02T10:00:00+00:00".to_string(),
+            requested_by: Some("jane@example.com".to_string()),
+            service_account: None,
+            from_version: Some("2.4.0".to_string()),
+            to_version: Some("2.5.0".to_string()),
+            values_overrides: ValuesOverrides::new(
+                vec![PathBuf::from("/upgrade/values.yaml")],
+                vec![SetValue::from_str("io_engine.logLevel=debug").unwrap()],
+                vec![SetValue::from_str("image.tag=v2.5.0").unwrap()],
+            ),
+            plan: UpgradePlan::default(),
+        };
+
+        let record_json = serde_json::to_string(&record).unwrap();
+        let document: serde_json::Value = serde_json::from_str(record_json.as_str()).unwrap();
+
+        assert_eq!(document["from_version"], "2.4.0");
+        assert_eq!(document["to_version"], "2.5.0");
+        assert_eq!(document["requested_by"], "jane@example.com");
+        assert!(document["service_account"].is_null());
+        assert_eq!(
+            document["values_overrides"],
+            serde_json::to_value(&record.values_overrides).unwrap()
+        );
+        assert_eq!(
+            document["values_overrides"]["values_files"][0],
+            "/upgrade/values.yaml"
+        );
+        assert!(record_json.contains("logLevel"));
+        assert!(record_json.contains("v2.5.0"));
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest_records() {
+        let keys = [
+            "audit-20240502T100000.000Z.json",
+            "audit-20240501T100000.000Z.json",
+            "notes",
+            "audit-20240503T100000.000Z.json",
+        ]
+        .map(ToString::to_string);
+
+        assert_eq!(
+            pruned_record_keys(keys.iter(), 2),
+            vec![
+                "audit-20240501T100000.000Z.json".to_string(),
+                "audit-20240502T100000.000Z.json".to_string(),
+            ]
+        );
+        assert!(pruned_record_keys(keys.iter(), 4).is_empty());
+        assert_eq!(pruned_record_keys(keys.iter(), 0).len(), 3);
+    }
+}
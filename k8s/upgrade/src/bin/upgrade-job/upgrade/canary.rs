@@ -0,0 +1,121 @@
+use crate::{
+    common::{
+        error::{CanaryVerificationTimeout, GetStorageNode, ListStoragePools, Result},
+        rest_client::RestClientSet,
+    },
+    upgrade::state::UpgradeState,
+};
+use openapi::models::{NodeStatus, PoolStatus};
+use snafu::ResultExt;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// This is true if the next batch of io-engine Pod restarts is that of the canary node, in canary
+/// mode. The canary node is the first node to be upgraded, so there is none if the io-engine Pod
+/// on any node is already upgraded, e.g. by an interrupted upgrade-job.
+pub(crate) fn canary_pending(canary: bool, state: &UpgradeState) -> bool {
+    canary && !state.has_completed_nodes()
+}
+
+/// This waits for the storage node and all of its pools to be Online, after the io-engine Pod on
+/// the canary node is restarted. The upgrade is aborted if they are not Online within the timeout,
+/// before any other io-engine Pod is restarted.
+pub(crate) async fn verify_canary_node(
+    node_name: &str,
+    rest_client: &RestClientSet,
+    timeout: Duration,
+) -> Result<()> {
+    let start = Instant::now();
+    info!(node.name = %node_name, "Verifying the canary node");
+
+    loop {
+        let unhealthy = canary_node_unhealthy(node_name, rest_client).await?;
+        if unhealthy.is_empty() {
+            break;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return CanaryVerificationTimeout {
+                node: node_name,
+                elapsed,
+                unhealthy: unhealthy.join(", "),
+            }
+            .fail();
+        }
+        tokio::time::sleep(Duration::from_secs(5_u64)).await;
+    }
+
+    info!(node.name = %node_name, "The canary node and its pools are Online");
+    Ok(())
+}
+
+/// This verifies the canary node once its io-engine Pod restart succeeds, and returns the outcome
+/// of the verification in place of that of the restart. The canary node is only saved as
+/// upgraded, and the upgrade is only paused, if the verification succeeds too, so that a re-run
+/// starts at the canary node again.
+pub(crate) async fn verify_canary_restart<F, Fut>(
+    restart_result: Result<()>,
+    verify: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    restart_result?;
+    verify().await
+}
+
+/// This lists the storage node and the pools on it which are not Online, e.g. 'node node-1',
+/// 'pool pool-1'.
+async fn canary_node_unhealthy(
+    node_name: &str,
+    rest_client: &RestClientSet,
+) -> Result<Vec<String>> {
+    let mut unhealthy: Vec<String> = Vec::new();
+
+    let node = rest_client
+        .nodes_api()
+        .get_node(node_name)
+        .await
+        .context(GetStorageNode {
+            node_id: node_name.to_string(),
+        })?
+        .into_body();
+    if !node
+        .state
+        .is_some_and(|state| state.status == NodeStatus::Online)
+    {
+        unhealthy.push(format!("node {node_name}"));
+    }
+
+    let pools = rest_client
+        .pools_api()
+        .get_pools()
+        .await
+        .context(ListStoragePools)?
+        .into_body();
+    unhealthy.extend(
+        pools
+            .into_iter()
+            .filter(|pool| {
+                pool.spec
+                    .as_ref()
+                    .map(|spec| spec.node.as_str())
+                    .or(pool.state.as_ref().map(|state| state.node.as_str()))
+                    .is_some_and(|node| node.eq(node_name))
+            })
+            .filter(|pool| {
+                !pool
+                    .state
+                    .as_ref()
+                    .is_some_and(|state| state.status == PoolStatus::Online)
+            })
+            .map(|pool| format!("pool {}", pool.id)),
+    );
+
+    Ok(unhealthy)
+}
@@ -0,0 +1,353 @@
+use crate::{
+    common::{
+        constants::{
+            DISK_PRESSURE_CONDITION, EPHEMERAL_STORAGE_RESOURCE, HUGEPAGES_2MI_RESOURCE,
+            IO_ENGINE_LABEL,
+        },
+        error::{
+            GetNode, InsufficientHugepages, InsufficientNodeDiskSpace, ListPodsWithLabel,
+            ListStoragePools, NodeDiskPressure, QuantityParse, Result,
+            ThinCommitmentBelowCurrentUsage,
+        },
+        kube_client::KubeClientSet,
+        rest_client::RestClientSet,
+    },
+    helm::{
+        chart::{CoreValues, Percentage},
+        values_validation::ThinCommitmentValues,
+    },
+};
+use k8s_openapi::api::core::v1::Node;
+use kube::api::ListParams;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeSet;
+use tracing::{info, warn};
+
+/// This fails if any of the nodes which run io-engine Pods has fewer allocatable 2MiB hugepages
+/// than the upgraded io-engine requests. An io-engine Pod which is restarted on such a node does
+/// not become Ready, so the upgrade is rejected ahead of the first restart.
+pub(crate) async fn check_hugepages(
+    k8s_client: &KubeClientSet,
+    namespace: String,
+    upgrade_values: &CoreValues,
+) -> Result<()> {
+    // Hugepages requests and limits have to be equal, so either of them will do.
+    let Some(required) = upgrade_values.io_engine_resources().and_then(|resources| {
+        resources
+            .requests()
+            .hugepages_2mi()
+            .or(resources.limits().hugepages_2mi())
+    }) else {
+        info!("io-engine requests no hugepages, skipping the hugepages check");
+        return Ok(());
+    };
+    let required_bytes = quantity_bytes(required)?;
+
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(IO_ENGINE_LABEL))
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL.to_string(),
+            namespace,
+        })?;
+    let node_names: BTreeSet<String> = pods
+        .items
+        .into_iter()
+        .filter_map(|pod| pod.spec.and_then(|spec| spec.node_name))
+        .collect();
+
+    for node_name in node_names {
+        let node = k8s_client
+            .nodes_api()
+            .get(node_name.as_str())
+            .await
+            .context(GetNode {
+                node_name: node_name.clone(),
+            })?;
+        let available = node
+            .status
+            .and_then(|status| status.allocatable)
+            .and_then(|mut allocatable| allocatable.remove(HUGEPAGES_2MI_RESOURCE))
+            .map(|quantity| quantity.0)
+            .unwrap_or_else(|| "0".to_string());
+
+        ensure!(
+            quantity_bytes(available.as_str())? >= required_bytes,
+            InsufficientHugepages {
+                node: node_name,
+                required,
+                available,
+            }
+        );
+    }
+
+    info!("All io-engine nodes have enough hugepages for the upgraded io-engine, {required}");
+    Ok(())
+}
+
+/// This checks that the nodes which run io-engine Pods are not low on disk space for pulling the
+/// upgraded io-engine image. The Node status has no disk usage, so only the DiskPressure condition
+/// shows that a node is low on free disk space. The min_free_disk quantity, if set, is compared
+/// with the allocatable ephemeral-storage of the node, which is an upper bound of its free disk
+/// space. Nodes which are low on disk space are logged, and fail the check if fail_on_low_disk is
+/// set.
+pub(crate) async fn check_node_disk_space(
+    k8s_client: &KubeClientSet,
+    namespace: String,
+    min_free_disk: Option<&str>,
+    fail_on_low_disk: bool,
+) -> Result<()> {
+    let min_free_bytes = min_free_disk.map(quantity_bytes).transpose()?;
+
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(IO_ENGINE_LABEL))
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL.to_string(),
+            namespace,
+        })?;
+    let node_names: BTreeSet<String> = pods
+        .items
+        .into_iter()
+        .filter_map(|pod| pod.spec.and_then(|spec| spec.node_name))
+        .collect();
+
+    for node_name in node_names {
+        let node = k8s_client
+            .nodes_api()
+            .get(node_name.as_str())
+            .await
+            .context(GetNode {
+                node_name: node_name.clone(),
+            })?;
+
+        if let Err(error) = check_node_disk(&node, node_name.as_str(), min_free_bytes) {
+            if fail_on_low_disk {
+                return Err(error);
+            }
+            warn!("{error}");
+        }
+    }
+
+    info!("Checked the disk space of the io-engine nodes");
+    Ok(())
+}
+
+/// This fails if the node has DiskPressure, or if it has less allocatable ephemeral-storage than
+/// min_free_bytes. A node without allocatable ephemeral-storage in its status is only checked for
+/// DiskPressure.
+fn check_node_disk(node: &Node, node_name: &str, min_free_bytes: Option<u64>) -> Result<()> {
+    let Some(status) = node.status.as_ref() else {
+        return Ok(());
+    };
+    let disk_pressure = status.conditions.iter().flatten().any(|condition| {
+        condition.type_.eq(DISK_PRESSURE_CONDITION) && condition.status.eq("True")
+    });
+    ensure!(!disk_pressure, NodeDiskPressure { node: node_name });
+
+    let (Some(required), Some(allocatable)) = (
+        min_free_bytes,
+        status
+            .allocatable
+            .as_ref()
+            .and_then(|allocatable| allocatable.get(EPHEMERAL_STORAGE_RESOURCE)),
+    ) else {
+        return Ok(());
+    };
+    let allocatable = quantity_bytes(allocatable.0.as_str())?;
+    ensure!(
+        allocatable >= required,
+        InsufficientNodeDiskSpace {
+            node: node_name,
+            required,
+            allocatable,
+        }
+    );
+
+    Ok(())
+}
+
+/// This fails if any of the storage pools is already committed to thin-provisioned volumes beyond
+/// the upgraded poolCommitment, or beyond the pool's override of it. Lowering the poolCommitment
+/// below the current commitment of a pool would have it reject the provisioning of any new volume
+/// replica.
+pub(crate) async fn check_pool_commitment(
+    rest_client: &RestClientSet,
+    thin_commitment: &ThinCommitmentValues,
+) -> Result<()> {
+    let pools = rest_client
+        .pools_api()
+        .get_pools()
+        .await
+        .context(ListStoragePools)?
+        .into_body();
+
+    for pool in pools {
+        let Some(current) = pool.state.as_ref().and_then(|state| {
+            state
+                .committed
+                .and_then(|committed| Percentage::from_ratio(committed, state.capacity))
+        }) else {
+            continue;
+        };
+
+        let pool_commitment = thin_commitment.pool_commitment_for(pool.id.as_str());
+        ensure!(
+            current <= pool_commitment,
+            ThinCommitmentBelowCurrentUsage {
+                pool: pool.id,
+                current,
+                target: pool_commitment,
+            }
+        );
+    }
+
+    info!(
+        "No pool is committed beyond the upgraded poolCommitment, {}",
+        thin_commitment.pool_commitment()
+    );
+    Ok(())
+}
+
+/// This is the total physical capacity of the storage pools, in bytes. Pools whose state is not
+/// known are left out.
+pub(crate) async fn total_pool_capacity(rest_client: &RestClientSet) -> Result<u64> {
+    let pools = rest_client
+        .pools_api()
+        .get_pools()
+        .await
+        .context(ListStoragePools)?
+        .into_body();
+
+    Ok(pools
+        .iter()
+        .filter_map(|pool| pool.state.as_ref().map(|state| state.capacity))
+        .fold(0_u64, u64::saturating_add))
+}
+
+/// This converts a Kubernetes resource quantity, e.g. '2Gi', '1024Mi', '1500m' or '128974848e0',
+/// into bytes. Fractional bytes are rounded down.
+fn quantity_bytes(quantity: &str) -> Result<u64> {
+    let quantity = quantity.trim();
+    let suffix_start = quantity
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(quantity.len());
+    let (number, suffix) = quantity.split_at(suffix_start);
+
+    let multiplier: f64 = match suffix {
+        "" => 1.0,
+        "Ki" => 1024_f64,
+        "Mi" => 1024_f64.powi(2),
+        "Gi" => 1024_f64.powi(3),
+        "Ti" => 1024_f64.powi(4),
+        "Pi" => 1024_f64.powi(5),
+        "Ei" => 1024_f64.powi(6),
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "P" => 1e15,
+        "E" => 1e18,
+        "m" => 1e-3,
+        // A decimal exponent, e.g. 'e3' or 'E-2'. A plain 'E' is the exa suffix.
+        exponent if exponent.starts_with(['e', 'E']) => {
+            let exponent: i32 = exponent[1 ..]
+                .parse()
+                .ok()
+                .context(QuantityParse { quantity })?;
+            10_f64.powi(exponent)
+        }
+        _ => return QuantityParse { quantity }.fail(),
+    };
+    let number: f64 = number.parse().ok().context(QuantityParse { quantity })?;
+
+    Ok((number * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use k8s_openapi::{
+        api::core::v1::{NodeCondition, NodeStatus},
+        apimachinery::pkg::api::resource::Quantity,
+    };
+
+    /// This builds a Node with an allocatable ephemeral-storage quantity and a DiskPressure
+    /// condition.
+    fn node(allocatable: Option<&str>, disk_pressure: bool) -> Node {
+        Node {
+            status: Some(NodeStatus {
+                allocatable: allocatable.map(|quantity| {
+                    [(
+                        EPHEMERAL_STORAGE_RESOURCE.to_string(),
+                        Quantity(quantity.to_string()),
+                    )]
+                    .into_iter()
+                    .collect()
+                }),
+                conditions: Some(vec![NodeCondition {
+                    type_: DISK_PRESSURE_CONDITION.to_string(),
+                    status: if disk_pressure { "True" } else { "False" }.to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn disk_pressure_is_low_on_disk() {
+        assert!(matches!(
+            check_node_disk(&node(Some("100Gi"), true), "node-1", None),
+            Err(Error::NodeDiskPressure { .. })
+        ));
+    }
+
+    #[test]
+    fn allocatable_below_min_free_disk_is_low_on_disk() {
+        let min_free_bytes = Some(2 * 1024_u64.pow(3));
+
+        assert!(matches!(
+            check_node_disk(&node(Some("1Gi"), false), "node-1", min_free_bytes),
+            Err(Error::InsufficientNodeDiskSpace { .. })
+        ));
+        assert!(check_node_disk(&node(Some("100Gi"), false), "node-1", min_free_bytes).is_ok());
+    }
+
+    #[test]
+    fn allocatable_is_only_checked_with_min_free_disk() {
+        assert!(check_node_disk(&node(Some("1Ki"), false), "node-1", None).is_ok());
+        assert!(check_node_disk(&node(None, false), "node-1", Some(1024)).is_ok());
+    }
+
+    #[test]
+    fn quantities_convert_to_bytes() {
+        for (quantity, bytes) in [
+            ("2Gi", 2 * 1024_u64.pow(3)),
+            ("1.5Ki", 1536),
+            ("5G", 5_000_000_000),
+            ("1E", 10_u64.pow(18)),
+            ("1500m", 1),
+            ("1e3", 1000),
+            ("128974848e0", 128974848),
+            ("12E-1", 1),
+            (" 100 ", 100),
+        ] {
+            assert_eq!(quantity_bytes(quantity).unwrap(), bytes, "{quantity}");
+        }
+    }
+
+    #[test]
+    fn malformed_quantities_are_rejected() {
+        for quantity in ["", "Gi", "2Gb", "1e", "1ex", "1.2.3Mi"] {
+            assert!(
+                matches!(quantity_bytes(quantity), Err(Error::QuantityParse { .. })),
+                "'{quantity}' should be rejected"
+            );
+        }
+    }
+}
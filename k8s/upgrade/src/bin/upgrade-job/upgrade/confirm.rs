@@ -0,0 +1,94 @@
+use crate::common::error::{
+    Result, UpgradeConfirmationPrompt, UpgradeConfirmationRequired, UpgradeNotConfirmed,
+};
+use snafu::{ensure, ResultExt};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// This fails if the confirmation cannot be prompted for, because stdin is not an interactive
+/// terminal. This is checked ahead of the upgrade's preparations, so that a non-interactive
+/// upgrade without '--yes' fails before it does any work. The upgrade Job which the kubectl plugin
+/// creates has no terminal, so the plugin always passes '--yes'.
+pub(crate) fn ensure_interactive() -> Result<()> {
+    ensure!(io::stdin().is_terminal(), UpgradeConfirmationRequired);
+    Ok(())
+}
+
+/// This prompts on stderr for the confirmation to go ahead, and reads the answer from stdin. Only
+/// 'y' or 'yes' confirm, any other answer declines.
+pub(crate) fn confirm(prompt: &str) -> Result<()> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    confirm_with(prompt, interactive, &mut stdin.lock(), &mut io::stderr())
+}
+
+/// This is the confirmation prompt, with the terminal detection and the streams passed in.
+fn confirm_with<R: BufRead, W: Write>(
+    prompt: &str,
+    interactive: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<()> {
+    ensure!(interactive, UpgradeConfirmationRequired);
+
+    write!(output, "{prompt} [y/N] ").context(UpgradeConfirmationPrompt)?;
+    output.flush().context(UpgradeConfirmationPrompt)?;
+
+    let mut answer = String::new();
+    input
+        .read_line(&mut answer)
+        .context(UpgradeConfirmationPrompt)?;
+
+    ensure!(
+        matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        UpgradeNotConfirmed
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This answers the confirmation prompt on an interactive terminal, and returns the outcome
+    /// and what was written to the terminal.
+    fn answer(answer: &str) -> (Result<()>, String) {
+        let mut output = Vec::new();
+        let result = confirm_with("Proceed?", true, &mut answer.as_bytes(), &mut output);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn no_terminal_fails_closed() {
+        let mut output = Vec::new();
+        let result = confirm_with("Proceed?", false, &mut "y\n".as_bytes(), &mut output);
+
+        assert!(matches!(result, Err(Error::UpgradeConfirmationRequired)));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn prompt_defaults_to_no() {
+        let (result, output) = answer("\n");
+        assert_eq!(output, "Proceed? [y/N] ");
+        assert!(matches!(result, Err(Error::UpgradeNotConfirmed)));
+    }
+
+    #[test]
+    fn yes_confirms() {
+        for yes in ["y\n", "yes\n", "Y\n", " YES \n"] {
+            assert!(answer(yes).0.is_ok(), "'{yes}' should confirm");
+        }
+    }
+
+    #[test]
+    fn other_answers_decline() {
+        for no in ["n\n", "no\n", "yep\n", ""] {
+            assert!(
+                matches!(answer(no).0, Err(Error::UpgradeNotConfirmed)),
+                "'{no}' should decline"
+            );
+        }
+    }
+}
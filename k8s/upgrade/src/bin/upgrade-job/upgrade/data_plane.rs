@@ -1,35 +1,60 @@
 use crate::{
     common::{
-        constants::{
-            AGENT_CORE_LABEL, CHART_VERSION_LABEL_KEY, DRAIN_FOR_UPGRADE, IO_ENGINE_LABEL, PRODUCT,
-        },
+        constants::{AGENT_CORE_LABEL, CHART_VERSION_LABEL_KEY, IO_ENGINE_LABEL, PRODUCT},
         error::{
-            DrainStorageNode, EmptyPodNodeName, EmptyPodSpec, EmptyStorageNodeSpec, GetStorageNode,
-            ListPodsWithLabel, ListPodsWithLabelAndField, ListStorageNodes, PodDelete, Result,
-            StorageNodeUncordon, TooManyIoEnginePods,
+            EmptyPodNodeName, EmptyPodSpec, Error, ListPodsWithLabel, ListPodsWithLabelAndField,
+            ListStorageNodes, NodeReadyTimeout, NodeVolumesNotOnline, PodDelete, Result,
+            SingleReplicaVolumesOnNode, TooManyIoEnginePods,
         },
         kube_client::KubeClientSet,
         rest_client::RestClientSet,
+        retry::{kube_delete_with_backoff, kube_with_backoff},
+    },
+    opts::CliArgs,
+    upgrade::{
+        canary::{canary_pending, verify_canary_node, verify_canary_restart},
+        check_overall_timeout,
+        drain::{drain_node, uncordon_node},
+        health::single_replica_volumes_by_node,
+        progress::{Progress, ProgressReporter, ProgressState},
+        reconcile::{
+            controller_rollout, detect_state, prepare_rollout, RolloutState, UpdateStrategy,
+        },
+        state::{StateStore, UpgradeClock},
+        utils::{
+            all_pods_are_ready, data_plane_is_upgraded, list_all_volumes, list_volume_placements,
+            rebuild_result, replica_rebuild_count, skipped_nodes, RebuildResult, VolumePlacement,
+        },
     },
-    upgrade::utils::{all_pods_are_ready, data_plane_is_upgraded, rebuild_result, RebuildResult},
 };
+use futures::future::join_all;
 use k8s_openapi::api::core::v1::Pod;
 use kube::{
     api::{DeleteParams, ListParams, ObjectList},
     ResourceExt,
 };
-use openapi::models::CordonDrainState;
+use openapi::models::VolumeStatus;
 use snafu::ResultExt;
-use std::time::Duration;
-use tracing::info;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use tracing::{info, info_span, warn, Instrument};
 use utils::{API_REST_LABEL, ETCD_LABEL};
 
 /// Upgrade data plane by controlled restart of io-engine pods
+#[tracing::instrument(
+    name = "restart",
+    skip_all,
+    fields(upgrade.phase = "restart", upgrade.to_version = %upgrade_to_version)
+)]
 pub(crate) async fn upgrade_data_plane(
-    namespace: String,
-    rest_endpoint: String,
+    opts: &CliArgs,
     upgrade_to_version: String,
+    reporter: &dyn ProgressReporter,
 ) -> Result<()> {
+    let namespace = opts.namespace();
+
     // Generate k8s clients.
     let k8s_client = KubeClientSet::builder()
         .with_namespace(namespace.clone())
@@ -39,29 +64,64 @@ pub(crate) async fn upgrade_data_plane(
     // This makes data-plane upgrade idempotent.
     let io_engine_label = format!("{IO_ENGINE_LABEL},{CHART_VERSION_LABEL_KEY}");
     let io_engine_listparams = ListParams::default().labels(io_engine_label.as_str());
-    let io_engine_pod_list = k8s_client
-        .pods_api()
-        .list(&io_engine_listparams)
-        .await
-        .context(ListPodsWithLabel {
-            label: io_engine_label,
-            namespace: namespace.clone(),
-        })?;
-    if data_plane_is_upgraded(&upgrade_to_version, &io_engine_pod_list).await? {
+    let io_engine_pod_list =
+        kube_with_backoff(|| k8s_client.pods_api().list(&io_engine_listparams))
+            .await
+            .context(ListPodsWithLabel {
+                label: io_engine_label,
+                namespace: namespace.clone(),
+            })?;
+    // The io-engine Pods on these nodes are left at the installed version.
+    let skipped_nodes = skipped_nodes(&k8s_client, opts.skip_node_label()).await?;
+    let state_store = StateStore::new(&k8s_client, opts.release_name().as_str());
+    if data_plane_is_upgraded(
+        &upgrade_to_version,
+        &io_engine_pod_list.items,
+        &skipped_nodes,
+    )? {
         info!("Skipping data-plane upgrade: All data-plane Pods are already upgraded");
-        return Ok(());
+        return state_store.clear().await;
+    }
+
+    // This resumes the progress of an interrupted upgrade-job, if any.
+    let mut state = state_store.load(upgrade_to_version.as_str()).await?;
+    // The ConfigMap is created ahead of the first restart, so that an operator may pause the
+    // upgrade from the start, with a patch of the ConfigMap.
+    state_store.save(&state).await?;
+    // The time spent by the previous runs counts toward the --overall-timeout.
+    let mut clock = UpgradeClock::new(opts.started_at(), state.elapsed());
+    // The io-engine Pods which are already upgraded need not be restarted again, even if the
+    // previous upgrade-job did not get to persist its progress.
+    if let RolloutState::InProgress {
+        done_nodes,
+        pending_nodes,
+    } = detect_state(&k8s_client, namespace.as_str(), upgrade_to_version.as_str()).await?
+    {
+        info!(
+            "Resuming an interrupted data-plane upgrade, io-engine Pods on nodes {} are already \
+            upgraded, and {} are pending",
+            done_nodes.join(", "),
+            pending_nodes.len()
+        );
+        for node in done_nodes.iter() {
+            state.mark_completed(node);
+        }
     }
 
+    // This is the number of io-engine Pods which may be restarted at the same time.
+    let max_unavailable = opts
+        .max_unavailable()
+        .resolve(io_engine_pod_list.items.len());
+
     // If here, then there is a need to proceed to data-plane upgrade.
 
     let yet_to_upgrade_io_engine_label_selector =
         format!("{IO_ENGINE_LABEL},{CHART_VERSION_LABEL_KEY}!={upgrade_to_version}");
     let io_engine_listparams =
         ListParams::default().labels(yet_to_upgrade_io_engine_label_selector.as_str());
-    let namespace = namespace.clone();
 
     // Generate storage REST API client.
-    let rest_client = RestClientSet::new_with_url(rest_endpoint)?;
+    let rest_client = RestClientSet::new_with_url(opts.rest_endpoint(), &opts.rest_tls())?;
 
     info!("Starting data-plane upgrade...");
 
@@ -80,26 +140,52 @@ pub(crate) async fn upgrade_data_plane(
         uncordon_node(storage_node.id.as_str(), &rest_client).await?;
     }
 
-    loop {
-        let initial_io_engine_pod_list: ObjectList<Pod> = k8s_client
-            .pods_api()
-            .list(&io_engine_listparams)
-            .await
-            .context(ListPodsWithLabel {
-                label: yet_to_upgrade_io_engine_label_selector.clone(),
-                namespace: namespace.clone(),
-            })?;
+    // The helm upgrade may have reset the update strategy of the io-engine DaemonSet. With
+    // OnDelete, the io-engine Pods are only replaced once the upgrade deletes them, after their
+    // node is drained. With RollingUpdate, the DaemonSet controller replaces them.
+    let update_strategy = prepare_rollout(
+        &k8s_client,
+        namespace.as_str(),
+        controller_rollout(opts, true),
+    )
+    .await?;
+
+    let node_restart = NodeRestart {
+        namespace: namespace.clone(),
+        update_strategy,
+        upgrade_to_version: upgrade_to_version.clone(),
+        node_ready_timeout: opts.node_ready_timeout(),
+        post_node_wait: opts.post_node_wait(),
+        no_drain: opts.no_drain(),
+        drain_grace_period: opts.drain_grace_period(),
+        k8s_client: &k8s_client,
+        rest_client: &rest_client,
+        reporter,
+    };
+
+    if !skipped_nodes.is_empty() {
+        let mut names: Vec<&str> = skipped_nodes.iter().map(String::as_str).collect();
+        names.sort();
+        info!(
+            "Skipping the io-engine Pods on nodes {}, they remain at the installed version",
+            names.join(", ")
+        );
+    }
 
-        // Infinite loop exit.
-        if initial_io_engine_pod_list.items.is_empty() {
-            break;
-        }
+    let mut canary_pending = canary_pending(opts.canary(), &state);
 
-        for pod in initial_io_engine_pod_list.iter() {
-            // Validate the control plane pod is up and running before we start.
-            verify_control_plane_is_running(namespace.clone(), &k8s_client, &upgrade_to_version)
-                .await?;
+    let mut nodes_completed = 0_usize;
+    loop {
+        let initial_io_engine_pod_list: ObjectList<Pod> =
+            kube_with_backoff(|| k8s_client.pods_api().list(&io_engine_listparams))
+                .await
+                .context(ListPodsWithLabel {
+                    label: yet_to_upgrade_io_engine_label_selector.clone(),
+                    namespace: namespace.clone(),
+                })?;
 
+        let mut pending_pods: Vec<(&str, &Pod)> = Vec::new();
+        for pod in initial_io_engine_pod_list.iter() {
             // Fetch the node name on which the io-engine pod is running
             let node_name = pod
                 .spec
@@ -122,89 +208,322 @@ pub(crate) async fn upgrade_data_plane(
                 )?
                 .as_str();
 
-            info!(
-                pod.name = %pod.name_any(),
-                node.name = %node_name,
-                "Starting upgrade for the data-plane pod"
-            );
-
-            // Wait for any rebuild to complete
-            wait_for_rebuild(node_name, &rest_client).await?;
+            if !skipped_nodes.contains(node_name) {
+                pending_pods.push((node_name, pod));
+            }
+        }
 
-            // Issue node drain command
-            drain_storage_node(node_name, &rest_client).await?;
+        // Infinite loop exit.
+        if pending_pods.is_empty() {
+            break;
+        }
 
-            // restart the data plane pod
-            delete_data_plane_pod(node_name, pod, &k8s_client).await?;
+        let mut nodes_remaining = pending_pods.len();
+        for (node_name, _) in pending_pods.iter() {
+            reporter.report(&Progress::new(
+                node_name,
+                ProgressState::Pending,
+                nodes_completed,
+                nodes_remaining,
+            ));
+        }
 
-            // validate the new pod is up and running
+        // The io-engine Pods on these nodes were restarted by an interrupted upgrade-job. The
+        // listed Pods are the terminating ones, so only wait for their replacements.
+        let (resumed_pods, pending_pods): (Vec<_>, Vec<_>) = pending_pods
+            .into_iter()
+            .partition(|(node_name, _)| state.is_completed(node_name));
+        for (node_name, _) in resumed_pods {
+            info!(
+                node.name = %node_name,
+                "Skipping the restart of the data-plane pod, already restarted by an \
+                interrupted upgrade"
+            );
             verify_data_plane_pod_is_running(
                 node_name,
                 namespace.clone(),
                 &upgrade_to_version,
+                opts.node_ready_timeout(),
                 &k8s_client,
             )
             .await?;
+        }
 
-            // Uncordon the drained node
-            uncordon_node(node_name, &rest_client).await?;
+        let mut pending_pods = pending_pods;
+        while !pending_pods.is_empty() {
+            // The upgrade may only be paused at the boundary between two batches of restarts.
+            clock.add_paused(state_store.wait_while_paused().await?);
+
+            // The progress up to here is saved, so a re-run resumes from this batch.
+            check_overall_timeout(opts.overall_timeout(), clock.elapsed())?;
+
+            // Draining a node moves its volume targets to other nodes, so the volume topology is
+            // listed afresh for each batch. The canary node is restarted in a batch of its own.
+            let topology = VolumeTopology::new(&list_volume_placements(&rest_client).await?);
+            let batch = if canary_pending {
+                vec![pending_pods.remove(0)]
+            } else {
+                next_batch(
+                    &mut pending_pods,
+                    &topology.volumes_by_node,
+                    max_unavailable,
+                )
+            };
+            // The volumes whose only replica is on a node are unavailable during its restart.
+            for (node_name, _) in batch.iter() {
+                if let Some(volumes) = topology.single_replica_volumes.get(*node_name) {
+                    warn!(
+                        "{}",
+                        SingleReplicaVolumesOnNode {
+                            node: *node_name,
+                            volumes: volumes.clone()
+                        }
+                        .build()
+                    );
+                }
+            }
+
+            // Validate the control plane pod is up and running before we start.
+            verify_control_plane_is_running(namespace.clone(), &k8s_client, &upgrade_to_version)
+                .await?;
+
+            let mut results = join_all(batch.iter().map(|(node_name, pod)| {
+                node_restart.restart(node_name, pod, nodes_completed, nodes_remaining)
+            }))
+            .await;
+            // The canary node is verified before its progress is saved.
+            if canary_pending {
+                for ((node_name, _), result) in batch.iter().zip(results.iter_mut()) {
+                    let restart_result = std::mem::replace(result, Ok(()));
+                    *result = verify_canary_restart(restart_result, || {
+                        verify_canary_node(node_name, &rest_client, opts.node_ready_timeout())
+                    })
+                    .await;
+                }
+            }
+
+            let mut first_error: Option<Error> = None;
+            for ((node_name, _), result) in batch.iter().zip(results) {
+                match result {
+                    Ok(()) => {
+                        state.mark_completed(node_name);
+                        nodes_completed += 1;
+                        nodes_remaining -= 1;
+                        reporter.report(&Progress::new(
+                            node_name,
+                            ProgressState::Completed,
+                            nodes_completed,
+                            nodes_remaining,
+                        ));
+                    }
+                    Err(error) => {
+                        reporter.report(&Progress::new(
+                            node_name,
+                            ProgressState::Failed,
+                            nodes_completed,
+                            nodes_remaining,
+                        ));
+                        first_error.get_or_insert(error);
+                    }
+                }
+            }
+
+            state.set_elapsed(clock.elapsed());
+            let batch_result = first_error.map_or(Ok(()), Err);
+            state_store
+                .end_batch(&state, canary_pending, batch_result)
+                .await?;
+
+            if canary_pending {
+                canary_pending = false;
+                for (node_name, _) in batch.iter() {
+                    info!(
+                        node.name = %node_name,
+                        configmap.name = %state_store.name(),
+                        "The canary node is upgraded, resume the data-plane upgrade to continue \
+                        with the rest of the nodes, e.g. kubectl patch configmap {} --type merge \
+                        -p '{{\"data\":{{\"paused\":\"false\"}}}}'",
+                        state_store.name()
+                    );
+                }
+            }
         }
 
         info!("Checking to see if new {PRODUCT} Nodes have been added to the cluster, which require upgrade");
     }
 
+    state_store.clear().await?;
+
     info!("Successfully upgraded data-plane!");
 
     Ok(())
 }
 
-/// Uncordon storage Node.
-async fn uncordon_node(node_id: &str, rest_client: &RestClientSet) -> Result<()> {
-    let drain_label_for_upgrade: String = DRAIN_FOR_UPGRADE.to_string();
-    let sleep_duration = Duration::from_secs(1_u64);
-    loop {
-        let storage_node =
-            rest_client
-                .nodes_api()
-                .get_node(node_id)
-                .await
-                .context(GetStorageNode {
-                    node_id: node_id.to_string(),
-                })?;
+/// This carries out the restart of the io-engine Pod on a node.
+struct NodeRestart<'a> {
+    namespace: String,
+    update_strategy: UpdateStrategy,
+    upgrade_to_version: String,
+    node_ready_timeout: Duration,
+    post_node_wait: Option<Duration>,
+    no_drain: bool,
+    drain_grace_period: Option<Duration>,
+    k8s_client: &'a KubeClientSet,
+    rest_client: &'a RestClientSet,
+    reporter: &'a dyn ProgressReporter,
+}
 
-        match storage_node
-            .into_body()
-            .spec
-            .ok_or(
-                EmptyStorageNodeSpec {
-                    node_id: node_id.to_string(),
-                }
-                .build(),
-            )?
-            .cordondrainstate
-        {
-            Some(CordonDrainState::drainedstate(drain_state))
-                if drain_state.drainlabels.contains(&drain_label_for_upgrade) =>
-            {
-                rest_client
-                    .nodes_api()
-                    .delete_node_cordon(node_id, DRAIN_FOR_UPGRADE)
-                    .await
-                    .context(StorageNodeUncordon {
-                        node_id: node_id.to_string(),
-                    })?;
-
-                info!(node.id = %node_id,
-                    label = %DRAIN_FOR_UPGRADE,
-                    "Removed drain label from {PRODUCT} Node"
-                );
+impl NodeRestart<'_> {
+    /// Drain the node, restart the io-engine Pod on it and wait for the new Pod to be Ready.
+    #[tracing::instrument(
+        name = "node_restart",
+        skip_all,
+        fields(
+            node.name = %node_name,
+            upgrade.phase = "restart",
+            upgrade.to_version = %self.upgrade_to_version
+        )
+    )]
+    async fn restart(
+        &self,
+        node_name: &str,
+        pod: &Pod,
+        nodes_completed: usize,
+        nodes_remaining: usize,
+    ) -> Result<()> {
+        info!(
+            pod.name = %pod.name_any(),
+            node.name = %node_name,
+            "Starting upgrade for the data-plane pod"
+        );
+
+        let report = |state: ProgressState| {
+            self.reporter.report(&Progress::new(
+                node_name,
+                state,
+                nodes_completed,
+                nodes_remaining,
+            ))
+        };
+
+        // Wait for any rebuild to complete
+        wait_for_rebuild(node_name, self.rest_client).await?;
+
+        // Move the volume targets off the node
+        if !self.no_drain {
+            report(ProgressState::DrainingNode);
+            drain_node(node_name, self.rest_client, self.drain_grace_period)
+                .instrument(info_span!(
+                    "drain",
+                    node.name = %node_name,
+                    upgrade.phase = "drain"
+                ))
+                .await?;
+        }
+
+        // restart the data plane pod
+        report(ProgressState::RestartingPod);
+        match self.update_strategy {
+            UpdateStrategy::OnDelete => {
+                delete_data_plane_pod(node_name, pod, self.k8s_client).await?
             }
-            _ => return Ok(()),
+            UpdateStrategy::RollingUpdate => info!(
+                pod.name = %pod.name_any(),
+                node.name = %node_name,
+                "Waiting for the io-engine DaemonSet to replace the data-plane pod"
+            ),
+        }
+
+        // validate the new pod is up and running
+        report(ProgressState::WaitingForReady);
+        verify_data_plane_pod_is_running(
+            node_name,
+            self.namespace.clone(),
+            &self.upgrade_to_version,
+            self.node_ready_timeout,
+            self.k8s_client,
+        )
+        .await?;
+
+        // Uncordon the drained node, this is a no-op if the node wasn't drained
+        uncordon_node(node_name, self.rest_client).await?;
+
+        // Let the volumes on the node recover, before the next node is restarted
+        if let Some(post_node_wait) = self.post_node_wait {
+            report(ProgressState::Stabilizing);
+            wait_for_node_volumes_online(
+                node_name,
+                post_node_wait,
+                self.node_ready_timeout,
+                self.rest_client,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// This is the volume topology which a batch of io-engine Pod restarts is picked from.
+struct VolumeTopology {
+    /// The uuids of the volumes which have either of their target or a replica on a node, by the
+    /// node.
+    volumes_by_node: HashMap<String, HashSet<String>>,
+    /// The uuids of the volumes which have only one replica, by the node of the replica.
+    single_replica_volumes: BTreeMap<String, Vec<String>>,
+}
+
+impl VolumeTopology {
+    /// This builds the volume topology from the placements of the volumes.
+    fn new(placements: &[VolumePlacement]) -> Self {
+        let mut volumes_by_node: HashMap<String, HashSet<String>> = HashMap::new();
+        for placement in placements {
+            let target_node = placement.target_node.iter();
+            for node in target_node.chain(placement.replica_nodes.iter()) {
+                volumes_by_node
+                    .entry(node.clone())
+                    .or_default()
+                    .insert(placement.uuid.clone());
+            }
+        }
+
+        Self {
+            volumes_by_node,
+            single_replica_volumes: single_replica_volumes_by_node(placements),
         }
-        tokio::time::sleep(sleep_duration).await;
     }
 }
 
+/// This takes the next batch of io-engine Pods which may be restarted at the same time out of the
+/// pending Pods, from the current volume topology. A batch has at most max_unavailable Pods, and
+/// no two Pods in a batch are on nodes which host the target or a replica of the same volume.
+fn next_batch<'a, T>(
+    pending_pods: &mut Vec<(&'a str, T)>,
+    volumes_by_node: &HashMap<String, HashSet<String>>,
+    max_unavailable: usize,
+) -> Vec<(&'a str, T)> {
+    let no_volumes = HashSet::new();
+    let mut batch: Vec<(&str, T)> = Vec::new();
+    let mut batch_volumes: HashSet<&String> = HashSet::new();
+    let mut deferred_pods = Vec::new();
+
+    for (node_name, pod) in pending_pods.drain(..) {
+        let node_volumes = volumes_by_node.get(node_name).unwrap_or(&no_volumes);
+        let shares_volumes = node_volumes
+            .iter()
+            .any(|volume| batch_volumes.contains(volume));
+        if batch.len() < max_unavailable && !shares_volumes {
+            batch_volumes.extend(node_volumes.iter());
+            batch.push((node_name, pod));
+        } else {
+            deferred_pods.push((node_name, pod));
+        }
+    }
+
+    *pending_pods = deferred_pods;
+    batch
+}
+
 /// Issue delete command on dataplane pods.
 async fn delete_data_plane_pod(
     node_name: &str,
@@ -218,34 +537,91 @@ async fn delete_data_plane_pod(
         node.name = node_name,
         "Deleting the pod"
     );
-    k8s_client
-        .pods_api()
-        .delete(pod_name.as_str(), &DeleteParams::default())
-        .await
-        .context(PodDelete {
-            name: pod_name,
-            node: node_name.to_string(),
-        })?;
+    let delete_params = DeleteParams::default();
+    kube_delete_with_backoff(|| {
+        k8s_client
+            .pods_api()
+            .delete(pod_name.as_str(), &delete_params)
+    })
+    .await
+    .context(PodDelete {
+        name: pod_name,
+        node: node_name.to_string(),
+    })?;
     info!(node.name = %node_name, "Pod delete command issued");
     Ok(())
 }
 
-/// Wait for all the node drain process to complete.
+/// Wait for the restarted data-plane pod to become Ready, for at most node_ready_timeout.
 async fn verify_data_plane_pod_is_running(
     node_name: &str,
     namespace: String,
     upgrade_to_version: &String,
+    node_ready_timeout: Duration,
     k8s_client: &KubeClientSet,
 ) -> Result<()> {
     let duration = Duration::from_secs(5_u64);
+    let start = Instant::now();
     // Validate the new pod is up and running
     info!(node.name = %node_name, "Waiting for data-plane Pod to come to Ready state");
-    while !data_plane_pod_is_running(node_name, namespace.clone(), upgrade_to_version, k8s_client)
-        .await?
-    {
-        tokio::time::sleep(duration).await;
+    loop {
+        let ready =
+            data_plane_pod_is_running(node_name, namespace.clone(), upgrade_to_version, k8s_client)
+                .await?;
+        let elapsed = start.elapsed();
+
+        match next_wait_step(ready, elapsed, node_ready_timeout) {
+            WaitStep::Wait => tokio::time::sleep(duration).await,
+            WaitStep::Done => return Ok(()),
+            WaitStep::TimedOut => {
+                return NodeReadyTimeout {
+                    node: node_name,
+                    elapsed,
+                    phase: data_plane_pod_phase(node_name, namespace, k8s_client).await?,
+                }
+                .fail();
+            }
+        }
     }
-    Ok(())
+}
+
+/// Fetch the phases of the data-plane pods on a node, of any version, for error reporting.
+async fn data_plane_pod_phase(
+    node: &str,
+    namespace: String,
+    k8s_client: &KubeClientSet,
+) -> Result<String> {
+    let node_name_pod_field = format!("spec.nodeName={node}");
+    let io_engine_listparam = ListParams::default()
+        .labels(IO_ENGINE_LABEL)
+        .fields(node_name_pod_field.as_str());
+
+    let pod_list: ObjectList<Pod> =
+        kube_with_backoff(|| k8s_client.pods_api().list(&io_engine_listparam))
+            .await
+            .context(ListPodsWithLabelAndField {
+                label: IO_ENGINE_LABEL.to_string(),
+                field: node_name_pod_field,
+                namespace,
+            })?;
+
+    let phases: Vec<String> = pod_list
+        .iter()
+        .map(|pod| {
+            let phase = pod
+                .status
+                .as_ref()
+                .and_then(|status| status.phase.clone())
+                .unwrap_or_else(|| "Unknown".to_string());
+            format!("{}={phase}", pod.name_any())
+        })
+        .collect();
+
+    if phases.is_empty() {
+        return Ok("no io-engine Pod".to_string());
+    }
+
+    Ok(phases.join(", "))
 }
 
 /// Wait for the rebuild to complete if any.
@@ -268,59 +644,101 @@ async fn wait_for_rebuild(node_name: &str, rest_client: &RestClientSet) -> Resul
     Ok(())
 }
 
-/// Issue the node drain command on the node.
-async fn drain_storage_node(node_id: &str, rest_client: &RestClientSet) -> Result<()> {
-    let drain_label_for_upgrade: String = DRAIN_FOR_UPGRADE.to_string();
-    let sleep_duration = Duration::from_secs(5_u64);
-    loop {
-        let storage_node =
-            rest_client
-                .nodes_api()
-                .get_node(node_id)
-                .await
-                .context(GetStorageNode {
-                    node_id: node_id.to_string(),
-                })?;
+/// Wait for the settling time, and then until the volumes which have their target or a replica on
+/// the node are Online and have no rebuilding replicas. This fails if the volumes are not Online
+/// within the timeout.
+async fn wait_for_node_volumes_online(
+    node_name: &str,
+    post_node_wait: Duration,
+    timeout: Duration,
+    rest_client: &RestClientSet,
+) -> Result<()> {
+    tokio::time::sleep(post_node_wait).await;
 
-        match storage_node
-            .into_body()
-            .spec
-            .ok_or(
-                EmptyStorageNodeSpec {
-                    node_id: node_id.to_string(),
-                }
-                .build(),
-            )?
-            .cordondrainstate
-        {
-            Some(CordonDrainState::drainingstate(drain_state))
-                if drain_state.drainlabels.contains(&drain_label_for_upgrade) =>
-            {
-                info!(node.id = %node_id, "Waiting for {PRODUCT} Node drain to complete");
-                // Wait for node drain to complete.
-                tokio::time::sleep(sleep_duration).await;
+    let start = Instant::now();
+    loop {
+        let volumes = node_volumes_not_online(node_name, rest_client).await?;
+        let elapsed = start.elapsed();
+
+        match next_wait_step(volumes.is_empty(), elapsed, timeout) {
+            WaitStep::Wait => {
+                info!(
+                    node.name = %node_name,
+                    ?volumes,
+                    "Waiting for the volumes on the node to be Online"
+                );
+                tokio::time::sleep(Duration::from_secs(10_u64)).await;
             }
-            Some(CordonDrainState::drainedstate(drain_state))
-                if drain_state.drainlabels.contains(&drain_label_for_upgrade) =>
-            {
-                info!(node.id = %node_id, "Drain completed for {PRODUCT} Node");
+            WaitStep::Done => {
+                info!(node.name = %node_name, "All volumes on the node are Online");
                 return Ok(());
             }
-            _ => {
-                rest_client
-                    .nodes_api()
-                    .put_node_drain(node_id, DRAIN_FOR_UPGRADE)
-                    .await
-                    .context(DrainStorageNode {
-                        node_id: node_id.to_string(),
-                    })?;
-
-                info!(node.id = %node_id, "Drain started for {PRODUCT} Node");
+            WaitStep::TimedOut => {
+                return NodeVolumesNotOnline {
+                    node: node_name,
+                    volumes,
+                    elapsed,
+                }
+                .fail();
             }
         }
     }
 }
 
+/// This is the next step of a wait on a node, e.g. for the io-engine Pod on the node to be Ready,
+/// or for the volumes on the node to be Online.
+#[derive(Debug, PartialEq)]
+enum WaitStep {
+    /// The wait goes on, e.g. the Pod is not Ready yet, or some volumes are not Online yet.
+    Wait,
+    /// The wait is over.
+    Done,
+    /// The wait was not over within the timeout.
+    TimedOut,
+}
+
+/// This decides the next step of a wait on a node, from whether the wait is over and the time
+/// waited so far.
+fn next_wait_step(done: bool, elapsed: Duration, timeout: Duration) -> WaitStep {
+    if done {
+        WaitStep::Done
+    } else if elapsed >= timeout {
+        WaitStep::TimedOut
+    } else {
+        WaitStep::Wait
+    }
+}
+
+/// List the uuids of the volumes with their target or a replica on the node, which are not Online
+/// or have rebuilding replicas.
+async fn node_volumes_not_online(
+    node_name: &str,
+    rest_client: &RestClientSet,
+) -> Result<Vec<String>> {
+    let volumes_not_online: Vec<String> = list_all_volumes(rest_client)
+        .await?
+        .iter()
+        .filter(|volume| {
+            let on_node = volume
+                .state
+                .target
+                .as_ref()
+                .is_some_and(|target| target.node.eq(node_name))
+                || volume
+                    .state
+                    .replica_topology
+                    .values()
+                    .any(|topology| topology.node.as_deref() == Some(node_name));
+            on_node
+                && (volume.state.status != VolumeStatus::Online
+                    || replica_rebuild_count(volume) > 0)
+        })
+        .map(|volume| volume.spec.uuid.to_string())
+        .collect();
+
+    Ok(volumes_not_online)
+}
+
 /// Validate if io-engine DaemonSet Pod is running.
 async fn data_plane_pod_is_running(
     node: &str,
@@ -334,15 +752,14 @@ async fn data_plane_pod_is_running(
         .labels(pod_label.as_str())
         .fields(node_name_pod_field.as_str());
 
-    let pod_list: ObjectList<Pod> = k8s_client
-        .pods_api()
-        .list(&io_engine_listparam)
-        .await
-        .context(ListPodsWithLabelAndField {
-            label: pod_label,
-            field: node_name_pod_field,
-            namespace: namespace.clone(),
-        })?;
+    let pod_list: ObjectList<Pod> =
+        kube_with_backoff(|| k8s_client.pods_api().list(&io_engine_listparam))
+            .await
+            .context(ListPodsWithLabelAndField {
+                label: pod_label,
+                field: node_name_pod_field,
+                namespace: namespace.clone(),
+            })?;
 
     if pod_list.items.is_empty() {
         return Ok(false);
@@ -376,37 +793,209 @@ async fn control_plane_is_running(
 ) -> Result<bool> {
     let agent_core_selector_label =
         format!("{AGENT_CORE_LABEL},{CHART_VERSION_LABEL_KEY}={upgrade_to_version}");
-    let pod_list: ObjectList<Pod> = k8s_client
-        .pods_api()
-        .list(&ListParams::default().labels(agent_core_selector_label.as_str()))
-        .await
-        .context(ListPodsWithLabel {
-            label: AGENT_CORE_LABEL.to_string(),
-            namespace: namespace.clone(),
-        })?;
+    let agent_core_listparams = ListParams::default().labels(agent_core_selector_label.as_str());
+    let pod_list: ObjectList<Pod> =
+        kube_with_backoff(|| k8s_client.pods_api().list(&agent_core_listparams))
+            .await
+            .context(ListPodsWithLabel {
+                label: AGENT_CORE_LABEL.to_string(),
+                namespace: namespace.clone(),
+            })?;
     let core_is_ready = all_pods_are_ready(pod_list);
 
     let api_rest_selector_label =
         format!("{API_REST_LABEL},{CHART_VERSION_LABEL_KEY}={upgrade_to_version}");
-    let pod_list: ObjectList<Pod> = k8s_client
-        .pods_api()
-        .list(&ListParams::default().labels(api_rest_selector_label.as_str()))
-        .await
-        .context(ListPodsWithLabel {
-            label: API_REST_LABEL.to_string(),
-            namespace: namespace.clone(),
-        })?;
+    let api_rest_listparams = ListParams::default().labels(api_rest_selector_label.as_str());
+    let pod_list: ObjectList<Pod> =
+        kube_with_backoff(|| k8s_client.pods_api().list(&api_rest_listparams))
+            .await
+            .context(ListPodsWithLabel {
+                label: API_REST_LABEL.to_string(),
+                namespace: namespace.clone(),
+            })?;
     let rest_is_ready = all_pods_are_ready(pod_list);
 
-    let pod_list: ObjectList<Pod> = k8s_client
-        .pods_api()
-        .list(&ListParams::default().labels(ETCD_LABEL))
-        .await
-        .context(ListPodsWithLabel {
-            label: ETCD_LABEL.to_string(),
-            namespace: namespace.clone(),
-        })?;
+    let etcd_listparams = ListParams::default().labels(ETCD_LABEL);
+    let pod_list: ObjectList<Pod> =
+        kube_with_backoff(|| k8s_client.pods_api().list(&etcd_listparams))
+            .await
+            .context(ListPodsWithLabel {
+                label: ETCD_LABEL.to_string(),
+                namespace: namespace.clone(),
+            })?;
     let etcd_is_ready = all_pods_are_ready(pod_list);
 
     Ok(core_is_ready && rest_is_ready && etcd_is_ready)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This builds the volume topology from (node, volumes) pairs.
+    fn topology(nodes: &[(&str, &[&str])]) -> HashMap<String, HashSet<String>> {
+        nodes
+            .iter()
+            .map(|(node, volumes)| {
+                let volumes = volumes.iter().map(ToString::to_string).collect();
+                (node.to_string(), volumes)
+            })
+            .collect()
+    }
+
+    fn node_names<T>(batch: &[(&str, T)]) -> Vec<String> {
+        batch.iter().map(|(node, _)| node.to_string()).collect()
+    }
+
+    #[test]
+    fn next_batch_keeps_nodes_sharing_a_volume_apart() {
+        let mut pending = vec![("node-a", ()), ("node-b", ()), ("node-c", ())];
+        let volumes = topology(&[
+            ("node-a", &["vol-1"]),
+            ("node-b", &["vol-1"]),
+            ("node-c", &[]),
+        ]);
+
+        let batch = next_batch(&mut pending, &volumes, 3);
+        assert_eq!(node_names(&batch), vec!["node-a", "node-c"]);
+        assert_eq!(node_names(&pending), vec!["node-b"]);
+    }
+
+    #[test]
+    fn next_batch_is_bounded_by_max_unavailable() {
+        let mut pending = vec![("node-a", ()), ("node-b", ()), ("node-c", ())];
+        let volumes = topology(&[]);
+
+        let batch = next_batch(&mut pending, &volumes, 2);
+        assert_eq!(node_names(&batch), vec!["node-a", "node-b"]);
+        assert_eq!(node_names(&pending), vec!["node-c"]);
+    }
+
+    #[test]
+    fn next_batch_follows_a_moved_target() {
+        let mut pending = vec![("node-a", ()), ("node-b", ()), ("node-c", ())];
+        // vol-1 has its target on node-a and its replica on node-b.
+        let before = topology(&[
+            ("node-a", &["vol-1"]),
+            ("node-b", &["vol-1"]),
+            ("node-c", &[]),
+        ]);
+        let batch = next_batch(&mut pending, &before, 2);
+        assert_eq!(node_names(&batch), vec!["node-a", "node-c"]);
+
+        // Draining node-c moves the target of vol-2 to node-b, next to a replica on node-d.
+        pending.push(("node-d", ()));
+        let after = topology(&[
+            ("node-a", &["vol-1"]),
+            ("node-b", &["vol-1", "vol-2"]),
+            ("node-d", &["vol-2"]),
+        ]);
+        let batch = next_batch(&mut pending, &after, 2);
+        assert_eq!(node_names(&batch), vec!["node-b"]);
+        assert_eq!(node_names(&pending), vec!["node-d"]);
+    }
+
+    /// This is the placement of a volume from its uuid, target node and replica nodes.
+    fn placement(uuid: &str, target_node: Option<&str>, replica_nodes: &[&str]) -> VolumePlacement {
+        VolumePlacement {
+            uuid: uuid.to_string(),
+            target_node: target_node.map(ToString::to_string),
+            replica_nodes: replica_nodes.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn topology_lists_target_and_replica_nodes() {
+        let topology = VolumeTopology::new(&[
+            placement("vol-1", Some("node-a"), &["node-b", "node-c"]),
+            placement("vol-2", None, &["node-c"]),
+        ]);
+
+        assert_eq!(topology.volumes_by_node["node-a"].len(), 1);
+        assert!(topology.volumes_by_node["node-b"].contains("vol-1"));
+        assert_eq!(topology.volumes_by_node["node-c"].len(), 2);
+    }
+
+    #[test]
+    fn topology_lists_single_replica_volumes_by_replica_node() {
+        let topology = VolumeTopology::new(&[
+            placement("vol-1", Some("node-a"), &["node-b"]),
+            placement("vol-2", Some("node-b"), &["node-b", "node-c"]),
+            placement("vol-3", None, &["node-b"]),
+            placement("vol-4", None, &[]),
+        ]);
+
+        assert_eq!(
+            topology.single_replica_volumes,
+            BTreeMap::from([(
+                "node-b".to_string(),
+                vec!["vol-1".to_string(), "vol-3".to_string()]
+            )])
+        );
+    }
+
+    #[test]
+    fn rebuild_within_the_timeout_is_waited_on() {
+        let timeout = Duration::from_secs(300);
+        let steps: Vec<WaitStep> = [(false, 0), (false, 120), (true, 240)]
+            .into_iter()
+            .map(|(volumes_online, elapsed)| {
+                next_wait_step(volumes_online, Duration::from_secs(elapsed), timeout)
+            })
+            .collect();
+
+        assert_eq!(steps, [WaitStep::Wait, WaitStep::Wait, WaitStep::Done]);
+    }
+
+    #[test]
+    fn rebuild_beyond_the_timeout_times_out() {
+        let timeout = Duration::from_secs(300);
+        assert_eq!(next_wait_step(false, timeout, timeout), WaitStep::TimedOut);
+        assert_eq!(next_wait_step(true, timeout * 2, timeout), WaitStep::Done);
+    }
+
+    #[test]
+    fn pod_never_ready_times_out() {
+        let node_ready_timeout = Duration::from_secs(30);
+        let poll_interval = Duration::from_secs(5);
+
+        // This fake clock advances by the poll interval on each check of a Pod which never
+        // becomes Ready.
+        let mut elapsed = Duration::ZERO;
+        let mut checks = 0;
+        loop {
+            checks += 1;
+            match next_wait_step(false, elapsed, node_ready_timeout) {
+                WaitStep::Wait => elapsed += poll_interval,
+                step => {
+                    assert_eq!(step, WaitStep::TimedOut);
+                    break;
+                }
+            }
+        }
+        assert_eq!(elapsed, node_ready_timeout);
+        assert_eq!(checks, 7);
+
+        let error = NodeReadyTimeout {
+            node: "node-1",
+            elapsed,
+            phase: "mayastor-io-engine-x7k2p=Pending",
+        }
+        .build();
+        assert!(matches!(error, Error::NodeReadyTimeout { .. }));
+        assert_eq!(
+            error.to_string(),
+            "Timed out waiting for the io-engine Pod on Node 'node-1' to become Ready, after 30s \
+            (last observed Pod phase: mayastor-io-engine-x7k2p=Pending)"
+        );
+    }
+
+    #[test]
+    fn pod_ready_before_the_timeout_is_done() {
+        let node_ready_timeout = Duration::from_secs(30);
+        assert_eq!(
+            next_wait_step(true, Duration::from_secs(10), node_ready_timeout),
+            WaitStep::Done
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    common::{
+        constants::{DRAIN_FOR_UPGRADE, PRODUCT},
+        error::{
+            DrainStorageNode, EmptyStorageNodeSpec, GetStorageNode, Result, StorageNodeUncordon,
+        },
+        rest_client::RestClientSet,
+    },
+    upgrade::utils::list_all_volumes,
+};
+use openapi::models::CordonDrainState;
+use snafu::ResultExt;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Move the volume targets off a storage Node, ahead of the restart of its io-engine Pod. The
+/// control-plane fails the targets over to other Nodes when the Node is drained. The Node is
+/// always drained, so that no volume target is placed on it until its io-engine Pod is restarted.
+/// If there is a grace period, the drain is only waited on for that long.
+pub(crate) async fn drain_node(
+    node_id: &str,
+    rest_client: &RestClientSet,
+    grace_period: Option<Duration>,
+) -> Result<()> {
+    info!(
+        node.id = %node_id,
+        "Draining {PRODUCT} Node to move volume targets off of it"
+    );
+    drain_storage_node(node_id, rest_client, grace_period).await
+}
+
+/// This is the state of the upgrade's drain of a storage Node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UpgradeDrain {
+    /// The Node does not carry the upgrade's drain label.
+    NotStarted,
+    /// The Node carries the upgrade's drain label, and is being drained.
+    Draining,
+    /// The Node carries the upgrade's drain label, and is drained.
+    Drained,
+}
+
+impl UpgradeDrain {
+    /// This is the state of the upgrade's drain, from the cordon and drain state of the Node.
+    fn of(state: Option<&CordonDrainState>) -> Self {
+        match state {
+            Some(CordonDrainState::drainingstate(drain_state))
+                if drain_state
+                    .drainlabels
+                    .iter()
+                    .any(|l| l.eq(DRAIN_FOR_UPGRADE)) =>
+            {
+                Self::Draining
+            }
+            Some(CordonDrainState::drainedstate(drain_state))
+                if drain_state
+                    .drainlabels
+                    .iter()
+                    .any(|l| l.eq(DRAIN_FOR_UPGRADE)) =>
+            {
+                Self::Drained
+            }
+            _ => Self::NotStarted,
+        }
+    }
+}
+
+/// This is the next step of the wait for the drain of a storage Node.
+#[derive(Debug, PartialEq)]
+enum DrainStep {
+    /// The drain label has to be applied to the Node.
+    Start,
+    /// The drain is still moving volume targets off the Node.
+    Wait,
+    /// The Node hosts no volume targets anymore.
+    Done,
+    /// The drain outlasted the grace period, the upgrade goes ahead without it.
+    GracePeriodElapsed,
+}
+
+/// This decides the next step of the drain wait. A Node which is being drained, but which hosts
+/// no volume targets, is done without waiting for the control-plane to mark it drained.
+fn next_drain_step(
+    drain: UpgradeDrain,
+    hosts_targets: bool,
+    elapsed: Duration,
+    grace_period: Option<Duration>,
+) -> DrainStep {
+    match drain {
+        UpgradeDrain::NotStarted => DrainStep::Start,
+        UpgradeDrain::Drained => DrainStep::Done,
+        UpgradeDrain::Draining if !hosts_targets => DrainStep::Done,
+        UpgradeDrain::Draining
+            if grace_period.is_some_and(|grace_period| elapsed >= grace_period) =>
+        {
+            DrainStep::GracePeriodElapsed
+        }
+        UpgradeDrain::Draining => DrainStep::Wait,
+    }
+}
+
+/// List the uuids of the volumes whose targets are on a storage Node.
+async fn volume_targets_on_node(node_id: &str, rest_client: &RestClientSet) -> Result<Vec<String>> {
+    let targets = list_all_volumes(rest_client)
+        .await?
+        .iter()
+        .filter(|volume| {
+            volume
+                .state
+                .target
+                .as_ref()
+                .is_some_and(|target| target.node.eq(node_id))
+        })
+        .map(|volume| volume.spec.uuid.to_string())
+        .collect();
+
+    Ok(targets)
+}
+
+/// Uncordon storage Node.
+pub(crate) async fn uncordon_node(node_id: &str, rest_client: &RestClientSet) -> Result<()> {
+    let drain_label_for_upgrade: String = DRAIN_FOR_UPGRADE.to_string();
+    let sleep_duration = Duration::from_secs(1_u64);
+    loop {
+        let storage_node =
+            rest_client
+                .nodes_api()
+                .get_node(node_id)
+                .await
+                .context(GetStorageNode {
+                    node_id: node_id.to_string(),
+                })?;
+
+        match storage_node
+            .into_body()
+            .spec
+            .ok_or(
+                EmptyStorageNodeSpec {
+                    node_id: node_id.to_string(),
+                }
+                .build(),
+            )?
+            .cordondrainstate
+        {
+            // The drain label is also removed from a Node whose drain outlasted the grace period.
+            Some(
+                CordonDrainState::drainedstate(drain_state)
+                | CordonDrainState::drainingstate(drain_state),
+            ) if drain_state.drainlabels.contains(&drain_label_for_upgrade) => {
+                rest_client
+                    .nodes_api()
+                    .delete_node_cordon(node_id, DRAIN_FOR_UPGRADE)
+                    .await
+                    .context(StorageNodeUncordon {
+                        node_id: node_id.to_string(),
+                    })?;
+
+                info!(node.id = %node_id,
+                    label = %DRAIN_FOR_UPGRADE,
+                    "Removed drain label from {PRODUCT} Node"
+                );
+            }
+            _ => return Ok(()),
+        }
+        tokio::time::sleep(sleep_duration).await;
+    }
+}
+
+/// Issue the node drain command on the node, and wait for the drain to complete, or for the
+/// grace period to elapse.
+async fn drain_storage_node(
+    node_id: &str,
+    rest_client: &RestClientSet,
+    grace_period: Option<Duration>,
+) -> Result<()> {
+    let sleep_duration = Duration::from_secs(5_u64);
+    let started_at = Instant::now();
+    loop {
+        let storage_node =
+            rest_client
+                .nodes_api()
+                .get_node(node_id)
+                .await
+                .context(GetStorageNode {
+                    node_id: node_id.to_string(),
+                })?;
+
+        let drain = UpgradeDrain::of(
+            storage_node
+                .into_body()
+                .spec
+                .ok_or(
+                    EmptyStorageNodeSpec {
+                        node_id: node_id.to_string(),
+                    }
+                    .build(),
+                )?
+                .cordondrainstate
+                .as_ref(),
+        );
+        // The volume targets are only listed while the drain is in progress.
+        let hosts_targets = drain.eq(&UpgradeDrain::Draining)
+            && !volume_targets_on_node(node_id, rest_client)
+                .await?
+                .is_empty();
+
+        match next_drain_step(drain, hosts_targets, started_at.elapsed(), grace_period) {
+            DrainStep::Start => {
+                rest_client
+                    .nodes_api()
+                    .put_node_drain(node_id, DRAIN_FOR_UPGRADE)
+                    .await
+                    .context(DrainStorageNode {
+                        node_id: node_id.to_string(),
+                    })?;
+
+                info!(node.id = %node_id, "Drain started for {PRODUCT} Node");
+            }
+            DrainStep::Wait => {
+                info!(node.id = %node_id, "Waiting for {PRODUCT} Node drain to complete");
+                // Wait for node drain to complete.
+                tokio::time::sleep(sleep_duration).await;
+            }
+            DrainStep::Done => {
+                info!(node.id = %node_id, "Drain completed for {PRODUCT} Node");
+                return Ok(());
+            }
+            DrainStep::GracePeriodElapsed => {
+                warn!(
+                    node.id = %node_id,
+                    "{PRODUCT} Node drain did not complete within the grace period of {}, \
+                    continuing without it",
+                    humantime::format_duration(grace_period.unwrap_or_default())
+                );
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn undrained_node_is_drained_even_without_targets() {
+        assert_eq!(
+            next_drain_step(UpgradeDrain::NotStarted, false, Duration::ZERO, None),
+            DrainStep::Start
+        );
+    }
+
+    #[test]
+    fn draining_node_without_targets_is_done() {
+        assert_eq!(
+            next_drain_step(UpgradeDrain::Draining, false, Duration::ZERO, None),
+            DrainStep::Done
+        );
+    }
+
+    #[test]
+    fn draining_node_with_targets_is_waited_on() {
+        assert_eq!(
+            next_drain_step(
+                UpgradeDrain::Draining,
+                true,
+                Duration::from_secs(59),
+                Some(GRACE_PERIOD)
+            ),
+            DrainStep::Wait
+        );
+        assert_eq!(
+            next_drain_step(
+                UpgradeDrain::Draining,
+                true,
+                Duration::from_secs(3600),
+                None
+            ),
+            DrainStep::Wait
+        );
+    }
+
+    #[test]
+    fn drain_wait_ends_with_the_grace_period() {
+        assert_eq!(
+            next_drain_step(
+                UpgradeDrain::Draining,
+                true,
+                GRACE_PERIOD,
+                Some(GRACE_PERIOD)
+            ),
+            DrainStep::GracePeriodElapsed
+        );
+    }
+
+    #[test]
+    fn drained_node_is_done() {
+        assert_eq!(
+            next_drain_step(
+                UpgradeDrain::Drained,
+                true,
+                Duration::ZERO,
+                Some(GRACE_PERIOD)
+            ),
+            DrainStep::Done
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    common::{
+        error::{
+            ListStoragePools, Result, SingleReplicaVolumesOnNode, UnhealthyPoolsPresent,
+            UnhealthyVolumesPresent,
+        },
+        rest_client::RestClientSet,
+    },
+    upgrade::utils::{list_all_volumes, list_volume_placements, VolumePlacement},
+};
+use openapi::models::{PoolStatus, VolumeStatus};
+use snafu::{ensure, ResultExt};
+use std::collections::{BTreeMap, HashSet};
+use tracing::{info, warn};
+
+/// This fails if any of the storage volumes is not Online. Upgrading while volumes are already
+/// degraded risks the availability of their data.
+pub(crate) async fn check_volumes(rest_client: &RestClientSet) -> Result<()> {
+    let volumes = list_all_volumes(rest_client).await?;
+    ensure_volumes_online(
+        volumes
+            .iter()
+            .map(|volume| (volume.spec.uuid.to_string(), &volume.state.status)),
+    )?;
+
+    info!("All volumes are Online");
+    Ok(())
+}
+
+/// This is like check_volumes, for the uuids and the states of the volumes.
+fn ensure_volumes_online<'a>(
+    volumes: impl IntoIterator<Item = (String, &'a VolumeStatus)>,
+) -> Result<()> {
+    let unhealthy_volumes: Vec<String> = volumes
+        .into_iter()
+        .filter(|(_, status)| *status != &VolumeStatus::Online)
+        .map(|(uuid, _)| uuid)
+        .collect();
+
+    ensure!(
+        unhealthy_volumes.is_empty(),
+        UnhealthyVolumesPresent {
+            volumes: unhealthy_volumes
+        }
+    );
+
+    Ok(())
+}
+
+/// This lists the uuids of the volumes which have only one replica, by the node of the replica.
+/// These volumes are unavailable while the io-engine Pod on that node restarts.
+pub(crate) async fn single_replica_volumes(
+    rest_client: &RestClientSet,
+) -> Result<BTreeMap<String, Vec<String>>> {
+    Ok(single_replica_volumes_by_node(
+        list_volume_placements(rest_client).await?.as_slice(),
+    ))
+}
+
+/// This is like single_replica_volumes, for the placements of the volumes.
+pub(crate) fn single_replica_volumes_by_node(
+    placements: &[VolumePlacement],
+) -> BTreeMap<String, Vec<String>> {
+    let mut volumes_by_node: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for placement in placements {
+        if let [node] = placement.replica_nodes.as_slice() {
+            volumes_by_node
+                .entry(node.clone())
+                .or_default()
+                .push(placement.uuid.clone());
+        }
+    }
+
+    volumes_by_node
+}
+
+/// This warns about the volumes whose only replica is on a node whose io-engine Pod is to be
+/// restarted, for each of the nodes. If fail_on_single_replica is set, this fails instead.
+pub(crate) async fn check_single_replica_volumes(
+    rest_client: &RestClientSet,
+    skipped_nodes: &HashSet<String>,
+    fail_on_single_replica: bool,
+) -> Result<()> {
+    let volumes_by_node = single_replica_volumes(rest_client).await?;
+
+    for (node, volumes) in volumes_by_node {
+        if skipped_nodes.contains(&node) {
+            continue;
+        }
+        let error = SingleReplicaVolumesOnNode { node, volumes }.build();
+        if fail_on_single_replica {
+            return Err(error);
+        }
+        warn!("{error}");
+    }
+
+    Ok(())
+}
+
+/// This fails if any of the storage pools is not Online.
+pub(crate) async fn check_pools(rest_client: &RestClientSet) -> Result<()> {
+    let pools = rest_client
+        .pools_api()
+        .get_pools()
+        .await
+        .context(ListStoragePools)?
+        .into_body();
+
+    ensure_pools_online(pools.iter().map(|pool| {
+        (
+            pool.id.clone(),
+            pool.state.as_ref().map(|state| &state.status),
+        )
+    }))?;
+
+    info!("All pools are Online");
+    Ok(())
+}
+
+/// This is like check_pools, for the ids and the states of the pools. A pool without a state is
+/// not Online.
+fn ensure_pools_online<'a>(
+    pools: impl IntoIterator<Item = (String, Option<&'a PoolStatus>)>,
+) -> Result<()> {
+    let unhealthy_pools: Vec<String> = pools
+        .into_iter()
+        .filter(|(_, status)| *status != Some(&PoolStatus::Online))
+        .map(|(id, _)| id)
+        .collect();
+
+    ensure!(
+        unhealthy_pools.is_empty(),
+        UnhealthyPoolsPresent {
+            pools: unhealthy_pools
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    #[test]
+    fn online_volumes_are_healthy() {
+        let online = VolumeStatus::Online;
+        assert!(ensure_volumes_online([("vol-1".to_string(), &online)]).is_ok());
+        assert!(ensure_volumes_online([]).is_ok());
+    }
+
+    #[test]
+    fn mixed_volume_states_list_the_volumes_not_online() {
+        let statuses = [
+            ("vol-1", VolumeStatus::Online),
+            ("vol-2", VolumeStatus::Degraded),
+            ("vol-3", VolumeStatus::Online),
+            ("vol-4", VolumeStatus::Faulted),
+            ("vol-5", VolumeStatus::Unknown),
+            ("vol-6", VolumeStatus::Shutdown),
+        ];
+        let result = ensure_volumes_online(
+            statuses
+                .iter()
+                .map(|(uuid, status)| (uuid.to_string(), status)),
+        );
+
+        let Err(Error::UnhealthyVolumesPresent { volumes }) = result else {
+            panic!("expected UnhealthyVolumesPresent, got {result:?}");
+        };
+        assert_eq!(volumes, ["vol-2", "vol-4", "vol-5", "vol-6"]);
+    }
+
+    #[test]
+    fn mixed_pool_states_list_the_pools_not_online() {
+        let (online, degraded) = (PoolStatus::Online, PoolStatus::Degraded);
+        let result = ensure_pools_online([
+            ("pool-1".to_string(), Some(&online)),
+            ("pool-2".to_string(), Some(&degraded)),
+            ("pool-3".to_string(), None),
+        ]);
+
+        let Err(Error::UnhealthyPoolsPresent { pools }) = result else {
+            panic!("expected UnhealthyPoolsPresent, got {result:?}");
+        };
+        assert_eq!(pools, ["pool-2", "pool-3"]);
+    }
+}
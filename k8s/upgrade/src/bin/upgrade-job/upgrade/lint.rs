@@ -0,0 +1,184 @@
+use crate::{
+    common::{
+        constants::UPGRADE_VALUES_SOURCE,
+        error::{Error, InvalidValuesFile, ReadingFile, Result, YamlParseFromSlice},
+    },
+    helm::{
+        chart::{deserialize_with_key_path, Agents, Chart, CoreValues, FromPath},
+        merge::deep_merge,
+        overrides::load_values_file,
+        schema::validate_values_against_chart_schema,
+        values_validation::ThinCommitmentValues,
+    },
+};
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::{fs, path::Path};
+use tracing::{error, info};
+
+/// This validates a values file against the helm chart in the chart directory, without a cluster.
+/// The values file is merged on top of the helm chart's values, like with 'helm install -f', and
+/// the merged values are validated against the helm chart's values schema and for the consistency
+/// of the thin-provisioning options. All of the problems are logged, and are carried by the error.
+pub(crate) fn validate_values(chart_dir: &Path, values_file: &Path) -> Result<()> {
+    let chart = Chart::from_path(chart_dir.join("Chart.yaml").as_path())?;
+    let default_values = read_yaml(chart_dir.join("values.yaml").as_path())?;
+    let values = deep_merge(default_values, load_values_file(values_file)?);
+
+    let mut problems: Vec<String> = Vec::new();
+    match validate_values_against_chart_schema(chart_dir, &values) {
+        Err(Error::ValuesSchemaViolations { errors }) => problems.extend(errors),
+        result => result?,
+    }
+
+    if let Err(error) = deserialize_with_key_path::<CoreValues, _>(values.clone()) {
+        problems.push(error.to_string());
+    }
+
+    // The thin-provisioning options are checked on their own, so that they are checked even if
+    // other helm values fail to deserialize. They are checked whenever they are set, e.g. with
+    // development helm charts.
+    match deserialize_with_key_path::<AgentsValues, _>(values) {
+        Ok(AgentsValues { agents }) => {
+            if let Err(error) = ThinCommitmentValues::try_from_agents_if_present(
+                &agents,
+                chart.version(),
+                UPGRADE_VALUES_SOURCE,
+            )
+            .and_then(|thin_commitment| {
+                thin_commitment.map_or(Ok(()), |thin_commitment| thin_commitment.validate())
+            }) {
+                problems.push(error.to_string());
+            }
+        }
+        Err(error) => {
+            let problem = error.to_string();
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+    }
+
+    for problem in problems.iter() {
+        error!(values_file = %values_file.display(), "{problem}");
+    }
+    ensure!(
+        problems.is_empty(),
+        InvalidValuesFile {
+            filepath: values_file.to_path_buf(),
+            problems
+        }
+    );
+
+    info!(
+        values_file = %values_file.display(),
+        chart.version = %chart.version(),
+        "The values file is valid for the {} helm chart",
+        chart.name()
+    );
+    Ok(())
+}
+
+/// This is the yaml object 'agents' of the helm values, without the rest of the helm values.
+#[derive(Deserialize)]
+struct AgentsValues {
+    #[serde(default)]
+    agents: Agents,
+}
+
+/// This reads and parses a yaml file.
+fn read_yaml(filepath: &Path) -> Result<serde_yaml::Value> {
+    let yaml = fs::read(filepath).context(ReadingFile {
+        filepath: filepath.to_path_buf(),
+    })?;
+
+    serde_yaml::from_slice(yaml.as_slice()).context(YamlParseFromSlice {
+        input_yaml: String::from_utf8_lossy(yaml.as_slice()).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+
+    /// This is a copy of the helm chart's Chart.yaml and values.yaml, with a values schema which
+    /// only allows the known image pull policies.
+    fn chart_dir() -> TempDir {
+        let chart_dir = TempDir::new().unwrap();
+        fs::write(
+            chart_dir.path().join("Chart.yaml"),
+            include_str!("../../../../../../chart/Chart.yaml"),
+        )
+        .unwrap();
+        fs::write(
+            chart_dir.path().join("values.yaml"),
+            include_str!("../../../../../../chart/values.yaml"),
+        )
+        .unwrap();
+        fs::write(
+            chart_dir.path().join("values.schema.json"),
+            r#"{"properties": {"image": {"properties": {"pullPolicy": {
+                "enum": ["Always", "IfNotPresent", "Never"]
+            }}}}}"#,
+        )
+        .unwrap();
+        chart_dir
+    }
+
+    /// This writes the yaml to a values file.
+    fn values_file(yaml: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(yaml.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn clean_values_file_is_valid() {
+        let chart_dir = chart_dir();
+        let values = values_file(
+            "image: {pullPolicy: IfNotPresent}\n\
+            agents: {core: {capacity: {thin: {poolCommitment: \"300%\"}}}}\n",
+        );
+
+        assert!(validate_values(chart_dir.path(), values.path()).is_ok());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let chart_dir = chart_dir();
+        let values = values_file(
+            "image: {pullPolicy: Sometimes, tag: [v2.5.0]}\n\
+            agents: {core: {capacity: {thin: {poolCommitment: abc}}}}\n",
+        );
+
+        let Err(Error::InvalidValuesFile { problems, .. }) =
+            validate_values(chart_dir.path(), values.path())
+        else {
+            panic!("expected the values file to be invalid");
+        };
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems
+            .iter()
+            .any(|problem| problem.contains("/image/pullPolicy")));
+        assert!(problems.iter().any(|problem| problem.contains("image.tag")));
+        assert!(problems.iter().any(|problem| problem.contains("abc")));
+    }
+
+    #[test]
+    fn inverted_commitments_are_reported() {
+        let chart_dir = chart_dir();
+        let values = values_file(
+            "agents: {core: {capacity: {thin: \
+            {volumeCommitment: \"40%\", volumeCommitmentInitial: \"60%\"}}}}\n",
+        );
+
+        let Err(Error::InvalidValuesFile { problems, .. }) =
+            validate_values(chart_dir.path(), values.path())
+        else {
+            panic!("expected the values file to be invalid");
+        };
+        assert_eq!(problems.len(), 1, "{problems:?}");
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{
+    common::error::{ErrorCategory, MetricsRegistration, MetricsServerBind, Result},
+    opts::CliArgs,
+    upgrade::progress::{Progress, ProgressReporter, ProgressState},
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server, StatusCode,
+};
+use prometheus::{Encoder, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use snafu::ResultExt;
+use std::{convert::Infallible, fmt, net::SocketAddr, sync::Arc};
+use tracing::{error, info};
+
+/// These are the phases of an upgrade, or of a rollback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum UpgradePhase {
+    /// The upgrade is being validated.
+    Validating,
+    /// The helm release is being upgraded, or rolled back.
+    ControlPlane,
+    /// The io-engine Pods are being restarted.
+    DataPlane,
+    /// The upgrade has succeeded.
+    Completed,
+    /// The upgrade has failed.
+    Failed,
+}
+
+impl UpgradePhase {
+    /// These are all of the phases, in the order which an upgrade goes through them.
+    const ALL: [UpgradePhase; 5] = [
+        Self::Validating,
+        Self::ControlPlane,
+        Self::DataPlane,
+        Self::Completed,
+        Self::Failed,
+    ];
+}
+
+impl fmt::Display for UpgradePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            Self::Validating => "Validating",
+            Self::ControlPlane => "ControlPlane",
+            Self::DataPlane => "DataPlane",
+            Self::Completed => "Completed",
+            Self::Failed => "Failed",
+        };
+        write!(f, "{phase}")
+    }
+}
+
+/// These are the Prometheus metrics of the upgrade.
+pub(crate) struct UpgradeMetrics {
+    registry: Registry,
+    /// The number of nodes whose io-engine Pods are upgraded in this run.
+    nodes_total: IntGauge,
+    /// The number of nodes whose io-engine Pods have been upgraded in this run.
+    nodes_completed: IntGauge,
+    /// This is 1 for the current phase of the upgrade, and 0 for the other phases.
+    current_phase: IntGaugeVec,
+    /// This is 1 for the state of the latest node state transition, and 0 for the other states.
+    node_state: IntGaugeVec,
+    /// The number of failures, by error category.
+    failures_total: IntCounterVec,
+}
+
+impl UpgradeMetrics {
+    /// This creates and registers the metrics.
+    pub(crate) fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let nodes_total = IntGauge::new(
+            "upgrade_nodes_total",
+            "Number of nodes whose io-engine Pods are upgraded",
+        )
+        .context(MetricsRegistration)?;
+        let nodes_completed = IntGauge::new(
+            "upgrade_nodes_completed",
+            "Number of nodes whose io-engine Pods have been upgraded",
+        )
+        .context(MetricsRegistration)?;
+        let current_phase = IntGaugeVec::new(
+            Opts::new("upgrade_current_phase", "Current phase of the upgrade"),
+            &["phase"],
+        )
+        .context(MetricsRegistration)?;
+        let node_state = IntGaugeVec::new(
+            Opts::new(
+                "upgrade_node_state",
+                "Data-plane upgrade state of the latest node state transition",
+            ),
+            &["state"],
+        )
+        .context(MetricsRegistration)?;
+        let failures_total = IntCounterVec::new(
+            Opts::new("upgrade_failures_total", "Number of upgrade failures"),
+            &["category"],
+        )
+        .context(MetricsRegistration)?;
+
+        registry
+            .register(Box::new(nodes_total.clone()))
+            .context(MetricsRegistration)?;
+        registry
+            .register(Box::new(nodes_completed.clone()))
+            .context(MetricsRegistration)?;
+        registry
+            .register(Box::new(current_phase.clone()))
+            .context(MetricsRegistration)?;
+        registry
+            .register(Box::new(node_state.clone()))
+            .context(MetricsRegistration)?;
+        registry
+            .register(Box::new(failures_total.clone()))
+            .context(MetricsRegistration)?;
+
+        Ok(Self {
+            registry,
+            nodes_total,
+            nodes_completed,
+            current_phase,
+            node_state,
+            failures_total,
+        })
+    }
+
+    /// This sets the current phase of the upgrade.
+    pub(crate) fn set_phase(&self, phase: UpgradePhase) {
+        for each in UpgradePhase::ALL {
+            self.current_phase
+                .with_label_values(&[each.to_string().as_str()])
+                .set(i64::from(each.eq(&phase)));
+        }
+    }
+
+    /// This updates the gauges with a node state transition.
+    fn observe(&self, progress: &Progress) {
+        let completed = progress.nodes_completed() as i64;
+        self.nodes_completed.set(completed);
+        self.nodes_total
+            .set(completed + progress.nodes_remaining() as i64);
+
+        for state in ProgressState::ALL {
+            self.node_state
+                .with_label_values(&[state.to_string().as_str()])
+                .set(i64::from(state.eq(&progress.state())));
+        }
+    }
+
+    /// This counts a failure of the upgrade.
+    fn record_failure(&self, category: ErrorCategory) {
+        self.failures_total
+            .with_label_values(&[category.to_string().as_str()])
+            .inc();
+    }
+
+    /// This sets the final phase of the upgrade, and counts the failure if it has failed.
+    pub(crate) fn record_outcome(&self, result: &Result<()>) {
+        match result {
+            Ok(()) => self.set_phase(UpgradePhase::Completed),
+            Err(error) => {
+                self.record_failure(error.category());
+                self.set_phase(UpgradePhase::Failed);
+            }
+        }
+    }
+
+    /// This renders the metrics in the Prometheus text format.
+    fn render(&self) -> std::result::Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// This creates the metrics and serves them, if they are enabled with --metrics-port. The upgrade
+/// starts out in the Validating phase.
+pub(crate) fn start_metrics(opts: &CliArgs) -> Result<Option<Arc<UpgradeMetrics>>> {
+    let Some(port) = opts.metrics_port() else {
+        return Ok(None);
+    };
+
+    let metrics = Arc::new(UpgradeMetrics::new()?);
+    metrics.set_phase(UpgradePhase::Validating);
+    serve_metrics(metrics.clone(), port)?;
+    Ok(Some(metrics))
+}
+
+/// This serves the metrics over HTTP on a port, for as long as the upgrade-job runs.
+fn serve_metrics(metrics: Arc<UpgradeMetrics>, port: u16) -> Result<()> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_request| {
+                let response = match metrics.render() {
+                    Ok(body) => Response::new(Body::from(body)),
+                    Err(error) => {
+                        error!(%error, "Failed to render the upgrade metrics");
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        response
+                    }
+                };
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .context(MetricsServerBind { port })?
+        .serve(make_service);
+    info!(%address, "Serving upgrade metrics");
+    tokio::spawn(async move {
+        if let Err(error) = server.await {
+            error!(%error, "Upgrade metrics server failed");
+        }
+    });
+
+    Ok(())
+}
+
+/// This updates the metrics at each state transition, and passes the progress on to another
+/// reporter.
+pub(crate) struct MetricsProgressReporter {
+    inner: Box<dyn ProgressReporter>,
+    metrics: Arc<UpgradeMetrics>,
+}
+
+impl MetricsProgressReporter {
+    /// This creates a MetricsProgressReporter which wraps another reporter.
+    pub(crate) fn new(inner: Box<dyn ProgressReporter>, metrics: Arc<UpgradeMetrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl ProgressReporter for MetricsProgressReporter {
+    fn report(&self, progress: &Progress) {
+        self.metrics.observe(progress);
+        self.inner.report(progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use prometheus::proto::MetricType;
+
+    /// This reporter drops the progress.
+    struct NoopProgressReporter;
+
+    impl ProgressReporter for NoopProgressReporter {
+        fn report(&self, _progress: &Progress) {}
+    }
+
+    /// This is the value of a gauge or counter of a metric family, by its label value.
+    fn labelled_value(metrics: &UpgradeMetrics, name: &str, label: &str) -> f64 {
+        let family = metrics
+            .registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .unwrap();
+        let metric = family
+            .get_metric()
+            .iter()
+            .find(|metric| metric.get_label()[0].get_value() == label)
+            .unwrap();
+        if family.get_field_type() == MetricType::COUNTER {
+            metric.get_counter().get_value()
+        } else {
+            metric.get_gauge().get_value()
+        }
+    }
+
+    #[test]
+    fn gauges_follow_the_node_completions() {
+        let metrics = Arc::new(UpgradeMetrics::new().unwrap());
+        let reporter =
+            MetricsProgressReporter::new(Box::new(NoopProgressReporter), metrics.clone());
+
+        reporter.report(&Progress::new("node-1", ProgressState::Pending, 0, 3));
+        reporter.report(&Progress::new("node-1", ProgressState::Completed, 1, 2));
+        reporter.report(&Progress::new("node-2", ProgressState::Completed, 2, 1));
+        reporter.report(&Progress::new("node-3", ProgressState::DrainingNode, 2, 1));
+
+        assert_eq!(metrics.nodes_total.get(), 3);
+        assert_eq!(metrics.nodes_completed.get(), 2);
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_node_state", "DrainingNode"),
+            1.0
+        );
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_node_state", "Completed"),
+            0.0
+        );
+    }
+
+    #[test]
+    fn phase_gauge_has_the_current_phase_only() {
+        let metrics = UpgradeMetrics::new().unwrap();
+        metrics.set_phase(UpgradePhase::Validating);
+        metrics.set_phase(UpgradePhase::DataPlane);
+
+        for phase in UpgradePhase::ALL {
+            let expected = if phase == UpgradePhase::DataPlane {
+                1.0
+            } else {
+                0.0
+            };
+            assert_eq!(
+                labelled_value(
+                    &metrics,
+                    "upgrade_current_phase",
+                    phase.to_string().as_str()
+                ),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn failed_outcome_counts_the_failure_by_category() {
+        let metrics = UpgradeMetrics::new().unwrap();
+        metrics.set_phase(UpgradePhase::ControlPlane);
+        let error = Error::UpgradeNotConfirmed;
+        let category = error.category().to_string();
+        metrics.record_outcome(&Err(error));
+
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_failures_total", category.as_str()),
+            1.0
+        );
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_current_phase", "Failed"),
+            1.0
+        );
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_current_phase", "ControlPlane"),
+            0.0
+        );
+
+        let rendered = String::from_utf8(metrics.render().unwrap()).unwrap();
+        assert!(rendered.contains("upgrade_current_phase{phase=\"Failed\"} 1"));
+    }
+
+    #[test]
+    fn succeeded_outcome_is_completed() {
+        let metrics = UpgradeMetrics::new().unwrap();
+        metrics.set_phase(UpgradePhase::DataPlane);
+        metrics.record_outcome(&Ok(()));
+
+        assert_eq!(
+            labelled_value(&metrics, "upgrade_current_phase", "Completed"),
+            1.0
+        );
+    }
+}
@@ -1,19 +1,18 @@
-use crate::{
-    common::{
-        constants::CHART_VERSION_LABEL_KEY,
-        error::{
-            ListDeploymentsWithLabel, NoRestDeployment, NoVersionLabelInDeployment, ReadingFile,
-            Result, SemverParse, YamlParseBufferForUnsupportedVersion, YamlParseFromFile,
-        },
-        kube_client::KubeClientSet,
+use crate::common::{
+    constants::{CHART_VERSION_LABEL_KEY, MIN_UPGRADABLE_FROM},
+    error::{
+        DowngradeNotSupported, InstalledVersionTooOldToUpgrade, ListDeploymentsWithLabel,
+        NoRestDeployment, NoVersionLabelInDeployment, PrereleaseRegression, Result, SemverParse,
+        UnsupportedUpgradePath, YamlParseBufferForCompatibilityMatrix,
+        YamlParseBufferForKnownVersions, YamlParseBufferForUnsupportedVersion,
     },
-    helm::chart::Chart,
+    kube_client::KubeClientSet,
 };
 use kube_client::{api::ListParams, ResourceExt};
 use semver::Version;
 use serde::Deserialize;
 use snafu::{ensure, ResultExt};
-use std::{fs, path::PathBuf};
+use tracing::info;
 use utils::API_REST_LABEL;
 
 /// Validates the upgrade path from 'from' Version to 'to' Version for the Core helm chart.
@@ -25,16 +24,94 @@ pub(crate) fn is_valid_for_core_chart(from: &Version) -> Result<bool> {
     Ok(!unsupported_versions.contains(from))
 }
 
-/// Generate a semver::Version from the helm chart in local directory.
-pub(crate) fn version_from_chart_yaml_file(path: PathBuf) -> Result<Version> {
-    let values_yaml = fs::read(path.as_path()).context(ReadingFile {
-        filepath: path.clone(),
-    })?;
+/// Checks the upgrade from 'from' Version to 'to' Version against the compatibility matrix of
+/// tested upgrades. Upgrades which the matrix does not list are checked by semver rules, i.e.
+/// they are supported if they are not downgrades and do not skip a major version.
+pub(crate) fn is_supported_pair(from: &Version, to: &Version) -> Result<bool> {
+    Ok(CompatibilityMatrix::embedded()?.is_supported_pair(from, to))
+}
+
+/// Validates the upgrade path from 'from' Version to 'to' Version by semver rules. Downgrades and
+/// upgrades which skip a major version are rejected. Upgrades from a release to a pre-release are
+/// only allowed if 'allow_prerelease' is set. Upgrades to the same version pass, they are only
+/// validated when they are forced with --force-upgrade, otherwise the helm release is already
+/// upgraded and the helm upgrade is skipped.
+pub(crate) fn validate(from: &Version, to: &Version, allow_prerelease: bool) -> Result<()> {
+    ensure!(
+        to.ge(from),
+        DowngradeNotSupported {
+            from_version: from.to_string(),
+            to_version: to.to_string()
+        }
+    );
+
+    ensure!(
+        allow_prerelease || !is_prerelease_regression(from, to),
+        PrereleaseRegression {
+            from_version: from.to_string(),
+            to_version: to.to_string()
+        }
+    );
+
+    ensure!(
+        to.major - from.major <= 1,
+        UnsupportedUpgradePath {
+            from_version: from.to_string(),
+            to_version: to.to_string()
+        }
+    );
 
-    let to_chart: Chart = serde_yaml::from_slice(values_yaml.as_slice())
-        .context(YamlParseFromFile { filepath: path })?;
+    Ok(())
+}
+
+/// Validates that the installed appVersion is not older than MIN_UPGRADABLE_FROM. The pre-releases
+/// of MIN_UPGRADABLE_FROM may be upgraded from as well.
+pub(crate) fn validate_minimum_from(installed: &Version) -> Result<()> {
+    let release = Version::new(installed.major, installed.minor, installed.patch);
+    ensure!(
+        release.ge(&MIN_UPGRADABLE_FROM),
+        InstalledVersionTooOldToUpgrade {
+            installed: installed.clone(),
+            minimum: MIN_UPGRADABLE_FROM,
+        }
+    );
+
+    Ok(())
+}
 
-    Ok(to_chart.version().clone())
+/// Returns the catalog of released versions of the Core helm chart. The catalog is generated from
+/// the published helm chart index by scripts/helm/generate-known-versions.sh, which runs when a
+/// release branch is prepared.
+pub(crate) fn known_versions() -> Result<Vec<Version>> {
+    let known_version_buf =
+        &include_bytes!("../../../../../upgrade/config/known_versions.yaml")[..];
+    let known_versions =
+        KnownVersions::try_from(known_version_buf).context(YamlParseBufferForKnownVersions)?;
+    Ok(known_versions.known_versions)
+}
+
+/// Returns the versions from the 'known' catalog of releases which lie strictly between the
+/// 'from' Version and the 'to' Version, sorted in ascending order.
+pub(crate) fn intermediate_versions(
+    from: &Version,
+    to: &Version,
+    known: &[Version],
+) -> Vec<Version> {
+    let mut versions: Vec<Version> = known
+        .iter()
+        .filter(|&version| version > from && version < to)
+        .cloned()
+        .collect();
+    versions.sort();
+    versions.dedup();
+    versions
+}
+
+/// This is a predicate for upgrades which move from a release onto a pre-release, e.g. 2.4.0 to
+/// 2.5.0-rc.1. Upgrades from a pre-release, to either a release or a pre-release, are not
+/// regressions.
+pub(crate) fn is_prerelease_regression(from: &Version, to: &Version) -> bool {
+    from.pre.is_empty() && !to.pre.is_empty()
 }
 
 /// Generate a semver::Version from the CHART_VERSION_LABEL_KEY label on the Storage REST API
@@ -89,6 +166,21 @@ pub(crate) async fn version_from_rest_deployment_label(ns: &str) -> Result<Versi
     })
 }
 
+/// Struct to deserialize the known version yaml.
+#[derive(Deserialize)]
+struct KnownVersions {
+    known_versions: Vec<Version>,
+}
+
+impl TryFrom<&[u8]> for KnownVersions {
+    type Error = serde_yaml::Error;
+
+    /// Returns a KnownVersions object.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        serde_yaml::from_reader(bytes)
+    }
+}
+
 /// Struct to deserialize the unsupported version yaml.
 #[derive(Deserialize)]
 struct UnsupportedVersions {
@@ -109,3 +201,257 @@ impl TryFrom<&[u8]> for UnsupportedVersions {
         serde_yaml::from_reader(bytes)
     }
 }
+
+/// Struct to deserialize the upgrade compatibility matrix yaml.
+#[derive(Deserialize)]
+struct CompatibilityMatrix {
+    #[serde(default)]
+    supported_upgrades: Vec<UpgradePairs>,
+    #[serde(default)]
+    unsupported_upgrades: Vec<UpgradePairs>,
+}
+
+/// These are the upgrades from one source version, in the upgrade compatibility matrix.
+#[derive(Deserialize)]
+struct UpgradePairs {
+    from: Version,
+    to: Vec<Version>,
+    /// The CI run, the issue or the release notes which establish the upgrades' support.
+    source: String,
+}
+
+impl CompatibilityMatrix {
+    /// Returns the compatibility matrix which is bundled with the upgrade-job.
+    fn embedded() -> Result<Self> {
+        let compatibility_matrix_buf =
+            &include_bytes!("../../../../../upgrade/config/compatibility_matrix.yaml")[..];
+        CompatibilityMatrix::try_from(compatibility_matrix_buf)
+            .context(YamlParseBufferForCompatibilityMatrix)
+    }
+
+    /// Returns true if the upgrade is supported. Upgrades which are listed as known to fail are
+    /// not supported, those listed as tested are. The rest are supported if they are not
+    /// downgrades and do not skip a major version.
+    fn is_supported_pair(&self, from: &Version, to: &Version) -> bool {
+        if let Some(pairs) = Self::find(&self.unsupported_upgrades, from, to) {
+            info!(
+                source = %pairs.source,
+                "Upgrading from {from} to {to} is known to fail"
+            );
+            return false;
+        }
+        if let Some(pairs) = Self::find(&self.supported_upgrades, from, to) {
+            info!(
+                source = %pairs.source,
+                "Upgrading from {from} to {to} has been tested"
+            );
+            return true;
+        }
+
+        to.ge(from) && to.major - from.major <= 1
+    }
+
+    /// Returns the entry which lists the upgrade, if any.
+    fn find<'a>(
+        upgrades: &'a [UpgradePairs],
+        from: &Version,
+        to: &Version,
+    ) -> Option<&'a UpgradePairs> {
+        upgrades
+            .iter()
+            .find(|pairs| pairs.from.eq(from) && pairs.to.contains(to))
+    }
+}
+
+impl TryFrom<&[u8]> for CompatibilityMatrix {
+    type Error = serde_yaml::Error;
+
+    /// Returns a CompatibilityMatrix object.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        serde_yaml::from_reader(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+
+    /// This validates the upgrade path between two versions, without pre-release upgrades.
+    fn validate_path(from: &str, to: &str) -> Result<()> {
+        validate(
+            &Version::parse(from).unwrap(),
+            &Version::parse(to).unwrap(),
+            false,
+        )
+    }
+
+    #[test]
+    fn minimum_from_version_is_upgradable() {
+        assert!(validate_minimum_from(&MIN_UPGRADABLE_FROM).is_ok());
+        assert!(validate_minimum_from(&Version::parse("2.0.0-rc.1").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn above_minimum_from_version_is_upgradable() {
+        assert!(validate_minimum_from(&Version::parse("2.0.1").unwrap()).is_ok());
+        assert!(validate_minimum_from(&Version::parse("2.5.0").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn below_minimum_from_version_is_too_old() {
+        let installed = Version::parse("1.0.5").unwrap();
+        assert!(matches!(
+            validate_minimum_from(&installed),
+            Err(Error::InstalledVersionTooOldToUpgrade { installed: v, minimum })
+                if v == installed && minimum == MIN_UPGRADABLE_FROM
+        ));
+    }
+
+    #[test]
+    fn patch_minor_and_major_upgrades_are_valid() {
+        assert!(validate_path("2.4.0", "2.4.1").is_ok());
+        assert!(validate_path("2.4.0", "2.5.0").is_ok());
+        assert!(validate_path("2.5.0", "3.0.0").is_ok());
+    }
+
+    #[test]
+    fn skipped_major_version_is_invalid() {
+        assert!(matches!(
+            validate_path("2.1.0", "4.0.0"),
+            Err(Error::UnsupportedUpgradePath { .. })
+        ));
+    }
+
+    #[test]
+    fn downgrade_is_invalid() {
+        assert!(matches!(
+            validate_path("2.5.0", "2.4.0"),
+            Err(Error::DowngradeNotSupported { .. })
+        ));
+    }
+
+    #[test]
+    fn same_version_is_valid() {
+        assert!(validate_path("2.5.0", "2.5.0").is_ok());
+    }
+
+    #[test]
+    fn prerelease_regression_needs_allow_prerelease() {
+        assert!(matches!(
+            validate_path("2.4.0", "2.5.0-rc.1"),
+            Err(Error::PrereleaseRegression { .. })
+        ));
+        assert!(validate(
+            &Version::parse("2.4.0").unwrap(),
+            &Version::parse("2.5.0-rc.1").unwrap(),
+            true
+        )
+        .is_ok());
+        assert!(validate_path("2.5.0-rc.1", "2.5.0").is_ok());
+    }
+
+    #[test]
+    fn only_release_to_prerelease_is_a_prerelease_regression() {
+        let regression = |from: &str, to: &str| {
+            is_prerelease_regression(&Version::parse(from).unwrap(), &Version::parse(to).unwrap())
+        };
+
+        assert!(regression("2.4.0", "2.5.0-rc.1"));
+        assert!(regression("2.5.0", "2.5.0-rc.1"));
+        assert!(!regression("2.5.0-rc.1", "2.5.0-rc.2"));
+        assert!(!regression("2.5.0-rc.1", "2.5.0"));
+        assert!(!regression("2.4.0", "2.5.0"));
+    }
+
+    /// This is a compatibility matrix which lists 2.0.0 to 4.0.0 as tested, and 2.3.0 to 2.4.0 as
+    /// known to fail.
+    fn test_matrix() -> CompatibilityMatrix {
+        let matrix_yaml = r#"
+supported_upgrades:
+  - from: 2.0.0
+    to: [4.0.0]
+    source: https://example.com/ci/1
+unsupported_upgrades:
+  - from: 2.3.0
+    to: [2.4.0, 2.4.1]
+    source: https://example.com/issues/2
+"#;
+        CompatibilityMatrix::try_from(matrix_yaml.as_bytes()).unwrap()
+    }
+
+    /// This checks an upgrade against the test compatibility matrix.
+    fn is_supported_in_test_matrix(from: &str, to: &str) -> bool {
+        test_matrix()
+            .is_supported_pair(&Version::parse(from).unwrap(), &Version::parse(to).unwrap())
+    }
+
+    #[test]
+    fn tested_pair_is_supported_over_semver_rules() {
+        assert!(is_supported_in_test_matrix("2.0.0", "4.0.0"));
+    }
+
+    #[test]
+    fn known_failing_pair_is_unsupported_over_semver_rules() {
+        assert!(!is_supported_in_test_matrix("2.3.0", "2.4.0"));
+        assert!(!is_supported_in_test_matrix("2.3.0", "2.4.1"));
+    }
+
+    #[test]
+    fn unknown_pair_is_checked_by_semver_rules() {
+        assert!(is_supported_in_test_matrix("2.3.0", "2.5.0"));
+        assert!(is_supported_in_test_matrix("2.5.0", "3.0.0"));
+        assert!(!is_supported_in_test_matrix("2.1.0", "4.0.0"));
+        assert!(!is_supported_in_test_matrix("2.5.0", "2.4.0"));
+    }
+
+    #[test]
+    fn embedded_matrix_parses() {
+        assert!(CompatibilityMatrix::embedded().is_ok());
+    }
+
+    #[test]
+    fn matrix_entry_without_source_is_rejected() {
+        let matrix_yaml = "supported_upgrades: [{from: 2.0.0, to: [2.1.0]}]";
+        assert!(CompatibilityMatrix::try_from(matrix_yaml.as_bytes()).is_err());
+    }
+
+    /// This parses a list of versions.
+    fn versions(versions: &[&str]) -> Vec<Version> {
+        versions
+            .iter()
+            .map(|version| Version::parse(version).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn intermediate_versions_skip_the_gaps_in_the_catalog() {
+        let known = versions(&["2.5.0", "2.0.0", "2.2.0", "2.4.0", "2.1.0", "2.2.0"]);
+        let (from, to) = (Version::new(2, 0, 0), Version::new(2, 5, 0));
+
+        assert_eq!(
+            intermediate_versions(&from, &to, known.as_slice()),
+            versions(&["2.1.0", "2.2.0", "2.4.0"])
+        );
+    }
+
+    #[test]
+    fn adjacent_versions_have_no_intermediate_versions() {
+        let known = versions(&["2.0.0", "2.1.0", "2.2.0"]);
+
+        assert!(intermediate_versions(
+            &Version::new(2, 1, 0),
+            &Version::new(2, 2, 0),
+            known.as_slice()
+        )
+        .is_empty());
+        assert!(
+            intermediate_versions(&Version::new(2, 0, 0), &Version::new(2, 2, 0), &[]).is_empty()
+        );
+    }
+
+    #[test]
+    fn known_versions_catalog_parses() {
+        assert!(!known_versions().unwrap().is_empty());
+    }
+}
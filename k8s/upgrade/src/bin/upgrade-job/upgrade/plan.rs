@@ -0,0 +1,557 @@
+use crate::{
+    common::{
+        constants::{
+            CHART_VERSION_LABEL_KEY, INSTALLED_VALUES_SOURCE, IO_ENGINE_LABEL, PRODUCT,
+            UPGRADE_VALUES_SOURCE,
+        },
+        error::{ListPodsWithLabel, Result, SerializeUpgradePlan, YamlParseFromSlice},
+        kube_client::KubeClientSet,
+        rest_client::RestClientSet,
+    },
+    helm::{
+        client::HelmReleaseClient,
+        diff::{unified, UpgradeValuesDiff},
+        redact::redact,
+        release::load_installed_values,
+        upgrade::HelmUpgrade,
+        values::{commitment_capacity_delta, CommitmentDelta},
+        values_validation::ThinCommitmentValues,
+    },
+    opts::{CliArgs, NodeLabel, OutputFormat},
+    upgrade::{
+        build_helm_upgrade, capacity::total_pool_capacity, check_crds, check_image_allowlist,
+        check_image_tag_app_version, check_installed_image_tags, check_node_capacity,
+        check_node_disk_space, check_pool_commitment, check_rbac, check_single_replica_volumes,
+        check_storage_health, utils::skipped_nodes, validate_component, UpgradeSteps,
+    },
+};
+use kube::api::ListParams;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use tracing::info;
+
+/// This is the set of changes which an upgrade would carry out.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct UpgradePlan {
+    /// The name of the helm release.
+    release_name: String,
+    /// The version of the installed helm chart.
+    from_version: Option<String>,
+    /// The version of the helm chart to upgrade to.
+    to_version: Option<String>,
+    /// This is true if the helm upgrade would be skipped, because the helm release is already at
+    /// the target version.
+    already_upgraded: bool,
+    /// The changes to the helm values.
+    values_diff: UpgradeValuesDiff,
+    /// The unified diff of the installed helm values and the helm values which helm upgrade
+    /// would be run with. This is empty if there is no change, or for dry-runs which fail early.
+    unified_values_diff: String,
+    /// This is true if the container images would be pulled from another registry or repository,
+    /// so that every node would have to pull them afresh.
+    requires_image_prepull: bool,
+    /// This is true if the io-engine Pods would not be restarted.
+    skip_data_plane_restart: bool,
+    /// This is true if the upgrade changes the io-engine Pod template, which only takes effect
+    /// once the io-engine Pods are restarted.
+    requires_io_engine_restart: bool,
+    /// The change in the maximum logical capacity of the storage pools, if the thin-provisioning
+    /// poolCommitment would change.
+    commitment_capacity: Option<CommitmentDelta>,
+    /// The names of the nodes whose io-engine Pods would be restarted.
+    data_plane_restarts: Vec<String>,
+    /// The errors which failed the validation of the upgrade. The upgrade is valid if this is
+    /// empty.
+    errors: Vec<String>,
+}
+
+impl UpgradePlan {
+    /// This computes the plan of a validated upgrade, e.g. for the pre-upgrade webhook.
+    pub(crate) async fn for_upgrade(
+        opts: &CliArgs,
+        k8s_client: &KubeClientSet,
+        helm_upgrade: &HelmUpgrade,
+    ) -> Result<Self> {
+        let mut plan = UpgradePlan {
+            release_name: opts.release_name(),
+            skip_data_plane_restart: opts.skip_data_plane_restart(),
+            ..Default::default()
+        };
+        plan.set_helm_upgrade(helm_upgrade);
+        plan.redact(opts.redact_paths().as_slice());
+        if plan.restarts_data_plane() {
+            plan.data_plane_restarts = io_engine_nodes_to_restart(
+                k8s_client,
+                opts.namespace().as_str(),
+                &helm_upgrade.upgrade_to_version(),
+                opts.skip_node_label(),
+            )
+            .await?;
+        }
+
+        Ok(plan)
+    }
+
+    /// This is a getter for the version of the installed helm chart, if it is known.
+    pub(crate) fn installed_version(&self) -> Option<&str> {
+        self.from_version.as_deref()
+    }
+
+    /// This is a getter for the version of the helm chart to upgrade to, if it is known.
+    pub(crate) fn target_version(&self) -> Option<&str> {
+        self.to_version.as_deref()
+    }
+
+    /// This decides if the upgrade has to restart the io-engine Pods. If not, only the
+    /// control-plane is upgraded, and the io-engine Pods keep running undisturbed.
+    pub(crate) fn requires_io_engine_restart(&self) -> bool {
+        self.requires_io_engine_restart
+    }
+
+    /// This decides if the io-engine Pods would be restarted.
+    fn restarts_data_plane(&self) -> bool {
+        !self.skip_data_plane_restart && self.requires_io_engine_restart()
+    }
+
+    /// This sets the helm chart versions and the helm values changes of the helm upgrade.
+    fn set_helm_upgrade(&mut self, helm_upgrade: &HelmUpgrade) {
+        self.from_version = Some(helm_upgrade.upgrade_from_version());
+        self.to_version = Some(helm_upgrade.upgrade_to_version());
+        self.already_upgraded = helm_upgrade.already_upgraded();
+        self.values_diff = helm_upgrade.values_diff().clone();
+        self.requires_image_prepull = self.values_diff.requires_image_prepull();
+        self.requires_io_engine_restart = helm_upgrade.requires_io_engine_restart();
+    }
+
+    /// This redacts the sensitive helm values changes, so that the plan may be logged and sent.
+    fn redact<S: AsRef<str>>(&mut self, paths: &[S]) {
+        self.values_diff.redact(paths);
+    }
+
+    /// This logs the plan in a human-readable form.
+    pub(crate) fn log(&self) {
+        info!("Upgrade plan for helm release '{}':", self.release_name);
+        let from_version = self.from_version.as_deref().unwrap_or_default();
+        let to_version = self.to_version.as_deref().unwrap_or_default();
+        if self.already_upgraded {
+            info!("  Helm chart: already at version {to_version}, helm upgrade would be skipped");
+        } else {
+            info!("  Helm chart: {from_version} -> {to_version}");
+        }
+
+        if self.values_diff.is_empty() {
+            info!("  Helm values: no changes");
+        } else {
+            info!("  Helm values:");
+            for change in self.values_diff.changes() {
+                info!(
+                    "    {}: '{}' -> '{}'",
+                    change.path(),
+                    change.old_value().unwrap_or_default(),
+                    change.new_value().unwrap_or_default()
+                );
+            }
+        }
+
+        if !self.unified_values_diff.is_empty() {
+            info!("  Helm values diff:");
+            for line in self.unified_values_diff.lines() {
+                info!("    {line}");
+            }
+        }
+
+        if self.requires_image_prepull {
+            info!(
+                "  Container images: moved to another registry or repository, every node would \
+                pull them afresh"
+            );
+        }
+
+        if let Some(delta) = self.commitment_capacity.as_ref() {
+            info!(
+                "  Thin-provisioning: maximum logical capacity {} -> {} bytes ({:+} bytes)",
+                delta.installed_bytes(),
+                delta.target_bytes(),
+                delta.delta_bytes()
+            );
+        }
+
+        if self.skip_data_plane_restart {
+            info!("  Data-plane: io-engine Pod restarts would be skipped");
+        } else if !self.requires_io_engine_restart {
+            info!(
+                "  Data-plane: no change to the io-engine Pods, io-engine Pod restarts would be \
+                skipped"
+            );
+        } else if self.data_plane_restarts.is_empty() {
+            info!("  Data-plane: all io-engine Pods are already upgraded");
+        } else {
+            info!(
+                "  Data-plane: io-engine Pods would be restarted one at a time on nodes {}",
+                self.data_plane_restarts.join(", ")
+            );
+        }
+    }
+}
+
+/// This validates the upgrade and computes the upgrade plan, without making any changes to the
+/// cluster. The validation is the same as that of an actual upgrade, so an invalid upgrade fails
+/// with the same error. The plan is printed as JSON to stdout if the JSON output format is
+/// selected, even if the validation fails.
+pub(crate) async fn dry_run(opts: &CliArgs) -> Result<()> {
+    let (plan, result) = plan_upgrade(opts).await;
+
+    match opts.output() {
+        OutputFormat::Json => {
+            let plan_json = serde_json::to_string(&plan).context(SerializeUpgradePlan)?;
+            println!("{plan_json}");
+        }
+        OutputFormat::Text if result.is_ok() => plan.log(),
+        OutputFormat::Text => {}
+    }
+
+    result?;
+    info!("Dry-run complete, no changes were made to the {PRODUCT} installation");
+
+    Ok(())
+}
+
+/// This validates the upgrade and computes the upgrade plan. The plan carries the validation
+/// error, if any, which is also returned.
+pub(crate) async fn plan_upgrade(opts: &CliArgs) -> (UpgradePlan, Result<()>) {
+    let mut plan = UpgradePlan {
+        release_name: opts.release_name(),
+        skip_data_plane_restart: opts.skip_data_plane_restart(),
+        ..Default::default()
+    };
+
+    let result = compute_plan(opts, &mut plan).await;
+    if let Err(error) = &result {
+        plan.errors.push(error.to_string());
+    }
+    plan.redact(opts.redact_paths().as_slice());
+
+    (plan, result)
+}
+
+/// This fills in the upgrade plan, and returns the first validation error, if any.
+async fn compute_plan(opts: &CliArgs, plan: &mut UpgradePlan) -> Result<()> {
+    let helm_upgrade = build_helm_upgrade(opts).await?;
+
+    plan.set_helm_upgrade(&helm_upgrade);
+    plan.unified_values_diff = unified_values_diff(opts, &helm_upgrade)?;
+
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(opts.namespace())
+        .build()
+        .await?;
+    compute_cluster_plan(opts, &k8s_client, &helm_upgrade, plan).await?;
+
+    // Runs 'helm upgrade --dry-run', the returned HelmUpgradeRunner is dropped without running it.
+    let _runner = helm_upgrade.dry_run().await?;
+
+    Ok(())
+}
+
+/// This fills in the parts of the upgrade plan which are read from the cluster, and runs the
+/// validation of the upgrade against the cluster. This only reads from the Kubernetes API.
+async fn compute_cluster_plan(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    helm_upgrade: &HelmUpgrade,
+    plan: &mut UpgradePlan,
+) -> Result<()> {
+    let to_version = helm_upgrade.upgrade_to_version();
+    validate_component(
+        opts.component(),
+        helm_upgrade.upgrade_from_version().as_str(),
+        to_version.as_str(),
+    )?;
+
+    if plan.restarts_data_plane() {
+        plan.data_plane_restarts = io_engine_nodes_to_restart(
+            k8s_client,
+            opts.namespace().as_str(),
+            to_version.as_str(),
+            opts.skip_node_label(),
+        )
+        .await?;
+    }
+
+    check_rbac(opts, k8s_client).await?;
+    check_storage_health(opts).await?;
+    let steps = UpgradeSteps::for_upgrade(opts, helm_upgrade.requires_io_engine_restart());
+    check_single_replica_volumes(opts, k8s_client, &steps).await?;
+    check_node_capacity(opts, k8s_client, helm_upgrade, &steps).await?;
+    check_node_disk_space(opts, k8s_client, &steps).await?;
+    check_pool_commitment(opts, helm_upgrade).await?;
+    plan.commitment_capacity = commitment_capacity(opts, k8s_client, helm_upgrade).await?;
+    check_image_allowlist(opts, helm_upgrade)?;
+    check_image_tag_app_version(opts, helm_upgrade)?;
+    check_crds(opts, k8s_client).await?;
+    check_installed_image_tags(opts, k8s_client).await?;
+
+    Ok(())
+}
+
+/// This is the unified diff of the installed helm values and the helm values which helm upgrade
+/// would be run with, with the sensitive values redacted.
+fn unified_values_diff(opts: &CliArgs, helm_upgrade: &HelmUpgrade) -> Result<String> {
+    let Some(mut upgrade_values) = helm_upgrade.upgrade_values_yaml()? else {
+        return Ok(String::new());
+    };
+
+    let installed_values_yaml = HelmReleaseClient::builder()
+        .with_namespace(opts.namespace())
+        .build()?
+        .get_values_as_yaml::<String, String>(opts.release_name(), None)?;
+    let mut installed_values: serde_yaml::Value =
+        serde_yaml::from_slice(installed_values_yaml.as_slice()).context(YamlParseFromSlice {
+            input_yaml: String::from_utf8_lossy(installed_values_yaml.as_slice()).to_string(),
+        })?;
+
+    let redact_paths = opts.redact_paths();
+    redact(&mut installed_values, redact_paths.as_slice());
+    redact(&mut upgrade_values, redact_paths.as_slice());
+
+    Ok(unified(&installed_values, &upgrade_values))
+}
+
+/// This lists the names of the nodes whose io-engine Pods are not at the target version, leaving
+/// out the nodes with the --skip-node-label.
+pub(crate) async fn io_engine_nodes_to_restart(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    to_version: &str,
+    skip_node_label: Option<&NodeLabel>,
+) -> Result<Vec<String>> {
+    let label = format!("{IO_ENGINE_LABEL},{CHART_VERSION_LABEL_KEY}!={to_version}");
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(label.as_str()))
+        .await
+        .context(ListPodsWithLabel { label, namespace })?;
+    let skipped_nodes = skipped_nodes(k8s_client, skip_node_label).await?;
+
+    let mut nodes: Vec<String> = pods
+        .items
+        .into_iter()
+        .filter_map(|pod| pod.spec.and_then(|spec| spec.node_name))
+        .filter(|node_name| !skipped_nodes.contains(node_name))
+        .collect();
+    nodes.sort();
+
+    Ok(nodes)
+}
+
+/// This computes the change in the maximum logical capacity of the storage pools, if the upgrade
+/// changes the thin-provisioning poolCommitment. This is None if either of the helm charts has no
+/// thin-provisioning options, or if the installed values cannot be read.
+async fn commitment_capacity(
+    opts: &CliArgs,
+    k8s_client: &KubeClientSet,
+    helm_upgrade: &HelmUpgrade,
+) -> Result<Option<CommitmentDelta>> {
+    let (from_version, to_version) = (
+        helm_upgrade.installed_version(),
+        helm_upgrade.target_version(),
+    );
+    let Some(upgrade_values) = helm_upgrade.upgrade_values()? else {
+        return Ok(None);
+    };
+    let Some(target) = ThinCommitmentValues::try_from_values_if_present(
+        &upgrade_values,
+        to_version,
+        UPGRADE_VALUES_SOURCE,
+    )?
+    else {
+        return Ok(None);
+    };
+
+    // Values of older helm charts may need to be migrated before they deserialize.
+    let Ok(installed_values) = load_installed_values(
+        k8s_client,
+        opts.release_name().as_str(),
+        opts.namespace().as_str(),
+    )
+    .await
+    else {
+        return Ok(None);
+    };
+    let Ok(installed) = ThinCommitmentValues::try_from_values(
+        &installed_values,
+        from_version,
+        INSTALLED_VALUES_SOURCE,
+    ) else {
+        return Ok(None);
+    };
+    if installed.pool_commitment() == target.pool_commitment() {
+        return Ok(None);
+    }
+
+    let rest_client = RestClientSet::new_with_url(opts.rest_endpoint(), &opts.rest_tls())?;
+    let physical_bytes = total_pool_capacity(&rest_client).await?;
+
+    Ok(Some(commitment_capacity_delta(
+        &installed,
+        &target,
+        physical_bytes,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::{chart::CoreValues, diff::diff_values, upgrade::tests::core_chart_upgrade};
+    use hyper::{Body, Method, Request, Response};
+    use k8s_openapi::api::authorization::v1::{SelfSubjectAccessReview, SubjectAccessReviewStatus};
+    use semver::Version;
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    /// These are the methods and paths of the requests which the Kubernetes API server received.
+    type Requests = Arc<Mutex<Vec<(Method, String)>>>;
+
+    /// This is a Kubernetes API server which records the requests. It allows every
+    /// SelfSubjectAccessReview, and lists no objects.
+    fn recording_k8s_client(requests: Requests) -> KubeClientSet {
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let requests = requests.clone();
+            async move {
+                let (parts, body) = request.into_parts();
+                requests
+                    .lock()
+                    .unwrap()
+                    .push((parts.method.clone(), parts.uri.path().to_string()));
+
+                let body = if parts.method.eq(&Method::POST) {
+                    let body = hyper::body::to_bytes(body).await.unwrap();
+                    let mut review: SelfSubjectAccessReview =
+                        serde_json::from_slice(body.as_ref()).unwrap();
+                    review.status = Some(SubjectAccessReviewStatus {
+                        allowed: true,
+                        ..Default::default()
+                    });
+                    serde_json::to_vec(&review).unwrap()
+                } else {
+                    serde_json::to_vec(&serde_json::json!({
+                        "apiVersion": "v1",
+                        "kind": "List",
+                        "metadata": {},
+                        "items": [],
+                    }))
+                    .unwrap()
+                };
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }
+        });
+        KubeClientSet::with_client(kube::Client::new(service, "mayastor"), "mayastor")
+    }
+
+    #[tokio::test]
+    async fn upgrade_plan_only_reads_from_the_cluster() {
+        let requests = Requests::default();
+        let k8s_client = recording_k8s_client(requests.clone());
+        let opts = crate::opts::tests::parse(&[
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "/nonexistent/chart",
+            "--skip-health-check",
+            "upgrade-job-pod",
+        ])
+        .unwrap();
+        let helm_upgrade = core_chart_upgrade("2.4.0", "2.5.0", false);
+        let mut plan = UpgradePlan::default();
+        plan.set_helm_upgrade(&helm_upgrade);
+
+        let result = compute_cluster_plan(&opts, &k8s_client, &helm_upgrade, &mut plan).await;
+
+        assert!(result.is_ok(), "{result:?}");
+        let requests = requests.lock().unwrap();
+        assert!(!requests.is_empty());
+        // SelfSubjectAccessReviews are only evaluated, they are not stored.
+        for (method, path) in requests.iter() {
+            assert!(
+                method.eq(&Method::GET)
+                    || (method.eq(&Method::POST) && path.ends_with("/selfsubjectaccessreviews")),
+                "{method} {path}"
+            );
+        }
+        assert!(requests
+            .iter()
+            .any(|(method, path)| method.eq(&Method::GET) && path.ends_with("/pods")));
+    }
+
+    /// This is the plan of a failed upgrade validation, with a change to the image tag.
+    fn failed_plan() -> UpgradePlan {
+        let installed: CoreValues =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        let mut target_yaml: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        target_yaml["image"]["tag"] = "v2.5.0".into();
+        let target: CoreValues = serde_yaml::from_value(target_yaml).unwrap();
+
+        UpgradePlan {
+            release_name: "mayastor".to_string(),
+            from_version: Some("2.4.0".to_string()),
+            to_version: Some("2.5.0".to_string()),
+            values_diff: diff_values(
+                &installed,
+                &Version::new(2, 4, 0),
+                &target,
+                &Version::new(2, 5, 0),
+            ),
+            requires_io_engine_restart: true,
+            commitment_capacity: serde_json::from_str(
+                r#"{"installed_bytes":1000,"target_bytes":2000}"#,
+            )
+            .unwrap(),
+            data_plane_restarts: vec!["node-1".to_string(), "node-2".to_string()],
+            errors: vec!["Storage is not healthy".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_plan_deserializes_into_the_plan() {
+        let plan = failed_plan();
+        assert!(!plan.values_diff.is_empty());
+
+        let plan_json = serde_json::to_string(&plan).unwrap();
+        let parsed: UpgradePlan = serde_json::from_str(plan_json.as_str()).unwrap();
+        assert_eq!(parsed, plan);
+    }
+
+    #[test]
+    fn control_plane_only_plan_does_not_restart_the_data_plane() {
+        let plan = UpgradePlan {
+            requires_io_engine_restart: false,
+            ..failed_plan()
+        };
+        assert!(!plan.requires_io_engine_restart());
+        assert!(!plan.restarts_data_plane());
+    }
+
+    #[test]
+    fn io_engine_change_plan_restarts_the_data_plane() {
+        let plan = failed_plan();
+        assert!(plan.requires_io_engine_restart());
+        assert!(plan.restarts_data_plane());
+    }
+
+    #[test]
+    fn json_plan_has_the_errors_array() {
+        let plan_json: serde_json::Value = serde_json::to_value(failed_plan()).unwrap();
+        assert_eq!(
+            plan_json["errors"],
+            serde_json::json!(["Storage is not healthy"])
+        );
+        assert_eq!(plan_json["from_version"], "2.4.0");
+        assert_eq!(plan_json["to_version"], "2.5.0");
+    }
+}
@@ -0,0 +1,288 @@
+use serde::Serialize;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tracing::{error, info};
+
+/// These are the states which a node goes through, during the rolling restart of the io-engine
+/// DaemonSet Pods.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ProgressState {
+    /// The io-engine Pod on the node is yet to be restarted.
+    Pending,
+    /// The storage node is being drained.
+    DrainingNode,
+    /// The io-engine Pod on the node is being deleted.
+    RestartingPod,
+    /// The replacement io-engine Pod on the node is yet to be Ready.
+    WaitingForReady,
+    /// The volumes on the node are yet to be Online again, after the restart.
+    Stabilizing,
+    /// The io-engine Pod on the node has been upgraded.
+    Completed,
+    /// The upgrade of the io-engine Pod on the node has failed.
+    Failed,
+}
+
+impl ProgressState {
+    /// These are all of the states, in the order which a node goes through them.
+    pub(crate) const ALL: [ProgressState; 7] = [
+        Self::Pending,
+        Self::DrainingNode,
+        Self::RestartingPod,
+        Self::WaitingForReady,
+        Self::Stabilizing,
+        Self::Completed,
+        Self::Failed,
+    ];
+}
+
+impl fmt::Display for ProgressState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::Pending => "Pending",
+            Self::DrainingNode => "DrainingNode",
+            Self::RestartingPod => "RestartingPod",
+            Self::WaitingForReady => "WaitingForReady",
+            Self::Stabilizing => "Stabilizing",
+            Self::Completed => "Completed",
+            Self::Failed => "Failed",
+        };
+        write!(f, "{state}")
+    }
+}
+
+/// This is the progress of the data-plane upgrade, at a state transition of a node.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Progress {
+    /// The name of the node whose state changed.
+    node_name: String,
+    /// The state of the node.
+    state: ProgressState,
+    /// The number of nodes whose io-engine Pods have been upgraded.
+    nodes_completed: usize,
+    /// The number of nodes whose io-engine Pods are yet to be upgraded.
+    nodes_remaining: usize,
+}
+
+impl Progress {
+    /// This creates a Progress for a node.
+    pub(crate) fn new(
+        node_name: &str,
+        state: ProgressState,
+        nodes_completed: usize,
+        nodes_remaining: usize,
+    ) -> Self {
+        Self {
+            node_name: node_name.to_string(),
+            state,
+            nodes_completed,
+            nodes_remaining,
+        }
+    }
+
+    /// This is a getter for the state of the node.
+    pub(crate) fn state(&self) -> ProgressState {
+        self.state
+    }
+
+    /// This is a getter for the number of nodes whose io-engine Pods have been upgraded.
+    pub(crate) fn nodes_completed(&self) -> usize {
+        self.nodes_completed
+    }
+
+    /// This is a getter for the number of nodes whose io-engine Pods are yet to be upgraded.
+    pub(crate) fn nodes_remaining(&self) -> usize {
+        self.nodes_remaining
+    }
+}
+
+/// This reports the progress of the data-plane upgrade, at each state transition of a node.
+pub(crate) trait ProgressReporter: Send + Sync {
+    /// This reports a state transition.
+    fn report(&self, progress: &Progress);
+}
+
+/// This reports the progress to the logs.
+pub(crate) struct LogProgressReporter;
+
+impl ProgressReporter for LogProgressReporter {
+    fn report(&self, progress: &Progress) {
+        match progress.state {
+            ProgressState::Failed => error!(
+                node.name = %progress.node_name,
+                state = %progress.state,
+                nodes.completed = progress.nodes_completed,
+                nodes.remaining = progress.nodes_remaining,
+                "Data-plane upgrade progress"
+            ),
+            _ => info!(
+                node.name = %progress.node_name,
+                state = %progress.state,
+                nodes.completed = progress.nodes_completed,
+                nodes.remaining = progress.nodes_remaining,
+                "Data-plane upgrade progress"
+            ),
+        }
+    }
+}
+
+/// This reports the progress to stdout, as one JSON document per line.
+pub(crate) struct JsonLinesProgressReporter;
+
+impl ProgressReporter for JsonLinesProgressReporter {
+    fn report(&self, progress: &Progress) {
+        match serde_json::to_string(progress) {
+            Ok(line) => println!("{line}"),
+            Err(error) => error!(%error, "Failed to serialize data-plane upgrade progress"),
+        }
+    }
+}
+
+/// This counts the nodes whose io-engine Pods have been upgraded, and passes the progress on to
+/// another reporter. The count is kept if the upgrade fails part of the way through.
+pub(crate) struct CountingProgressReporter {
+    inner: Box<dyn ProgressReporter>,
+    nodes_upgraded: Arc<AtomicUsize>,
+}
+
+impl CountingProgressReporter {
+    /// This creates a CountingProgressReporter which wraps another reporter.
+    pub(crate) fn new(inner: Box<dyn ProgressReporter>, nodes_upgraded: Arc<AtomicUsize>) -> Self {
+        Self {
+            inner,
+            nodes_upgraded,
+        }
+    }
+}
+
+impl ProgressReporter for CountingProgressReporter {
+    fn report(&self, progress: &Progress) {
+        if progress.state().eq(&ProgressState::Completed) {
+            self.nodes_upgraded.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.report(progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// This records the reported progress.
+    #[derive(Default, Clone)]
+    struct RecordingProgressReporter(Arc<Mutex<Vec<Progress>>>);
+
+    impl ProgressReporter for RecordingProgressReporter {
+        fn report(&self, progress: &Progress) {
+            self.0.lock().unwrap().push(progress.clone());
+        }
+    }
+
+    /// This reports the transitions of a rolling restart of the io-engine Pods on the nodes, one
+    /// node at a time, the way the data-plane upgrade does. The restart fails on the failing node.
+    fn simulate_rolling_restart(reporter: &dyn ProgressReporter, nodes: &[&str], failing: &str) {
+        let mut nodes_completed = 0;
+        let mut nodes_remaining = nodes.len();
+        for node in nodes {
+            reporter.report(&Progress::new(
+                node,
+                ProgressState::Pending,
+                nodes_completed,
+                nodes_remaining,
+            ));
+        }
+
+        for node in nodes {
+            for state in [
+                ProgressState::DrainingNode,
+                ProgressState::RestartingPod,
+                ProgressState::WaitingForReady,
+            ] {
+                reporter.report(&Progress::new(
+                    node,
+                    state,
+                    nodes_completed,
+                    nodes_remaining,
+                ));
+            }
+
+            if node.eq(&failing) {
+                reporter.report(&Progress::new(
+                    node,
+                    ProgressState::Failed,
+                    nodes_completed,
+                    nodes_remaining,
+                ));
+                return;
+            }
+            nodes_completed += 1;
+            nodes_remaining -= 1;
+            reporter.report(&Progress::new(
+                node,
+                ProgressState::Completed,
+                nodes_completed,
+                nodes_remaining,
+            ));
+        }
+    }
+
+    #[test]
+    fn reporter_is_driven_through_each_transition() {
+        let reporter = RecordingProgressReporter::default();
+        simulate_rolling_restart(&reporter, &["node-1", "node-2"], "");
+
+        use ProgressState::*;
+        let expected = [
+            ("node-1", Pending, 0, 2),
+            ("node-2", Pending, 0, 2),
+            ("node-1", DrainingNode, 0, 2),
+            ("node-1", RestartingPod, 0, 2),
+            ("node-1", WaitingForReady, 0, 2),
+            ("node-1", Completed, 1, 1),
+            ("node-2", DrainingNode, 1, 1),
+            ("node-2", RestartingPod, 1, 1),
+            ("node-2", WaitingForReady, 1, 1),
+            ("node-2", Completed, 2, 0),
+        ]
+        .map(|(node, state, completed, remaining)| {
+            Progress::new(node, state, completed, remaining)
+        });
+        assert_eq!(*reporter.0.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn counting_reporter_counts_the_nodes_upgraded_before_a_failure() {
+        let recording = RecordingProgressReporter::default();
+        let nodes_upgraded = Arc::new(AtomicUsize::new(0));
+        let reporter =
+            CountingProgressReporter::new(Box::new(recording.clone()), nodes_upgraded.clone());
+        simulate_rolling_restart(&reporter, &["node-1", "node-2", "node-3"], "node-2");
+
+        assert_eq!(nodes_upgraded.load(Ordering::Relaxed), 1);
+        let reported = recording.0.lock().unwrap();
+        let last = reported.last().unwrap();
+        assert_eq!(last.state(), ProgressState::Failed);
+        assert_eq!(last.nodes_completed(), 1);
+        assert_eq!(last.nodes_remaining(), 2);
+    }
+
+    #[test]
+    fn progress_is_serialized_as_a_json_line() {
+        let progress = Progress::new("node-1", ProgressState::WaitingForReady, 1, 2);
+        assert_eq!(
+            serde_json::to_string(&progress).unwrap(),
+            concat!(
+                r#"{"nodeName":"node-1","state":"waitingForReady","#,
+                r#""nodesCompleted":1,"nodesRemaining":2}"#
+            )
+        );
+    }
+}
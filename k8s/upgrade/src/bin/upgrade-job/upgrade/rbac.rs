@@ -0,0 +1,261 @@
+use crate::common::{
+    error::{Error, InsufficientRbac, Result, SelfSubjectAccessReviewCreate},
+    kube_client::KubeClientSet,
+};
+use futures::future::join_all;
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::api::{Api, PostParams};
+use snafu::{ensure, ResultExt};
+use tracing::info;
+
+/// This is a permission which the upgrade-job needs, to carry out the upgrade.
+struct Permission {
+    /// The API group of the resource, "" for the core API group.
+    group: &'static str,
+    resource: &'static str,
+    verb: &'static str,
+    /// This is false for cluster-scoped resources.
+    namespaced: bool,
+}
+
+impl Permission {
+    /// This is a human-readable form of the permission, e.g. 'patch daemonsets.apps'.
+    fn describe(&self) -> String {
+        match self.group {
+            "" => format!("{} {}", self.verb, self.resource),
+            group => format!("{} {}.{}", self.verb, self.resource, group),
+        }
+    }
+}
+
+/// These are the permissions which the upgrade-job uses directly. The permissions for the helm
+/// upgrade itself depend on the helm chart, and are checked by the helm dry-run.
+const REQUIRED_PERMISSIONS: &[Permission] = &[
+    Permission {
+        group: "apps",
+        resource: "daemonsets",
+        verb: "list",
+        namespaced: true,
+    },
+    Permission {
+        group: "apps",
+        resource: "daemonsets",
+        verb: "patch",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "pods",
+        verb: "list",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "pods",
+        verb: "delete",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "secrets",
+        verb: "get",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "secrets",
+        verb: "list",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "configmaps",
+        verb: "get",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "configmaps",
+        verb: "patch",
+        namespaced: true,
+    },
+    Permission {
+        group: "events.k8s.io",
+        resource: "events",
+        verb: "create",
+        namespaced: true,
+    },
+    Permission {
+        group: "",
+        resource: "nodes",
+        verb: "get",
+        namespaced: false,
+    },
+];
+
+/// This fails if the upgrade-job's ServiceAccount lacks any of the permissions which the upgrade
+/// needs, so that RBAC can be fixed before the upgrade makes any changes. Each permission is
+/// checked with a SelfSubjectAccessReview, and all of the denied permissions are reported together.
+pub(crate) async fn check_permissions(k8s_client: &KubeClientSet, namespace: &str) -> Result<()> {
+    review_permissions(k8s_client.self_subject_access_reviews_api(), namespace).await?;
+
+    info!("The upgrade-job has all of the RBAC permissions which the upgrade needs");
+    Ok(())
+}
+
+/// This is like check_permissions, with the SelfSubjectAccessReview API passed in.
+async fn review_permissions(
+    reviews_api: &Api<SelfSubjectAccessReview>,
+    namespace: &str,
+) -> Result<()> {
+    let reviews = REQUIRED_PERMISSIONS.iter().map(|permission| async move {
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    group: Some(permission.group.to_string()),
+                    resource: Some(permission.resource.to_string()),
+                    verb: Some(permission.verb.to_string()),
+                    namespace: permission.namespaced.then(|| namespace.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let review = reviews_api
+            .create(&PostParams::default(), &review)
+            .await
+            .context(SelfSubjectAccessReviewCreate {
+                permission: permission.describe(),
+            })?;
+        let allowed = review.status.is_some_and(|status| status.allowed);
+
+        Ok::<_, Error>((permission, allowed))
+    });
+
+    let mut denied: Vec<String> = Vec::new();
+    for result in join_all(reviews).await {
+        let (permission, allowed) = result?;
+        if !allowed {
+            denied.push(permission.describe());
+        }
+    }
+
+    ensure!(denied.is_empty(), InsufficientRbac { denied });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, Request, Response};
+    use k8s_openapi::api::authorization::v1::SubjectAccessReviewStatus;
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    /// These are the resource attributes of the reviews which the mocked authorization API
+    /// received.
+    type Reviewed = Arc<Mutex<Vec<ResourceAttributes>>>;
+
+    /// This is a mocked authorization API, which denies the permissions with the descriptions,
+    /// e.g. 'patch daemonsets.apps', and allows the rest. A review of a permission in 'no_status'
+    /// is answered without a status.
+    #[derive(Clone)]
+    struct MockAuthorization {
+        denied: &'static [&'static str],
+        no_status: &'static [&'static str],
+        reviewed: Reviewed,
+    }
+
+    impl MockAuthorization {
+        /// This creates a MockAuthorization.
+        fn new(denied: &'static [&'static str], no_status: &'static [&'static str]) -> Self {
+            Self {
+                denied,
+                no_status,
+                reviewed: Reviewed::default(),
+            }
+        }
+
+        /// This is a SelfSubjectAccessReview API which talks to the mocked authorization API.
+        fn api(&self) -> Api<SelfSubjectAccessReview> {
+            let mock = self.clone();
+            let service = tower::service_fn(move |request| mock.clone().serve(request));
+            Api::all(kube::Client::new(service, "default"))
+        }
+
+        /// This answers a SelfSubjectAccessReview create request.
+        async fn serve(self, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            let mut review: SelfSubjectAccessReview =
+                serde_json::from_slice(body.as_ref()).unwrap();
+            let attributes = review.spec.resource_attributes.clone().unwrap();
+            let permission = match attributes.group.as_deref().unwrap_or_default() {
+                "" => format!(
+                    "{} {}",
+                    attributes.verb.as_deref().unwrap_or_default(),
+                    attributes.resource.as_deref().unwrap_or_default()
+                ),
+                group => format!(
+                    "{} {}.{group}",
+                    attributes.verb.as_deref().unwrap_or_default(),
+                    attributes.resource.as_deref().unwrap_or_default()
+                ),
+            };
+            self.reviewed.lock().unwrap().push(attributes);
+
+            if !self.no_status.contains(&permission.as_str()) {
+                review.status = Some(SubjectAccessReviewStatus {
+                    allowed: !self.denied.contains(&permission.as_str()),
+                    ..Default::default()
+                });
+            }
+            Ok(Response::new(Body::from(
+                serde_json::to_vec(&review).unwrap(),
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn all_allowed_permissions_pass() {
+        let mock = MockAuthorization::new(&[], &[]);
+        assert!(review_permissions(&mock.api(), "mayastor").await.is_ok());
+
+        let reviewed = mock.reviewed.lock().unwrap();
+        assert_eq!(reviewed.len(), REQUIRED_PERMISSIONS.len());
+        for attributes in reviewed.iter() {
+            let namespace = match attributes.resource.as_deref() {
+                Some("nodes") => None,
+                _ => Some("mayastor"),
+            };
+            assert_eq!(attributes.namespace.as_deref(), namespace, "{attributes:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_reviews_list_every_denied_permission() {
+        let mock = MockAuthorization::new(
+            &["patch daemonsets.apps", "create events.events.k8s.io"],
+            &["get nodes"],
+        );
+
+        let result = review_permissions(&mock.api(), "mayastor").await;
+        let Err(Error::InsufficientRbac { denied }) = result else {
+            panic!("expected InsufficientRbac, got {result:?}");
+        };
+        assert_eq!(
+            denied,
+            [
+                "patch daemonsets.apps",
+                "create events.events.k8s.io",
+                "get nodes"
+            ]
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{
+    common::{
+        constants::{CHART_VERSION_LABEL_KEY, IO_ENGINE_CONTAINER_NAME, IO_ENGINE_LABEL},
+        error::{
+            IoEngineDaemonSetAbsent, IoEngineDaemonSetContainerAbsent, ListDaemonSetsWithLabel,
+            ListPodsWithLabel, PatchIoEngineDaemonSet, Result,
+        },
+        kube_client::KubeClientSet,
+        retry::kube_with_backoff,
+    },
+    opts::{CliArgs, MaxUnavailable},
+    upgrade::verify::image_tag,
+};
+use k8s_openapi::api::{
+    apps::v1::DaemonSet,
+    core::v1::{Pod, PodSpec},
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    ResourceExt,
+};
+use snafu::ResultExt;
+use tracing::{info, warn};
+
+/// This is the rollout state of the io-engine DaemonSet, i.e. how many of its Pods are upgraded.
+/// An io-engine Pod is upgraded if it runs the container image of the DaemonSet's Pod template,
+/// and carries the helm chart version label of the upgrade.
+#[derive(Debug, PartialEq)]
+pub(crate) enum RolloutState {
+    /// None of the io-engine Pods are upgraded.
+    NotStarted,
+    /// Some of the io-engine Pods are upgraded.
+    InProgress {
+        /// The nodes whose io-engine Pods are upgraded.
+        done_nodes: Vec<String>,
+        /// The nodes whose io-engine Pods are not upgraded.
+        pending_nodes: Vec<String>,
+    },
+    /// All of the io-engine Pods are upgraded.
+    Completed,
+}
+
+/// This compares the io-engine image tag of every io-engine Pod with that of the io-engine
+/// DaemonSet's Pod template, and the helm chart version label of every io-engine Pod with the
+/// target helm chart version, to find out how far an earlier data-plane upgrade got. The
+/// data-plane upgrade picks the io-engine Pods to restart by their helm chart version label, so a
+/// Pod which runs the target image tag with an older label is not upgraded.
+pub(crate) async fn detect_state(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    to_version: &str,
+) -> Result<RolloutState> {
+    let listparams = ListParams::default().labels(IO_ENGINE_LABEL);
+
+    let daemonset = io_engine_daemonset(k8s_client, namespace).await?;
+    let target_tag = daemonset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.template.spec.as_ref())
+        .and_then(io_engine_image_tag)
+        .ok_or(
+            IoEngineDaemonSetContainerAbsent {
+                name: daemonset.name_any(),
+            }
+            .build(),
+        )?;
+
+    let pods = k8s_client
+        .pods_api()
+        .list(&listparams)
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL,
+            namespace,
+        })?;
+
+    Ok(rollout_state(target_tag, to_version, pods.items.as_slice()))
+}
+
+/// This is how the io-engine DaemonSet replaces its Pods when its Pod template changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UpdateStrategy {
+    /// The Pods are only replaced once they are deleted, so the upgrade deletes them.
+    OnDelete,
+    /// The DaemonSet controller replaces the Pods by itself, so the upgrade waits for it.
+    RollingUpdate,
+}
+
+impl From<&DaemonSet> for UpdateStrategy {
+    /// Kubernetes defaults to RollingUpdate, if the DaemonSet has no update strategy.
+    fn from(daemonset: &DaemonSet) -> Self {
+        let strategy_type = daemonset
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.update_strategy.as_ref())
+            .and_then(|strategy| strategy.type_.as_deref());
+        match strategy_type {
+            Some("OnDelete") => Self::OnDelete,
+            _ => Self::RollingUpdate,
+        }
+    }
+}
+
+/// This is the merge patch which sets the update strategy of a DaemonSet to OnDelete.
+fn on_delete_patch() -> serde_json::Value {
+    serde_json::json!({
+        "spec": {
+            "updateStrategy": {
+                "type": "OnDelete",
+                "rollingUpdate": null
+            }
+        }
+    })
+}
+
+/// This is the merge patch which keeps the RollingUpdate update strategy of a DaemonSet, and lets
+/// the DaemonSet controller replace at most max_unavailable Pods at the same time.
+fn rolling_update_patch(max_unavailable: usize) -> serde_json::Value {
+    serde_json::json!({
+        "spec": {
+            "updateStrategy": {
+                "type": "RollingUpdate",
+                "rollingUpdate": {
+                    "maxUnavailable": max_unavailable
+                }
+            }
+        }
+    })
+}
+
+/// This returns the maximum number of io-engine Pods which the DaemonSet controller may replace
+/// at the same time, if the upgrade may leave the restarts to it. The DaemonSet controller picks
+/// the Pods to replace by itself, and does not stop until all of them are replaced, so the
+/// control-plane only upgrades, the upgrades with a --skip-node-label and the canary upgrades
+/// delete the io-engine Pods themselves.
+pub(crate) fn controller_rollout(
+    opts: &CliArgs,
+    io_engine_restart: bool,
+) -> Option<MaxUnavailable> {
+    (io_engine_restart && opts.skip_node_label().is_none() && !opts.canary())
+        .then(|| opts.max_unavailable())
+}
+
+/// This prepares the io-engine DaemonSet for the restart of its Pods, ahead of the helm upgrade
+/// which changes its Pod template, and returns the update strategy the Pods are restarted with.
+/// An OnDelete DaemonSet is left as is. A RollingUpdate DaemonSet is left to the DaemonSet
+/// controller, with the maxUnavailable of the upgrade, if the controller_rollout allows it.
+/// Otherwise, it is set to OnDelete, so that the io-engine Pods are only replaced once the upgrade
+/// deletes them.
+pub(crate) async fn prepare_rollout(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    controller_rollout: Option<MaxUnavailable>,
+) -> Result<UpdateStrategy> {
+    let daemonset = io_engine_daemonset(k8s_client, namespace).await?;
+    if UpdateStrategy::from(&daemonset).eq(&UpdateStrategy::OnDelete) {
+        return Ok(UpdateStrategy::OnDelete);
+    }
+
+    let name = daemonset.name_any();
+    let (strategy, patch) = match controller_rollout {
+        Some(max_unavailable) => {
+            let total = daemonset
+                .status
+                .as_ref()
+                .map(|status| status.desired_number_scheduled)
+                .unwrap_or_default();
+            let max_unavailable = max_unavailable.resolve(total.max(0) as usize);
+            warn!(
+                daemonset.name = %name,
+                "The io-engine DaemonSet uses the RollingUpdate update strategy, the DaemonSet \
+                controller replaces the io-engine Pods in its own order, {max_unavailable} at a \
+                time, and the upgrade waits for each of them"
+            );
+            (
+                UpdateStrategy::RollingUpdate,
+                rolling_update_patch(max_unavailable),
+            )
+        }
+        None => {
+            warn!(
+                daemonset.name = %name,
+                "The io-engine DaemonSet uses the RollingUpdate update strategy, which replaces \
+                the io-engine Pods without draining their nodes, setting it to OnDelete so that \
+                the upgrade restarts the io-engine Pods one batch at a time"
+            );
+            (UpdateStrategy::OnDelete, on_delete_patch())
+        }
+    };
+
+    let patch = Patch::Merge(patch);
+    let patch_params = PatchParams::default();
+    kube_with_backoff(|| {
+        k8s_client
+            .daemonsets_api()
+            .patch(name.as_str(), &patch_params, &patch)
+    })
+    .await
+    .context(PatchIoEngineDaemonSet {
+        name: name.clone(),
+        namespace,
+        strategy: format!("{strategy:?}"),
+    })?;
+    info!(daemonset.name = %name, update_strategy = ?strategy, "Prepared the io-engine DaemonSet");
+
+    Ok(strategy)
+}
+
+/// This fetches the io-engine DaemonSet.
+async fn io_engine_daemonset(k8s_client: &KubeClientSet, namespace: &str) -> Result<DaemonSet> {
+    let listparams = ListParams::default().labels(IO_ENGINE_LABEL);
+    kube_with_backoff(|| k8s_client.daemonsets_api().list(&listparams))
+        .await
+        .context(ListDaemonSetsWithLabel {
+            label: IO_ENGINE_LABEL,
+            namespace,
+        })?
+        .items
+        .into_iter()
+        .next()
+        .ok_or(IoEngineDaemonSetAbsent { namespace }.build())
+}
+
+/// This sorts the nodes of the io-engine Pods by whether their Pods run the target image tag and
+/// carry the target helm chart version label. Pods which are not scheduled to a node yet are left
+/// out.
+fn rollout_state(target_tag: &str, to_version: &str, pods: &[Pod]) -> RolloutState {
+    let mut done_nodes: Vec<String> = Vec::new();
+    let mut pending_nodes: Vec<String> = Vec::new();
+    for pod in pods {
+        let Some((spec, node)) = pod
+            .spec
+            .as_ref()
+            .and_then(|spec| Some((spec, spec.node_name.clone()?)))
+        else {
+            continue;
+        };
+        let has_target_tag = io_engine_image_tag(spec).is_some_and(|tag| tag.eq(target_tag));
+        let has_target_label = pod
+            .labels()
+            .get(CHART_VERSION_LABEL_KEY)
+            .is_some_and(|version| version.eq(to_version));
+        if has_target_tag && has_target_label {
+            done_nodes.push(node);
+        } else {
+            pending_nodes.push(node);
+        }
+    }
+
+    match (done_nodes.is_empty(), pending_nodes.is_empty()) {
+        (_, true) => RolloutState::Completed,
+        (true, false) => RolloutState::NotStarted,
+        (false, false) => RolloutState::InProgress {
+            done_nodes,
+            pending_nodes,
+        },
+    }
+}
+
+/// This picks out the image tag of the io-engine container in a Pod spec.
+fn io_engine_image_tag(spec: &PodSpec) -> Option<&str> {
+    spec.containers
+        .iter()
+        .find(|container| container.name.eq(IO_ENGINE_CONTAINER_NAME))
+        .and_then(|container| container.image.as_deref())
+        .map(image_tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, Method, Request, Response};
+    use k8s_openapi::{
+        api::{
+            apps::v1::{DaemonSetSpec, DaemonSetStatus, DaemonSetUpdateStrategy},
+            core::v1::Container,
+        },
+        apimachinery::pkg::apis::meta::v1::ObjectMeta,
+    };
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    /// This builds an io-engine DaemonSet with an update strategy type, if any.
+    fn io_engine_daemonset(strategy_type: Option<&str>) -> DaemonSet {
+        DaemonSet {
+            spec: Some(DaemonSetSpec {
+                update_strategy: strategy_type.map(|strategy_type| DaemonSetUpdateStrategy {
+                    type_: Some(strategy_type.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn on_delete_daemonset_is_left_as_is() {
+        assert_eq!(
+            UpdateStrategy::from(&io_engine_daemonset(Some("OnDelete"))),
+            UpdateStrategy::OnDelete
+        );
+    }
+
+    #[test]
+    fn rolling_update_daemonset_is_switched_to_on_delete() {
+        assert_eq!(
+            UpdateStrategy::from(&io_engine_daemonset(Some("RollingUpdate"))),
+            UpdateStrategy::RollingUpdate
+        );
+        // Kubernetes defaults to RollingUpdate.
+        assert_eq!(
+            UpdateStrategy::from(&io_engine_daemonset(None)),
+            UpdateStrategy::RollingUpdate
+        );
+
+        let patched: DaemonSet = serde_json::from_value(on_delete_patch()).unwrap();
+        assert_eq!(UpdateStrategy::from(&patched), UpdateStrategy::OnDelete);
+    }
+
+    /// These are the merge patches which the Kubernetes API server received.
+    type Patches = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// This is a Kubernetes API server with the io-engine DaemonSet, which records the patches of
+    /// the DaemonSet.
+    fn k8s_client(daemonset: DaemonSet, patches: Patches) -> KubeClientSet {
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let daemonset = daemonset.clone();
+            let patches = patches.clone();
+            async move {
+                let body = if request.method().eq(&Method::PATCH) {
+                    let patch = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    patches
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_slice(&patch).unwrap());
+                    serde_json::to_value(&daemonset).unwrap()
+                } else {
+                    serde_json::json!({
+                        "apiVersion": "apps/v1",
+                        "kind": "DaemonSetList",
+                        "metadata": {},
+                        "items": [daemonset],
+                    })
+                };
+                Ok::<_, Infallible>(Response::new(Body::from(body.to_string())))
+            }
+        });
+        KubeClientSet::with_client(kube::Client::new(service, "mayastor"), "mayastor")
+    }
+
+    /// This builds a named io-engine DaemonSet with an update strategy type, which schedules an
+    /// io-engine Pod on four nodes.
+    fn scheduled_io_engine_daemonset(strategy_type: Option<&str>) -> DaemonSet {
+        let mut daemonset = io_engine_daemonset(strategy_type);
+        daemonset.metadata.name = Some("mayastor-io-engine".to_string());
+        daemonset.status = Some(DaemonSetStatus {
+            desired_number_scheduled: 4,
+            ..Default::default()
+        });
+        daemonset
+    }
+
+    /// This parses the upgrade-job's arguments, with the extra arguments.
+    fn cli_args(extra_args: &[&str]) -> CliArgs {
+        let mut args = vec![
+            "--namespace",
+            "mayastor",
+            "--release-name",
+            "mayastor",
+            "--core-chart-dir",
+            "chart",
+        ];
+        args.extend(extra_args);
+        args.push("upgrade-job-pod");
+        crate::opts::tests::parse(args.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn controller_rollout_is_only_left_to_the_daemonset_controller_for_full_restarts() {
+        assert!(controller_rollout(&cli_args(&[]), true).is_some());
+        assert!(controller_rollout(&cli_args(&[]), false).is_none());
+        assert!(controller_rollout(&cli_args(&["--canary"]), true).is_none());
+        assert!(
+            controller_rollout(&cli_args(&["--skip-node-label", "upgrade=skip"]), true).is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn on_delete_daemonset_is_restarted_by_pod_deletes_without_a_patch() {
+        let patches = Patches::default();
+        let k8s_client = k8s_client(
+            scheduled_io_engine_daemonset(Some("OnDelete")),
+            patches.clone(),
+        );
+
+        let strategy = prepare_rollout(&k8s_client, "mayastor", Some(MaxUnavailable::Count(2)))
+            .await
+            .unwrap();
+
+        assert_eq!(strategy, UpdateStrategy::OnDelete);
+        assert!(patches.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rolling_update_daemonset_is_patched_with_the_max_unavailable() {
+        let patches = Patches::default();
+        let k8s_client = k8s_client(
+            scheduled_io_engine_daemonset(Some("RollingUpdate")),
+            patches.clone(),
+        );
+
+        let strategy = prepare_rollout(&k8s_client, "mayastor", Some(MaxUnavailable::Percent(50)))
+            .await
+            .unwrap();
+
+        assert_eq!(strategy, UpdateStrategy::RollingUpdate);
+        assert_eq!(*patches.lock().unwrap(), vec![rolling_update_patch(2)]);
+    }
+
+    #[tokio::test]
+    async fn rolling_update_daemonset_is_set_to_on_delete_without_a_controller_rollout() {
+        let patches = Patches::default();
+        let k8s_client = k8s_client(scheduled_io_engine_daemonset(None), patches.clone());
+
+        let strategy = prepare_rollout(&k8s_client, "mayastor", None)
+            .await
+            .unwrap();
+
+        assert_eq!(strategy, UpdateStrategy::OnDelete);
+        assert_eq!(*patches.lock().unwrap(), vec![on_delete_patch()]);
+    }
+
+    /// This builds an io-engine Pod on a node, with an image tag and a helm chart version label.
+    fn io_engine_pod(node: &str, tag: &str, chart_version: &str) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                labels: Some(
+                    [(
+                        CHART_VERSION_LABEL_KEY.to_string(),
+                        chart_version.to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some(node.to_string()),
+                containers: vec![Container {
+                    name: IO_ENGINE_CONTAINER_NAME.to_string(),
+                    image: Some(format!("docker.io/openebs/mayastor-io-engine:{tag}")),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rollout_state_is_in_progress_for_some_upgraded_pods() {
+        let pods = [
+            io_engine_pod("node-a", "v2.5.0", "2.5.0"),
+            io_engine_pod("node-b", "v2.4.0", "2.4.0"),
+        ];
+        assert_eq!(
+            rollout_state("v2.5.0", "2.5.0", &pods),
+            RolloutState::InProgress {
+                done_nodes: vec!["node-a".to_string()],
+                pending_nodes: vec!["node-b".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn rollout_state_does_not_count_tag_only_matches() {
+        let pods = [
+            io_engine_pod("node-a", "v2.5.0", "2.4.0"),
+            io_engine_pod("node-b", "v2.4.0", "2.4.0"),
+        ];
+        assert_eq!(
+            rollout_state("v2.5.0", "2.5.0", &pods),
+            RolloutState::NotStarted
+        );
+    }
+
+    #[test]
+    fn rollout_state_is_completed_for_all_upgraded_pods() {
+        let pods = [
+            io_engine_pod("node-a", "v2.5.0", "2.5.0"),
+            io_engine_pod("node-b", "v2.5.0", "2.5.0"),
+        ];
+        assert_eq!(
+            rollout_state("v2.5.0", "2.5.0", &pods),
+            RolloutState::Completed
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    common::{
+        error::{ReadingFile, Result, U8VectorToString, ValuesToJson},
+        kube_client::KubeClientSet,
+    },
+    helm::{
+        chart::{validate_chart_name_match, Chart, FromPath},
+        client::HelmReleaseClient,
+        release::load_installed_chart,
+        values::generate_values_yaml_file,
+    },
+    opts::{CliArgs, OutputFormat},
+};
+use snafu::ResultExt;
+use std::{fs, str};
+use tracing::info;
+
+/// This prints the helm values which the upgrade would pass to 'helm upgrade', to stdout. These
+/// are the installed release's values, migrated to the target version and merged with the target
+/// helm chart's values. Other than reading the installed helm release, this makes no requests to
+/// the cluster.
+pub(crate) async fn render_values(opts: &CliArgs) -> Result<()> {
+    let namespace = opts.namespace();
+    let release_name = opts.release_name();
+    let chart_dir = opts.core_chart_dir();
+
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(namespace.as_str())
+        .build()
+        .await?;
+    let installed_chart =
+        load_installed_chart(&k8s_client, release_name.as_str(), namespace.as_str()).await?;
+    let to_chart = Chart::from_path(chart_dir.join("Chart.yaml").as_path())?;
+    validate_chart_name_match(&installed_chart, &to_chart)?;
+
+    let client = HelmReleaseClient::builder()
+        .with_namespace(namespace)
+        .build()?;
+    let (values_file, _) = generate_values_yaml_file(
+        installed_chart.version(),
+        to_chart.version(),
+        chart_dir.as_path(),
+        opts.values_dir().as_path(),
+        &client,
+        release_name,
+        &opts.values_overrides(),
+    )?;
+    let values_yaml = fs::read(values_file.path()).context(ReadingFile {
+        filepath: values_file.path().to_path_buf(),
+    })?;
+
+    print!("{}", render(values_yaml.as_slice(), opts.output())?);
+
+    info!(
+        "Rendered helm values for the upgrade from {} to {}, no changes were made",
+        installed_chart.version(),
+        to_chart.version()
+    );
+
+    Ok(())
+}
+
+/// This renders the helm values yaml in the output format, i.e. as the yaml itself, or as a line
+/// of JSON.
+fn render(values_yaml: &[u8], output: OutputFormat) -> Result<String> {
+    match output {
+        OutputFormat::Json => {
+            let values: serde_yaml::Value = serde_yaml::from_slice(values_yaml)?;
+            let values_json = serde_json::to_string(&values).context(ValuesToJson)?;
+            Ok(format!("{values_json}\n"))
+        }
+        OutputFormat::Text => Ok(str::from_utf8(values_yaml)
+            .context(U8VectorToString)?
+            .to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::{merge::deep_merge, migration::apply_migrations};
+    use semver::Version;
+
+    /// These are the helm values of a release of helm chart version 2.0.1, which were nested under
+    /// the Core chart's name.
+    const INSTALLED_VALUES_YAML: &str = "\
+mayastor:
+  io_engine:
+    logLevel: debug
+  base:
+    default_req_timeout: 15s
+";
+
+    /// These are the default helm values of the helm chart to upgrade to.
+    const TARGET_VALUES_YAML: &str = "\
+image:
+  registry: docker.io
+  repo: openebs
+  tag: v2.5.0
+base:
+  default_req_timeout: 5s
+  logging:
+    format: pretty
+io_engine:
+  logLevel: info
+  cpuCount: '2'
+";
+
+    /// This renders the helm values for an upgrade from 2.0.1 to 2.5.0, i.e. the installed values
+    /// migrated to 2.5.0 and merged over the target helm chart's values.
+    fn render_upgrade_values(output: OutputFormat) -> String {
+        let mut installed: serde_yaml::Value = serde_yaml::from_str(INSTALLED_VALUES_YAML).unwrap();
+        apply_migrations(
+            &Version::new(2, 0, 1),
+            &Version::new(2, 5, 0),
+            &mut installed,
+        )
+        .unwrap();
+        let target: serde_yaml::Value = serde_yaml::from_str(TARGET_VALUES_YAML).unwrap();
+        let values_yaml = serde_yaml::to_string(&deep_merge(target, installed)).unwrap();
+
+        render(values_yaml.as_bytes(), output).unwrap()
+    }
+
+    #[test]
+    fn rendered_yaml_matches_the_golden_file() {
+        assert_eq!(
+            render_upgrade_values(OutputFormat::Text),
+            include_str!("testdata/render_values.golden.yaml")
+        );
+    }
+
+    #[test]
+    fn rendered_json_matches_the_golden_file() {
+        assert_eq!(
+            render_upgrade_values(OutputFormat::Json),
+            include_str!("testdata/render_values.golden.json")
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{
+    common::{constants::PRODUCT, error::Result, kube_client::KubeClientSet},
+    events::event_recorder::{EventAction, EventRecorder},
+    helm::{
+        client::HelmReleaseClient,
+        migration::validate_reversible,
+        release::{load_installed_chart, load_previous_release},
+    },
+    opts::CliArgs,
+    upgrade::{
+        check_storage_health,
+        metrics::{UpgradeMetrics, UpgradePhase},
+        restart_data_plane,
+        state::StateStore,
+    },
+};
+use semver::Version;
+use std::sync::Arc;
+use tracing::info;
+
+/// This is the helm release revision which the rollback restores.
+#[derive(Debug, PartialEq)]
+struct RollbackTarget {
+    /// The installed helm chart version.
+    from_version: Version,
+    /// The helm chart version of the previously deployed revision.
+    to_version: Version,
+    /// The previously deployed helm release revision.
+    revision: u32,
+}
+
+impl RollbackTarget {
+    /// This fails if the rollback would have to reverse a migration which is not reversible. The
+    /// migrations of the upgrade to the installed version are the ones to be reversed.
+    fn validate(&self) -> Result<()> {
+        validate_reversible(&self.to_version, &self.from_version)
+    }
+}
+
+/// This finds the installed helm chart version and the previously deployed revision of the helm
+/// release, from the helm release Secrets.
+async fn rollback_target(
+    k8s_client: &KubeClientSet,
+    release_name: &str,
+    namespace: &str,
+) -> Result<RollbackTarget> {
+    let installed_chart = load_installed_chart(k8s_client, release_name, namespace).await?;
+    let previous_release = load_previous_release(k8s_client, release_name, namespace).await?;
+
+    Ok(RollbackTarget {
+        from_version: installed_chart.version().clone(),
+        to_version: previous_release.chart().version().clone(),
+        revision: previous_release.revision(),
+    })
+}
+
+/// This rolls the helm release back to the previously deployed revision, i.e. the chart version
+/// and the helm values from before the last upgrade, and then restarts the io-engine DaemonSet
+/// Pods. The same storage health check and storage Node drains as that of an upgrade apply.
+pub(crate) async fn rollback(
+    opts: &CliArgs,
+    event: &mut EventRecorder,
+    maybe_metrics: Option<&Arc<UpgradeMetrics>>,
+) -> Result<()> {
+    let namespace = opts.namespace();
+    let release_name = opts.release_name();
+
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(namespace.as_str())
+        .build()
+        .await?;
+    let target = rollback_target(&k8s_client, release_name.as_str(), namespace.as_str()).await?;
+    let RollbackTarget {
+        from_version,
+        to_version,
+        revision,
+    } = &target;
+    event.set_from_version(from_version.to_string());
+    event.set_to_version(to_version.to_string());
+
+    let validation_result = match target.validate() {
+        Ok(()) => check_storage_health(opts).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = validation_result {
+        event.publish_unrecoverable(&error, true).await;
+        return Err(error);
+    }
+
+    event
+        .publish_normal(
+            format!(
+                "Rolling back {PRODUCT} from version {from_version} to version {to_version}, \
+                helm release revision {revision}"
+            ),
+            EventAction::RollingBack,
+        )
+        .await?;
+
+    if let Some(metrics) = maybe_metrics {
+        metrics.set_phase(UpgradePhase::ControlPlane);
+    }
+    let helm_client = HelmReleaseClient::builder()
+        .with_namespace(namespace.as_str())
+        .build()?;
+    if let Err(error) = helm_client.rollback(release_name.as_str(), *revision) {
+        event.publish_unrecoverable(&error, false).await;
+        return Err(error);
+    }
+    info!("Helm rollback successful!");
+
+    if !opts.skip_data_plane_restart() {
+        // The progress of an interrupted upgrade is of no use to the rollback.
+        let restart_result = match StateStore::new(&k8s_client, release_name.as_str())
+            .clear()
+            .await
+        {
+            Ok(()) => {
+                restart_data_plane(opts, to_version.to_string(), Arc::default(), maybe_metrics)
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+        if let Err(error) = restart_result {
+            event.publish_unrecoverable(&error, false).await;
+            return Err(error);
+        }
+    }
+
+    event
+        .publish_normal(
+            format!("Rolled back {PRODUCT} to version {to_version}"),
+            EventAction::RolledBack,
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helm::release::tests::release_secret_with_payload;
+    use hyper::{Body, Request, Response};
+    use k8s_openapi::api::core::v1::Secret;
+    use kube::ResourceExt;
+    use std::convert::Infallible;
+
+    /// This is a Kubernetes API server with the helm release Secrets. The Secrets are listed by the
+    /// status in the label selector, i.e. the superseded revisions, or the current ones.
+    fn k8s_client(secrets: Vec<Secret>) -> KubeClientSet {
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let statuses: &[&str] = if request.uri().query().unwrap().contains("superseded") {
+                &["superseded"]
+            } else {
+                &["deployed", "failed"]
+            };
+            let items: Vec<&Secret> = secrets
+                .iter()
+                .filter(|secret| {
+                    secret
+                        .labels()
+                        .get("status")
+                        .is_some_and(|status| statuses.contains(&status.as_str()))
+                })
+                .collect();
+            let list = serde_json::json!({
+                "apiVersion": "v1",
+                "kind": "SecretList",
+                "metadata": {},
+                "items": items,
+            });
+            async move { Ok::<_, Infallible>(Response::new(Body::from(list.to_string()))) }
+        });
+        KubeClientSet::with_client(kube::Client::new(service, "mayastor"), "mayastor")
+    }
+
+    #[tokio::test]
+    async fn tag_only_rollback_restores_the_previous_revision() {
+        let k8s_client = k8s_client(vec![
+            release_secret_with_payload(
+                1,
+                "superseded",
+                "2.5.0",
+                serde_json::json!({"image": {"tag": "v2.5.0"}}),
+            ),
+            release_secret_with_payload(
+                2,
+                "deployed",
+                "2.5.1",
+                serde_json::json!({"image": {"tag": "v2.5.1"}}),
+            ),
+        ]);
+
+        let target = rollback_target(&k8s_client, "mayastor", "mayastor")
+            .await
+            .unwrap();
+        assert_eq!(
+            target,
+            RollbackTarget {
+                from_version: Version::new(2, 5, 1),
+                to_version: Version::new(2, 5, 0),
+                revision: 1,
+            }
+        );
+        assert!(target.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn rollback_without_a_previous_revision_fails() {
+        let k8s_client = k8s_client(vec![release_secret_with_payload(
+            1,
+            "deployed",
+            "2.5.1",
+            serde_json::Value::Null,
+        )]);
+
+        assert!(matches!(
+            rollback_target(&k8s_client, "mayastor", "mayastor").await,
+            Err(crate::common::error::Error::NoPreviousHelmRelease { .. })
+        ));
+    }
+}
@@ -0,0 +1,739 @@
+use crate::{
+    common::{
+        constants::{
+            IO_ENGINE_LABEL, UPGRADE_PAUSED_CONFIGMAP_DATA_KEY, UPGRADE_PAUSE_POLL_INTERVAL,
+            UPGRADE_STATE_CONFIGMAP_DATA_KEY, UPGRADE_STATE_CONFIGMAP_NAME_SUFFIX,
+        },
+        error::{
+            DeleteUpgradeStateConfigMap, GetUpgradeStateConfigMap, JsonParseUpgradeState,
+            ListPodsWithLabel, MixedIoEngineImageTags, PatchUpgradeStateConfigMap, Result,
+            SerializeUpgradeState, UpgradeTargetChanged,
+        },
+        kube_client::KubeClientSet,
+        retry::kube_with_backoff,
+    },
+    upgrade::verify::{image_tag, io_engine_image},
+};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams},
+    core::ObjectMeta,
+    ResourceExt,
+};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// This is the field manager for the server-side apply of the upgrade state ConfigMap.
+const FIELD_MANAGER: &str = "upgrade-job";
+
+/// This is the field manager for the server-side apply of the paused key, when the upgrade-job
+/// pauses the upgrade itself. This is apart from FIELD_MANAGER, so that saving the UpgradeState
+/// does not remove the key.
+const PAUSE_FIELD_MANAGER: &str = "upgrade-job-pause";
+
+/// This is the progress of a data-plane upgrade, as persisted across upgrade-job restarts.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UpgradeState {
+    /// The version which the upgrade is upgrading to.
+    target_version: String,
+    /// The names of the nodes whose io-engine Pods have been upgraded.
+    completed_nodes: BTreeSet<String>,
+    /// The time which the upgrade has spent so far, in seconds, across upgrade-job runs and
+    /// without the time for which it was paused. This is for the --overall-timeout.
+    #[serde(default)]
+    elapsed_secs: u64,
+}
+
+impl UpgradeState {
+    /// This creates an UpgradeState with no completed nodes.
+    pub(crate) fn new(target_version: String) -> Self {
+        Self {
+            target_version,
+            completed_nodes: BTreeSet::new(),
+            elapsed_secs: 0,
+        }
+    }
+
+    /// This returns the time which the upgrade has spent, as of the last save.
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_secs(self.elapsed_secs)
+    }
+
+    /// This records the time which the upgrade has spent so far.
+    pub(crate) fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed_secs = elapsed.as_secs();
+    }
+
+    /// This returns true if the io-engine Pod on the node has been upgraded.
+    pub(crate) fn is_completed(&self, node_name: &str) -> bool {
+        self.completed_nodes.contains(node_name)
+    }
+
+    /// This returns true if the io-engine Pod on any node has been upgraded.
+    pub(crate) fn has_completed_nodes(&self) -> bool {
+        !self.completed_nodes.is_empty()
+    }
+
+    /// This records that the io-engine Pod on the node has been upgraded.
+    pub(crate) fn mark_completed(&mut self, node_name: &str) {
+        self.completed_nodes.insert(node_name.to_string());
+    }
+}
+
+/// This measures the time which the upgrade spends, for the --overall-timeout. The time for
+/// which the upgrade is paused is left out, and the time spent by the previous upgrade-job runs
+/// is carried over. Within a run, the time is measured with a monotonic clock.
+pub(crate) struct UpgradeClock {
+    started_at: Instant,
+    carried_over: Duration,
+    paused: Duration,
+}
+
+impl UpgradeClock {
+    /// This creates an UpgradeClock for a run which started at 'started_at', after the previous
+    /// runs spent 'carried_over'.
+    pub(crate) fn new(started_at: Instant, carried_over: Duration) -> Self {
+        Self {
+            started_at,
+            carried_over,
+            paused: Duration::ZERO,
+        }
+    }
+
+    /// This records that the upgrade was paused for a while.
+    pub(crate) fn add_paused(&mut self, paused: Duration) {
+        self.paused += paused;
+    }
+
+    /// This returns the time which the upgrade has spent so far.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.elapsed_at(Instant::now())
+    }
+
+    /// This returns the time which the upgrade has spent as of 'now'.
+    fn elapsed_at(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.started_at)
+            .saturating_sub(self.paused)
+            + self.carried_over
+    }
+}
+
+/// This persists the UpgradeState in a ConfigMap, so that a restarted upgrade-job may resume the
+/// data-plane upgrade instead of starting over.
+pub(crate) struct StateStore {
+    configmaps_api: Api<ConfigMap>,
+    name: String,
+    /// The interval at which a paused upgrade checks if it has been resumed.
+    pause_poll_interval: Duration,
+}
+
+impl StateStore {
+    /// This creates a StateStore for a helm release.
+    pub(crate) fn new(k8s_client: &KubeClientSet, release_name: &str) -> Self {
+        Self::with_api(
+            k8s_client.configmaps_api().clone(),
+            release_name,
+            UPGRADE_PAUSE_POLL_INTERVAL,
+        )
+    }
+
+    /// This creates a StateStore for a helm release, with a ConfigMap API client.
+    fn with_api(
+        configmaps_api: Api<ConfigMap>,
+        release_name: &str,
+        pause_poll_interval: Duration,
+    ) -> Self {
+        Self {
+            configmaps_api,
+            name: format!("{release_name}{UPGRADE_STATE_CONFIGMAP_NAME_SUFFIX}"),
+            pause_poll_interval,
+        }
+    }
+
+    /// This reads the UpgradeState from the ConfigMap. A new UpgradeState is returned if the
+    /// ConfigMap does not exist. The upgrade is aborted if the stored state is of an upgrade to
+    /// a different version.
+    pub(crate) async fn load(&self, target_version: &str) -> Result<UpgradeState> {
+        let configmap = self
+            .configmaps_api
+            .get_opt(self.name.as_str())
+            .await
+            .context(GetUpgradeStateConfigMap {
+                name: self.name.clone(),
+            })?;
+
+        let Some(state_json) = configmap
+            .and_then(|configmap| configmap.data)
+            .and_then(|mut data| data.remove(UPGRADE_STATE_CONFIGMAP_DATA_KEY))
+        else {
+            return Ok(UpgradeState::new(target_version.to_string()));
+        };
+
+        let state: UpgradeState =
+            serde_json::from_str(state_json.as_str()).context(JsonParseUpgradeState {
+                name: self.name.clone(),
+            })?;
+
+        ensure!(
+            state.target_version.eq(target_version),
+            UpgradeTargetChanged {
+                stored: state.target_version,
+                target: target_version.to_string(),
+                name: self.name.clone(),
+            }
+        );
+
+        info!(
+            nodes = ?state.completed_nodes,
+            "Resuming data-plane upgrade, io-engine Pods on these nodes are already upgraded"
+        );
+
+        Ok(state)
+    }
+
+    /// This writes the UpgradeState to the ConfigMap.
+    pub(crate) async fn save(&self, state: &UpgradeState) -> Result<()> {
+        let state_json = serde_json::to_string(state).context(SerializeUpgradeState)?;
+
+        let configmap = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                UPGRADE_STATE_CONFIGMAP_DATA_KEY.to_string(),
+                state_json,
+            )])),
+            ..Default::default()
+        };
+
+        self.configmaps_api
+            .patch(
+                self.name.as_str(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&configmap),
+            )
+            .await
+            .context(PatchUpgradeStateConfigMap {
+                name: self.name.clone(),
+            })?;
+
+        Ok(())
+    }
+
+    /// This decides if the data-plane upgrade is paused. An operator pauses the upgrade by setting
+    /// the 'paused' key of the ConfigMap to "true", and resumes it by removing the key or setting
+    /// it to anything else, e.g.
+    /// kubectl patch configmap <name> --type merge -p '{"data":{"paused":"true"}}'
+    pub(crate) async fn is_paused(&self) -> Result<bool> {
+        let configmap = self
+            .configmaps_api
+            .get_opt(self.name.as_str())
+            .await
+            .context(GetUpgradeStateConfigMap {
+                name: self.name.clone(),
+            })?;
+
+        Ok(configmap
+            .and_then(|configmap| configmap.data)
+            .and_then(|data| data.get(UPGRADE_PAUSED_CONFIGMAP_DATA_KEY).cloned())
+            .is_some_and(|paused| paused.trim().eq_ignore_ascii_case("true")))
+    }
+
+    /// This pauses the data-plane upgrade, in the same way as an operator would, so that it stays
+    /// paused until an operator resumes it, even across upgrade-job restarts.
+    pub(crate) async fn pause(&self) -> Result<()> {
+        let configmap = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                UPGRADE_PAUSED_CONFIGMAP_DATA_KEY.to_string(),
+                "true".to_string(),
+            )])),
+            ..Default::default()
+        };
+
+        self.configmaps_api
+            .patch(
+                self.name.as_str(),
+                &PatchParams::apply(PAUSE_FIELD_MANAGER).force(),
+                &Patch::Apply(&configmap),
+            )
+            .await
+            .context(PatchUpgradeStateConfigMap {
+                name: self.name.clone(),
+            })?;
+
+        Ok(())
+    }
+
+    /// This saves the progress at the end of a batch of io-engine Pod restarts, and returns the
+    /// error of the batch, if any. A successful canary batch pauses the upgrade before the progress
+    /// is saved, so that a restarted upgrade-job does not continue past the canary node without
+    /// approval. A failed canary batch aborts the upgrade without pausing it.
+    pub(crate) async fn end_batch(
+        &self,
+        state: &UpgradeState,
+        canary_batch: bool,
+        batch_result: Result<()>,
+    ) -> Result<()> {
+        if canary_batch && batch_result.is_ok() {
+            self.pause().await?;
+        }
+        self.save(state).await?;
+
+        batch_result
+    }
+
+    /// This returns the name of the upgrade state ConfigMap.
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// This blocks for as long as the data-plane upgrade is paused, and returns the time for
+    /// which it was paused. This is only called between io-engine Pod restarts, so that a node is
+    /// never left in the middle of its restart.
+    pub(crate) async fn wait_while_paused(&self) -> Result<Duration> {
+        if !self.is_paused().await? {
+            return Ok(Duration::ZERO);
+        }
+        let paused_at = Instant::now();
+
+        info!(
+            configmap.name = %self.name,
+            "Data-plane upgrade is paused, waiting for it to be resumed"
+        );
+        while self.is_paused().await? {
+            tokio::time::sleep(self.pause_poll_interval).await;
+        }
+        info!(configmap.name = %self.name, "Data-plane upgrade is resumed");
+
+        Ok(paused_at.elapsed())
+    }
+
+    /// This deletes the ConfigMap, once the data-plane upgrade is complete.
+    pub(crate) async fn clear(&self) -> Result<()> {
+        match self
+            .configmaps_api
+            .delete(self.name.as_str(), &DeleteParams::default())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(error)) if error.code == 404 => Ok(()),
+            Err(error) => Err(error).context(DeleteUpgradeStateConfigMap {
+                name: self.name.clone(),
+            }),
+        }
+    }
+}
+
+/// This is a warning for io-engine Pods which run different container image tags, e.g. midway
+/// through a manual rollout. The installed state is then not that of any single version.
+pub(crate) struct ClusterNotUniform {
+    /// The names of the nodes, by the io-engine container image tag which their Pod runs.
+    tags: BTreeMap<String, Vec<String>>,
+}
+
+impl fmt::Display for ClusterNotUniform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(tag, nodes)| format!("'{tag}' on nodes {}", nodes.join(", ")))
+            .collect();
+        write!(
+            f,
+            "The io-engine Pods run {} different container image tags: {}",
+            self.tags.len(),
+            tags.join("; ")
+        )
+    }
+}
+
+/// This lists the io-engine DaemonSet Pods, and groups the names of their nodes by the io-engine
+/// container image tag which they run.
+pub(crate) async fn installed_image_tags(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+) -> Result<HashMap<String, Vec<String>>> {
+    let listparams = ListParams::default().labels(IO_ENGINE_LABEL);
+    let pods = kube_with_backoff(|| k8s_client.pods_api().list(&listparams))
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL.to_string(),
+            namespace: namespace.to_string(),
+        })?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for pod in pods.iter() {
+        let tag = image_tag(io_engine_image(pod, namespace)?).to_string();
+        let node = pod
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.node_name.clone())
+            .unwrap_or_else(|| pod.name_any());
+        tags.entry(tag).or_default().push(node);
+    }
+
+    Ok(tags)
+}
+
+/// This returns a ClusterNotUniform warning if the io-engine Pods run more than one container
+/// image tag. None of the tags is picked over the others, the operator decides to go ahead or not.
+/// If fail_on_mixed_tags is set, this fails instead.
+pub(crate) fn check_uniform(
+    tags: &HashMap<String, Vec<String>>,
+    fail_on_mixed_tags: bool,
+) -> Result<Option<ClusterNotUniform>> {
+    if tags.len() <= 1 {
+        return Ok(None);
+    }
+
+    let tags = tags
+        .iter()
+        .map(|(tag, nodes)| {
+            let mut nodes = nodes.clone();
+            nodes.sort();
+            (tag.clone(), nodes)
+        })
+        .collect();
+    let warning = ClusterNotUniform { tags };
+    ensure!(
+        !fail_on_mixed_tags,
+        MixedIoEngineImageTags {
+            warning: warning.to_string()
+        }
+    );
+
+    Ok(Some(warning))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::error::{CanaryVerificationTimeout, Error, NodeReadyTimeout},
+        upgrade::{
+            canary::{canary_pending, verify_canary_restart},
+            check_overall_timeout,
+        },
+    };
+    use hyper::{Body, Method, Request, Response, StatusCode};
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    /// This is an in-memory Kubernetes API server for the upgrade state ConfigMap. A patch merges
+    /// its data into the ConfigMap, as the server-side apply of disjoint keys would.
+    #[derive(Clone, Default)]
+    struct FakeConfigMaps(Arc<Mutex<Option<ConfigMap>>>);
+
+    impl FakeConfigMaps {
+        /// This creates a StateStore which talks to the fake API server.
+        fn state_store(&self) -> StateStore {
+            let fake = self.clone();
+            let service = tower::service_fn(move |request| fake.clone().serve(request));
+            let client = kube::Client::new(service, "default");
+            StateStore::with_api(
+                Api::namespaced(client, "default"),
+                "mayastor",
+                Duration::from_millis(10),
+            )
+        }
+
+        /// This sets a key of the ConfigMap's data, as an operator would.
+        fn set(&self, key: &str, value: &str) {
+            let mut configmap = self.0.lock().unwrap();
+            configmap
+                .as_mut()
+                .expect("the ConfigMap should exist")
+                .data
+                .get_or_insert_with(BTreeMap::new)
+                .insert(key.to_string(), value.to_string());
+        }
+
+        /// This answers a request of the ConfigMap API.
+        async fn serve(self, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+            let method = request.method().clone();
+            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+            let mut stored = self.0.lock().unwrap();
+
+            let response =
+                match (method, stored.as_mut()) {
+                    (Method::GET, None) => Response::builder().status(StatusCode::NOT_FOUND).body(
+                        Body::from(
+                            r#"{"kind":"Status","apiVersion":"v1","metadata":{},"status":"Failure",
+                        "message":"not found","reason":"NotFound","code":404}"#,
+                        ),
+                    ),
+                    (Method::GET, Some(configmap)) => {
+                        Response::builder().body(Body::from(serde_json::to_vec(configmap).unwrap()))
+                    }
+                    (Method::PATCH, _) => {
+                        let patch: ConfigMap = serde_json::from_slice(body.as_ref()).unwrap();
+                        let configmap = stored.get_or_insert_with(|| ConfigMap {
+                            metadata: patch.metadata.clone(),
+                            ..Default::default()
+                        });
+                        configmap
+                            .data
+                            .get_or_insert_with(BTreeMap::new)
+                            .extend(patch.data.unwrap_or_default());
+                        Response::builder().body(Body::from(serde_json::to_vec(configmap).unwrap()))
+                    }
+                    (method, _) => panic!("unexpected {method} request"),
+                };
+
+            Ok(response.unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_is_waited_on_at_a_node_boundary() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        let mut state = store.load("2.5.0").await.unwrap();
+        // The ConfigMap is created ahead of the first restart, so that it can be patched.
+        store.save(&state).await.unwrap();
+        fake.set(UPGRADE_PAUSED_CONFIGMAP_DATA_KEY, "true");
+
+        // The node which is being restarted completes, and its progress is saved.
+        state.mark_completed("node-1");
+        store.save(&state).await.unwrap();
+        assert!(store.is_paused().await.unwrap());
+
+        let waiting = tokio::spawn(async move { store.wait_while_paused().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished());
+
+        fake.set(UPGRADE_PAUSED_CONFIGMAP_DATA_KEY, "false");
+        let paused = waiting.await.unwrap().unwrap();
+        assert!(paused >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn canary_success_pauses_until_resumed() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        let mut state = store.load("2.5.0").await.unwrap();
+        store.save(&state).await.unwrap();
+        assert!(canary_pending(true, &state));
+
+        // The canary node's io-engine Pod is upgraded.
+        state.mark_completed("node-1");
+        store.end_batch(&state, true, Ok(())).await.unwrap();
+        assert!(store.is_paused().await.unwrap());
+
+        // A restarted upgrade-job carries on past the canary node, once it is resumed.
+        let state = store.load("2.5.0").await.unwrap();
+        assert!(!canary_pending(true, &state));
+
+        let waiting = tokio::spawn(async move { store.wait_while_paused().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished());
+
+        fake.set(UPGRADE_PAUSED_CONFIGMAP_DATA_KEY, "false");
+        assert!(waiting.await.unwrap().unwrap() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn canary_failure_aborts_without_pausing() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        let state = store.load("2.5.0").await.unwrap();
+        store.save(&state).await.unwrap();
+
+        let canary_error = NodeReadyTimeout {
+            node: "node-1",
+            elapsed: Duration::from_secs(600),
+            phase: "mayastor-io-engine-x7k2p=Pending",
+        }
+        .build();
+        let result = store.end_batch(&state, true, Err(canary_error)).await;
+        assert!(matches!(result, Err(Error::NodeReadyTimeout { .. })));
+        assert!(!store.is_paused().await.unwrap());
+
+        // No other node was upgraded, so a re-run starts at the canary node again.
+        let state = store.load("2.5.0").await.unwrap();
+        assert!(canary_pending(true, &state));
+    }
+
+    #[tokio::test]
+    async fn canary_verification_failure_is_retried_by_a_rerun() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        let state = store.load("2.5.0").await.unwrap();
+        store.save(&state).await.unwrap();
+
+        // The canary node's io-engine Pod is restarted, but its pools do not come back Online.
+        let result = verify_canary_restart(Ok(()), || async {
+            CanaryVerificationTimeout {
+                node: "node-1",
+                elapsed: Duration::from_secs(600),
+                unhealthy: "pool pool-1",
+            }
+            .fail()
+        })
+        .await;
+        let result = store.end_batch(&state, true, result).await;
+        assert!(matches!(
+            result,
+            Err(Error::CanaryVerificationTimeout { .. })
+        ));
+        assert!(!store.is_paused().await.unwrap());
+
+        // The re-run restarts and verifies the canary node again, and pauses once it is verified.
+        let mut rerun_state = store.load("2.5.0").await.unwrap();
+        assert!(canary_pending(true, &rerun_state));
+        let result = verify_canary_restart(Ok(()), || async { Ok(()) }).await;
+        assert!(result.is_ok());
+        rerun_state.mark_completed("node-1");
+        store.end_batch(&rerun_state, true, result).await.unwrap();
+        assert!(store.is_paused().await.unwrap());
+        assert!(!canary_pending(true, &store.load("2.5.0").await.unwrap()));
+
+        // The verification is not run if the restart fails.
+        let restart_error = NodeReadyTimeout {
+            node: "node-1",
+            elapsed: Duration::from_secs(600),
+            phase: "mayastor-io-engine-x7k2p=Pending",
+        }
+        .build();
+        let result = verify_canary_restart(Err(restart_error), || async {
+            panic!("the canary node should not be verified")
+        })
+        .await;
+        assert!(matches!(result, Err(Error::NodeReadyTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn unpaused_upgrade_is_not_waited_on() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        store
+            .save(&UpgradeState::new("2.5.0".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(store.wait_while_paused().await.unwrap(), Duration::ZERO);
+        assert!(store.load("2.5.0").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn changed_target_names_the_state_configmap() {
+        let fake = FakeConfigMaps::default();
+        let store = fake.state_store();
+        store
+            .save(&UpgradeState::new("2.5.0".to_string()))
+            .await
+            .unwrap();
+
+        let error = store.load("2.6.0").await.unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::UpgradeTargetChanged { stored, target, name }
+                if stored == "2.5.0" && target == "2.6.0" && name == "mayastor-upgrade-state"
+        ));
+        assert!(error
+            .to_string()
+            .contains("delete the ConfigMap 'mayastor-upgrade-state'"));
+    }
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(600));
+
+    #[test]
+    fn overall_timeout_trips_between_nodes() {
+        let started_at = Instant::now();
+        let clock = UpgradeClock::new(started_at, Duration::ZERO);
+
+        let first_node = clock.elapsed_at(started_at + Duration::from_secs(300));
+        assert!(check_overall_timeout(TIMEOUT, first_node).is_ok());
+
+        let second_node = clock.elapsed_at(started_at + Duration::from_secs(600));
+        assert!(matches!(
+            check_overall_timeout(TIMEOUT, second_node),
+            Err(Error::OverallUpgradeTimeout { .. })
+        ));
+        assert!(check_overall_timeout(None, second_node).is_ok());
+    }
+
+    #[test]
+    fn paused_time_is_left_out() {
+        let started_at = Instant::now();
+        let mut clock = UpgradeClock::new(started_at, Duration::ZERO);
+        clock.add_paused(Duration::from_secs(3600));
+
+        let elapsed = clock.elapsed_at(started_at + Duration::from_secs(3900));
+        assert_eq!(elapsed, Duration::from_secs(300));
+        assert!(check_overall_timeout(TIMEOUT, elapsed).is_ok());
+    }
+
+    #[test]
+    fn elapsed_time_is_carried_over_to_a_rerun() {
+        let mut state = UpgradeState::new("2.5.0".to_string());
+        state.set_elapsed(Duration::from_secs(500));
+        let state_json = serde_json::to_string(&state).unwrap();
+        let state: UpgradeState = serde_json::from_str(state_json.as_str()).unwrap();
+
+        let started_at = Instant::now();
+        let clock = UpgradeClock::new(started_at, state.elapsed());
+        let elapsed = clock.elapsed_at(started_at + Duration::from_secs(100));
+        assert_eq!(elapsed, Duration::from_secs(600));
+        assert!(check_overall_timeout(TIMEOUT, elapsed).is_err());
+    }
+
+    /// These are io-engine Pods on three nodes, which run two different container image tags.
+    fn two_tags() -> HashMap<String, Vec<String>> {
+        HashMap::from([
+            (
+                "v2.4.0".to_string(),
+                vec!["node-2".to_string(), "node-1".to_string()],
+            ),
+            ("v2.5.0".to_string(), vec!["node-3".to_string()]),
+        ])
+    }
+
+    #[test]
+    fn uniform_tags_are_not_warned_about() {
+        let tags = HashMap::from([("v2.5.0".to_string(), vec!["node-1".to_string()])]);
+        assert!(check_uniform(&tags, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn two_tags_are_warned_about() {
+        let warning = check_uniform(&two_tags(), false).unwrap().unwrap();
+        assert_eq!(
+            warning.to_string(),
+            "The io-engine Pods run 2 different container image tags: 'v2.4.0' on nodes node-1, \
+            node-2; 'v2.5.0' on nodes node-3"
+        );
+    }
+
+    #[test]
+    fn two_tags_fail_with_fail_on_mixed_tags() {
+        assert!(matches!(
+            check_uniform(&two_tags(), true),
+            Err(Error::MixedIoEngineImageTags { warning })
+                if warning.starts_with("The io-engine Pods run 2 different container image tags")
+        ));
+    }
+
+    #[test]
+    fn state_without_elapsed_time_is_loaded() {
+        let state: UpgradeState =
+            serde_json::from_str(r#"{"targetVersion":"2.5.0","completedNodes":["node-1"]}"#)
+                .unwrap();
+        assert!(state.is_completed("node-1"));
+        assert_eq!(state.elapsed(), Duration::ZERO);
+    }
+}
@@ -1,12 +1,20 @@
-use crate::common::{
-    constants::CHART_VERSION_LABEL_KEY,
-    error::{
-        HelmChartVersionLabelHasNoValue, ListStorageVolumes, NoNamespaceInPod, Result, SemverParse,
+use crate::{
+    common::{
+        constants::CHART_VERSION_LABEL_KEY,
+        error::{
+            HelmChartVersionLabelHasNoValue, ListNodesWithLabel, ListStorageVolumes,
+            NoNamespaceInPod, Result, SemverParse,
+        },
+        kube_client::KubeClientSet,
+        rest_client::RestClientSet,
     },
-    rest_client::RestClientSet,
+    opts::NodeLabel,
 };
 use k8s_openapi::api::core::v1::Pod;
-use kube::{api::ObjectList, ResourceExt};
+use kube::{
+    api::{ListParams, ObjectList},
+    ResourceExt,
+};
 use openapi::models::{Volume, VolumeStatus};
 use semver::Version;
 use snafu::ResultExt;
@@ -85,7 +93,23 @@ pub(crate) async fn list_unhealthy_volumes(
     rest_client: &RestClientSet,
     discarded_volumes: &[Volume],
 ) -> Result<Vec<Volume>> {
-    let mut unhealthy_volumes: Vec<Volume> = Vec::new();
+    let mut unhealthy_volumes: Vec<Volume> = list_all_volumes(rest_client)
+        .await?
+        .into_iter()
+        .filter(|volume| {
+            matches!(
+                volume.state.status,
+                VolumeStatus::Faulted | VolumeStatus::Degraded
+            )
+        })
+        .collect();
+    unhealthy_volumes.retain(|v| !discarded_volumes.contains(v));
+    Ok(unhealthy_volumes)
+}
+
+/// List all of the volumes, across the paginated responses of the REST API.
+pub(crate) async fn list_all_volumes(rest_client: &RestClientSet) -> Result<Vec<Volume>> {
+    let mut all_volumes: Vec<Volume> = Vec::new();
     // The number of volumes to get per request.
     let max_entries = 200;
     let mut starting_token = Some(0_isize);
@@ -100,17 +124,50 @@ pub(crate) async fn list_unhealthy_volumes(
 
         let volumes = vols.into_body();
         starting_token = volumes.next_token;
-        for volume in volumes.entries {
-            match volume.state.status {
-                VolumeStatus::Faulted | VolumeStatus::Degraded => {
-                    unhealthy_volumes.push(volume);
-                }
-                _ => continue,
-            }
+        all_volumes.extend(volumes.entries);
+    }
+
+    Ok(all_volumes)
+}
+
+/// This is the placement of a volume, i.e. the nodes of its target and of its replicas.
+pub(crate) struct VolumePlacement {
+    /// The uuid of the volume.
+    pub(crate) uuid: String,
+    /// The node of the volume's target, if the volume is published.
+    pub(crate) target_node: Option<String>,
+    /// The nodes of the volume's replicas.
+    pub(crate) replica_nodes: Vec<String>,
+}
+
+impl From<&Volume> for VolumePlacement {
+    fn from(volume: &Volume) -> Self {
+        Self {
+            uuid: volume.spec.uuid.to_string(),
+            target_node: volume
+                .state
+                .target
+                .as_ref()
+                .map(|target| target.node.clone()),
+            replica_nodes: volume
+                .state
+                .replica_topology
+                .values()
+                .filter_map(|topology| topology.node.clone())
+                .collect(),
         }
     }
-    unhealthy_volumes.retain(|v| !discarded_volumes.contains(v));
-    Ok(unhealthy_volumes)
+}
+
+/// List the placements of all of the volumes.
+pub(crate) async fn list_volume_placements(
+    rest_client: &RestClientSet,
+) -> Result<Vec<VolumePlacement>> {
+    Ok(list_all_volumes(rest_client)
+        .await?
+        .iter()
+        .map(VolumePlacement::from)
+        .collect())
 }
 
 /// Count of number of replica rebuilding.
@@ -174,16 +231,21 @@ pub(crate) fn all_pods_are_ready(pod_list: ObjectList<Pod>) -> bool {
 }
 
 /// Checks to see if all of io-engine Pods are already upgraded to the version of the local helm
-/// chart.
-pub(crate) async fn data_plane_is_upgraded(
+/// chart. The io-engine Pods on the skipped nodes remain at the installed version, so they are
+/// left out.
+pub(crate) fn data_plane_is_upgraded(
     to_version: &str,
-    io_engine_pod_list: &ObjectList<Pod>,
+    io_engine_pods: &[Pod],
+    skipped_nodes: &HashSet<String>,
 ) -> Result<bool> {
     let to_version_requirement: Version = Version::parse(to_version).context(SemverParse {
         version_string: to_version.to_string(),
     })?;
 
-    for pod in io_engine_pod_list {
+    for pod in io_engine_pods {
+        if is_on_skipped_node(pod, skipped_nodes) {
+            continue;
+        }
         let version_str = pod.labels().get(CHART_VERSION_LABEL_KEY).ok_or(
             HelmChartVersionLabelHasNoValue {
                 pod_name: pod.name_any(),
@@ -208,3 +270,86 @@ pub(crate) async fn data_plane_is_upgraded(
 
     Ok(true)
 }
+
+/// This is true if the Pod is scheduled to one of the skipped nodes.
+pub(crate) fn is_on_skipped_node(pod: &Pod, skipped_nodes: &HashSet<String>) -> bool {
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.node_name.as_ref())
+        .is_some_and(|node_name| skipped_nodes.contains(node_name))
+}
+
+/// This lists the names of the Kubernetes Nodes with the --skip-node-label, whose io-engine Pods
+/// are not to be restarted. There are none if the label is not set.
+pub(crate) async fn skipped_nodes(
+    k8s_client: &KubeClientSet,
+    skip_node_label: Option<&NodeLabel>,
+) -> Result<HashSet<String>> {
+    let Some(label) = skip_node_label else {
+        return Ok(HashSet::new());
+    };
+
+    let label = label.to_string();
+    let nodes = k8s_client
+        .nodes_api()
+        .list(&ListParams::default().labels(label.as_str()))
+        .await
+        .context(ListNodesWithLabel {
+            label: label.clone(),
+        })?;
+
+    Ok(nodes.iter().map(|node| node.name_any()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::{api::core::v1::PodSpec, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+
+    /// This builds an io-engine Pod on a node, with a helm chart version label.
+    fn io_engine_pod(node: &str, chart_version: &str) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                namespace: Some("mayastor".to_string()),
+                labels: Some(
+                    [(
+                        CHART_VERSION_LABEL_KEY.to_string(),
+                        chart_version.to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            spec: Some(PodSpec {
+                node_name: Some(node.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn labeled_node_is_skipped() {
+        let pods = [
+            io_engine_pod("node-1", "2.5.0"),
+            io_engine_pod("node-2", "2.4.0"),
+        ];
+        let skipped_nodes = HashSet::from(["node-2".to_string()]);
+
+        assert!(is_on_skipped_node(&pods[1], &skipped_nodes));
+        assert!(data_plane_is_upgraded("2.5.0", &pods, &skipped_nodes).unwrap());
+    }
+
+    #[test]
+    fn unlabeled_node_is_processed() {
+        let pods = [
+            io_engine_pod("node-1", "2.5.0"),
+            io_engine_pod("node-2", "2.4.0"),
+        ];
+        let skipped_nodes = HashSet::from(["node-3".to_string()]);
+
+        assert!(!is_on_skipped_node(&pods[1], &skipped_nodes));
+        assert!(!data_plane_is_upgraded("2.5.0", &pods, &skipped_nodes).unwrap());
+        assert!(!data_plane_is_upgraded("2.5.0", &pods, &HashSet::new()).unwrap());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{
+    common::error::{Result, SerializeValuesSchema},
+    helm::chart::CoreValues,
+};
+use schemars::{
+    schema::{InstanceType, RootSchema, Schema, SchemaObject},
+    schema_for,
+};
+use snafu::ResultExt;
+
+/// This prints the JSON schema of the Core chart's helm values which the upgrade-job reads, to
+/// stdout. The schema only has the keys which the upgrade-job deserializes, the helm chart's own
+/// values schema covers the rest. If a key is set, the Core chart's values are nested under it,
+/// as in the values of the Umbrella chart.
+pub(crate) fn print_values_schema(core_values_key: Option<&str>) -> Result<()> {
+    let schema_json = serde_json::to_string_pretty(&values_schema(core_values_key))
+        .context(SerializeValuesSchema)?;
+    println!("{schema_json}");
+    Ok(())
+}
+
+/// This is the JSON schema of the Core chart's helm values, nested under the key if it is set.
+fn values_schema(core_values_key: Option<&str>) -> RootSchema {
+    let schema = schema_for!(CoreValues);
+    match core_values_key {
+        Some(key) => nest_under(schema, key),
+        None => schema,
+    }
+}
+
+/// This moves the root of a schema under an object key, keeping the definitions at the root.
+fn nest_under(mut schema: RootSchema, key: &str) -> RootSchema {
+    let nested = Schema::Object(schema.schema);
+    let mut root = SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    };
+    root.object().properties.insert(key.to_string(), nested);
+    root.object().required.insert(key.to_string());
+    schema.schema = root;
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// This follows the yaml keys of a path through the schema's properties, resolving the
+    /// references to the schema's definitions on the way. This is None if a key is absent.
+    fn property<'a>(schema: &'a Value, mut node: &'a Value, path: &str) -> Option<&'a Value> {
+        for key in path.split('.') {
+            node = resolve(schema, node)?.get("properties")?.get(key)?;
+        }
+        resolve(schema, node)
+    }
+
+    /// This resolves a schema node to the schema object with properties, following '$ref's and
+    /// the alternatives of 'allOf' and 'anyOf', e.g. for an Option.
+    fn resolve<'a>(schema: &'a Value, node: &'a Value) -> Option<&'a Value> {
+        if let Some(reference) = node.get("$ref").and_then(Value::as_str) {
+            let name = reference.strip_prefix("#/definitions/")?;
+            return resolve(schema, schema.get("definitions")?.get(name)?);
+        }
+        for alternatives in ["allOf", "anyOf"] {
+            if let Some(alternatives) = node.get(alternatives).and_then(Value::as_array) {
+                return alternatives
+                    .iter()
+                    .find_map(|alternative| resolve(schema, alternative));
+            }
+        }
+        node.is_object().then_some(node)
+    }
+
+    #[test]
+    fn schema_has_the_pool_commitment_path() {
+        let schema = serde_json::to_value(values_schema(Some("mayastor"))).unwrap();
+        let pool_commitment = property(
+            &schema,
+            &schema,
+            "mayastor.agents.core.capacity.thin.poolCommitment",
+        )
+        .unwrap();
+
+        assert!(pool_commitment["type"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("string")));
+    }
+
+    #[test]
+    fn schema_is_not_nested_without_a_key() {
+        let schema = serde_json::to_value(values_schema(None)).unwrap();
+        assert!(property(&schema, &schema, "agents.core.capacity.thin.poolCommitment").is_some());
+        assert!(property(&schema, &schema, "mayastor").is_none());
+    }
+}
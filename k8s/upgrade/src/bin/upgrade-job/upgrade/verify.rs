@@ -0,0 +1,536 @@
+use crate::{
+    common::{
+        constants::{IO_ENGINE_CONTAINER_NAME, IO_ENGINE_LABEL, IO_ENGINE_LOG_LEVEL_ENV},
+        error::{
+            ConfigDriftDetected, EmptyPodSpec, GetStatefulSet, IoEngineContainerAbsent,
+            ListPodsWithLabel, PodImageTagMismatch, Result, SerializeConfigDrift,
+        },
+        kube_client::KubeClientSet,
+    },
+    helm::{
+        chart::{detect_and_load, LoadedValues},
+        client::HelmReleaseClient,
+        release::load_installed_chart,
+    },
+    opts::{CliArgs, NodeLabel, OutputFormat},
+    upgrade::utils::skipped_nodes,
+};
+use k8s_openapi::api::{apps::v1::StatefulSet, core::v1::Pod};
+use kube::{api::ListParams, ResourceExt};
+use serde::Serialize;
+use snafu::{ensure, ResultExt};
+use std::{collections::HashSet, fmt};
+use tracing::{info, warn};
+use utils::ETCD_LABEL;
+
+/// This is a difference between the installed helm release's values and the cluster.
+#[derive(Serialize)]
+pub(crate) struct ConfigDrift {
+    /// The object and the setting which differ, e.g. 'Pod mayastor-io-engine-x4f2k image tag'.
+    field: String,
+    /// The setting as per the installed helm values.
+    expected: String,
+    /// The setting in the cluster.
+    actual: String,
+}
+
+impl fmt::Display for ConfigDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is '{}', expected '{}'",
+            self.field, self.actual, self.expected
+        )
+    }
+}
+
+impl ConfigDrift {
+    /// This returns a ConfigDrift if the expected and the actual settings differ.
+    fn of<E, A>(field: String, expected: E, actual: A) -> Option<Self>
+    where
+        E: ToString,
+        A: ToString,
+    {
+        let (expected, actual) = (expected.to_string(), actual.to_string());
+        (expected != actual).then_some(Self {
+            field,
+            expected,
+            actual,
+        })
+    }
+}
+
+/// This checks that the cluster matches the installed helm release's values, and prints the
+/// differences. The io-engine Pods' container image tags and log levels, and the replicas of the
+/// etcd StatefulSet are compared. This fails if there is any difference.
+pub(crate) async fn verify_installed(opts: &CliArgs) -> Result<()> {
+    let namespace = opts.namespace();
+    let release_name = opts.release_name();
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(namespace.as_str())
+        .build()
+        .await?;
+    let values = installed_values(&k8s_client, namespace.as_str(), release_name.as_str()).await?;
+
+    let mut drifts = io_engine_drifts(&k8s_client, namespace.as_str(), &values).await?;
+    drifts.extend(etcd_drift(&k8s_client, namespace.as_str(), &values).await?);
+
+    match opts.output() {
+        OutputFormat::Json => {
+            let drifts_json = serde_json::to_string(&drifts).context(SerializeConfigDrift)?;
+            println!("{drifts_json}");
+        }
+        OutputFormat::Text => {
+            for drift in drifts.iter() {
+                println!("{drift}");
+            }
+        }
+    }
+    ensure!(
+        drifts.is_empty(),
+        ConfigDriftDetected {
+            release_name,
+            drifts: drifts.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        }
+    );
+
+    info!("The cluster matches the values of helm release '{release_name}'");
+    Ok(())
+}
+
+/// This reads the installed helm release's values. The Core chart's values are nested under the
+/// alias of the Core chart dependency, if the release is of the Umbrella chart.
+async fn installed_values(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    release_name: &str,
+) -> Result<LoadedValues> {
+    let helm_client = HelmReleaseClient::builder()
+        .with_namespace(namespace)
+        .build()?;
+
+    let chart = load_installed_chart(k8s_client, release_name, namespace).await?;
+    let values_yaml = helm_client.get_values_as_yaml::<&str, String>(release_name, None)?;
+    detect_and_load(
+        &String::from_utf8_lossy(values_yaml.as_slice()),
+        chart.core_values_key(),
+    )
+}
+
+/// This compares the io-engine containers' image tags and log levels against the helm values.
+async fn io_engine_drifts(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    values: &LoadedValues,
+) -> Result<Vec<ConfigDrift>> {
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(IO_ENGINE_LABEL))
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL.to_string(),
+            namespace: namespace.to_string(),
+        })?;
+
+    io_engine_pod_drifts(pods.items.as_slice(), namespace, values)
+}
+
+/// This is like io_engine_drifts, for the listed io-engine Pods.
+fn io_engine_pod_drifts(
+    pods: &[Pod],
+    namespace: &str,
+    values: &LoadedValues,
+) -> Result<Vec<ConfigDrift>> {
+    let mut drifts = Vec::new();
+    for pod in pods {
+        let image = io_engine_image(pod, namespace)?;
+        drifts.extend(ConfigDrift::of(
+            format!("Pod {} image tag", pod.name_any()),
+            values.io_engine_image_tag(),
+            image_tag(image),
+        ));
+        drifts.extend(ConfigDrift::of(
+            format!("Pod {} log level", pod.name_any()),
+            values.io_engine_log_level(),
+            io_engine_log_level(pod).unwrap_or_default(),
+        ));
+    }
+
+    Ok(drifts)
+}
+
+/// This compares the replicas of the etcd StatefulSet against the helm values. There is nothing
+/// to compare if there are no etcd Pods, e.g. if the etcd cluster is external.
+async fn etcd_drift(
+    k8s_client: &KubeClientSet,
+    namespace: &str,
+    values: &LoadedValues,
+) -> Result<Option<ConfigDrift>> {
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(ETCD_LABEL))
+        .await
+        .context(ListPodsWithLabel {
+            label: ETCD_LABEL.to_string(),
+            namespace: namespace.to_string(),
+        })?;
+    let Some(statefulset_name) = pods.iter().find_map(|pod| {
+        pod.owner_references()
+            .iter()
+            .find(|owner| owner.kind.eq("StatefulSet"))
+            .map(|owner| owner.name.clone())
+    }) else {
+        info!("There are no etcd Pods, skipping the etcd replicas check");
+        return Ok(None);
+    };
+
+    let statefulset = k8s_client
+        .statefulsets_api()
+        .get(statefulset_name.as_str())
+        .await
+        .context(GetStatefulSet {
+            name: statefulset_name.clone(),
+            namespace: namespace.to_string(),
+        })?;
+
+    Ok(etcd_replicas_drift(&statefulset, values))
+}
+
+/// This is like etcd_drift, for the etcd StatefulSet.
+fn etcd_replicas_drift(statefulset: &StatefulSet, values: &LoadedValues) -> Option<ConfigDrift> {
+    // The replicas default to 1, if they are not set.
+    let replicas = statefulset
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.replicas)
+        .unwrap_or(1);
+
+    ConfigDrift::of(
+        format!("StatefulSet {} replicas", statefulset.name_any()),
+        values.etcd_replica_count(),
+        replicas,
+    )
+}
+
+/// This confirms that the io-engine DaemonSet Pods run the io-engine container image of the
+/// upgraded helm release, after the data-plane upgrade.
+pub(crate) async fn verify_data_plane(
+    namespace: String,
+    release_name: String,
+    skip_node_label: Option<&NodeLabel>,
+) -> Result<()> {
+    let k8s_client = KubeClientSet::builder()
+        .with_namespace(namespace.as_str())
+        .build()
+        .await?;
+    let values = installed_values(&k8s_client, namespace.as_str(), release_name.as_str()).await?;
+
+    let skipped_nodes = skipped_nodes(&k8s_client, skip_node_label).await?;
+
+    confirm_image_tags(
+        &k8s_client,
+        namespace,
+        values.io_engine_image_tag(),
+        &skipped_nodes,
+    )
+    .await
+}
+
+/// This lists the io-engine DaemonSet Pods and fails if any of their io-engine containers does
+/// not run an image with the expected tag. The io-engine Pods on the skipped nodes are expected to
+/// remain at the installed version, they are only warned about.
+pub(crate) async fn confirm_image_tags(
+    k8s_client: &KubeClientSet,
+    namespace: String,
+    expected_tag: &str,
+    skipped_nodes: &HashSet<String>,
+) -> Result<()> {
+    let pods = k8s_client
+        .pods_api()
+        .list(&ListParams::default().labels(IO_ENGINE_LABEL))
+        .await
+        .context(ListPodsWithLabel {
+            label: IO_ENGINE_LABEL.to_string(),
+            namespace: namespace.clone(),
+        })?;
+
+    let verified_pods = check_image_tags(
+        pods.items.as_slice(),
+        namespace.as_str(),
+        expected_tag,
+        skipped_nodes,
+    )?;
+
+    info!(
+        "Verified that all {verified_pods} io-engine Pods on the upgraded nodes run container \
+        image tag '{expected_tag}'"
+    );
+    Ok(())
+}
+
+/// This is like confirm_image_tags, for the listed io-engine Pods. This returns the number of
+/// io-engine Pods which were verified.
+fn check_image_tags(
+    pods: &[Pod],
+    namespace: &str,
+    expected_tag: &str,
+    skipped_nodes: &HashSet<String>,
+) -> Result<usize> {
+    let mut verified_pods = 0_usize;
+    for pod in pods {
+        let image = io_engine_image(pod, namespace)?;
+
+        let actual = image_tag(image);
+        let node_name = pod.spec.as_ref().and_then(|spec| spec.node_name.as_deref());
+        if let Some(node_name) = node_name.filter(|node_name| skipped_nodes.contains(*node_name)) {
+            if actual.ne(expected_tag) {
+                warn!(
+                    pod.name = %pod.name_any(),
+                    node.name = %node_name,
+                    "The io-engine Pod on the skipped node remains at container image tag \
+                    '{actual}', instead of '{expected_tag}'"
+                );
+            }
+            continue;
+        }
+        ensure!(
+            actual.eq(expected_tag),
+            PodImageTagMismatch {
+                pod: pod.name_any(),
+                expected: expected_tag,
+                actual,
+            }
+        );
+        verified_pods += 1;
+    }
+
+    Ok(verified_pods)
+}
+
+/// This returns the container image of the io-engine container of an io-engine DaemonSet Pod.
+pub(crate) fn io_engine_image<'a>(pod: &'a Pod, namespace: &str) -> Result<&'a str> {
+    let spec = pod.spec.as_ref().ok_or(
+        EmptyPodSpec {
+            name: pod.name_any(),
+            namespace: namespace.to_string(),
+        }
+        .build(),
+    )?;
+
+    spec.containers
+        .iter()
+        .find(|container| container.name.eq(IO_ENGINE_CONTAINER_NAME))
+        .and_then(|container| container.image.as_deref())
+        .ok_or(
+            IoEngineContainerAbsent {
+                pod: pod.name_any(),
+            }
+            .build(),
+        )
+}
+
+/// This returns the log level of the io-engine container of an io-engine DaemonSet Pod, if it is
+/// set.
+fn io_engine_log_level(pod: &Pod) -> Option<&str> {
+    pod.spec
+        .as_ref()?
+        .containers
+        .iter()
+        .find(|container| container.name.eq(IO_ENGINE_CONTAINER_NAME))?
+        .env
+        .iter()
+        .flatten()
+        .find(|env| env.name.eq(IO_ENGINE_LOG_LEVEL_ENV))?
+        .value
+        .as_deref()
+}
+
+/// This picks out the tag from a container image reference, e.g. '2.4.0' from
+/// 'docker.io/openebs/mayastor-io-engine:2.4.0'. The port of the registry, if any, is not a tag.
+pub(crate) fn image_tag(image: &str) -> &str {
+    let image = image.split('@').next().unwrap_or_default();
+    let name_start = image.rfind('/').map(|index| index + 1).unwrap_or(0);
+    match image[name_start ..].rfind(':') {
+        Some(index) => &image[name_start + index + 1 ..],
+        None => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::error::Error;
+    use k8s_openapi::api::core::v1::EnvVar;
+
+    /// This is an io-engine DaemonSet Pod on a node, which runs the io-engine container image with
+    /// the tag.
+    fn io_engine_pod(name: &str, node_name: &str, tag: &str) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "name": name, "labels": { "app": "io-engine" } },
+            "spec": {
+                "nodeName": node_name,
+                "containers": [
+                    { "name": "agent-core-grpc-probe", "image": "busybox:latest" },
+                    {
+                        "name": IO_ENGINE_CONTAINER_NAME,
+                        "image": format!("docker.io/openebs/mayastor-io-engine:{tag}")
+                    }
+                ]
+            }
+        }))
+        .unwrap()
+    }
+
+    /// This is a list of io-engine Pods, of which the one on node-2 still runs the old tag.
+    fn pods_with_a_lagging_pod() -> Vec<Pod> {
+        vec![
+            io_engine_pod("mayastor-io-engine-a1b2c", "node-1", "v2.5.0"),
+            io_engine_pod("mayastor-io-engine-d3e4f", "node-2", "v2.4.0"),
+            io_engine_pod("mayastor-io-engine-g5h6i", "node-3", "v2.5.0"),
+        ]
+    }
+
+    /// These are the installed values, with the image tag v2.5.0 and 3 etcd replicas.
+    fn installed_values() -> LoadedValues {
+        let mut values: serde_yaml::Value =
+            serde_yaml::from_str(include_str!("../../../../../../chart/values.yaml")).unwrap();
+        values["image"]["tag"] = serde_yaml::Value::from("v2.5.0");
+        values["io_engine"]["logLevel"] = serde_yaml::Value::from("info");
+        values["etcd"]["replicaCount"] = serde_yaml::Value::from(3);
+        detect_and_load(serde_yaml::to_string(&values).unwrap().as_str(), "mayastor").unwrap()
+    }
+
+    /// This is an io-engine Pod like io_engine_pod, whose io-engine container has the log level.
+    fn io_engine_pod_with_log_level(name: &str, tag: &str, log_level: &str) -> Pod {
+        let mut pod = io_engine_pod(name, "node-1", tag);
+        pod.spec.as_mut().unwrap().containers[1].env = Some(vec![EnvVar {
+            name: IO_ENGINE_LOG_LEVEL_ENV.to_string(),
+            value: Some(log_level.to_string()),
+            ..Default::default()
+        }]);
+        pod
+    }
+
+    /// This is the etcd StatefulSet with the replicas, if any.
+    fn etcd_statefulset(replicas: Option<i32>) -> StatefulSet {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "mayastor-etcd" },
+            "spec": {
+                "replicas": replicas,
+                "selector": {},
+                "serviceName": "mayastor-etcd-headless",
+                "template": {}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_cluster_has_no_drift() {
+        let values = installed_values();
+        let pods = [
+            io_engine_pod_with_log_level("mayastor-io-engine-a1b2c", "v2.5.0", "info"),
+            io_engine_pod_with_log_level("mayastor-io-engine-d3e4f", "v2.5.0", "info"),
+        ];
+
+        assert!(io_engine_pod_drifts(&pods, "mayastor", &values)
+            .unwrap()
+            .is_empty());
+        assert!(etcd_replicas_drift(&etcd_statefulset(Some(3)), &values).is_none());
+    }
+
+    #[test]
+    fn drifted_cluster_lists_each_drift() {
+        let values = installed_values();
+        let pods = [
+            io_engine_pod_with_log_level("mayastor-io-engine-a1b2c", "v2.5.0", "info"),
+            io_engine_pod_with_log_level("mayastor-io-engine-d3e4f", "v2.4.0", "debug"),
+        ];
+
+        let drifts: Vec<String> = io_engine_pod_drifts(&pods, "mayastor", &values)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            drifts,
+            vec![
+                "Pod mayastor-io-engine-d3e4f image tag is 'v2.4.0', expected 'v2.5.0'",
+                "Pod mayastor-io-engine-d3e4f log level is 'debug', expected 'info'",
+            ]
+        );
+
+        // The replicas default to 1, if they are not set.
+        let drift = etcd_replicas_drift(&etcd_statefulset(None), &values).unwrap();
+        assert_eq!(
+            drift.to_string(),
+            "StatefulSet mayastor-etcd replicas is '1', expected '3'"
+        );
+        let drift_json = serde_json::to_value(&drift).unwrap();
+        assert_eq!(
+            drift_json,
+            serde_json::json!({
+                "field": "StatefulSet mayastor-etcd replicas",
+                "expected": "3",
+                "actual": "1"
+            })
+        );
+    }
+
+    #[test]
+    fn lagging_pod_is_an_image_tag_mismatch() {
+        let result = check_image_tags(
+            pods_with_a_lagging_pod().as_slice(),
+            "mayastor",
+            "v2.5.0",
+            &HashSet::new(),
+        );
+
+        let Err(Error::PodImageTagMismatch {
+            pod,
+            expected,
+            actual,
+        }) = result
+        else {
+            panic!("expected PodImageTagMismatch, got {result:?}");
+        };
+        assert_eq!(pod, "mayastor-io-engine-d3e4f");
+        assert_eq!(expected, "v2.5.0");
+        assert_eq!(actual, "v2.4.0");
+    }
+
+    #[test]
+    fn lagging_pod_on_a_skipped_node_is_not_verified() {
+        let skipped_nodes = HashSet::from(["node-2".to_string()]);
+        let verified_pods = check_image_tags(
+            pods_with_a_lagging_pod().as_slice(),
+            "mayastor",
+            "v2.5.0",
+            &skipped_nodes,
+        )
+        .unwrap();
+        assert_eq!(verified_pods, 2);
+    }
+
+    #[test]
+    fn pod_without_an_io_engine_container_fails() {
+        let mut pod = io_engine_pod("mayastor-io-engine-a1b2c", "node-1", "v2.5.0");
+        pod.spec.as_mut().unwrap().containers.pop();
+
+        let result = check_image_tags(&[pod], "mayastor", "v2.5.0", &HashSet::new());
+        assert!(matches!(result, Err(Error::IoEngineContainerAbsent { .. })));
+    }
+
+    #[test]
+    fn image_tag_is_picked_out_of_the_image_reference() {
+        assert_eq!(
+            image_tag("docker.io/openebs/mayastor-io-engine:v2.5.0"),
+            "v2.5.0"
+        );
+        assert_eq!(image_tag("registry:5000/openebs/mayastor-io-engine"), "");
+        assert_eq!(
+            image_tag("registry:5000/io-engine:v2.5.0@sha256:abcd"),
+            "v2.5.0"
+        );
+    }
+}
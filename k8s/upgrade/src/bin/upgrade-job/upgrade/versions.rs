@@ -0,0 +1,52 @@
+use crate::{
+    common::{
+        constants::CORE_CHART_NAME,
+        error::{Result, SerializeChartVersions},
+    },
+    helm::repo::{chart_versions, fetch_index},
+    opts::OutputFormat,
+};
+use serde::Serialize;
+use snafu::ResultExt;
+
+/// This is a published version of the helm chart.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChartVersion {
+    /// The version of the helm chart.
+    version: String,
+    /// The version of the PRODUCT release which the helm chart ships, if it is a semver.
+    app_version: Option<String>,
+}
+
+/// This prints the published versions of the Core helm chart in a helm repository to stdout, the
+/// latest version first, so that an upgrade target can be picked.
+pub(crate) async fn list_versions(repo_url: &str, output: OutputFormat) -> Result<()> {
+    let index_yaml = fetch_index(repo_url).await?;
+    let versions: Vec<ChartVersion> =
+        chart_versions(index_yaml.as_slice(), CORE_CHART_NAME, repo_url)?
+            .iter()
+            .map(|chart| ChartVersion {
+                version: chart.version().to_string(),
+                app_version: chart.app_version().map(ToString::to_string),
+            })
+            .collect();
+
+    match output {
+        OutputFormat::Json => {
+            let versions_json = serde_json::to_string(&versions).context(SerializeChartVersions)?;
+            println!("{versions_json}");
+        }
+        OutputFormat::Text => {
+            for version in versions.iter() {
+                println!(
+                    "{}\t{}",
+                    version.version,
+                    version.app_version.as_deref().unwrap_or("-")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::{
+    common::{
+        constants::PRE_UPGRADE_WEBHOOK_MAX_ATTEMPTS,
+        error::{
+            Error, PreUpgradeWebhookHttpsConnector, PreUpgradeWebhookRejected,
+            PreUpgradeWebhookRequest, PreUpgradeWebhookRequestBuild, PreUpgradeWebhookTimeout,
+            PreUpgradeWebhookUriParse, Result, SerializeUpgradePlan,
+        },
+        retry::with_backoff,
+    },
+    upgrade::plan::UpgradePlan,
+};
+use hyper::{body, header, Body, Client, Request, Uri};
+use hyper_openssl::HttpsConnector;
+use snafu::{ensure, ResultExt};
+use std::time::Duration;
+use tracing::info;
+
+/// This POSTs the upgrade plan, as JSON, to a user-provided webhook, and fails unless the webhook
+/// approves the upgrade with a 2xx response. A request which fails or times out is retried once.
+pub(crate) async fn call_pre_upgrade_webhook(
+    url: &str,
+    timeout: Duration,
+    plan: &UpgradePlan,
+) -> Result<()> {
+    let uri: Uri = url.parse().context(PreUpgradeWebhookUriParse { url })?;
+    let plan_json = serde_json::to_vec(plan).context(SerializeUpgradePlan)?;
+    let connector = HttpsConnector::new().context(PreUpgradeWebhookHttpsConnector)?;
+    let client = Client::builder().build::<_, Body>(connector);
+
+    with_backoff(
+        PRE_UPGRADE_WEBHOOK_MAX_ATTEMPTS,
+        |error: &Error| {
+            matches!(
+                error,
+                Error::PreUpgradeWebhookRequest { .. } | Error::PreUpgradeWebhookTimeout { .. }
+            )
+        },
+        || async {
+            let request = Request::post(uri.clone())
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(plan_json.clone()))
+                .context(PreUpgradeWebhookRequestBuild { url })?;
+
+            let exchange = async {
+                let response = client.request(request).await?;
+                let status = response.status();
+                let body = body::to_bytes(response.into_body()).await?;
+                Ok::<_, hyper::Error>((status, body))
+            };
+            let (status, body) = tokio::time::timeout(timeout, exchange)
+                .await
+                .map_err(|_| PreUpgradeWebhookTimeout { url, timeout }.build())?
+                .context(PreUpgradeWebhookRequest { url })?;
+
+            ensure!(
+                status.is_success(),
+                PreUpgradeWebhookRejected {
+                    status: status.as_u16(),
+                    body: String::from_utf8_lossy(&body).to_string(),
+                }
+            );
+            Ok(())
+        },
+    )
+    .await?;
+
+    info!(
+        webhook.url = url,
+        "Pre-upgrade webhook approved the upgrade"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server, StatusCode,
+    };
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    /// These are the JSON bodies of the requests which a mocked webhook received.
+    type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// This serves a mocked webhook on a local port, which responds with the status and the body
+    /// after the delay. This returns the webhook's URL and the requests it receives.
+    fn serve_mock_webhook(
+        status: StatusCode,
+        response_body: &'static str,
+        delay: Duration,
+    ) -> (String, Requests) {
+        let requests = Requests::default();
+        let received = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = received.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let received = received.clone();
+                    async move {
+                        let request_body = body::to_bytes(request.into_body()).await.unwrap();
+                        received
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice(&request_body).unwrap());
+                        tokio::time::sleep(delay).await;
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::from(response_body))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}/approve", server.local_addr());
+        tokio::spawn(server);
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn approval_lets_the_upgrade_proceed() {
+        let (url, requests) = serve_mock_webhook(StatusCode::OK, "", Duration::ZERO);
+        let plan = UpgradePlan::default();
+
+        let result = call_pre_upgrade_webhook(url.as_str(), Duration::from_secs(5), &plan).await;
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![serde_json::to_value(&plan).unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejection_aborts_with_the_status_and_the_body() {
+        let (url, requests) =
+            serve_mock_webhook(StatusCode::FORBIDDEN, "change freeze", Duration::ZERO);
+
+        let result = call_pre_upgrade_webhook(
+            url.as_str(),
+            Duration::from_secs(5),
+            &UpgradePlan::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::PreUpgradeWebhookRejected { status: 403, ref body })
+                if body.eq("change freeze")
+        ));
+        // A rejection is not retried.
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn timed_out_request_is_retried_once() {
+        let (url, requests) = serve_mock_webhook(StatusCode::OK, "", Duration::from_secs(2));
+
+        let result = call_pre_upgrade_webhook(
+            url.as_str(),
+            Duration::from_millis(100),
+            &UpgradePlan::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::PreUpgradeWebhookTimeout { .. })
+        ));
+        assert_eq!(
+            requests.lock().unwrap().len(),
+            PRE_UPGRADE_WEBHOOK_MAX_ATTEMPTS as usize
+        );
+    }
+}
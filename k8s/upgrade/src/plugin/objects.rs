@@ -288,6 +288,9 @@ pub(crate) fn upgrade_job(
         format!("--release-name={release_name}"),
         format!("--helm-args-set={helm_args_set}"),
         format!("--helm-args-set-file={set_file}"),
+        // The upgrade Job has no terminal to confirm the upgrade on, the plugin's invocation is
+        // the confirmation.
+        "--yes".to_string(),
     ];
     if args.skip_data_plane_restart {
         job_args.push("--skip-data-plane-restart".to_string());
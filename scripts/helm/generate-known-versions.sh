@@ -0,0 +1,42 @@
+#!/usr/bin/env bash
+
+# Generates the upgrade-job's catalog of released Core helm chart versions from the published
+# helm chart index, i.e. the index.yaml on the gh-pages branch. Pre-releases are left out.
+
+SCRIPTDIR=$(dirname "$0")
+ROOTDIR="$SCRIPTDIR"/../../
+KNOWN_VERSIONS=${KNOWN_VERSIONS:-"$ROOTDIR/k8s/upgrade/config/known_versions.yaml"}
+CHART_NAME="mayastor"
+INDEX_REMOTE="${INDEX_REMOTE:-origin}"
+INDEX_BRANCH="gh-pages"
+INDEX_BRANCH_FILE="index.yaml"
+INDEX_FILE=${INDEX_FILE:-}
+SKIP_GIT=${SKIP_GIT:-}
+
+set -euo pipefail
+
+index_yaml() {
+  if [ -n "$INDEX_FILE" ]; then
+    cat "$INDEX_FILE"
+  else
+    git fetch "$INDEX_REMOTE" "$INDEX_BRANCH" --depth 1 2>/dev/null
+    git show "$INDEX_REMOTE"/"$INDEX_BRANCH":"$INDEX_BRANCH_FILE"
+  fi
+}
+
+versions=$(index_yaml \
+  | yq ".entries.$CHART_NAME[].version" \
+  | grep -E '^[0-9]+\.[0-9]+\.[0-9]+$' \
+  | sort -V -u)
+
+{
+  echo "# This file is generated by scripts/helm/generate-known-versions.sh, do not edit it."
+  echo "known_versions:"
+  for version in $versions; do
+    echo "  - $version"
+  done
+} > "$KNOWN_VERSIONS"
+
+if [ -z "$SKIP_GIT" ]; then
+  git diff --exit-code "$KNOWN_VERSIONS"
+fi